@@ -0,0 +1,82 @@
+use async_tftp::packet::{Mode, Opts, Packet, RwReq};
+use bytes::BytesMut;
+use criterion::{
+    black_box, criterion_group, criterion_main, Criterion, Throughput,
+};
+
+fn rrq() -> Packet<'static> {
+    let mut opts = Opts::default();
+    opts.block_size = Some(1468);
+    opts.timeout = Some(1);
+    opts.transfer_size = Some(0);
+
+    Packet::Rrq(RwReq {
+        filename: b"pxelinux.cfg/default".to_vec(),
+        mode: Mode::Octet,
+        opts,
+    })
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+
+    let rrq = rrq().to_bytes();
+    group.throughput(Throughput::Bytes(rrq.len() as u64));
+    group.bench_function("rrq", |b| {
+        b.iter(|| Packet::decode(black_box(&rrq[..])).unwrap())
+    });
+
+    let data = Packet::Data(1, &[0xaa; 1468]).to_bytes();
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("data", |b| {
+        b.iter(|| Packet::decode(black_box(&data[..])).unwrap())
+    });
+
+    let ack = Packet::Ack(1).to_bytes();
+    group.throughput(Throughput::Bytes(ack.len() as u64));
+    group.bench_function("ack", |b| {
+        b.iter(|| Packet::decode(black_box(&ack[..])).unwrap())
+    });
+
+    group.finish();
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    let mut buf = BytesMut::with_capacity(2048);
+
+    let rrq = rrq();
+    group.bench_function("rrq", |b| {
+        b.iter(|| {
+            buf.clear();
+            black_box(&rrq).encode(&mut buf);
+        })
+    });
+
+    let mut opts = Opts::default();
+    opts.block_size = Some(1468);
+    opts.timeout = Some(1);
+    opts.transfer_size = Some(33_554_432);
+    opts.window_size = Some(16);
+    let oack = Packet::OAck(opts);
+    group.bench_function("oack", |b| {
+        b.iter(|| {
+            buf.clear();
+            black_box(&oack).encode(&mut buf);
+        })
+    });
+
+    let payload = [0xaa; 1468];
+    group.throughput(Throughput::Bytes(payload.len() as u64));
+    group.bench_function("data", |b| {
+        b.iter(|| {
+            buf.clear();
+            Packet::Data(1, black_box(&payload[..])).encode(&mut buf);
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode, bench_encode);
+criterion_main!(benches);
@@ -0,0 +1,158 @@
+use async_tftp::packet::{self, Mode, Opts, Packet, RwReq};
+use async_tftp::server::{Handler, TftpServerBuilder};
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use futures_lite::future::block_on;
+use futures_lite::io::{Cursor, Sink};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+const FILE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Serves the same in-memory file for every read request.
+struct MemHandler {
+    // Shared by the readers of all requests, without copying
+    content: Bytes,
+}
+
+#[async_tftp::async_trait]
+impl Handler for MemHandler {
+    type Reader = Cursor<Bytes>;
+    type Writer = Sink;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        let len = self.content.len() as u64;
+        Ok((Cursor::new(self.content.clone()), Some(len)))
+    }
+
+    async fn write_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        Err(packet::Error::IllegalOperation)
+    }
+}
+
+fn start_server() -> SocketAddr {
+    let handler = MemHandler {
+        content: Bytes::from(vec![0xaa; FILE_SIZE]),
+    };
+
+    let tftpd = block_on(
+        TftpServerBuilder::with_handler(handler)
+            .bind("127.0.0.1:0".parse().unwrap())
+            .max_window_size(64)
+            .build(),
+    )
+    .unwrap();
+    let addr = tftpd.listen_addr().unwrap();
+
+    thread::spawn(move || block_on(tftpd.serve()).unwrap());
+
+    addr
+}
+
+/// Download a file with a minimal client and return its size.
+///
+/// Only the last block of every window is acknowledged, with the block
+/// size and the window size that the server acknowledged.
+fn download(server: SocketAddr, opts: Opts) -> usize {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let req = Packet::Rrq(RwReq {
+        filename: b"bench".to_vec(),
+        mode: Mode::Octet,
+        opts,
+    });
+    socket.send_to(&req.to_bytes(), server).unwrap();
+
+    let mut buf = vec![0u8; 65536];
+    let mut block_size = 512;
+    let mut window_size = 1;
+    // Last block that was received in order
+    let mut last_block = 0u16;
+    let mut unacked = 0;
+    let mut size = 0;
+
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).expect("server timeout");
+
+        match Packet::decode(&buf[..len]).unwrap() {
+            Packet::OAck(opts) => {
+                block_size = opts.block_size.map_or(512, usize::from);
+                window_size = opts.window_size.unwrap_or(1);
+                socket.send_to(&Packet::Ack(0).to_bytes(), peer).unwrap();
+            }
+            Packet::Data(block_id, data) => {
+                // Blocks out of order make the server send the window
+                // again after the last acknowledged block
+                if block_id != last_block.wrapping_add(1) {
+                    let ack = Packet::Ack(last_block).to_bytes();
+                    socket.send_to(&ack, peer).unwrap();
+                    unacked = 0;
+                    continue;
+                }
+
+                last_block = block_id;
+                size += data.len();
+                unacked += 1;
+                let last = data.len() < block_size;
+
+                if unacked == window_size || last {
+                    let ack = Packet::Ack(block_id).to_bytes();
+                    socket.send_to(&ack, peer).unwrap();
+                    unacked = 0;
+                }
+
+                if last {
+                    return size;
+                }
+            }
+            packet => panic!("unexpected packet: {:?}", packet),
+        }
+    }
+}
+
+fn bench_rrq(c: &mut Criterion) {
+    let server = start_server();
+
+    let mut group = c.benchmark_group("rrq");
+    group.sample_size(10);
+
+    for &block_size in &[512u16, 1024, 1468, 8192, 65464] {
+        for &window_size in &[None, Some(4), Some(16)] {
+            let name = match window_size {
+                Some(window_size) => {
+                    format!("blksize={}/windowsize={}", block_size, window_size)
+                }
+                None => format!("blksize={}", block_size),
+            };
+
+            let mut opts = Opts::default();
+            opts.block_size = Some(block_size);
+            opts.window_size = window_size;
+
+            // Reported as blocks/sec, the file ends with a short block
+            let blocks = FILE_SIZE / usize::from(block_size) + 1;
+            group.throughput(Throughput::Elements(blocks as u64));
+
+            group.bench_function(name, |b| {
+                b.iter(|| assert_eq!(download(server, opts.clone()), FILE_SIZE))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_rrq);
+criterion_main!(benches);
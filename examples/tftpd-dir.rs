@@ -13,7 +13,7 @@ async fn main() -> Result<()> {
     let tftpd = TftpServerBuilder::with_dir_ro(".")?
         .bind("0.0.0.0:6969".parse().unwrap())
         // Workaround to handle cases where client is behind VPN
-        .block_size_limit(1024)
+        .max_block_size(1024)
         .build()
         .await?;
 
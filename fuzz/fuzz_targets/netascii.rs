@@ -0,0 +1,29 @@
+#![no_main]
+
+use async_tftp::netascii::{self, Decoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Arbitrary bytes from the wire must decode without panics
+    let mut decoder = Decoder::new();
+    let mut decoded = Vec::new();
+    decoder.decode(data, &mut decoded);
+    decoder.finish(&mut decoded);
+
+    // Encoded data decodes back to itself, wherever it is split into
+    // chunks
+    let mut encoded = Vec::new();
+    netascii::encode(data, &mut encoded);
+
+    let split =
+        data.first().map_or(0, |&b| usize::from(b)) % (encoded.len() + 1);
+    let (a, b) = encoded.split_at(split);
+
+    let mut decoder = Decoder::new();
+    let mut decoded = Vec::new();
+    decoder.decode(a, &mut decoded);
+    decoder.decode(b, &mut decoded);
+    decoder.finish(&mut decoded);
+
+    assert_eq!(decoded, data);
+});
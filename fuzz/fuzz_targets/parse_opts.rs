@@ -0,0 +1,8 @@
+#![no_main]
+
+use async_tftp::parse::parse_opts;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_opts(data);
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use async_tftp::packet::Packet;
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(packet) = Packet::decode(data) {
+        // Whatever we accept must be encodable and decodable again
+        let mut buf = BytesMut::new();
+        packet.encode(&mut buf);
+        Packet::decode(&buf).expect("re-encoded packet is invalid");
+    }
+});
@@ -0,0 +1,186 @@
+//! Random packets for property-based testing.
+//!
+//! Generated values are always valid on the wire, so encoding and then
+//! decoding them is expected to produce the same value.
+
+use crate::packet::{self, Mode, Opts, OwnedPacket, RwReq};
+
+/// Largest payload of generated DATA packets.
+const MAX_DATA_LEN: usize = 1024;
+
+#[cfg(any(test, feature = "proptest"))]
+mod proptest_impls {
+    use proptest::arbitrary::{any, Arbitrary};
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::prelude::*;
+    use proptest::strategy::BoxedStrategy;
+
+    use super::*;
+
+    impl Arbitrary for Mode {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Mode>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            prop_oneof![
+                Just(Mode::Netascii),
+                Just(Mode::Octet),
+                Just(Mode::Mail)
+            ]
+            .boxed()
+        }
+    }
+
+    impl Arbitrary for Opts {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Opts>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            (
+                option::of(8u16..=65464),
+                option::of(1u8..=255),
+                option::of(any::<u64>()),
+                option::of(1u64..=u64::MAX),
+            )
+                .prop_map(
+                    |(block_size, timeout, transfer_size, window_size)| Opts {
+                        block_size,
+                        timeout,
+                        transfer_size,
+                        window_size,
+                    },
+                )
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for RwReq {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<RwReq>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            // Filenames without NUL
+            (vec(1u8..=255, 1..128), any::<Mode>(), any::<Opts>())
+                .prop_map(|(filename, mode, opts)| RwReq {
+                    filename,
+                    mode,
+                    opts,
+                })
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for packet::Error {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<packet::Error>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            // `UnknownError` is not generated, because it is decoded as
+            // `Msg`.
+            prop_oneof![
+                "[a-zA-Z0-9]{0,64}".prop_map(packet::Error::Msg),
+                (1u16..=8)
+                    .prop_map(|code| packet::Error::from_code(code, None)),
+            ]
+            .boxed()
+        }
+    }
+
+    impl Arbitrary for OwnedPacket {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<OwnedPacket>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            prop_oneof![
+                any::<RwReq>().prop_map(OwnedPacket::Rrq),
+                any::<RwReq>().prop_map(OwnedPacket::Wrq),
+                (any::<u16>(), vec(any::<u8>(), 0..=MAX_DATA_LEN))
+                    .prop_map(|(block, data)| OwnedPacket::Data(block, data)),
+                any::<u16>().prop_map(OwnedPacket::Ack),
+                any::<packet::Error>().prop_map(OwnedPacket::Error),
+                any::<Opts>().prop_map(OwnedPacket::OAck),
+            ]
+            .boxed()
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+mod quickcheck_impls {
+    use quickcheck::{Arbitrary, Gen};
+
+    use super::*;
+
+    const ALPHANUMERIC: &[u8] =
+        b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+    impl Arbitrary for Mode {
+        fn arbitrary(g: &mut Gen) -> Self {
+            *g.choose(&[Mode::Netascii, Mode::Octet, Mode::Mail]).unwrap()
+        }
+    }
+
+    impl Arbitrary for Opts {
+        fn arbitrary(g: &mut Gen) -> Self {
+            Opts {
+                block_size: bool::arbitrary(g)
+                    .then(|| 8 + u16::arbitrary(g) % (65464 - 8 + 1)),
+                timeout: bool::arbitrary(g).then(|| u8::arbitrary(g).max(1)),
+                transfer_size: Option::arbitrary(g),
+                window_size: bool::arbitrary(g)
+                    .then(|| u64::arbitrary(g).max(1)),
+            }
+        }
+    }
+
+    impl Arbitrary for RwReq {
+        fn arbitrary(g: &mut Gen) -> Self {
+            // Filenames without NUL
+            let len = 1 + usize::arbitrary(g) % g.size().max(1);
+            let filename = (0..len).map(|_| u8::arbitrary(g).max(1)).collect();
+
+            RwReq {
+                filename,
+                mode: Mode::arbitrary(g),
+                opts: Opts::arbitrary(g),
+            }
+        }
+    }
+
+    impl Arbitrary for packet::Error {
+        fn arbitrary(g: &mut Gen) -> Self {
+            // `UnknownError` is not generated, because it is decoded as
+            // `Msg`.
+            match bool::arbitrary(g) {
+                true => {
+                    let len = usize::arbitrary(g) % 65;
+                    let msg = (0..len)
+                        .map(|_| char::from(*g.choose(ALPHANUMERIC).unwrap()))
+                        .collect();
+                    packet::Error::Msg(msg)
+                }
+                false => {
+                    packet::Error::from_code(1 + u16::arbitrary(g) % 8, None)
+                }
+            }
+        }
+    }
+
+    impl Arbitrary for OwnedPacket {
+        fn arbitrary(g: &mut Gen) -> Self {
+            match u8::arbitrary(g) % 6 {
+                0 => OwnedPacket::Rrq(RwReq::arbitrary(g)),
+                1 => OwnedPacket::Wrq(RwReq::arbitrary(g)),
+                2 => {
+                    let mut data = Vec::<u8>::arbitrary(g);
+                    data.truncate(MAX_DATA_LEN);
+                    OwnedPacket::Data(u16::arbitrary(g), data)
+                }
+                3 => OwnedPacket::Ack(u16::arbitrary(g)),
+                4 => OwnedPacket::Error(packet::Error::arbitrary(g)),
+                _ => OwnedPacket::OAck(Opts::arbitrary(g)),
+            }
+        }
+    }
+}
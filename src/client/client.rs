@@ -0,0 +1,560 @@
+use futures_lite::AsyncRead;
+use log::trace;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::connection::Connection;
+use super::read::ReadStream;
+use super::trace::{PacketTrace, PacketTracer, TraceDirection};
+use crate::clock::{Clock, SystemClock};
+use crate::error::{Error, RemoteError, Result};
+use crate::packet::{self, Mode, Opts, Packet, RwReq};
+use crate::transport::Transport;
+use crate::utils::{bind_async, io_timeout, is_conn_reset};
+
+/// What to do with an `UnknownTransferId` ERROR that answers a request.
+///
+/// See [`TftpClient::stray_errors`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrayErrors {
+    /// Treat it as a reply of a stray responder and send the request again.
+    Ignore,
+    /// Fail the request, like with any other error.
+    Fail,
+}
+
+/// TFTP client.
+///
+/// Every transfer runs over its own socket, so a client can serve many
+/// transfers concurrently.
+///
+/// # Example
+///
+/// ```ignore
+/// use async_tftp::client::TftpClient;
+///
+/// let client = TftpClient::new("192.168.1.1:69".parse()?).block_size(1428);
+/// let content = client.read_to_vec("pxelinux.0").await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct TftpClient {
+    // Tried in order until one answers
+    servers: Vec<SocketAddr>,
+    prefer_ipv4: bool,
+    block_size: Option<u16>,
+    timeout: Duration,
+    request_timeout: Option<Duration>,
+    max_retries: u32,
+    stray_errors: StrayErrors,
+    tracer: Option<PacketTracer>,
+    transport: Option<ClientTransport>,
+}
+
+/// Socket that binds the sockets of transfers.
+#[derive(Clone)]
+struct ClientTransport(Arc<dyn Transport>);
+
+impl fmt::Debug for ClientTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ClientTransport")
+    }
+}
+
+impl TftpClient {
+    /// Create new client for the server at `server`.
+    pub fn new(server: SocketAddr) -> Self {
+        TftpClient::from_addrs([server])
+    }
+
+    /// Create new client for a server with several addresses.
+    ///
+    /// Requests try the addresses until one answers, IPv6 addresses first
+    /// unless [`prefer_ipv4`](Self::prefer_ipv4) is set.
+    ///
+    /// # Panics
+    ///
+    /// If `addrs` is empty.
+    pub fn from_addrs<I>(addrs: I) -> Self
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        let servers: Vec<_> = addrs.into_iter().collect();
+        assert!(!servers.is_empty(), "no server address");
+
+        TftpClient {
+            servers,
+            prefer_ipv4: false,
+            block_size: None,
+            timeout: Duration::from_secs(3),
+            request_timeout: None,
+            max_retries: 5,
+            stray_errors: StrayErrors::Ignore,
+            tracer: None,
+            transport: None,
+        }
+    }
+
+    /// Create new client for `host`, e.g. a DNS name.
+    ///
+    /// The host is resolved to all its addresses, which are tried like
+    /// in [`from_addrs`](Self::from_addrs).
+    pub async fn resolve(host: &str, port: u16) -> Result<Self> {
+        let host = host.to_owned();
+        let addrs: Vec<_> = blocking::unblock(move || {
+            (host.as_str(), port).to_socket_addrs().map(Iterator::collect)
+        })
+        .await?;
+
+        if addrs.is_empty() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "host has no addresses",
+            )));
+        }
+
+        Ok(TftpClient::from_addrs(addrs))
+    }
+
+    /// Try IPv4 addresses of the server before IPv6 ones.
+    ///
+    /// **Default:** IPv6 addresses are tried first
+    pub fn prefer_ipv4(self) -> Self {
+        TftpClient {
+            prefer_ipv4: true,
+            ..self
+        }
+    }
+
+    /// Request a block size (RFC2348).
+    ///
+    /// The server may choose a smaller one.
+    ///
+    /// **Default:** 512 bytes, without requesting the option
+    pub fn block_size(self, size: u16) -> Self {
+        TftpClient {
+            block_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Set retry timeout.
+    ///
+    /// **Default:** 3 seconds
+    pub fn timeout(self, timeout: Duration) -> Self {
+        TftpClient {
+            timeout,
+            ..self
+        }
+    }
+
+    /// Set retry timeout of the request, until the server replies for the
+    /// first time.
+    ///
+    /// A dead server can be detected quickly with a short one, while
+    /// [`timeout`](Self::timeout) allows long gaps in a slow transfer.
+    ///
+    /// **Default:** Same as [`timeout`](Self::timeout)
+    pub fn request_timeout(self, timeout: Duration) -> Self {
+        TftpClient {
+            request_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Set maximum retries of a packet.
+    ///
+    /// **Default:** 5
+    pub fn max_retries(self, retries: u32) -> Self {
+        TftpClient {
+            max_retries: retries,
+            ..self
+        }
+    }
+
+    /// Set what to do with an `UnknownTransferId` ERROR that answers a
+    /// request.
+    ///
+    /// A request does not belong to any transfer yet, so this error can
+    /// only come from a stray responder on a network with several of them.
+    /// Errors of other addresses during the transfer are always ignored.
+    ///
+    /// **Default:** [`StrayErrors::Ignore`]
+    pub fn stray_errors(self, policy: StrayErrors) -> Self {
+        TftpClient {
+            stray_errors: policy,
+            ..self
+        }
+    }
+
+    /// Run transfers over another [`Transport`] than UDP.
+    ///
+    /// Every transfer runs over a socket that is bound with
+    /// [`Transport::bind_transfer`] of `transport`.
+    pub fn transport<T>(self, transport: T) -> Self
+    where
+        T: Transport + 'static,
+    {
+        TftpClient {
+            transport: Some(ClientTransport(Arc::new(transport))),
+            ..self
+        }
+    }
+
+    /// Call `f` for every packet that is sent or received.
+    ///
+    /// This helps to debug interoperability problems with other servers
+    /// without capturing the traffic.
+    ///
+    /// **Default:** Packets are not traced
+    pub fn trace_packets<F>(self, f: F) -> Self
+    where
+        F: Fn(&PacketTrace<'_>) + Send + Sync + 'static,
+    {
+        TftpClient {
+            tracer: Some(PacketTracer(Arc::new(f))),
+            ..self
+        }
+    }
+
+    /// Address of the server that is tried first.
+    pub fn server(&self) -> SocketAddr {
+        self.servers()[0]
+    }
+
+    /// Addresses of the server, in the order they are tried.
+    pub fn servers(&self) -> Vec<SocketAddr> {
+        let mut servers = self.servers.clone();
+        // Stable, so the order of the resolver is kept within a family
+        servers.sort_by_key(|addr| addr.is_ipv4() != self.prefer_ipv4);
+        servers
+    }
+
+    /// Start downloading `filename`.
+    ///
+    /// This returns after the server accepts the request. The content is
+    /// received while [`ReadStream`] is read.
+    pub async fn read<F>(&self, filename: F) -> Result<ReadStream>
+    where
+        F: AsRef<[u8]>,
+    {
+        let opts = Opts {
+            block_size: self.block_size,
+            transfer_size: Some(0),
+            ..Opts::default()
+        };
+
+        let req = Packet::Rrq(RwReq {
+            filename: filename.as_ref().to_vec(),
+            mode: Mode::Octet,
+            opts,
+        });
+
+        let mut buf = vec![0u8; 65536];
+        let (mut session, len, peer) = self.request(&req, &mut buf).await?;
+
+        match Packet::decode(&buf[..len]) {
+            Ok(Packet::OAck(opts)) => {
+                session.peer = peer;
+                session.block_size = opts.block_size.map_or(512, usize::from);
+                session.send(&Packet::Ack(0)).await?;
+
+                Ok(ReadStream::new(session, Vec::new(), opts.transfer_size))
+            }
+            Ok(Packet::Data(1, payload)) => {
+                let payload = payload.to_vec();
+
+                session.peer = peer;
+                session.block_id = 1;
+                session.send(&Packet::Ack(1)).await?;
+
+                let mut stream = ReadStream::new(session, payload, None);
+                stream.check_last_block();
+                Ok(stream)
+            }
+            Ok(Packet::Error(_)) => {
+                Err(Error::Remote(RemoteError::decode(&buf[..len])?))
+            }
+            _ => Err(Error::InvalidPacket),
+        }
+    }
+
+    /// Download `filename` in memory.
+    pub async fn read_to_vec<F>(&self, filename: F) -> Result<Vec<u8>>
+    where
+        F: AsRef<[u8]>,
+    {
+        use futures_lite::AsyncReadExt;
+
+        let mut stream = self.read(filename).await?;
+        let mut content = Vec::new();
+
+        stream
+            .read_to_end(&mut content)
+            .await
+            .map_err(ReadStream::into_error)?;
+
+        Ok(content)
+    }
+
+    /// Upload the content of `reader` as `filename`.
+    ///
+    /// `size` is sent as `tsize` option (RFC2349), so the server can reject
+    /// files that do not fit. Returns the number of bytes that were sent.
+    pub async fn write<F, R>(
+        &self,
+        filename: F,
+        reader: R,
+        size: Option<u64>,
+    ) -> Result<u64>
+    where
+        F: AsRef<[u8]>,
+        R: AsyncRead + Unpin,
+    {
+        let opts = Opts {
+            block_size: self.block_size,
+            transfer_size: size,
+            ..Opts::default()
+        };
+
+        let req = Packet::Wrq(RwReq {
+            filename: filename.as_ref().to_vec(),
+            mode: Mode::Octet,
+            opts,
+        });
+
+        let mut buf = vec![0u8; 65536];
+        let (mut session, len, peer) = self.request(&req, &mut buf).await?;
+
+        match Packet::decode(&buf[..len]) {
+            Ok(Packet::OAck(opts)) => {
+                session.block_size = opts.block_size.map_or(512, usize::from);
+            }
+            Ok(Packet::Ack(0)) => {}
+            Ok(Packet::Error(_)) => {
+                return Err(Error::Remote(RemoteError::decode(&buf[..len])?))
+            }
+            _ => return Err(Error::InvalidPacket),
+        }
+
+        session.peer = peer;
+        super::write::send_file(&mut session, reader).await
+    }
+
+    /// Open a low-level [`Connection`] to the server.
+    ///
+    /// Only the address that is tried first is used.
+    pub fn connect(&self) -> Result<Connection> {
+        Ok(Connection::new(self.session(self.server())?))
+    }
+
+    /// Send `req` to the addresses of the server until one replies.
+    async fn request(
+        &self,
+        req: &Packet<'_>,
+        buf: &mut [u8],
+    ) -> Result<(Session, usize, SocketAddr)> {
+        let req = req.to_vec();
+        let mut last_error = None;
+
+        for server in self.servers() {
+            let res = match self.session(server) {
+                Ok(mut session) => session
+                    .request(&req, buf)
+                    .await
+                    .map(|(len, peer)| (session, len, peer)),
+                Err(e) => Err(e),
+            };
+
+            match res {
+                Ok(x) => return Ok(x),
+                Err(e) => {
+                    trace!("Server {} did not answer: {}", server, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("no server address"))
+    }
+
+    fn session(&self, server: SocketAddr) -> Result<Session> {
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+
+        let socket: Box<dyn Transport> = match self.transport {
+            Some(ref transport) => {
+                transport.0.bind_transfer(server).map_err(Error::Bind)?
+            }
+            None => Box::new(bind_async(local).map_err(Error::Bind)?),
+        };
+
+        Ok(Session {
+            socket,
+            peer: server,
+            block_id: 0,
+            block_size: 512,
+            last_sent: Vec::new(),
+            timeout: self.timeout,
+            request_timeout: self.request_timeout.unwrap_or(self.timeout),
+            tracer: self.tracer.clone(),
+            max_retries: self.max_retries,
+            stray_errors: self.stray_errors,
+        })
+    }
+}
+
+/// Socket and state of a transfer.
+pub(crate) struct Session {
+    pub(crate) socket: Box<dyn Transport>,
+    // Transfer socket of the server, once it replies
+    pub(crate) peer: SocketAddr,
+    pub(crate) block_id: u16,
+    pub(crate) block_size: usize,
+    // Sent again when a reply times out
+    pub(crate) last_sent: Vec<u8>,
+    pub(crate) timeout: Duration,
+    pub(crate) request_timeout: Duration,
+    pub(crate) max_retries: u32,
+    pub(crate) stray_errors: StrayErrors,
+    pub(crate) tracer: Option<PacketTracer>,
+}
+
+impl Session {
+    /// Send `packet` to the server, and keep it for retransmissions.
+    pub(crate) async fn send(&mut self, packet: &Packet<'_>) -> Result<()> {
+        self.last_sent.clear();
+        packet.encode_vec(&mut self.last_sent);
+        self.send_to(&self.last_sent, self.peer).await?;
+        Ok(())
+    }
+
+    /// Send request and wait for the first reply, from any port of the
+    /// server.
+    pub(crate) async fn request(
+        &mut self,
+        req: &[u8],
+        buf: &mut [u8],
+    ) -> Result<(usize, SocketAddr)> {
+        let server = self.peer;
+
+        for _ in 0..=self.max_retries {
+            self.send_to(req, server).await?;
+
+            match self.recv_from(buf, self.request_timeout).await {
+                Ok((len, from)) if self.is_stray_error(&buf[..len]) => {
+                    trace!("Stray error from {}, retrying request", from);
+                    continue;
+                }
+                Ok((len, from)) if from.ip() == server.ip() => {
+                    return Ok((len, from))
+                }
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(Error::MaxSendRetriesReached(server, 0))
+    }
+
+    /// Receive a packet from the server, sending the last packet again on
+    /// timeout. Packets of other peers are rejected.
+    pub(crate) async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut retries = 0;
+
+        loop {
+            let (len, from) = match self.recv_from(buf, self.timeout).await {
+                Ok(x) => x,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                    retries += 1;
+
+                    if retries > self.max_retries {
+                        return Err(Error::MaxSendRetriesReached(
+                            self.peer,
+                            self.block_id,
+                        ));
+                    }
+
+                    self.send_to(&self.last_sent, self.peer).await?;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if from == self.peer {
+                return Ok(len);
+            }
+
+            // Errors are never answered
+            if let Ok(Packet::Error(_)) = Packet::decode(&buf[..len]) {
+                continue;
+            }
+
+            // RFC1350: packets from another port get an error, but the
+            // transfer goes on
+            let error = Packet::Error(packet::Error::UnknownTransferId);
+            self.send_to(&error.to_vec(), from).await?;
+        }
+    }
+
+    /// Whether `data` is an error that a stray responder sent to the
+    /// request.
+    fn is_stray_error(&self, data: &[u8]) -> bool {
+        self.stray_errors == StrayErrors::Ignore
+            && matches!(
+                Packet::decode(data),
+                Ok(Packet::Error(packet::Error::UnknownTransferId))
+            )
+    }
+
+    /// Send the last packet again.
+    pub(crate) async fn resend(&mut self) -> Result<()> {
+        self.send_to(&self.last_sent, self.peer).await?;
+        Ok(())
+    }
+
+    /// Send `data` to `peer` without waiting, e.g. from `Drop`.
+    pub(crate) fn send_now(&self, data: &[u8], peer: SocketAddr) {
+        self.trace(TraceDirection::Sent, peer, data);
+        self.socket.try_send_to(data, peer);
+    }
+
+    async fn send_to(&self, data: &[u8], peer: SocketAddr) -> io::Result<()> {
+        self.trace(TraceDirection::Sent, peer, data);
+        self.socket.send_to(data, peer).await?;
+        Ok(())
+    }
+
+    fn trace(&self, direction: TraceDirection, peer: SocketAddr, data: &[u8]) {
+        if let Some(ref tracer) = self.tracer {
+            tracer.trace(direction, peer, data);
+        }
+    }
+
+    async fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<(usize, SocketAddr)> {
+        let socket = &self.socket;
+
+        let (len, from) =
+            io_timeout(&SystemClock as &dyn Clock, timeout, async {
+                loop {
+                    match socket.recv_from(&mut *buf).await {
+                        Err(ref e) if is_conn_reset(e) => continue,
+                        res => return res,
+                    }
+                }
+            })
+            .await?;
+
+        self.trace(TraceDirection::Received, from, &buf[..len]);
+        Ok((len, from))
+    }
+}
@@ -0,0 +1,92 @@
+use std::net::SocketAddr;
+
+use super::client::Session;
+use crate::error::Result;
+use crate::packet::{OwnedPacket, Packet};
+
+/// Low-level connection to a server that exchanges single packets.
+///
+/// Unlike [`TftpClient::read`] and [`TftpClient::write`], nothing is
+/// negotiated or acknowledged automatically, so the caller can inspect
+/// each reply and decide what to send next. This is meant for protocol
+/// testing tools.
+///
+/// # Example
+///
+/// ```ignore
+/// let mut conn = client.connect()?;
+///
+/// match conn.send_request(&Packet::Rrq(req)).await? {
+///     OwnedPacket::OAck(opts) if opts.block_size == Some(1428) => {
+///         conn.send_packet(&Packet::Ack(0)).await?;
+///     }
+///     _ => conn.send_packet(&Packet::Error(error)).await?,
+/// }
+/// ```
+///
+/// [`TftpClient::read`]: super::TftpClient::read
+/// [`TftpClient::write`]: super::TftpClient::write
+pub struct Connection {
+    session: Session,
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    pub(crate) fn new(session: Session) -> Self {
+        Connection {
+            session,
+            buf: vec![0u8; 65536],
+        }
+    }
+
+    /// Send `req` to the server and wait for the first reply.
+    ///
+    /// The request is sent again on timeout. Replies are accepted from any
+    /// port of the server, which becomes the peer of the connection.
+    pub async fn send_request(
+        &mut self,
+        req: &Packet<'_>,
+    ) -> Result<OwnedPacket> {
+        let req = req.to_vec();
+        let (len, peer) = self.session.request(&req, &mut self.buf).await?;
+
+        self.session.peer = peer;
+        self.session.last_sent = req;
+
+        OwnedPacket::decode(&self.buf[..len])
+    }
+
+    /// Send `packet` to the peer.
+    ///
+    /// It is sent again when [`recv_packet`](Self::recv_packet) times out.
+    pub async fn send_packet(&mut self, packet: &Packet<'_>) -> Result<()> {
+        self.session.send(packet).await
+    }
+
+    /// Receive the next packet of the peer.
+    ///
+    /// On timeout the last sent packet is sent again, up to the maximum
+    /// retries of the client. Packets from other ports are answered with
+    /// [`UnknownTransferId`] error and skipped, except ERROR packets which
+    /// are skipped silently. ERROR packets of the peer are returned like any
+    /// other packet.
+    ///
+    /// [`UnknownTransferId`]: crate::packet::Error::UnknownTransferId
+    pub async fn recv_packet(&mut self) -> Result<OwnedPacket> {
+        let len = self.session.recv(&mut self.buf).await?;
+        OwnedPacket::decode(&self.buf[..len])
+    }
+
+    /// Address of the server that the packets are exchanged with.
+    ///
+    /// This is the transfer socket of the server after
+    /// [`send_request`](Self::send_request) returns.
+    pub fn peer(&self) -> SocketAddr {
+        self.session.peer
+    }
+
+    /// Local address of the connection.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.session.socket.local_addr()?)
+    }
+}
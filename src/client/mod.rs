@@ -0,0 +1,13 @@
+//! Client side implementation.
+
+#[allow(clippy::module_inception)]
+mod client;
+mod connection;
+mod read;
+mod trace;
+mod write;
+
+pub use self::client::*;
+pub use self::connection::*;
+pub use self::read::*;
+pub use self::trace::{PacketTrace, TraceDirection};
@@ -0,0 +1,167 @@
+use futures_lite::AsyncRead;
+use std::cmp;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::client::Session;
+use crate::error::{Error, RemoteError, Result};
+use crate::packet::{self, Packet};
+
+type Fetch = Pin<Box<dyn Future<Output = (Session, Result<Vec<u8>>)> + Send>>;
+
+/// Content of a file that is downloaded by [`TftpClient::read`].
+///
+/// Blocks are received and acknowledged while it is read. Errors of the
+/// transfer are reported as [`io::Error`] that wraps the [`Error`] of this
+/// crate.
+///
+/// [`TftpClient::read`]: super::TftpClient::read
+pub struct ReadStream {
+    // Taken while the next block is received
+    session: Option<Session>,
+    fetch: Option<Fetch>,
+    block: Vec<u8>,
+    pos: usize,
+    done: bool,
+    transfer_size: Option<u64>,
+}
+
+impl ReadStream {
+    pub(crate) fn new(
+        session: Session,
+        block: Vec<u8>,
+        transfer_size: Option<u64>,
+    ) -> Self {
+        ReadStream {
+            session: Some(session),
+            fetch: None,
+            block,
+            pos: 0,
+            done: false,
+            transfer_size,
+        }
+    }
+
+    /// Size of the file, if the server reported it.
+    pub fn transfer_size(&self) -> Option<u64> {
+        self.transfer_size
+    }
+
+    /// Convert an error of [`AsyncRead`] back to the [`Error`] of the
+    /// transfer.
+    pub fn into_error(e: io::Error) -> Error {
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = e.into_inner().expect("error has no inner error");
+            *inner.downcast::<Error>().expect("inner error is not Error")
+        } else {
+            Error::Io(e)
+        }
+    }
+
+    /// Mark the transfer as done if the current block is the last one.
+    pub(crate) fn check_last_block(&mut self) {
+        if let Some(ref session) = self.session {
+            self.done = self.block.len() < session.block_size;
+        }
+    }
+}
+
+impl AsyncRead for ReadStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            if self.pos < self.block.len() {
+                let len = cmp::min(buf.len(), self.block.len() - self.pos);
+                buf[..len].copy_from_slice(&self.block[self.pos..][..len]);
+                self.pos += len;
+                return Poll::Ready(Ok(len));
+            }
+
+            if self.done {
+                return Poll::Ready(Ok(0));
+            }
+
+            if self.fetch.is_none() {
+                let session = match self.session.take() {
+                    Some(session) => session,
+                    None => {
+                        let e = io::Error::other("transfer failed");
+                        return Poll::Ready(Err(e));
+                    }
+                };
+
+                self.fetch = Some(Box::pin(next_block(session)));
+            }
+
+            let fetch = self.fetch.as_mut().expect("no pending fetch");
+            let (session, res) = match fetch.as_mut().poll(cx) {
+                Poll::Ready(x) => x,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.fetch = None;
+
+            match res {
+                Ok(block) => {
+                    self.done = block.len() < session.block_size;
+                    self.block = block;
+                    self.pos = 0;
+                    self.session = Some(session);
+                }
+                Err(e) => {
+                    return Poll::Ready(Err(io::Error::other(e)));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ReadStream {
+    fn drop(&mut self) {
+        // Tell the server to stop instead of letting it retransmit
+        if let (Some(session), false) = (&self.session, self.done) {
+            let error = Packet::Error(packet::Error::Msg(
+                "Transfer cancelled".to_string(),
+            ));
+            session.send_now(&error.to_vec(), session.peer);
+        }
+    }
+}
+
+/// Receive and acknowledge the next block.
+async fn next_block(mut session: Session) -> (Session, Result<Vec<u8>>) {
+    let res = recv_block(&mut session).await;
+    (session, res)
+}
+
+async fn recv_block(session: &mut Session) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; session.block_size + 4];
+    let next_id = session.block_id.wrapping_add(1);
+
+    loop {
+        let len = session.recv(&mut buf).await?;
+
+        match Packet::decode(&buf[..len]) {
+            Ok(Packet::Data(id, payload)) if id == next_id => {
+                let payload = payload.to_vec();
+
+                session.block_id = id;
+                session.send(&Packet::Ack(id)).await?;
+
+                return Ok(payload);
+            }
+            // Our ACK was lost
+            Ok(Packet::Data(id, _)) if id == session.block_id => {
+                session.resend().await?;
+            }
+            Ok(Packet::Error(_)) => {
+                return Err(Error::Remote(RemoteError::decode(&buf[..len])?))
+            }
+            _ => {}
+        }
+    }
+}
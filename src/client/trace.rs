@@ -0,0 +1,65 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::packet::Packet;
+
+/// Whether a traced packet was sent or received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceDirection {
+    /// Client sent the packet to the server.
+    Sent,
+    /// Client received the packet from the server.
+    Received,
+}
+
+/// Packet that the client sent or received.
+///
+/// See [`TftpClient::trace_packets`].
+///
+/// [`TftpClient::trace_packets`]: super::TftpClient::trace_packets
+#[derive(Debug, Clone)]
+pub struct PacketTrace<'a> {
+    /// Whether the packet was sent or received.
+    pub direction: TraceDirection,
+    /// Address of the server that the packet was sent to or received from.
+    pub peer: SocketAddr,
+    /// Time just before the packet was sent, or just after it was received.
+    pub time: SystemTime,
+    /// Datagram as it was sent or received.
+    pub data: &'a [u8],
+    /// Decoded datagram, or `None` if it is not a valid packet.
+    pub packet: Option<Packet<'a>>,
+}
+
+/// Callback of [`TftpClient::trace_packets`].
+///
+/// [`TftpClient::trace_packets`]: super::TftpClient::trace_packets
+#[derive(Clone)]
+pub(crate) struct PacketTracer(
+    pub(crate) Arc<dyn Fn(&PacketTrace<'_>) + Send + Sync>,
+);
+
+impl PacketTracer {
+    pub(crate) fn trace(
+        &self,
+        direction: TraceDirection,
+        peer: SocketAddr,
+        data: &[u8],
+    ) {
+        (self.0)(&PacketTrace {
+            direction,
+            peer,
+            time: SystemTime::now(),
+            data,
+            packet: Packet::decode(data).ok(),
+        });
+    }
+}
+
+impl fmt::Debug for PacketTracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PacketTracer")
+    }
+}
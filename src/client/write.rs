@@ -0,0 +1,84 @@
+use futures_lite::{AsyncRead, AsyncReadExt};
+use log::trace;
+
+use super::client::Session;
+use crate::error::{Error, RemoteError, Result};
+use crate::packet::Packet;
+
+/// Send the content of `reader`, after the server acknowledged the request.
+pub(crate) async fn send_file<R>(
+    session: &mut Session,
+    mut reader: R,
+) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut block = vec![0u8; session.block_size];
+    let mut buf = vec![0u8; 1024];
+    let mut sent = 0;
+
+    loop {
+        let len = read_block(&mut reader, &mut block).await?;
+        let block_id = session.block_id.wrapping_add(1);
+
+        session.send(&Packet::Data(block_id, &block[..len])).await?;
+        session.block_id = block_id;
+
+        // Stale ACKs are dropped, but they count like timeouts so a peer
+        // that keeps acknowledging old blocks can not stall the upload
+        let mut stale = 0;
+
+        loop {
+            let reply_len = session.recv(&mut buf).await?;
+
+            match Packet::decode(&buf[..reply_len]) {
+                Ok(Packet::Ack(id)) if id == block_id => break,
+                Ok(Packet::Ack(id)) => {
+                    stale += 1;
+
+                    if stale > session.max_retries {
+                        return Err(Error::MaxSendRetriesReached(
+                            session.peer,
+                            block_id,
+                        ));
+                    }
+
+                    trace!(
+                        "WRQ (peer: {}, block_id: {}) - Dropped stale ACK {}",
+                        session.peer,
+                        block_id,
+                        id
+                    );
+                }
+                Ok(Packet::Error(_)) => {
+                    let e = RemoteError::decode(&buf[..reply_len])?;
+                    return Err(Error::Remote(e));
+                }
+                _ => {}
+            }
+        }
+
+        sent += len as u64;
+
+        if len < session.block_size {
+            return Ok(sent);
+        }
+    }
+}
+
+/// Fill `block`, unless the reader ends earlier.
+async fn read_block<R>(reader: &mut R, block: &mut [u8]) -> Result<usize>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut len = 0;
+
+    while len < block.len() {
+        match reader.read(&mut block[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+
+    Ok(len)
+}
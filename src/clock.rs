@@ -0,0 +1,27 @@
+use async_io::Timer;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+pub(crate) type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Source of time for retransmission and deadline logic.
+///
+/// Production code uses [`SystemClock`]. Tests can provide an implementation
+/// that is advanced manually, so timeouts can be checked without real sleeps.
+pub(crate) trait Clock: Send + Sync {
+    /// Returns a future that resolves after `dur` elapsed.
+    fn sleep(&self, dur: Duration) -> Sleep;
+}
+
+/// Clock that is backed by `async_io::Timer`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn sleep(&self, dur: Duration) -> Sleep {
+        Box::pin(async move {
+            Timer::after(dur).await;
+        })
+    }
+}
@@ -0,0 +1,126 @@
+//! Packet codec for custom transports.
+//!
+//! [`TftpCodec`] has the `encode`/`decode` shape of the `Encoder` and
+//! `Decoder` traits of framed transports, so a transport such as TFTP over
+//! DTLS or over a tunnel can reuse the wire layer of
+//! [`packet`](crate::packet). With the `tokio-codec` feature it implements
+//! the traits of `tokio_util::codec`, and with the `asynchronous-codec`
+//! feature the ones of `asynchronous_codec`, over [`OwnedPacket`].
+//!
+//! TFTP has no framing of its own: a packet is a whole datagram. The codec
+//! expects the buffer to hold exactly one datagram, as datagram framed
+//! transports pass it, and consumes all of it on decode.
+
+use bytes::BytesMut;
+
+use crate::error::Result;
+use crate::packet::{OwnedPacket, Packet};
+
+/// Encoder and decoder of TFTP packets, one per datagram.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TftpCodec {
+    _priv: (),
+}
+
+impl TftpCodec {
+    /// Create a new codec.
+    pub fn new() -> Self {
+        TftpCodec::default()
+    }
+
+    /// Decode the datagram in `src`.
+    ///
+    /// Returns `None` if `src` is empty. Otherwise `src` is consumed, even
+    /// if it is not a valid packet, so the next datagram can be decoded.
+    pub fn decode(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<OwnedPacket>> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+
+        let datagram = src.split();
+        OwnedPacket::decode(&datagram).map(Some)
+    }
+
+    /// Same as [`decode`](Self::decode), for the end of the stream.
+    pub fn decode_eof(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<OwnedPacket>> {
+        self.decode(src)
+    }
+
+    /// Encode `item` as a datagram at the end of `dst`.
+    pub fn encode(
+        &mut self,
+        item: &Packet<'_>,
+        dst: &mut BytesMut,
+    ) -> Result<()> {
+        item.encode(dst);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio-codec")]
+impl tokio_util::codec::Decoder for TftpCodec {
+    type Item = OwnedPacket;
+    type Error = crate::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<OwnedPacket>> {
+        TftpCodec::decode(self, src)
+    }
+
+    fn decode_eof(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<OwnedPacket>> {
+        TftpCodec::decode_eof(self, src)
+    }
+}
+
+#[cfg(feature = "tokio-codec")]
+impl tokio_util::codec::Encoder<OwnedPacket> for TftpCodec {
+    type Error = crate::Error;
+
+    fn encode(&mut self, item: OwnedPacket, dst: &mut BytesMut) -> Result<()> {
+        TftpCodec::encode(self, &item.as_packet(), dst)
+    }
+}
+
+#[cfg(feature = "tokio-codec")]
+impl tokio_util::codec::Encoder<Packet<'_>> for TftpCodec {
+    type Error = crate::Error;
+
+    fn encode(&mut self, item: Packet<'_>, dst: &mut BytesMut) -> Result<()> {
+        TftpCodec::encode(self, &item, dst)
+    }
+}
+
+#[cfg(feature = "asynchronous-codec")]
+impl asynchronous_codec::Decoder for TftpCodec {
+    type Item = OwnedPacket;
+    type Error = crate::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<OwnedPacket>> {
+        TftpCodec::decode(self, src)
+    }
+
+    fn decode_eof(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<OwnedPacket>> {
+        TftpCodec::decode_eof(self, src)
+    }
+}
+
+#[cfg(feature = "asynchronous-codec")]
+impl asynchronous_codec::Encoder for TftpCodec {
+    type Item<'a> = OwnedPacket;
+    type Error = crate::Error;
+
+    fn encode(&mut self, item: OwnedPacket, dst: &mut BytesMut) -> Result<()> {
+        TftpCodec::encode(self, &item.as_packet(), dst)
+    }
+}
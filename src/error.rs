@@ -1,3 +1,5 @@
+use std::io;
+use std::net::SocketAddr;
 use thiserror::Error;
 
 /// Type alias to [`Result<T, Error>`](std::result::Result).
@@ -5,6 +7,7 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// Error type of this crate.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Invalid packet")]
     InvalidPacket,
@@ -12,17 +15,72 @@ pub enum Error {
     #[error("TFTP protocol error: {0:?}")]
     Packet(crate::packet::Error),
 
+    #[error("{0}")]
+    Remote(RemoteError),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
     #[error("Failed to bind socket: {0}")]
     Bind(#[source] std::io::Error),
 
+    #[error(transparent)]
+    BindListen(BindError),
+
     #[error("Path '{}' is not a directory", .0.display())]
     NotDir(std::path::PathBuf),
 
+    #[error("Failed to initialize logger: {0}")]
+    Logger(String),
+
+    #[error("Failed to watch directory: {0}")]
+    Watch(String),
+
+    #[error("Invalid MAC address")]
+    InvalidMacAddr,
+
+    #[error("Invalid saved transfer state")]
+    InvalidTransferState,
+
+    #[error("Invalid configuration: {}", .0.join("; "))]
+    InvalidConfig(Vec<String>),
+
+    #[error("Transfer cancelled")]
+    Cancelled,
+
     #[error("Max send retries reached (peer: {0},  block id: {1})")]
     MaxSendRetriesReached(std::net::SocketAddr, u16),
+
+    /// Transfer mode of the request is not supported. It is sent as
+    /// `IllegalOperation` with a message that explains the reason.
+    #[error("Transfer mode is not supported")]
+    UnsupportedMode,
+
+    /// Datagram of the client is bigger than the negotiated block size. It
+    /// is sent as `IllegalOperation` with a message that explains the
+    /// reason.
+    #[error("Datagram exceeds block size")]
+    OversizedDatagram,
+
+    /// Duplicate of a written block differs from the written data. It is
+    /// sent as `IllegalOperation` with a message that explains the reason.
+    #[error("Duplicate block differs from written data")]
+    DuplicateMismatch,
+}
+
+impl Error {
+    /// Message of the ERROR packet of reasons that have no code of their
+    /// own.
+    pub(crate) fn wire_msg(&self) -> Option<&'static str> {
+        match self {
+            Error::UnsupportedMode => Some("Transfer mode is not supported"),
+            Error::OversizedDatagram => Some("Datagram exceeds block size"),
+            Error::DuplicateMismatch => {
+                Some("Duplicate block differs from written data")
+            }
+            _ => None,
+        }
+    }
 }
 
 impl From<nom::Err<nom::error::Error<&[u8]>>> for Error {
@@ -30,3 +88,96 @@ impl From<nom::Err<nom::error::Error<&[u8]>>> for Error {
         Error::InvalidPacket
     }
 }
+
+/// ERROR packet that the remote peer sent.
+///
+/// Unlike [`packet::Error`], the message of the peer is kept for every
+/// code.
+///
+/// [`packet::Error`]: crate::packet::Error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteError {
+    /// Error code on the wire.
+    pub code: u16,
+    /// Message of the peer. It can be empty.
+    pub message: String,
+}
+
+impl RemoteError {
+    /// Decode the ERROR packet in `data`.
+    pub(crate) fn decode(data: &[u8]) -> Result<RemoteError> {
+        if data.len() < 4 || data[..2] != [0, 5] {
+            return Err(Error::InvalidPacket);
+        }
+
+        let code = u16::from_be_bytes([data[2], data[3]]);
+        let message = data[4..].split(|&c| c == 0).next().unwrap_or_default();
+
+        Ok(RemoteError {
+            code,
+            message: String::from_utf8_lossy(message).into_owned(),
+        })
+    }
+
+    /// Error that the code stands for, e.g. [`FileNotFound`] to try the next
+    /// file and [`PermissionDenied`] to give up.
+    ///
+    /// [`FileNotFound`]: crate::packet::Error::FileNotFound
+    /// [`PermissionDenied`]: crate::packet::Error::PermissionDenied
+    pub fn kind(&self) -> crate::packet::Error {
+        let message = Some(self.message.as_str()).filter(|m| !m.is_empty());
+        crate::packet::Error::from_code(self.code, message)
+    }
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Remote error {}: {}", self.code, self.message)
+    }
+}
+
+/// Failure to bind the listening socket of the server.
+#[derive(Debug)]
+pub struct BindError {
+    /// Address that could not be bound.
+    pub addr: SocketAddr,
+    /// Error of the OS.
+    pub source: io::Error,
+}
+
+impl BindError {
+    /// How to fix the common causes of the error.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self.source.kind() {
+            io::ErrorKind::PermissionDenied if self.addr.port() < 1024 => Some(
+                "ports below 1024 need root, or the CAP_NET_BIND_SERVICE \
+                 capability on Linux",
+            ),
+            io::ErrorKind::AddrInUse => Some(
+                "another process listens on the port, e.g. a TFTP server of \
+                 the system",
+            ),
+            io::ErrorKind::AddrNotAvailable => {
+                Some("the IP address is not assigned to this host")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for BindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to bind {}: {}", self.addr, self.source)?;
+
+        match self.hint() {
+            Some(hint) => write!(f, " ({})", hint),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for BindError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
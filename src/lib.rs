@@ -1,5 +1,5 @@
 //! Executor agnostic async TFTP implementation, written with [smol]
-//! building blocks. Currently it implements only server side.
+//! building blocks. It implements the server side and a basic client.
 //!
 //! The following RFCs are implemented:
 //!
@@ -7,6 +7,7 @@
 //! * [RFC 2347] - TFTP Option Extension.
 //! * [RFC 2348] - TFTP Blocksize Option.
 //! * [RFC 2349] - TFTP Timeout Interval and Transfer Size Options.
+//! * [RFC 7440] - TFTP Windowsize Option, for read requests.
 //!
 //! Features:
 //!
@@ -20,6 +21,8 @@
 //!   client through a VPN.
 //! * You can implement your own [`Handler`] for more advance cases than
 //!   just serving a directory. Check [`tftpd-targz.rs`] for an example.
+//! * Relay requests to an upstream server, with optional caching, with
+//!   [`RelayHandler`].
 //!
 //! # Example
 //!
@@ -47,21 +50,39 @@
 //! [smol]: https://docs.rs/smol
 //!
 //! [`timeout`]: server::TftpServerBuilder::timeout
-//! [block size limit]: server::TftpServerBuilder::block_size_limit
+//! [block size limit]: server::TftpServerBuilder::max_block_size
 //! [`Handler`]: server::Handler
+//! [`RelayHandler`]: server::handlers::RelayHandler
 //! [`tftpd-targz.rs`]: https://github.com/oblique/async-tftp-rs/blob/master/examples/tftpd-targz.rs
 //!
 //! [RFC 1350]: https://tools.ietf.org/html/rfc1350
 //! [RFC 2347]: https://tools.ietf.org/html/rfc2347
 //! [RFC 2348]: https://tools.ietf.org/html/rfc2348
 //! [RFC 2349]: https://tools.ietf.org/html/rfc2349
+//! [RFC 7440]: https://tools.ietf.org/html/rfc7440
 
+pub mod client;
 pub mod server;
 
-/// Packet definitions that are needed in public API.
+#[cfg(feature = "bytes-api")]
+pub mod codec;
+pub mod netascii;
 pub mod packet;
 pub mod parse;
+pub mod transport;
 
+#[cfg(any(
+    all(feature = "syslog", unix),
+    all(feature = "journald", target_os = "linux")
+))]
+pub mod logging;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
+#[cfg(any(test, feature = "proptest", feature = "quickcheck"))]
+mod arbitrary;
+mod clock;
 mod error;
 mod tests;
 mod utils;
@@ -70,3 +91,9 @@ pub use crate::error::*;
 
 /// Re-export of `async_trait:async_trait`.
 pub use async_trait::async_trait;
+
+/// Re-export of `socket2`, which [`TftpServerBuilder::transfer_socket_hook`]
+/// is called with.
+///
+/// [`TftpServerBuilder::transfer_socket_hook`]: server::TftpServerBuilder::transfer_socket_hook
+pub use socket2;
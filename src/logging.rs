@@ -0,0 +1,49 @@
+//! Routing of logs to the system logger.
+//!
+//! This crate logs with the [`log`] crate. Standalone daemons that run
+//! under an init system can use one of these functions to send the logs
+//! to syslog or journald. Log levels are mapped to the respective
+//! priorities (e.g. `Warn` becomes `warning`).
+//!
+//! [`log`]: https://docs.rs/log
+
+use log::LevelFilter;
+
+use crate::error::{Error, Result};
+
+/// Send logs to the local syslog daemon, with `daemon` facility.
+///
+/// Available with the `syslog` feature.
+#[cfg(all(feature = "syslog", unix))]
+pub fn init_syslog(process: &str, level: LevelFilter) -> Result<()> {
+    let formatter = syslog::Formatter3164 {
+        facility: syslog::Facility::LOG_DAEMON,
+        hostname: None,
+        process: process.to_owned(),
+        pid: std::process::id(),
+    };
+
+    let logger =
+        syslog::unix(formatter).map_err(|e| Error::Logger(e.to_string()))?;
+
+    log::set_boxed_logger(Box::new(syslog::BasicLogger::new(logger)))
+        .map_err(|e| Error::Logger(e.to_string()))?;
+    log::set_max_level(level);
+
+    Ok(())
+}
+
+/// Send logs to journald.
+///
+/// Available with the `journald` feature.
+#[cfg(all(feature = "journald", target_os = "linux"))]
+pub fn init_journald(identifier: &str, level: LevelFilter) -> Result<()> {
+    systemd_journal_logger::JournalLog::new()
+        .map_err(|e| Error::Logger(e.to_string()))?
+        .with_syslog_identifier(identifier.to_owned())
+        .install()
+        .map_err(|e| Error::Logger(e.to_string()))?;
+    log::set_max_level(level);
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+//! Transcoding of `netascii` transfers (RFC 764).
+//!
+//! On the wire a line ends with CR LF, and a bare CR is sent as CR NUL.
+
+/// Append `data` to `out` in netascii.
+pub fn encode(data: &[u8], out: &mut Vec<u8>) {
+    out.reserve(data.len());
+
+    for &b in data {
+        match b {
+            b'\n' => out.extend_from_slice(b"\r\n"),
+            b'\r' => out.extend_from_slice(b"\r\0"),
+            b => out.push(b),
+        }
+    }
+}
+
+/// Decoder of netascii data that arrives in chunks, e.g. DATA blocks.
+///
+/// A CR at the end of a chunk is held back until the next chunk tells
+/// what it stands for. A CR that is followed by anything other than LF or
+/// NUL is kept as it is.
+#[derive(Debug, Clone, Default)]
+pub struct Decoder {
+    pending_cr: bool,
+}
+
+impl Decoder {
+    /// Create a decoder.
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    /// Append the decoded bytes of `chunk` to `out`.
+    pub fn decode(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        out.reserve(chunk.len());
+
+        for &b in chunk {
+            if self.pending_cr {
+                self.pending_cr = false;
+
+                match b {
+                    b'\n' => {
+                        out.push(b'\n');
+                        continue;
+                    }
+                    b'\0' => {
+                        out.push(b'\r');
+                        continue;
+                    }
+                    _ => out.push(b'\r'),
+                }
+            }
+
+            match b {
+                b'\r' => self.pending_cr = true,
+                b => out.push(b),
+            }
+        }
+    }
+
+    /// Append a CR that was held back at the end of the data to `out`.
+    pub fn finish(self, out: &mut Vec<u8>) {
+        if self.pending_cr {
+            out.push(b'\r');
+        }
+    }
+}
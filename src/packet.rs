@@ -1,86 +1,164 @@
 #![allow(clippy::octal_escapes)]
+#![warn(missing_docs)]
 
 //! Packet definitions.
+//!
+//! This is the wire format of TFTP (RFC1350) and of its options (RFC2347,
+//! RFC2348, RFC2349 and RFC7440). It does not depend on the server, so
+//! other tools, e.g. proxies and analyzers, can use it on its own.
+//!
+//! [`Packet`] borrows the payload of DATA packets from the decoded buffer,
+//! and [`OwnedPacket`] owns it.
 
-use bytes::{BufMut, Bytes, BytesMut};
-use std::convert::From;
+use bytes::BufMut;
+#[cfg(feature = "bytes-api")]
+use bytes::{Bytes, BytesMut};
+use std::borrow::Cow;
+use std::convert::{From, TryFrom};
+use std::fmt;
 use std::io;
+use std::path::Path;
 use std::str;
 
 use crate::error::Result;
 use crate::parse::*;
 
+/// Length of the opcode and the block number of a DATA packet.
 pub const PACKET_DATA_HEADER_LEN: usize = 4;
 
+/// Opcode of a packet.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum PacketType {
+    /// Read request.
     Rrq = 1,
+    /// Write request.
     Wrq = 2,
+    /// Block of data.
     Data = 3,
+    /// Acknowledgment of a block.
     Ack = 4,
+    /// Error that terminates the transfer.
     Error = 5,
+    /// Acknowledgment of options (RFC2347).
     OAck = 6,
 }
 
 /// TFTP protocol error. Should not be confused with `async_tftp::Error`.
-#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
+    /// Error code 0 with a message.
     #[error("client message: {0}")]
     Msg(String),
+    /// Error code 0 without a message.
     #[error("unknown error")]
     UnknownError,
+    /// Error code 1.
     #[error("file not found")]
     FileNotFound,
+    /// Error code 2.
     #[error("permission denied")]
     PermissionDenied,
+    /// Error code 3.
     #[error("disk full")]
     DiskFull,
+    /// Error code 4.
     #[error("illegal operation")]
     IllegalOperation,
+    /// Error code 5.
     #[error("unknown transfer ID")]
     UnknownTransferId,
+    /// Error code 6.
     #[error("file already exists")]
     FileAlreadyExists,
+    /// Error code 7.
     #[error("no such user")]
     NoSuchUser,
+    /// Error code 8 (RFC2347).
     #[error("options negotiation failed")]
     OptionsNegotiationFailed,
 }
 
-#[derive(Debug)]
+/// TFTP packet that borrows the payload of DATA packets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Packet<'a> {
+    /// Read request.
     Rrq(RwReq),
+    /// Write request.
     Wrq(RwReq),
+    /// Block number and payload.
     Data(u16, &'a [u8]),
+    /// Acknowledged block number.
     Ack(u16),
+    /// Error that terminates the transfer.
     Error(Error),
+    /// Acknowledged options.
     OAck(Opts),
 }
 
-#[derive(Debug, PartialEq)]
+/// TFTP packet that owns its data.
+///
+/// Use it to keep packets around, e.g. in queues of proxies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OwnedPacket {
+    /// Read request.
+    Rrq(RwReq),
+    /// Write request.
+    Wrq(RwReq),
+    /// Block number and payload.
+    Data(u16, Vec<u8>),
+    /// Acknowledged block number.
+    Ack(u16),
+    /// Error that terminates the transfer.
+    Error(Error),
+    /// Acknowledged options.
+    OAck(Opts),
+}
+
+/// Transfer mode of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Mode {
+    /// Text with CR LF line endings.
     Netascii,
+    /// Raw bytes.
     Octet,
+    /// Mail to a user, which is obsolete.
     Mail,
 }
 
-#[derive(Debug, PartialEq)]
+/// Read or write request.
+#[derive(Clone, PartialEq, Eq)]
 pub struct RwReq {
-    pub filename: String,
+    /// Filename as it was sent by the client.
+    ///
+    /// RFC1350 defines it as netascii, but clients in the wild send any
+    /// encoding, so it is kept as raw bytes.
+    pub filename: Vec<u8>,
+    /// Transfer mode.
     pub mode: Mode,
+    /// Requested options (RFC2347).
     pub opts: Opts,
 }
 
-#[derive(Debug, Clone, Default, PartialEq)]
+/// Options of a request or an OACK. Unknown options are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct Opts {
+    /// `blksize` option (RFC2348).
     pub block_size: Option<u16>,
+    /// `timeout` option in seconds (RFC2349).
     pub timeout: Option<u8>,
+    /// `tsize` option (RFC2349).
     pub transfer_size: Option<u64>,
+    /// `windowsize` option (RFC7440).
     pub window_size: Option<u64>,
 }
 
 impl PacketType {
+    /// Packet type of an opcode.
     pub fn from_u16(n: u16) -> Option<PacketType> {
         match n {
             1 => Some(PacketType::Rrq),
@@ -100,16 +178,75 @@ impl From<PacketType> for u16 {
     }
 }
 
+impl TryFrom<u16> for PacketType {
+    type Error = crate::Error;
+
+    fn try_from(value: u16) -> Result<Self> {
+        PacketType::from_u16(value).ok_or(crate::Error::InvalidPacket)
+    }
+}
+
 impl<'a> Packet<'a> {
-    pub fn decode(data: &[u8]) -> Result<Packet> {
+    /// Decode a packet. DATA packets borrow their payload from `data`.
+    pub fn decode(data: &[u8]) -> Result<Packet<'_>> {
         parse_packet(data)
     }
 
+    /// Encode packet at the end of `buf`.
+    pub fn encode_vec(&self, buf: &mut Vec<u8>) {
+        self.encode_to(buf);
+    }
+
+    /// Encode packet in a new buffer.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_to(&mut buf);
+        buf
+    }
+
+    /// Encode packet at the end of `buf`.
+    #[cfg(feature = "bytes-api")]
     pub fn encode(&self, buf: &mut BytesMut) {
+        self.encode_to(buf);
+    }
+
+    /// Encode the header of a DATA packet, so the payload can be appended
+    /// without copying it in a [`Packet::Data`].
+    #[cfg(feature = "bytes-api")]
+    pub fn encode_data_head(block_id: u16, buf: &mut BytesMut) {
+        Packet::encode_data_head_to(block_id, buf);
+    }
+
+    /// Encode packet in a new buffer.
+    #[cfg(feature = "bytes-api")]
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_to(&mut buf);
+        buf.freeze()
+    }
+
+    /// Packet that owns its data.
+    pub fn to_owned_packet(&self) -> OwnedPacket {
+        OwnedPacket::from(self.clone())
+    }
+
+    /// Type of the packet.
+    pub fn packet_type(&self) -> PacketType {
+        match self {
+            Packet::Rrq(_) => PacketType::Rrq,
+            Packet::Wrq(_) => PacketType::Wrq,
+            Packet::Data(..) => PacketType::Data,
+            Packet::Ack(_) => PacketType::Ack,
+            Packet::Error(_) => PacketType::Error,
+            Packet::OAck(_) => PacketType::OAck,
+        }
+    }
+
+    pub(crate) fn encode_to<B: BufMut>(&self, buf: &mut B) {
         match self {
             Packet::Rrq(req) => {
                 buf.put_u16(PacketType::Rrq.into());
-                buf.put_slice(req.filename.as_bytes());
+                buf.put_slice(&req.filename);
                 buf.put_u8(0);
                 buf.put_slice(req.mode.to_str().as_bytes());
                 buf.put_u8(0);
@@ -117,7 +254,7 @@ impl<'a> Packet<'a> {
             }
             Packet::Wrq(req) => {
                 buf.put_u16(PacketType::Wrq.into());
-                buf.put_slice(req.filename.as_bytes());
+                buf.put_slice(&req.filename);
                 buf.put_u8(0);
                 buf.put_slice(req.mode.to_str().as_bytes());
                 buf.put_u8(0);
@@ -132,12 +269,7 @@ impl<'a> Packet<'a> {
                 buf.put_u16(PacketType::Ack.into());
                 buf.put_u16(*block);
             }
-            Packet::Error(error) => {
-                buf.put_u16(PacketType::Error.into());
-                buf.put_u16(error.code());
-                buf.put_slice(error.msg().as_bytes());
-                buf.put_u8(0);
-            }
+            Packet::Error(error) => error.encode_with_msg(error.msg(), buf),
             Packet::OAck(opts) => {
                 buf.put_u16(PacketType::OAck.into());
                 opts.encode(buf);
@@ -145,47 +277,164 @@ impl<'a> Packet<'a> {
         }
     }
 
-    pub fn encode_data_head(block_id: u16, buf: &mut BytesMut) {
+    pub(crate) fn encode_data_head_to<B: BufMut>(block_id: u16, buf: &mut B) {
         buf.put_u16(PacketType::Data.into());
         buf.put_u16(block_id);
     }
+}
 
-    pub fn to_bytes(&self) -> Bytes {
-        let mut buf = BytesMut::new();
-        self.encode(&mut buf);
-        buf.freeze()
+impl<'a> TryFrom<&'a [u8]> for Packet<'a> {
+    type Error = crate::Error;
+
+    fn try_from(data: &'a [u8]) -> Result<Self> {
+        Packet::decode(data)
+    }
+}
+
+impl OwnedPacket {
+    /// Decode a packet.
+    pub fn decode(data: &[u8]) -> Result<OwnedPacket> {
+        Packet::decode(data).map(OwnedPacket::from)
+    }
+
+    /// Packet that borrows the payload of DATA packets from `self`.
+    pub fn as_packet(&self) -> Packet<'_> {
+        match self {
+            OwnedPacket::Rrq(req) => Packet::Rrq(req.clone()),
+            OwnedPacket::Wrq(req) => Packet::Wrq(req.clone()),
+            OwnedPacket::Data(block, data) => Packet::Data(*block, data),
+            OwnedPacket::Ack(block) => Packet::Ack(*block),
+            OwnedPacket::Error(error) => Packet::Error(error.clone()),
+            OwnedPacket::OAck(opts) => Packet::OAck(opts.clone()),
+        }
+    }
+
+    /// Encode packet at the end of `buf`.
+    pub fn encode_vec(&self, buf: &mut Vec<u8>) {
+        self.as_packet().encode_vec(buf);
+    }
+
+    /// Encode packet in a new buffer.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.as_packet().to_vec()
+    }
+
+    /// Type of the packet.
+    pub fn packet_type(&self) -> PacketType {
+        self.as_packet().packet_type()
+    }
+}
+
+impl From<Packet<'_>> for OwnedPacket {
+    fn from(packet: Packet<'_>) -> Self {
+        match packet {
+            Packet::Rrq(req) => OwnedPacket::Rrq(req),
+            Packet::Wrq(req) => OwnedPacket::Wrq(req),
+            Packet::Data(block, data) => OwnedPacket::Data(block, data.into()),
+            Packet::Ack(block) => OwnedPacket::Ack(block),
+            Packet::Error(error) => OwnedPacket::Error(error),
+            Packet::OAck(opts) => OwnedPacket::OAck(opts),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for OwnedPacket {
+    type Error = crate::Error;
+
+    fn try_from(data: &[u8]) -> Result<Self> {
+        OwnedPacket::decode(data)
+    }
+}
+
+impl RwReq {
+    /// Filename as UTF-8. Invalid sequences are replaced with
+    /// `U+FFFD REPLACEMENT CHARACTER`.
+    pub fn filename_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.filename)
+    }
+
+    /// Filename as a path.
+    ///
+    /// On Unix this is lossless, so non UTF-8 filenames can be resolved. On
+    /// other platforms invalid UTF-8 sequences are replaced.
+    pub fn filename_path(&self) -> Cow<'_, Path> {
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+
+            Cow::Borrowed(Path::new(OsStr::from_bytes(&self.filename)))
+        }
+
+        #[cfg(not(unix))]
+        {
+            match self.filename_lossy() {
+                Cow::Borrowed(s) => Cow::Borrowed(Path::new(s)),
+                Cow::Owned(s) => Cow::Owned(s.into()),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for RwReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RwReq")
+            .field("filename", &self.filename_lossy())
+            .field("mode", &self.mode)
+            .field("opts", &self.opts)
+            .finish()
     }
 }
 
 impl Opts {
-    fn encode(&self, buf: &mut BytesMut) {
+    fn encode<B: BufMut>(&self, buf: &mut B) {
         if let Some(block_size) = self.block_size {
             buf.put_slice(&b"blksize\0"[..]);
-            buf.put_slice(block_size.to_string().as_bytes());
+            put_decimal(buf, block_size.into());
             buf.put_u8(0);
         }
 
         if let Some(timeout) = self.timeout {
             buf.put_slice(&b"timeout\0"[..]);
-            buf.put_slice(timeout.to_string().as_bytes());
+            put_decimal(buf, timeout.into());
             buf.put_u8(0);
         }
 
         if let Some(window_size) = self.window_size {
             buf.put_slice(&b"windowsize\0"[..]);
-            buf.put_slice(window_size.to_string().as_bytes());
+            put_decimal(buf, window_size);
             buf.put_u8(0);
         }
 
         if let Some(transfer_size) = self.transfer_size {
             buf.put_slice(&b"tsize\0"[..]);
-            buf.put_slice(transfer_size.to_string().as_bytes());
+            put_decimal(buf, transfer_size);
             buf.put_u8(0);
         }
     }
 }
 
+/// Write `n` as decimal digits, without allocating.
+fn put_decimal<B: BufMut>(buf: &mut B, mut n: u64) {
+    // u64::MAX has 20 digits
+    let mut digits = [0u8; 20];
+    let mut start = digits.len();
+
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+
+        if n == 0 {
+            break;
+        }
+    }
+
+    buf.put_slice(&digits[start..]);
+}
+
 impl Mode {
+    /// Name of the mode on the wire.
     pub fn to_str(&self) -> &'static str {
         match self {
             Mode::Netascii => "netascii",
@@ -196,6 +445,8 @@ impl Mode {
 }
 
 impl Error {
+    /// Error of an error code. The message is used only for code 0 and
+    /// unknown codes.
     pub fn from_code(code: u16, msg: Option<&str>) -> Self {
         #[allow(clippy::wildcard_in_or_patterns)]
         match code {
@@ -214,6 +465,7 @@ impl Error {
         }
     }
 
+    /// Error code on the wire.
     pub fn code(&self) -> u16 {
         match self {
             Error::Msg(..) => 0,
@@ -229,6 +481,15 @@ impl Error {
         }
     }
 
+    /// Encode ERROR packet of the code of `self` with `msg`.
+    pub(crate) fn encode_with_msg<B: BufMut>(&self, msg: &str, buf: &mut B) {
+        buf.put_u16(PacketType::Error.into());
+        buf.put_u16(self.code());
+        buf.put_slice(msg.as_bytes());
+        buf.put_u8(0);
+    }
+
+    /// Message on the wire.
     pub fn msg(&self) -> &str {
         match self {
             Error::Msg(msg) => msg,
@@ -253,6 +514,13 @@ impl From<Error> for Packet<'_> {
 
 impl From<io::Error> for Error {
     fn from(io_err: io::Error) -> Self {
+        // Errors of the protocol that handlers pass through I/O
+        if let Some(e) =
+            io_err.get_ref().and_then(|e| e.downcast_ref::<Error>())
+        {
+            return e.clone();
+        }
+
         match io_err.kind() {
             io::ErrorKind::NotFound => Error::FileNotFound,
             io::ErrorKind::PermissionDenied => Error::PermissionDenied,
@@ -270,11 +538,18 @@ impl From<crate::Error> for Error {
     fn from(err: crate::Error) -> Self {
         match err {
             crate::Error::Packet(e) => e,
+            crate::Error::Remote(e) => e.kind(),
             crate::Error::Io(e) => e.into(),
-            crate::Error::InvalidPacket => Error::IllegalOperation,
+            crate::Error::InvalidPacket
+            | crate::Error::UnsupportedMode
+            | crate::Error::OversizedDatagram
+            | crate::Error::DuplicateMismatch => Error::IllegalOperation,
             crate::Error::MaxSendRetriesReached(..) => {
                 Error::Msg("Max retries reached".to_string())
             }
+            crate::Error::Cancelled => {
+                Error::Msg("Transfer cancelled".to_string())
+            }
             _ => Error::UnknownError,
         }
     }
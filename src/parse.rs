@@ -16,10 +16,12 @@ enum Opt<'a> {
     Timeout(u8),
     WindowSize(u64),
     Tsize(u64),
+    // Key and value, kept for the `Debug` output
+    #[allow(dead_code)]
     Invalid(&'a str, &'a str),
 }
 
-pub fn parse_packet(input: &[u8]) -> Result<Packet> {
+pub fn parse_packet(input: &[u8]) -> Result<Packet<'_>> {
     let (rest, packet) = match parse_packet_type(input)? {
         (data, PacketType::Rrq) => parse_rrq(data)?,
         (data, PacketType::Wrq) => parse_wrq(data)?,
@@ -43,6 +45,10 @@ fn nul_str(input: &[u8]) -> IResult<&[u8], &str> {
     )(input)
 }
 
+fn nul_bytes(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    map(tuple((take_till(|c| c == b'\0'), tag(b"\0"))), |(s, _)| s)(input)
+}
+
 fn parse_packet_type(input: &[u8]) -> IResult<&[u8], PacketType> {
     map_opt(be_u16, PacketType::from_u16)(input)
 }
@@ -55,7 +61,7 @@ fn parse_mode(input: &[u8]) -> IResult<&[u8], Mode> {
     ))(input)
 }
 
-fn parse_opt_blksize(input: &[u8]) -> IResult<&[u8], Opt> {
+fn parse_opt_blksize(input: &[u8]) -> IResult<&[u8], Opt<'_>> {
     map_opt(tuple((tag_no_case(b"blksize\0"), nul_str)), |(_, n): (_, &str)| {
         u16::from_str(n)
             .ok()
@@ -64,13 +70,13 @@ fn parse_opt_blksize(input: &[u8]) -> IResult<&[u8], Opt> {
     })(input)
 }
 
-fn parse_opt_timeout(input: &[u8]) -> IResult<&[u8], Opt> {
+fn parse_opt_timeout(input: &[u8]) -> IResult<&[u8], Opt<'_>> {
     map_opt(tuple((tag_no_case(b"timeout\0"), nul_str)), |(_, n): (_, &str)| {
         u8::from_str(n).ok().filter(|n| *n >= 1).map(Opt::Timeout)
     })(input)
 }
 
-fn parse_opt_windowsize(input: &[u8]) -> IResult<&[u8], Opt> {
+fn parse_opt_windowsize(input: &[u8]) -> IResult<&[u8], Opt<'_>> {
     map_opt(
         tuple((tag_no_case(b"windowsize\0"), nul_str)),
         |(_, n): (_, &str)| {
@@ -79,7 +85,7 @@ fn parse_opt_windowsize(input: &[u8]) -> IResult<&[u8], Opt> {
     )(input)
 }
 
-fn parse_opt_tsize(input: &[u8]) -> IResult<&[u8], Opt> {
+fn parse_opt_tsize(input: &[u8]) -> IResult<&[u8], Opt<'_>> {
     map_opt(tuple((tag_no_case(b"tsize\0"), nul_str)), |(_, n): (_, &str)| {
         u64::from_str(n).ok().map(Opt::Tsize)
     })(input)
@@ -128,49 +134,49 @@ fn to_opts(opt_vec: Vec<Opt>) -> Opts {
     opts
 }
 
-fn parse_rrq(input: &[u8]) -> IResult<&[u8], Packet> {
+fn parse_rrq(input: &[u8]) -> IResult<&[u8], Packet<'_>> {
     let (input, (filename, mode, opts)) =
-        tuple((nul_str, parse_mode, parse_opts))(input)?;
+        tuple((nul_bytes, parse_mode, parse_opts))(input)?;
 
     Ok((
         input,
         Packet::Rrq(RwReq {
-            filename: filename.to_owned(),
+            filename: filename.to_vec(),
             mode,
             opts,
         }),
     ))
 }
 
-fn parse_wrq(input: &[u8]) -> IResult<&[u8], Packet> {
+fn parse_wrq(input: &[u8]) -> IResult<&[u8], Packet<'_>> {
     let (input, (filename, mode, opts)) =
-        tuple((nul_str, parse_mode, parse_opts))(input)?;
+        tuple((nul_bytes, parse_mode, parse_opts))(input)?;
 
     Ok((
         input,
         Packet::Wrq(RwReq {
-            filename: filename.to_owned(),
+            filename: filename.to_vec(),
             mode,
             opts,
         }),
     ))
 }
 
-fn parse_data(input: &[u8]) -> IResult<&[u8], Packet> {
+fn parse_data(input: &[u8]) -> IResult<&[u8], Packet<'_>> {
     tuple((be_u16, rest))(input)
         .map(|(i, (block_nr, data))| (i, Packet::Data(block_nr, data)))
 }
 
-fn parse_ack(input: &[u8]) -> IResult<&[u8], Packet> {
+fn parse_ack(input: &[u8]) -> IResult<&[u8], Packet<'_>> {
     be_u16(input).map(|(i, block_nr)| (i, Packet::Ack(block_nr)))
 }
 
-fn parse_error(input: &[u8]) -> IResult<&[u8], Packet> {
+fn parse_error(input: &[u8]) -> IResult<&[u8], Packet<'_>> {
     tuple((be_u16, nul_str))(input).map(|(i, (code, msg))| {
         (i, packet::Error::from_code(code, Some(msg)).into())
     })
 }
 
-fn parse_oack(input: &[u8]) -> IResult<&[u8], Packet> {
+fn parse_oack(input: &[u8]) -> IResult<&[u8], Packet<'_>> {
     parse_opts(input).map(|(i, opts)| (i, Packet::OAck(opts)))
 }
@@ -0,0 +1,465 @@
+use async_channel::{Receiver, Sender};
+use blocking::unblock;
+use log::{trace, warn};
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{Direction, NegotiatedOptions};
+use crate::error::Result;
+use crate::packet;
+
+/// Audit record of a request.
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    /// When the request was received.
+    pub time: SystemTime,
+    /// How long it took to serve the request.
+    pub duration: Duration,
+    pub client: SocketAddr,
+    /// Local address of the socket that served the transfer, if the request
+    /// was not rejected before.
+    ///
+    /// See [`TransferContext::local_addr`].
+    ///
+    /// [`TransferContext::local_addr`]: super::TransferContext::local_addr
+    pub local_addr: Option<SocketAddr>,
+    pub direction: Direction,
+    /// Options of the transfer, if the request was not rejected before.
+    ///
+    /// See [`TransferContext::options`].
+    ///
+    /// [`TransferContext::options`]: super::TransferContext::options
+    pub options: Option<NegotiatedOptions>,
+    /// Requested filename. Invalid UTF-8 sequences are replaced.
+    pub filename: String,
+    /// Bytes of file data that were transferred.
+    pub transferred: u64,
+    /// Error that was sent to the client, if request failed. If the client
+    /// terminated the transfer, this is the error of the client.
+    pub error: Option<packet::Error>,
+    /// Why the request finished.
+    pub outcome: TransferOutcome,
+    /// Whether the request restarted an abandoned read request of the same
+    /// client from another port.
+    ///
+    /// See [`TftpServerBuilder::restart_window`].
+    ///
+    /// [`TftpServerBuilder::restart_window`]: super::TftpServerBuilder::restart_window
+    pub restarted: bool,
+    /// Packets that the server retransmitted during the transfer.
+    pub retransmissions: Retransmissions,
+    /// Datagrams of the client that exceeded the negotiated block size.
+    ///
+    /// See [`TftpServerBuilder::oversized_datagrams`].
+    ///
+    /// [`TftpServerBuilder::oversized_datagrams`]: super::TftpServerBuilder::oversized_datagrams
+    pub oversized_datagrams: u64,
+    /// Delayed duplicates of already written blocks, that the server
+    /// acknowledged again without writing them.
+    ///
+    /// See [`TftpServerBuilder::duplicate_blocks`].
+    ///
+    /// [`TftpServerBuilder::duplicate_blocks`]: super::TftpServerBuilder::duplicate_blocks
+    pub duplicate_blocks: u64,
+}
+
+impl AuditRecord {
+    /// Record of a request that was refused before it was handled.
+    pub(crate) fn refused(
+        client: SocketAddr,
+        direction: Direction,
+        filename: String,
+        error: Option<packet::Error>,
+        outcome: TransferOutcome,
+    ) -> Self {
+        AuditRecord {
+            time: SystemTime::now(),
+            duration: Duration::ZERO,
+            client,
+            local_addr: None,
+            direction,
+            options: None,
+            filename,
+            transferred: 0,
+            error,
+            outcome,
+            restarted: false,
+            retransmissions: Retransmissions::default(),
+            oversized_datagrams: 0,
+            duplicate_blocks: 0,
+        }
+    }
+}
+
+/// Retransmissions of a transfer.
+///
+/// Read requests retransmit DATA packets and write requests retransmit ACK
+/// packets. Block 0 is the OACK, or the first ACK of a write request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Retransmissions {
+    /// Number of retransmitted packets.
+    pub total: u64,
+    /// Retransmitted block IDs, in order, with how many times each was
+    /// retransmitted.
+    ///
+    /// Only the first [`MAX_BLOCKS`](Self::MAX_BLOCKS) runs of the same
+    /// block are kept, so a transfer over a lossy link cannot grow the
+    /// record without bounds. Later retransmissions are counted only in
+    /// `total`.
+    pub blocks: Vec<(u16, u32)>,
+}
+
+impl Retransmissions {
+    /// Maximum length of [`blocks`](Self::blocks).
+    pub const MAX_BLOCKS: usize = 64;
+
+    pub(crate) fn add(&mut self, block_id: u16) {
+        self.total += 1;
+
+        let len = self.blocks.len();
+
+        match self.blocks.last_mut() {
+            Some((id, count)) if *id == block_id => *count += 1,
+            _ if len < Self::MAX_BLOCKS => self.blocks.push((block_id, 1)),
+            _ => {}
+        }
+    }
+}
+
+/// Queue of audit records of requests that the server refused without
+/// spawning a task for them.
+///
+/// A single task passes the records to the sink, so a flood of refused
+/// requests does not spawn a task each. Records that do not fit in the
+/// queue are dropped.
+pub(crate) struct RefusedRecords {
+    tx: Sender<AuditRecord>,
+    rx: Receiver<AuditRecord>,
+}
+
+impl RefusedRecords {
+    const CAPACITY: usize = 1024;
+
+    pub(crate) fn new() -> Self {
+        let (tx, rx) = async_channel::bounded(Self::CAPACITY);
+        RefusedRecords {
+            tx,
+            rx,
+        }
+    }
+
+    /// Queue `record`, or drop it if the queue is full.
+    pub(crate) fn push(&self, record: AuditRecord) {
+        if let Err(e) = self.tx.try_send(record) {
+            trace!("Audit record dropped (client: {})", e.into_inner().client);
+        }
+    }
+
+    /// Pass queued records to `sink` as they arrive.
+    pub(crate) async fn run(&self, sink: &dyn AuditSink) {
+        while let Ok(record) = self.rx.recv().await {
+            sink.record(&record).await;
+        }
+    }
+
+    /// Pass the records that are still queued to `sink`.
+    pub(crate) async fn flush(&self, sink: &dyn AuditSink) {
+        while let Ok(record) = self.rx.try_recv() {
+            sink.record(&record).await;
+        }
+    }
+}
+
+/// How a request finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TransferOutcome {
+    /// File was transferred.
+    Completed,
+    /// Request was refused before the transfer started, e.g. because the
+    /// file was not found or access was denied.
+    Rejected,
+    /// Request was refused, because its transfer mode is not supported.
+    UnsupportedMode,
+    /// Client stopped replying.
+    Timeout,
+    /// Client terminated the transfer with an error.
+    ClientError,
+    /// Handler failed to read or write the file.
+    HandlerIo,
+    /// Server cancelled the transfer, e.g. because the client restarted it
+    /// from another port.
+    Cancelled,
+    /// Client sent a datagram bigger than the negotiated block size.
+    OversizedDatagram,
+    /// Client sent a duplicate of a written block with different data.
+    ///
+    /// See [`DuplicateBlocks::RejectMismatch`].
+    ///
+    /// [`DuplicateBlocks::RejectMismatch`]: super::DuplicateBlocks::RejectMismatch
+    DuplicateMismatch,
+    /// Request was answered with "Server busy" error, because the server
+    /// handles too many requests.
+    Shed,
+    /// Handler panicked. The client gets an error and the server keeps
+    /// running.
+    Panicked,
+    /// Request exceeded the size, filename length or number of options
+    /// that the server accepts.
+    ///
+    /// See [`TftpServerBuilder::max_request_size`].
+    ///
+    /// [`TftpServerBuilder::max_request_size`]: super::TftpServerBuilder::max_request_size
+    OversizedRequest,
+    /// Request was ignored without a reply, because too many handshakes
+    /// were pending.
+    ///
+    /// See [`TftpServerBuilder::max_pending_handshakes`].
+    ///
+    /// [`TftpServerBuilder::max_pending_handshakes`]: super::TftpServerBuilder::max_pending_handshakes
+    Dropped,
+    /// Transfer failed for another reason, e.g. a socket error.
+    Other,
+}
+
+impl TransferOutcome {
+    /// Name of the outcome, as used in logs and metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferOutcome::Completed => "completed",
+            TransferOutcome::Rejected => "rejected",
+            TransferOutcome::UnsupportedMode => "unsupported_mode",
+            TransferOutcome::Timeout => "timeout",
+            TransferOutcome::ClientError => "client_error",
+            TransferOutcome::HandlerIo => "handler_io",
+            TransferOutcome::Cancelled => "cancelled",
+            TransferOutcome::OversizedDatagram => "oversized_datagram",
+            TransferOutcome::DuplicateMismatch => "duplicate_mismatch",
+            TransferOutcome::Shed => "shed",
+            TransferOutcome::Panicked => "panicked",
+            TransferOutcome::OversizedRequest => "oversized_request",
+            TransferOutcome::Dropped => "dropped",
+            TransferOutcome::Other => "other",
+        }
+    }
+}
+
+impl std::fmt::Display for TransferOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Trait for implementing destinations of audit records.
+#[crate::async_trait]
+pub trait AuditSink: Send + Sync {
+    /// Store `record`. This is called when a request is finished.
+    async fn record(&self, record: &AuditRecord);
+}
+
+/// Audit sink that appends records as JSON lines to a file.
+///
+/// Cloned values write to the same file.
+#[derive(Clone)]
+pub struct JsonLinesAudit {
+    inner: Arc<JsonLinesInner>,
+}
+
+struct JsonLinesInner {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditRecord {
+    /// Encode record as a single line of JSON, including the trailing
+    /// newline.
+    pub fn to_json_line(&self) -> String {
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let direction = match self.direction {
+            Direction::Read => "read",
+            Direction::Write => "write",
+        };
+
+        let mut line = String::new();
+
+        // Writing in a `String` never fails
+        let _ = write!(
+            line,
+            "{{\"time\":{}.{:03},\"duration_ms\":{},\"client\":\"{}\",\
+             \"direction\":\"{}\",\"filename\":\"{}\",\"transferred\":{}",
+            time.as_secs(),
+            time.subsec_millis(),
+            self.duration.as_millis(),
+            self.client,
+            direction,
+            json_escape(&self.filename),
+            self.transferred,
+        );
+
+        if let Some(local_addr) = self.local_addr {
+            let _ = write!(line, ",\"local_addr\":\"{}\"", local_addr);
+        }
+
+        if let Some(ref options) = self.options {
+            let _ = write!(
+                line,
+                ",\"block_size\":{},\"timeout_ms\":{},\"window_size\":{}",
+                options.block_size,
+                options.timeout.as_millis(),
+                options.window_size
+            );
+
+            if let Some(size) = options.transfer_size {
+                let _ = write!(line, ",\"transfer_size\":{}", size);
+            }
+        }
+
+        if self.restarted {
+            line.push_str(",\"restarted\":true");
+        }
+
+        if self.retransmissions.total > 0 {
+            let _ = write!(
+                line,
+                ",\"retransmissions\":{},\"retransmitted_blocks\":[",
+                self.retransmissions.total
+            );
+
+            for (i, (id, count)) in
+                self.retransmissions.blocks.iter().enumerate()
+            {
+                if i > 0 {
+                    line.push(',');
+                }
+
+                let _ = write!(line, "[{},{}]", id, count);
+            }
+
+            line.push(']');
+        }
+
+        if self.oversized_datagrams > 0 {
+            let _ = write!(
+                line,
+                ",\"oversized_datagrams\":{}",
+                self.oversized_datagrams
+            );
+        }
+
+        if self.duplicate_blocks > 0 {
+            let _ =
+                write!(line, ",\"duplicate_blocks\":{}", self.duplicate_blocks);
+        }
+
+        let _ = write!(line, ",\"outcome\":\"{}\"", self.outcome);
+
+        match self.error {
+            Some(ref e) => {
+                let _ = write!(
+                    line,
+                    ",\"result\":\"error\",\"error_code\":{},\"error\":\"{}\"}}",
+                    e.code(),
+                    json_escape(e.msg()),
+                );
+            }
+            None => line.push_str(",\"result\":\"ok\"}"),
+        }
+
+        line.push('\n');
+        line
+    }
+}
+
+impl JsonLinesAudit {
+    /// Open `path` for appending. The file is created if it does
+    /// not exist.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_owned();
+        let file = open_append(&path)?;
+
+        Ok(JsonLinesAudit {
+            inner: Arc::new(JsonLinesInner {
+                path,
+                file: Mutex::new(file),
+            }),
+        })
+    }
+
+    /// Reopen the file.
+    ///
+    /// Call this after the file was rotated by an external tool (e.g. on
+    /// `SIGHUP` from logrotate), so new records go to the new file.
+    pub fn reopen(&self) -> Result<()> {
+        let file = open_append(&self.inner.path)?;
+        *self.inner.file.lock().unwrap() = file;
+        Ok(())
+    }
+}
+
+#[crate::async_trait]
+impl AuditSink for JsonLinesAudit {
+    async fn record(&self, record: &AuditRecord) {
+        let line = record.to_json_line();
+        let inner = Arc::clone(&self.inner);
+
+        let res = unblock(move || {
+            let mut file = inner.file.lock().unwrap();
+            file.write_all(line.as_bytes())
+        })
+        .await;
+
+        if let Err(e) = res {
+            warn!(
+                "Failed to write audit record to {}: {}",
+                self.inner.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Send records to both sinks.
+#[crate::async_trait]
+impl<A, B> AuditSink for (A, B)
+where
+    A: AuditSink,
+    B: AuditSink,
+{
+    async fn record(&self, record: &AuditRecord) {
+        self.0.record(record).await;
+        self.1.record(record).await;
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,31 @@
+use std::net::SocketAddr;
+use std::path::Path;
+
+use crate::packet;
+
+/// Direction of a transfer, from the point of view of the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Client reads a file (RRQ).
+    Read,
+    /// Client writes a file (WRQ).
+    Write,
+}
+
+/// Trait for implementing access control.
+///
+/// Authorizer is consulted for every request before [`Handler`] opens
+/// the file. Return `Err(packet::Error::PermissionDenied)` to deny a request
+/// or any other error to reply with it.
+///
+/// [`Handler`]: super::Handler
+#[crate::async_trait]
+pub trait Authorizer: Send + Sync {
+    /// Check if `client` is allowed to transfer `path`.
+    async fn authorize(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+        direction: Direction,
+    ) -> Result<(), packet::Error>;
+}
@@ -0,0 +1,20 @@
+use bytes::Bytes;
+use std::io;
+
+/// Trait for implementing sources that produce the data of a read request
+/// in blocks.
+///
+/// This is an alternative to `AsyncRead` for sources that naturally
+/// produce fixed-size chunks, such as flash readers or chunked object
+/// stores. See [`Handler::read_req_open_blocks`].
+///
+/// [`Handler::read_req_open_blocks`]: super::Handler::read_req_open_blocks
+#[crate::async_trait]
+pub trait BlockSource: Send {
+    /// Return block `index` of the file.
+    ///
+    /// `index` counts from zero and does not wrap around like block
+    /// numbers of TFTP, so the block starts at offset `index * len`.
+    /// A block shorter than `len` marks the end of the file.
+    async fn block(&mut self, index: u64, len: usize) -> io::Result<Bytes>;
+}
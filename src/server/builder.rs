@@ -1,26 +1,162 @@
 use async_executor::Executor;
 use async_io::Async;
-use async_lock::Mutex;
-use std::collections::HashSet;
-use std::net::{SocketAddr, UdpSocket};
+use async_lock::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::ops::RangeInclusive;
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
+use super::counter::RequestCounter;
 use super::handlers::{DirHandler, DirHandlerMode};
-use super::{Handler, ServerConfig, TftpServer};
-use crate::error::{Error, Result};
+use super::limiter::{
+    HandshakeLimiter, MemoryBudget, RequestLimiter, TransferLimiter,
+};
+use super::mirror::{Mirror, MirrorMode};
+use super::restart::RestartDetector;
+use super::server::{busy_reply, HandlerLock};
+use super::shards::Shards;
+use super::{
+    AuditSink, Authorizer, BootSessionResolver, CancellationToken,
+    ErrorMessageFn, Handler, KeepState, RefusedRecords, ServerConfig,
+    SocketHookFn, TftpServer, TransferAddr, TransferParams, TransferSnapshot,
+};
+use crate::clock::{Clock, SystemClock};
+use crate::error::{BindError, Error, Result};
+use crate::packet;
+use crate::transport::Transport;
+use crate::utils::{udp_socket, SocketOpts};
 
 /// TFTP server builder.
 pub struct TftpServerBuilder<H: Handler> {
     handle: H,
     addr: SocketAddr,
     socket: Option<Async<UdpSocket>>,
+    transport: Option<Arc<dyn Transport>>,
     timeout: Duration,
     block_size_limit: Option<u16>,
+    min_block_size: Option<(u16, BlockSizePolicy)>,
+    oversized_datagrams: OversizedDatagrams,
+    duplicate_blocks: DuplicateBlocks,
     max_send_retries: u32,
+    max_bytes_per_sec: Option<u64>,
+    write_buffer_size: usize,
+    legacy_params: Option<TransferParams>,
+    max_oack_retries: Option<u32>,
+    oack_timeout: Option<Duration>,
+    max_window_size: Option<u16>,
+    adaptive_window: bool,
+    window_packet_gap: Option<Duration>,
     ignore_client_timeout: bool,
+    min_client_timeout: Option<Duration>,
+    max_client_timeout: Option<Duration>,
     ignore_client_block_size: bool,
+    omit_transfer_size: bool,
+    transfer_ip: Option<IpAddr>,
+    socket_hook: Option<Arc<SocketHookFn>>,
+    socket_opts: SocketOpts,
+    reply_from_listen_port: bool,
+    lookup_client_mac: bool,
+    transfer_ports: Option<(u16, u16)>,
+    error_messages: Option<Arc<ErrorMessageFn>>,
+    max_filename_len: Option<usize>,
+    max_request_options: Option<usize>,
+    max_request_size: Option<usize>,
+    max_write_size: Option<u64>,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    resolver: Option<Arc<dyn BootSessionResolver>>,
+    audit: Option<Arc<dyn AuditSink>>,
+    max_concurrent_transfers: Option<usize>,
+    max_active_requests: Option<usize>,
+    memory_budget: Option<usize>,
+    max_pending_handshakes: Option<usize>,
+    max_pending_handshakes_per_ip: Option<usize>,
+    handshake_timeout: Option<Duration>,
+    restart_window: Option<Duration>,
+    request_count_window: Option<Duration>,
+    duplicate_request_window: Option<Duration>,
+    downgrade_rejected_options: bool,
+    mirror: Option<(SocketAddr, MirrorMode)>,
+    scheduling: Scheduling,
+    // Clones the handler for every shard
+    shard_handlers: Option<fn(&H) -> H>,
+    cancellation_token: Option<CancellationToken>,
+    keep_state: Option<KeepState>,
+    resumed: Vec<TransferSnapshot>,
+    clock: Arc<dyn Clock>,
+}
+
+/// What to do with a request whose block size is too small.
+///
+/// See [`TftpServerBuilder::min_block_size`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockSizePolicy {
+    /// Reject the request with [`OptionsNegotiationFailed`] error.
+    ///
+    /// [`OptionsNegotiationFailed`]: crate::packet::Error::OptionsNegotiationFailed
+    Reject,
+    /// Ignore the `blksize` option and transfer with the default block size
+    /// of 512 bytes, even if the minimum is bigger. The minimum itself is
+    /// never acknowledged, because RFC2348 does not allow the server to
+    /// reply with a bigger block size than the one that client requested.
+    Ignore,
+}
+
+/// What to do with a DATA datagram that is bigger than the negotiated block
+/// size.
+///
+/// See [`TftpServerBuilder::oversized_datagrams`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizedDatagrams {
+    /// Terminate the transfer with `IllegalOperation` error, with outcome
+    /// [`OversizedDatagram`].
+    ///
+    /// [`OversizedDatagram`]: super::TransferOutcome::OversizedDatagram
+    Reject,
+    /// Drop the datagram and wait for the next one, as if it was lost.
+    Ignore,
+}
+
+/// What to do with a delayed duplicate of a block that was already written.
+///
+/// See [`TftpServerBuilder::duplicate_blocks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateBlocks {
+    /// Acknowledge the block again without writing it.
+    Reack,
+    /// Like [`Reack`](Self::Reack), but terminate the transfer with
+    /// `IllegalOperation` error, with outcome [`DuplicateMismatch`], if the
+    /// data of the duplicate differs from the written data.
+    ///
+    /// [`DuplicateMismatch`]: super::TransferOutcome::DuplicateMismatch
+    RejectMismatch,
+}
+
+/// Where the tasks of requests run.
+///
+/// See [`TftpServerBuilder::scheduling`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduling {
+    /// Run all tasks on the executor of [`TftpServer::serve`].
+    ///
+    /// [`TftpServer::serve`]: super::TftpServer::serve
+    Shared,
+    /// Distribute tasks across `executors` single-threaded executors, each
+    /// on its own thread. Requests are assigned by hash of the client IP, so
+    /// all transfers of a client run on the same thread.
+    ///
+    /// With `pin_to_cores`, the threads are pinned to distinct CPUs where
+    /// possible. This is supported only on Linux and ignored elsewhere.
+    ///
+    /// Every shard keeps its own table of requests. The handler is shared by
+    /// all shards, unless every shard gets a clone of it with
+    /// [`TftpServerBuilder::shard_handlers`].
+    Sharded {
+        executors: usize,
+        pin_to_cores: bool,
+    },
 }
 
 impl TftpServerBuilder<DirHandler> {
@@ -61,17 +197,142 @@ impl<H: Handler> TftpServerBuilder<H> {
             handle: handler,
             addr: "0.0.0.0:69".parse().unwrap(),
             socket: None,
+            transport: None,
             timeout: Duration::from_secs(3),
             block_size_limit: None,
+            min_block_size: None,
+            oversized_datagrams: OversizedDatagrams::Reject,
+            duplicate_blocks: DuplicateBlocks::Reack,
             max_send_retries: 100,
+            max_bytes_per_sec: None,
+            write_buffer_size: 0,
+            legacy_params: None,
+            max_oack_retries: None,
+            oack_timeout: None,
+            max_window_size: None,
+            adaptive_window: false,
+            window_packet_gap: None,
             ignore_client_timeout: false,
+            min_client_timeout: None,
+            max_client_timeout: None,
             ignore_client_block_size: false,
+            omit_transfer_size: false,
+            transfer_ip: None,
+            socket_hook: None,
+            socket_opts: SocketOpts::default(),
+            reply_from_listen_port: false,
+            lookup_client_mac: false,
+            transfer_ports: None,
+            error_messages: None,
+            max_filename_len: None,
+            max_request_options: None,
+            max_request_size: None,
+            max_write_size: None,
+            authorizer: None,
+            resolver: None,
+            audit: None,
+            max_concurrent_transfers: None,
+            max_active_requests: None,
+            memory_budget: None,
+            max_pending_handshakes: None,
+            max_pending_handshakes_per_ip: None,
+            handshake_timeout: None,
+            restart_window: None,
+            request_count_window: None,
+            duplicate_request_window: None,
+            downgrade_rejected_options: false,
+            mirror: None,
+            scheduling: Scheduling::Shared,
+            shard_handlers: None,
+            cancellation_token: None,
+            keep_state: None,
+            resumed: Vec::new(),
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Apply settings for network boot (PXE, iPXE, U-Boot).
+    ///
+    /// Boot ROMs are often buggy and boot storms bring many clients at
+    /// once, so this sets:
+    ///
+    /// * [`max_block_size`](Self::max_block_size) of 1468, which fits in an
+    ///   Ethernet frame without IP fragmentation
+    /// * [`max_window_size`](Self::max_window_size) of 8 with
+    ///   [`adaptive_window`](Self::adaptive_window)
+    /// * [`timeout`](Self::timeout) of 2 seconds, clients can request from
+    ///   1 to 5 seconds, and [`handshake_timeout`](Self::handshake_timeout)
+    ///   of 1 second
+    /// * [`downgrade_rejected_options`](Self::downgrade_rejected_options)
+    /// * [`restart_window`](Self::restart_window) and
+    ///   [`duplicate_request_window`](Self::duplicate_request_window) of
+    ///   1 second
+    /// * [`max_filename_len`](Self::max_filename_len) of 255,
+    ///   [`max_request_options`](Self::max_request_options) of 16 and
+    ///   [`max_request_size`](Self::max_request_size) of 512
+    /// * [`max_pending_handshakes_per_ip`](Self::max_pending_handshakes_per_ip)
+    ///   of 8
+    ///
+    /// Every setting can be changed after this call.
+    pub fn pxe_defaults(self) -> Self {
+        self.max_block_size(1468)
+            .max_window_size(8)
+            .adaptive_window()
+            .timeout(Duration::from_secs(2))
+            .min_client_timeout(Duration::from_secs(1))
+            .max_client_timeout(Duration::from_secs(5))
+            .handshake_timeout(Duration::from_secs(1))
+            .downgrade_rejected_options()
+            .restart_window(Duration::from_secs(1))
+            .duplicate_request_window(Duration::from_secs(1))
+            .max_filename_len(255)
+            .max_request_options(16)
+            .max_request_size(512)
+            .max_pending_handshakes_per_ip(8)
+    }
+
+    /// Apply settings for devices that upload firmware or crash dumps.
+    ///
+    /// Uploads are long and come from few devices, often over tunnels, so
+    /// this sets:
+    ///
+    /// * [`max_block_size`](Self::max_block_size) of 1428, which fits in
+    ///   tunneled networks without IP fragmentation
+    /// * [`timeout`](Self::timeout) of 5 seconds, clients can request from
+    ///   1 to 10 seconds
+    /// * [`max_send_retries`](Self::max_send_retries) of 10
+    /// * [`max_write_size`](Self::max_write_size) of 256 MiB
+    /// * [`duplicate_request_window`](Self::duplicate_request_window) of
+    ///   2 seconds, so a retransmitted request does not truncate the upload
+    /// * [`max_filename_len`](Self::max_filename_len) of 255,
+    ///   [`max_request_options`](Self::max_request_options) of 16 and
+    ///   [`max_request_size`](Self::max_request_size) of 512
+    /// * [`max_pending_handshakes_per_ip`](Self::max_pending_handshakes_per_ip)
+    ///   of 2
+    ///
+    /// Every setting can be changed after this call.
+    pub fn firmware_upload_defaults(self) -> Self {
+        self.max_block_size(1428)
+            .timeout(Duration::from_secs(5))
+            .min_client_timeout(Duration::from_secs(1))
+            .max_client_timeout(Duration::from_secs(10))
+            .max_send_retries(10)
+            .max_write_size(256 * 1024 * 1024)
+            .duplicate_request_window(Duration::from_secs(2))
+            .max_filename_len(255)
+            .max_request_options(16)
+            .max_request_size(512)
+            .max_pending_handshakes_per_ip(2)
+    }
+
     /// Set listening address.
     ///
-    /// This is ignored if underling socket is set.
+    /// This is ignored if underling socket is set. If it can not be bound,
+    /// [`build`](Self::build) fails with [`Error::BindListen`], which tells
+    /// how to fix the common causes.
+    ///
+    /// With port 0 the OS assigns a free port, which
+    /// [`TftpServer::listen_addr`] reports once the server is built.
     ///
     /// **Default:** `0.0.0.0:69`
     pub fn bind(self, addr: SocketAddr) -> Self {
@@ -99,10 +360,191 @@ impl<H: Handler> TftpServerBuilder<H> {
         })
     }
 
+    /// Serve requests over another [`Transport`] than UDP.
+    ///
+    /// The sockets of transfers are bound with
+    /// [`Transport::bind_transfer`] of `transport`. The options of UDP
+    /// sockets, like [`transfer_ip`](Self::transfer_ip) and
+    /// [`transfer_port_range`](Self::transfer_port_range), are ignored.
+    pub fn transport<T>(self, transport: T) -> Self
+    where
+        T: Transport + 'static,
+    {
+        TftpServerBuilder {
+            transport: Some(Arc::new(transport)),
+            ..self
+        }
+    }
+
+    /// Set local IP of the sockets that serve transfers.
+    ///
+    /// Replies to a request are sent from a new socket. Set this if they
+    /// must come from another IP than the listening socket, e.g. when it
+    /// listens on an unspecified address behind NAT.
+    ///
+    /// **Default:** IP of the listening socket
+    pub fn transfer_ip(self, ip: IpAddr) -> Self {
+        TftpServerBuilder {
+            transfer_ip: Some(ip),
+            ..self
+        }
+    }
+
+    /// Set a hook that sets up every socket of a transfer before it is
+    /// bound.
+    ///
+    /// `f` gets the socket and the address of the client, and can set
+    /// options that this crate does not expose, e.g. `SO_MARK` for policy
+    /// routing or `IP_TRANSPARENT`. The sockets of error replies to failed
+    /// requests are set up as well. If `f` fails, the request fails.
+    ///
+    /// The socket is of the [`socket2`](crate::socket2) version that this
+    /// crate re-exports.
+    pub fn transfer_socket_hook<F>(self, f: F) -> Self
+    where
+        F: Fn(&socket2::Socket, SocketAddr) -> io::Result<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        TftpServerBuilder {
+            socket_hook: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Send replies from the listening port, instead of a new port per
+    /// transfer.
+    ///
+    /// Clients behind NAT, or servers behind DNAT, may not accept replies
+    /// from another port than the one they sent the request to. With this
+    /// option, the socket of a transfer is bound to the listening port and
+    /// connected to the client, so the kernel delivers the packets of the
+    /// client to it.
+    ///
+    /// The listening socket must have `SO_REUSEPORT` set. This is done
+    /// unless the socket is set with [`socket`](Self::socket) or
+    /// [`std_socket`](Self::std_socket).
+    #[cfg(target_os = "linux")]
+    pub fn reply_from_listen_port(self) -> Self {
+        TftpServerBuilder {
+            reply_from_listen_port: true,
+            ..self
+        }
+    }
+
+    /// Set `SO_MARK` of the listening socket and the sockets of transfers.
+    ///
+    /// Policy routing rules match the mark (fwmark), so replies can be
+    /// steered through a specific routing table, VRF or uplink. Setting it
+    /// needs the `CAP_NET_ADMIN` capability. The listening socket is not
+    /// marked if it is set with [`socket`](Self::socket) or
+    /// [`std_socket`](Self::std_socket).
+    ///
+    /// **Default:** Sockets are not marked
+    #[cfg(target_os = "linux")]
+    pub fn fwmark(self, mark: u32) -> Self {
+        TftpServerBuilder {
+            socket_opts: SocketOpts {
+                mark: Some(mark),
+                ..self.socket_opts
+            },
+            ..self
+        }
+    }
+
+    /// Bind the listening socket and the sockets of transfers to the
+    /// network device `name` (`SO_BINDTODEVICE`).
+    ///
+    /// With a VRF (l3mdev) device the server serves only the clients of
+    /// the VRF, and its replies are routed by the routing table of the VRF.
+    /// This allows to run a server per VRF on the same port. Setting it
+    /// needs the `CAP_NET_RAW` capability. The listening socket is not
+    /// bound to the device if it is set with [`socket`](Self::socket) or
+    /// [`std_socket`](Self::std_socket).
+    ///
+    /// **Default:** Sockets are not bound to a device
+    #[cfg(target_os = "linux")]
+    pub fn bind_device(self, name: &str) -> Self {
+        TftpServerBuilder {
+            socket_opts: SocketOpts {
+                device: Some(name.into()),
+                ..self.socket_opts
+            },
+            ..self
+        }
+    }
+
+    /// Fail a transfer as soon as the port of its client is unreachable.
+    ///
+    /// Windows reports an ICMP "port unreachable" of a sent datagram on the
+    /// next receive of the UDP socket, as `WSAECONNRESET`
+    /// (`SIO_UDP_CONNRESET`). The server disables this on the sockets that
+    /// it binds, so a transfer whose client went away is retransmitted
+    /// until [`max_send_retries`](Self::max_send_retries) run out, like on
+    /// other platforms. With this option a transfer fails at the first ICMP
+    /// error instead. The listening socket keeps receiving either way. It
+    /// is not set up if it is set with [`socket`](Self::socket) or
+    /// [`std_socket`](Self::std_socket).
+    ///
+    /// **Default:** ICMP errors are not reported
+    #[cfg(windows)]
+    pub fn fail_on_port_unreachable(self) -> Self {
+        TftpServerBuilder {
+            socket_opts: SocketOpts {
+                conn_reset: true,
+                ..self.socket_opts
+            },
+            ..self
+        }
+    }
+
+    /// Bind the socket of every transfer to a random port of `ports`.
+    ///
+    /// The port is the transfer ID (TID) of the server, so an attacker that
+    /// guesses it can inject DATA and ERROR packets into a transfer. Ports
+    /// are drawn from the CSPRNG of the OS, instead of the assignment of the
+    /// OS, which is sequential on some systems. This is ignored with
+    /// [`reply_from_listen_port`](Self::reply_from_listen_port).
+    ///
+    /// **Default:** Ports are assigned by the OS
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ports` is empty.
+    pub fn transfer_port_range(self, ports: RangeInclusive<u16>) -> Self {
+        assert!(!ports.is_empty(), "empty transfer port range");
+
+        TftpServerBuilder {
+            transfer_ports: Some((*ports.start(), *ports.end())),
+            ..self
+        }
+    }
+
+    /// Look up the MAC address of every client in the neighbor table.
+    ///
+    /// The address is reported in [`TransferContext::client_mac`], as PXE
+    /// logic is usually keyed by MAC. Only clients on the same link are in
+    /// the table, and only IPv4 neighbors are looked up.
+    ///
+    /// **Default:** `client_mac` is `None`
+    ///
+    /// [`TransferContext::client_mac`]: super::TransferContext::client_mac
+    #[cfg(target_os = "linux")]
+    pub fn lookup_client_mac(self) -> Self {
+        TftpServerBuilder {
+            lookup_client_mac: true,
+            ..self
+        }
+    }
+
     /// Set retry timeout.
     ///
-    /// Client can override this (RFC2349). If you want to enforce it you must
-    /// combine it [`ignore_client_timeout`](Self::ignore_client_timeout).
+    /// Client can override this (RFC2349), within
+    /// [`min_client_timeout`](Self::min_client_timeout) and
+    /// [`max_client_timeout`](Self::max_client_timeout). If you want to
+    /// enforce it you must combine it
+    /// [`ignore_client_timeout`](Self::ignore_client_timeout).
     ///
     /// This crate allows you to set non-standard timeouts (i.e. timeouts that are less
     /// than a second). However if you choose to do it make sure you test it well in your
@@ -119,19 +561,93 @@ impl<H: Handler> TftpServerBuilder<H> {
     /// Set maximum block size.
     ///
     /// Client can request a specific block size (RFC2348). Use this option if you
-    /// want to set a limit.
+    /// want to set a limit. Bigger requests are clamped down to `size`, after
+    /// they are checked against [`min_block_size`](Self::min_block_size).
+    /// Handlers can lower it further per transfer with
+    /// [`TransferParams::max_block_size`].
     ///
     /// **Real life scenario:** U-Boot does not support IP fragmentation and requests
     /// block size of 1468. This works fine if your MTU is 1500 bytes, however if
     /// you are accessing client through a VPN, then transfer will never start. Use
-    /// this option to workaround the problem.
-    pub fn block_size_limit(self, size: u16) -> Self {
+    /// this option to workaround the problem, e.g. with 1428 for tunneled
+    /// networks.
+    ///
+    /// **Default:** No limit
+    ///
+    /// [`TransferParams::max_block_size`]: super::TransferParams::max_block_size
+    pub fn max_block_size(self, size: u16) -> Self {
         TftpServerBuilder {
             block_size_limit: Some(size),
             ..self
         }
     }
 
+    /// Set maximum block size.
+    ///
+    /// This is the same as [`max_block_size`](Self::max_block_size).
+    pub fn block_size_limit(self, size: u16) -> Self {
+        self.max_block_size(size)
+    }
+
+    /// Set minimum block size.
+    ///
+    /// A client that requests a tiny block size (e.g. 8 bytes) forces the
+    /// server to send an enormous number of packets for big files. Requests
+    /// with a smaller block size than `size` are handled according to
+    /// `policy`.
+    ///
+    /// **Real life scenario:** Some legacy devices support only small blocks,
+    /// so keep `size` low enough for the clients of your network.
+    ///
+    /// **Default:** No limit
+    pub fn min_block_size(self, size: u16, policy: BlockSizePolicy) -> Self {
+        TftpServerBuilder {
+            min_block_size: Some((size, policy)),
+            ..self
+        }
+    }
+
+    /// Set how DATA datagrams that are bigger than the negotiated block size
+    /// are handled.
+    ///
+    /// Such datagrams can not be written without dropping data. They are
+    /// counted in [`AuditRecord::oversized_datagrams`] in any case. ERROR
+    /// packets of the client are not limited to the block size, a message
+    /// that does not fit is cut off.
+    ///
+    /// **Default:** [`OversizedDatagrams::Reject`]
+    ///
+    /// [`AuditRecord::oversized_datagrams`]: super::AuditRecord::oversized_datagrams
+    pub fn oversized_datagrams(self, policy: OversizedDatagrams) -> Self {
+        TftpServerBuilder {
+            oversized_datagrams: policy,
+            ..self
+        }
+    }
+
+    /// Set how delayed duplicates of already written blocks are handled on
+    /// write requests.
+    ///
+    /// A client retransmits a block when our ACK is lost or late, so a copy
+    /// of the previous block can arrive after it was written. The duplicate
+    /// is never written again, but acknowledged, so the client can continue
+    /// even if the original ACK was lost. Duplicates are counted in
+    /// [`AuditRecord::duplicate_blocks`] in any case.
+    ///
+    /// With [`DuplicateBlocks::RejectMismatch`] a digest of every written
+    /// block is kept, to detect clients that send different data for the
+    /// same block.
+    ///
+    /// **Default:** [`DuplicateBlocks::Reack`]
+    ///
+    /// [`AuditRecord::duplicate_blocks`]: super::AuditRecord::duplicate_blocks
+    pub fn duplicate_blocks(self, policy: DuplicateBlocks) -> Self {
+        TftpServerBuilder {
+            duplicate_blocks: policy,
+            ..self
+        }
+    }
+
     /// Set maximum send retries for a data block.
     ///
     /// On timeout server will try to send the data block again. When retries are
@@ -146,6 +662,240 @@ impl<H: Handler> TftpServerBuilder<H> {
         }
     }
 
+    /// Set maximum rate of file data of each transfer, in bytes per second.
+    ///
+    /// Handlers can set a different rate for a transfer with
+    /// [`Handler::transfer_params`].
+    ///
+    /// **Default:** No limit
+    pub fn max_bytes_per_sec(self, rate: u64) -> Self {
+        TftpServerBuilder {
+            max_bytes_per_sec: Some(rate),
+            ..self
+        }
+    }
+
+    /// Set size of the buffer that collects the blocks of a write request
+    /// before they are written to the writer of the handler.
+    ///
+    /// Writing every block of 512 bytes on its own is slow for disks and
+    /// object storage, so blocks are written in chunks of at least `bytes`,
+    /// and the rest with the last block. Data that is buffered when a
+    /// transfer fails is never written. Buffers count against
+    /// [`memory_budget`](Self::memory_budget).
+    ///
+    /// Handlers can set a different size for a transfer with
+    /// [`Handler::transfer_params`].
+    ///
+    /// **Default:** 0, every block is written when it arrives
+    pub fn write_buffer_size(self, bytes: usize) -> Self {
+        TftpServerBuilder {
+            write_buffer_size: bytes,
+            ..self
+        }
+    }
+
+    /// Set parameters of transfers whose request has no options.
+    ///
+    /// Clients without options are usually RFC1350-only boot ROMs, which
+    /// can not negotiate a timeout, so e.g. a shorter retransmission timeout
+    /// speeds up their transfers on lossy networks. Transfers without
+    /// options already use buffers of the default block size of 512 bytes.
+    /// This also applies to transfers that are restarted without options,
+    /// see [`downgrade_rejected_options`](Self::downgrade_rejected_options).
+    /// Parameters of [`Handler::transfer_params`] take precedence.
+    ///
+    /// **Default:** Parameters of the server are used
+    pub fn legacy_client_params(self, params: TransferParams) -> Self {
+        TftpServerBuilder {
+            legacy_params: Some(params),
+            ..self
+        }
+    }
+
+    /// Set maximum retries of OACK packets.
+    ///
+    /// Lost OACKs are a common failure of some network boot ROMs, so they
+    /// may need more retries than data blocks. For write requests this
+    /// applies until the first data block arrives.
+    ///
+    /// **Default:** Same as [`max_send_retries`](Self::max_send_retries)
+    pub fn max_oack_retries(self, retries: u32) -> Self {
+        TftpServerBuilder {
+            max_oack_retries: Some(retries),
+            ..self
+        }
+    }
+
+    /// Set retry timeout of OACK packets.
+    ///
+    /// A short timeout retransmits lost OACKs more aggressively. When it is
+    /// set, the timeout that the client requested is ignored for OACKs.
+    ///
+    /// **Default:** Same as [`handshake_timeout`](Self::handshake_timeout)
+    pub fn oack_timeout(self, timeout: Duration) -> Self {
+        TftpServerBuilder {
+            oack_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Set maximum window size of read requests (RFC7440).
+    ///
+    /// Clients that request the `windowsize` option receive up to `size`
+    /// blocks before they acknowledge them, which speeds up transfers on
+    /// links with high latency. Write requests are always acknowledged
+    /// block by block.
+    ///
+    /// **Default:** Option is ignored and blocks are sent one at a time
+    pub fn max_window_size(self, size: u16) -> Self {
+        TftpServerBuilder {
+            max_window_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Adapt the window of read requests to packet loss.
+    ///
+    /// The window is halved when blocks are lost, and grows by one block
+    /// with every window that is acknowledged in full, up to the negotiated
+    /// window size. This keeps windowed transfers from collapsing on lossy
+    /// links such as Wi-Fi.
+    ///
+    /// **Default:** Disabled, the negotiated window is always used
+    pub fn adaptive_window(self) -> Self {
+        TftpServerBuilder {
+            adaptive_window: true,
+            ..self
+        }
+    }
+
+    /// Set gap between the DATA packets of a window.
+    ///
+    /// Cheap switches and embedded NICs drop bursts of back to back packets,
+    /// which makes big windows slower than small ones. Pacing the packets
+    /// avoids the loss.
+    ///
+    /// **Default:** Packets of a window are sent without a gap
+    pub fn window_packet_gap(self, gap: Duration) -> Self {
+        TftpServerBuilder {
+            window_packet_gap: Some(gap),
+            ..self
+        }
+    }
+
+    /// Set rate of the DATA packets of a window, in packets per second.
+    ///
+    /// This is the same as [`window_packet_gap`](Self::window_packet_gap)
+    /// with a gap of `1s / rate`.
+    pub fn window_packet_rate(self, rate: u32) -> Self {
+        self.window_packet_gap(Duration::from_secs(1) / rate.max(1))
+    }
+
+    /// Restart transfers without options when the client rejects the OACK.
+    ///
+    /// Clients reject an OACK with `OptionsNegotiationFailed` error. By
+    /// default the transfer is terminated, as RFC2347 describes. Several old
+    /// clients use this error to ask for a transfer without options, so with
+    /// this flag the transfer is restarted from the same address, as if the
+    /// request had no options.
+    pub fn downgrade_rejected_options(self) -> Self {
+        TftpServerBuilder {
+            downgrade_rejected_options: true,
+            ..self
+        }
+    }
+
+    /// Set where the tasks of requests run.
+    ///
+    /// For thread-per-core deployments, [`Scheduling::Sharded`] spreads the
+    /// transfers across threads without contention between them on the hot
+    /// path. The threads run while [`TftpServer::serve`] runs.
+    ///
+    /// [`TftpServer::serve`]: super::TftpServer::serve
+    ///
+    /// **Default:** [`Scheduling::Shared`]
+    pub fn scheduling(self, scheduling: Scheduling) -> Self {
+        if let Scheduling::Sharded {
+            executors,
+            ..
+        } = scheduling
+        {
+            assert!(executors > 0, "at least one executor is needed");
+        }
+
+        TftpServerBuilder {
+            scheduling,
+            ..self
+        }
+    }
+
+    /// Set the token that stops the server.
+    ///
+    /// When `token` is cancelled, [`TftpServer::serve`] stops receiving
+    /// requests, cancels the running transfers and returns after their
+    /// clients are notified. Transfers can also be cancelled one by one with
+    /// [`TransferParams::cancellation_token`].
+    ///
+    /// [`TftpServer::serve`]: super::TftpServer::serve
+    /// [`TransferParams::cancellation_token`]: super::TransferParams::cancellation_token
+    ///
+    /// **Default:** Server runs until [`TftpServer::serve`] is dropped
+    pub fn cancellation_token(self, token: CancellationToken) -> Self {
+        TftpServerBuilder {
+            cancellation_token: Some(token),
+            ..self
+        }
+    }
+
+    /// Record the progress of read transfers in `state`, so they can be
+    /// resumed by the next server process.
+    ///
+    /// See [`KeepState`].
+    ///
+    /// **Default:** Progress is not recorded
+    pub fn keep_state(self, state: KeepState) -> Self {
+        TftpServerBuilder {
+            keep_state: Some(state),
+            ..self
+        }
+    }
+
+    /// Resume the read transfers of a previous server process when serving
+    /// starts.
+    ///
+    /// Each transfer binds the transfer address that it had, opens the file
+    /// with the [`Handler`] again and sends the block after the last
+    /// acknowledged one. Authorization and resolution of the filename are
+    /// not repeated. Transfers whose address can not be bound, or whose file
+    /// can not be opened, fail as usual.
+    ///
+    /// See [`KeepState`].
+    ///
+    /// **Default:** No transfers are resumed
+    pub fn resume_transfers(self, snapshots: Vec<TransferSnapshot>) -> Self {
+        TftpServerBuilder {
+            resumed: snapshots,
+            ..self
+        }
+    }
+
+    /// Mirror read requests to the secondary server at `addr`.
+    ///
+    /// This replays production traffic to a new server for testing. The
+    /// secondary server is contacted in the background, so its replies and
+    /// failures never affect the clients. Only requests that pass the
+    /// [`authorizer`](Self::authorizer) are mirrored, and at most 32 of them
+    /// at a time; the others are not mirrored. See [`MirrorMode`].
+    ///
+    /// **Default:** Requests are not mirrored
+    pub fn mirror(self, addr: SocketAddr, mode: MirrorMode) -> Self {
+        TftpServerBuilder {
+            mirror: Some((addr, mode)),
+            ..self
+        }
+    }
+
     /// Ignore client's `timeout` option.
     ///
     /// With this you enforce server's timeout by ignoring client's
@@ -157,6 +907,34 @@ impl<H: Handler> TftpServerBuilder<H> {
         }
     }
 
+    /// Set minimum timeout that a client can request.
+    ///
+    /// Shorter timeouts of the `timeout` option (RFC2349) are raised to this
+    /// one, and the OACK carries the raised value. It is rounded up to whole
+    /// seconds.
+    ///
+    /// **Default:** No minimum
+    pub fn min_client_timeout(self, timeout: Duration) -> Self {
+        TftpServerBuilder {
+            min_client_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Set maximum timeout that a client can request.
+    ///
+    /// Longer timeouts of the `timeout` option (RFC2349) are lowered to this
+    /// one, and the OACK carries the lowered value. It is rounded down to
+    /// whole seconds, but it is at least one second.
+    ///
+    /// **Default:** No maximum
+    pub fn max_client_timeout(self, timeout: Duration) -> Self {
+        TftpServerBuilder {
+            max_client_timeout: Some(timeout),
+            ..self
+        }
+    }
+
     /// Ignore client's block size option.
     ///
     /// With this you can ignore client's `blksize` option of RFC2348.
@@ -168,29 +946,543 @@ impl<H: Handler> TftpServerBuilder<H> {
         }
     }
 
+    /// Leave the `tsize` option (RFC2349) out of OACKs.
+    ///
+    /// Some clients misbehave when the size of a file is 0 or does not fit
+    /// in 32 bits. Without the option they transfer the file until the last
+    /// block, like clients that did not request it. Handlers can decide it
+    /// for a transfer with [`TransferParams::omit_transfer_size`].
+    ///
+    /// **Default:** `tsize` is acknowledged when the client requests it
+    ///
+    /// [`TransferParams::omit_transfer_size`]: super::TransferParams::omit_transfer_size
+    pub fn omit_transfer_size(self) -> Self {
+        TftpServerBuilder {
+            omit_transfer_size: true,
+            ..self
+        }
+    }
+
+    /// Set maximum length of the requested filename in bytes.
+    ///
+    /// Requests with longer filename are rejected with `IllegalOperation`.
+    ///
+    /// **Default:** No limit
+    pub fn max_filename_len(self, len: usize) -> Self {
+        TftpServerBuilder {
+            max_filename_len: Some(len),
+            ..self
+        }
+    }
+
+    /// Set maximum number of options in a request.
+    ///
+    /// Unknown and duplicate options are counted too. Requests with more
+    /// options are rejected with `IllegalOperation`.
+    ///
+    /// **Default:** No limit
+    pub fn max_request_options(self, count: usize) -> Self {
+        TftpServerBuilder {
+            max_request_options: Some(count),
+            ..self
+        }
+    }
+
+    /// Set maximum size of a request packet in bytes.
+    ///
+    /// Bigger requests are rejected with `IllegalOperation` before they
+    /// are parsed.
+    ///
+    /// **Default:** No limit
+    pub fn max_request_size(self, size: usize) -> Self {
+        TftpServerBuilder {
+            max_request_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Set maximum size of an uploaded file in bytes.
+    ///
+    /// Write requests that announce a bigger `tsize` (RFC2349) are rejected
+    /// with `DiskFull` before the handler opens the file. Transfers that
+    /// exceed it anyway are aborted with `DiskFull`.
+    ///
+    /// **Default:** No limit
+    pub fn max_write_size(self, size: u64) -> Self {
+        TftpServerBuilder {
+            max_write_size: Some(size),
+            ..self
+        }
+    }
+
+    /// Set [`Authorizer`] that is consulted for every request.
+    ///
+    /// **Default:** All requests are passed to the handler
+    pub fn authorizer<A>(self, authorizer: A) -> Self
+    where
+        A: Authorizer + 'static,
+    {
+        TftpServerBuilder {
+            authorizer: Some(Arc::new(authorizer)),
+            ..self
+        }
+    }
+
+    /// Set [`BootSessionResolver`] that decides which file is served for
+    /// every read request.
+    ///
+    /// **Default:** Requested files are served
+    pub fn boot_session_resolver<R>(self, resolver: R) -> Self
+    where
+        R: BootSessionResolver + 'static,
+    {
+        TftpServerBuilder {
+            resolver: Some(Arc::new(resolver)),
+            ..self
+        }
+    }
+
+    /// Set the messages of the ERROR packets that are sent to clients.
+    ///
+    /// `f` returns the message for an error, or `None` to keep the default
+    /// one. The error code is not changed. This allows to avoid leaking
+    /// paths or other details of the handler, or to adapt the messages for
+    /// clients that parse them.
+    ///
+    /// **Default:** Messages of [`packet::Error::msg`]
+    ///
+    /// [`packet::Error::msg`]: crate::packet::Error::msg
+    pub fn error_messages<F>(self, f: F) -> Self
+    where
+        F: Fn(&packet::Error) -> Option<String> + Send + Sync + 'static,
+    {
+        TftpServerBuilder {
+            error_messages: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Send ERROR packets with empty messages.
+    ///
+    /// This is the same as [`error_messages`](Self::error_messages) with a
+    /// function that returns an empty message for every error.
+    pub fn blank_error_messages(self) -> Self {
+        self.error_messages(|_| Some(String::new()))
+    }
+
+    /// Set [`AuditSink`] that records every request and its outcome.
+    ///
+    /// Use [`JsonLinesAudit`] to log in a file.
+    ///
+    /// [`JsonLinesAudit`]: super::JsonLinesAudit
+    pub fn audit<A>(self, sink: A) -> Self
+    where
+        A: AuditSink + 'static,
+    {
+        TftpServerBuilder {
+            audit: Some(Arc::new(sink)),
+            ..self
+        }
+    }
+
+    /// Set maximum number of concurrent transfers.
+    ///
+    /// When the limit is reached, new requests wait for a running transfer to
+    /// finish. They start in the order of their [`Priority`], as it is
+    /// reported by [`Handler::transfer_priority`].
+    ///
+    /// [`Priority`]: super::Priority
+    ///
+    /// **Default:** No limit
+    pub fn max_concurrent_transfers(self, max: usize) -> Self {
+        TftpServerBuilder {
+            max_concurrent_transfers: Some(max),
+            ..self
+        }
+    }
+
+    /// Set maximum number of requests that are handled at the same time.
+    ///
+    /// Unlike [`max_concurrent_transfers`](Self::max_concurrent_transfers),
+    /// requests over the limit are not queued. They are answered immediately
+    /// with "Server busy" error from the listening socket, without a task or
+    /// a socket of their own, so the server sheds load predictably under
+    /// extreme request rates. Shed requests are reported with
+    /// [`TransferOutcome::Shed`], which is also the outcome that the metric
+    /// sinks count.
+    ///
+    /// [`TransferOutcome::Shed`]: super::TransferOutcome::Shed
+    ///
+    /// **Default:** No limit
+    pub fn max_active_requests(self, max: usize) -> Self {
+        TftpServerBuilder {
+            max_active_requests: Some(max),
+            ..self
+        }
+    }
+
+    /// Set maximum number of bytes that transfers hold in their buffers.
+    ///
+    /// Windowed read transfers hold up to `blksize` × `windowsize` bytes,
+    /// which adds up with thousands of transfers. When the budget is tight,
+    /// new windows are shrunk to the blocks that fit in it, down to one
+    /// block. While the budget is exhausted, new requests are shed like
+    /// with [`max_active_requests`](Self::max_active_requests).
+    ///
+    /// Every transfer holds at least the buffer of one packet, so the budget
+    /// can be exceeded by that much per transfer. Packet buffers are sized
+    /// to the negotiated block size, so transfers of clients with small
+    /// blocks hold little memory.
+    ///
+    /// Only the buffers that the server allocates for transfers are
+    /// counted:
+    ///
+    /// * the DATA packets of the window of a read request
+    /// * the DATA packet and the [`write_buffer_size`](Self::write_buffer_size)
+    ///   buffer of a write request
+    ///
+    /// Memory of the handler is not counted, e.g. the contents of files that
+    /// [`DirHandler::share_reads`] shares or [`DirHandler::preload`] loads,
+    /// the decoders of compressed variants of `DirHandler`, listings of
+    /// [`DirHandler::dir_listing`] and the cache of [`RelayHandler::cache`].
+    /// Bound them with the options of the handler.
+    ///
+    /// [`DirHandler::share_reads`]: super::handlers::DirHandler::share_reads
+    /// [`DirHandler::preload`]: super::handlers::DirHandler::preload
+    /// [`DirHandler::dir_listing`]: super::handlers::DirHandler::dir_listing
+    /// [`RelayHandler::cache`]: super::handlers::RelayHandler::cache
+    ///
+    /// **Default:** No limit
+    pub fn memory_budget(self, bytes: usize) -> Self {
+        TftpServerBuilder {
+            memory_budget: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Set maximum number of pending handshakes.
+    ///
+    /// A handshake is pending from the moment a request is received until
+    /// the client replies for the first time. Requests that exceed the limit
+    /// are dropped silently, so a flood of spoofed requests can not exhaust
+    /// the sockets and tasks of the server. They are passed to the
+    /// [`audit`](Self::audit) sink with [`TransferOutcome::Dropped`].
+    ///
+    /// [`TransferOutcome::Dropped`]: super::TransferOutcome::Dropped
+    ///
+    /// **Default:** No limit
+    pub fn max_pending_handshakes(self, max: usize) -> Self {
+        TftpServerBuilder {
+            max_pending_handshakes: Some(max),
+            ..self
+        }
+    }
+
+    /// Set maximum number of pending handshakes per client IP.
+    ///
+    /// See [`max_pending_handshakes`](Self::max_pending_handshakes).
+    ///
+    /// **Default:** No limit
+    pub fn max_pending_handshakes_per_ip(self, max: usize) -> Self {
+        TftpServerBuilder {
+            max_pending_handshakes_per_ip: Some(max),
+            ..self
+        }
+    }
+
+    /// Set retry timeout of pending handshakes.
+    ///
+    /// It is used instead of [`timeout`](Self::timeout) until the client
+    /// replies for the first time, so unanswered handshakes are abandoned
+    /// early. The timeout that the client requested is ignored during this
+    /// time.
+    ///
+    /// **Default:** Same as [`timeout`](Self::timeout)
+    pub fn handshake_timeout(self, timeout: Duration) -> Self {
+        TftpServerBuilder {
+            handshake_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Recognize clients that abandon a read request and retry it from a
+    /// new port.
+    ///
+    /// Some buggy network boot ROMs do this. A read request from the same
+    /// IP for the same file that was requested less than `window` ago is
+    /// treated as a restart: the old transfer is aborted immediately, instead
+    /// of retransmitting to the old port until it times out. Restarts are
+    /// reported with [`AuditRecord::restarted`].
+    ///
+    /// Clients behind the same NAT that request the same file at the same
+    /// time are indistinguishable from a restart, so keep `window` short.
+    ///
+    /// [`AuditRecord::restarted`]: super::AuditRecord::restarted
+    ///
+    /// **Default:** Disabled
+    pub fn restart_window(self, window: Duration) -> Self {
+        TftpServerBuilder {
+            restart_window: Some(window),
+            ..self
+        }
+    }
+
+    /// Count how many times every client IP requests every file.
+    ///
+    /// Read requests of the same file from the same IP are counted until
+    /// none arrives for `window`. Only requests that the
+    /// [`authorizer`](Self::authorizer) allows are counted. Up to 4096
+    /// files are counted, after which the least recently requested one is
+    /// forgotten. The count is passed to
+    /// [`Handler::read_req_open_counted`] and reported in
+    /// [`TransferContext::request_count`], so the handler can serve
+    /// alternate content after repeated failures, e.g. a rescue image to a
+    /// PXE client that keeps rebooting.
+    ///
+    /// **Default:** Disabled
+    ///
+    /// [`Handler::read_req_open_counted`]: super::Handler::read_req_open_counted
+    /// [`TransferContext::request_count`]: super::TransferContext::request_count
+    pub fn count_requests(self, window: Duration) -> Self {
+        TftpServerBuilder {
+            request_count_window: Some(window),
+            ..self
+        }
+    }
+
+    /// Ignore retransmissions of a request that arrive after its transfer
+    /// finished.
+    ///
+    /// Clients often retransmit their request before the first reply
+    /// arrives. Requests from the same address are always ignored while a
+    /// transfer is in progress, but transfers of small files can finish
+    /// before the retransmission arrives, which starts a second transfer.
+    /// With this option, identical requests from the same IP and port are
+    /// ignored for `window` after the transfer finished.
+    ///
+    /// **Default:** Disabled
+    pub fn duplicate_request_window(self, window: Duration) -> Self {
+        TftpServerBuilder {
+            duplicate_request_window: Some(window),
+            ..self
+        }
+    }
+
+    fn handshake_limiter(&self) -> Option<Arc<HandshakeLimiter>> {
+        if self.max_pending_handshakes.is_none()
+            && self.max_pending_handshakes_per_ip.is_none()
+        {
+            return None;
+        }
+
+        Some(Arc::new(HandshakeLimiter::new(
+            self.max_pending_handshakes,
+            self.max_pending_handshakes_per_ip,
+        )))
+    }
+
+    /// Set the clock that drives timeouts.
+    ///
+    /// Used by tests to control time deterministically.
+    #[cfg(test)]
+    pub(crate) fn clock(self, clock: Arc<dyn Clock>) -> Self {
+        TftpServerBuilder {
+            clock,
+            ..self
+        }
+    }
+
+    /// Check the configuration before the server is built.
+    ///
+    /// Problems of the [`Handler`], e.g. a served directory that is missing,
+    /// not readable, or not writable while uploads are served, and
+    /// conflicting settings of the builder are all reported at once with
+    /// [`Error::InvalidConfig`], instead of failing on the first request
+    /// that runs into them.
+    pub fn validate(self) -> Result<Self> {
+        let mut problems = self.handle.validate();
+
+        if let (Some((min, _)), Some(max)) =
+            (self.min_block_size, self.block_size_limit)
+        {
+            if min > max {
+                problems.push(format!(
+                    "min_block_size {} is bigger than max_block_size {}",
+                    min, max
+                ));
+            }
+        }
+
+        if let (Some(min), Some(max)) =
+            (self.min_client_timeout, self.max_client_timeout)
+        {
+            if min > max {
+                problems.push(format!(
+                    "min_client_timeout {:?} is bigger than max_client_timeout \
+                     {:?}",
+                    min, max
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(self)
+        } else {
+            Err(Error::InvalidConfig(problems))
+        }
+    }
+
     /// Build [`TftpServer`].
     pub async fn build(mut self) -> Result<TftpServer<H>> {
-        let socket = match self.socket.take() {
-            Some(socket) => socket,
-            None => Async::<UdpSocket>::bind(self.addr).map_err(Error::Bind)?,
+        let addr = self.addr;
+        let bind_error = |source| {
+            Error::BindListen(BindError {
+                addr,
+                source,
+            })
+        };
+
+        let socket: Arc<dyn Transport> = match self.socket.take() {
+            _ if self.transport.is_some() => self.transport.clone().unwrap(),
+            Some(socket) => Arc::new(socket),
+            None => {
+                let socket = udp_socket(addr, self.reply_from_listen_port)
+                    .map_err(Error::Bind)?;
+                self.socket_opts.apply(&socket).map_err(Error::Bind)?;
+                socket.bind(&addr.into()).map_err(bind_error)?;
+
+                Arc::new(
+                    Async::new(UdpSocket::from(socket)).map_err(Error::Bind)?,
+                )
+            }
         };
 
+        let handshake_limiter = self.handshake_limiter();
+
         let config = ServerConfig {
             timeout: self.timeout,
             block_size_limit: self.block_size_limit,
+            min_block_size: self.min_block_size,
+            oversized_datagrams: self.oversized_datagrams,
+            duplicate_blocks: self.duplicate_blocks,
+            fail_on_conn_reset: self.socket_opts.conn_reset,
             max_send_retries: self.max_send_retries,
+            max_bytes_per_sec: self.max_bytes_per_sec,
+            write_buffer_size: self.write_buffer_size,
+            legacy_params: self.legacy_params,
+            max_oack_retries: self.max_oack_retries,
+            oack_timeout: self.oack_timeout,
+            max_window_size: self.max_window_size,
+            adaptive_window: self.adaptive_window,
+            window_packet_gap: self.window_packet_gap,
             ignore_client_timeout: self.ignore_client_timeout,
+            min_client_timeout: self.min_client_timeout,
+            max_client_timeout: self.max_client_timeout,
+            client_timeout_override: None,
             ignore_client_block_size: self.ignore_client_block_size,
+            omit_transfer_size: self.omit_transfer_size,
+            max_filename_len: self.max_filename_len,
+            max_request_options: self.max_request_options,
+            max_request_size: self.max_request_size,
+            max_write_size: self.max_write_size,
+            authorizer: self.authorizer,
+            resolver: self.resolver,
+            audit: self.audit,
+            transfer_limiter: self
+                .max_concurrent_transfers
+                .map(|max| Arc::new(TransferLimiter::new(max))),
+            handshake_limiter,
+            request_limiter: self
+                .max_active_requests
+                .map(|max| Arc::new(RequestLimiter::new(max))),
+            memory_budget: self
+                .memory_budget
+                .map(|max| Arc::new(MemoryBudget::new(max))),
+            handshake_timeout: self.handshake_timeout,
+            restart_detector: self
+                .restart_window
+                .map(|window| Arc::new(RestartDetector::new(window))),
+            request_counter: self
+                .request_count_window
+                .map(|window| Arc::new(RequestCounter::new(window))),
+            duplicate_request_window: self.duplicate_request_window,
+            downgrade_rejected_options: self.downgrade_rejected_options,
+            lookup_client_mac: self.lookup_client_mac,
+            error_messages: self.error_messages,
+            mirror: self
+                .mirror
+                .map(|(addr, mode)| Arc::new(Mirror::new(addr, mode))),
+            keep_state: self.keep_state,
+            cancellation: self.cancellation_token,
+            clock: self.clock,
+        };
+
+        let listen_addr = socket.local_addr()?;
+        let transfer_addr = TransferAddr {
+            addr: SocketAddr::new(
+                self.transfer_ip.unwrap_or_else(|| listen_addr.ip()),
+                match self.reply_from_listen_port {
+                    true => listen_addr.port(),
+                    false => 0,
+                },
+            ),
+            ports: self.transfer_ports,
+            opts: self.socket_opts,
+            socket_hook: self.socket_hook,
+            transport: self.transport,
         };
 
-        let local_ip = socket.as_ref().local_addr()?.ip();
+        let shards = match self.scheduling {
+            Scheduling::Shared => None,
+            Scheduling::Sharded {
+                executors,
+                pin_to_cores,
+            } => Some(Shards::new(executors, pin_to_cores)),
+        };
+        let shard_count = shards.as_ref().map_or(1, Shards::len);
+
+        let mut handlers = Vec::new();
+        if let Some(clone) = self.shard_handlers {
+            for _ in 1..shard_count {
+                handlers.push(Arc::new(HandlerLock::new(clone(&self.handle))));
+            }
+        }
+        handlers.insert(0, Arc::new(HandlerLock::new(self.handle)));
+
         Ok(TftpServer {
             socket,
-            handler: Arc::new(Mutex::new(self.handle)),
-            reqs_in_progress: Arc::new(Mutex::new(HashSet::new())),
-            ex: Executor::new(),
+            handlers,
+            reqs: (0..shard_count)
+                .map(|_| Arc::new(Mutex::new(HashMap::new())))
+                .collect(),
+            ex: Arc::new(Executor::new()),
+            running: Arc::new(RwLock::new(())),
+            shards,
+            busy_reply: busy_reply(config.error_messages.as_deref()),
+            refused: RefusedRecords::new(),
             config,
-            local_ip,
+            transfer_addr,
+            resumed: self.resumed,
         })
     }
 }
+
+impl<H: Handler + Clone> TftpServerBuilder<H> {
+    /// Give every shard of [`Scheduling::Sharded`] its own clone of the
+    /// handler.
+    ///
+    /// The server locks the handler while it opens a file, so requests of
+    /// all shards wait for each other there. With a clone per shard, they
+    /// wait only for the requests of their own shard. Clones of
+    /// [`DirHandler`] share their caches.
+    ///
+    /// [`DirHandler`]: super::handlers::DirHandler
+    ///
+    /// **Default:** All shards share one handler
+    pub fn shard_handlers(self) -> Self {
+        TftpServerBuilder {
+            shard_handlers: Some(H::clone),
+            ..self
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use async_lock::Semaphore;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Token that cancels transfers.
+///
+/// A token of the server is set with
+/// [`TftpServerBuilder::cancellation_token`] and a token of a single
+/// transfer with [`TransferParams::cancellation_token`]. Cancelled transfers
+/// are terminated with an error and reported as
+/// [`TransferOutcome::Cancelled`].
+///
+/// Cloned values refer to the same token.
+///
+/// [`TftpServerBuilder::cancellation_token`]: super::TftpServerBuilder::cancellation_token
+/// [`TransferParams::cancellation_token`]: super::TransferParams::cancellation_token
+/// [`TransferOutcome::Cancelled`]: super::TransferOutcome::Cancelled
+#[derive(Clone)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    cancelled: AtomicBool,
+    // No permits until the token is cancelled
+    wake: Semaphore,
+    children: Mutex<Vec<Weak<Inner>>>,
+}
+
+impl CancellationToken {
+    /// Create a new token.
+    pub fn new() -> Self {
+        CancellationToken {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                wake: Semaphore::new(0),
+                children: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Create a token that is cancelled when this one is cancelled.
+    ///
+    /// Cancelling the child does not cancel this token.
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        self.add_child(&child);
+        child
+    }
+
+    /// Cancel the token and its children.
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    /// Whether the token is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Resolves when the token is cancelled.
+    pub async fn cancelled(&self) {
+        if self.is_cancelled() {
+            return;
+        }
+
+        // The permit is returned on drop, so every waiter gets it
+        self.inner.wake.acquire().await;
+    }
+
+    /// Cancel `child` when this token is cancelled.
+    pub(crate) fn add_child(&self, child: &CancellationToken) {
+        let mut children = self.inner.children.lock().unwrap();
+
+        if self.is_cancelled() {
+            drop(children);
+            child.cancel();
+            return;
+        }
+
+        children.retain(|child| child.strong_count() > 0);
+        children.push(Arc::downgrade(&child.inner));
+    }
+}
+
+impl Inner {
+    fn cancel(&self) {
+        let children = {
+            let mut children = self.children.lock().unwrap();
+
+            if self.cancelled.swap(true, Ordering::AcqRel) {
+                return;
+            }
+
+            std::mem::take(&mut *children)
+        };
+
+        self.wake.add_permits(1);
+
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        CancellationToken::new()
+    }
+}
+
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl Eq for CancellationToken {}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Files that are counted before the least recently requested one is
+/// forgotten.
+pub(crate) const MAX_ENTRIES: usize = 4096;
+
+/// Shards of the counts by client IP and filename, so concurrent requests
+/// rarely contend for the same lock.
+const SHARDS: usize = 16;
+
+/// Files that are counted by each shard.
+const MAX_SHARD_ENTRIES: usize = MAX_ENTRIES / SHARDS;
+
+/// Counts the read requests of every file by every client IP.
+pub(crate) struct RequestCounter {
+    window: Duration,
+    shards: Vec<Mutex<Shard>>,
+}
+
+struct Shard {
+    counts: HashMap<(IpAddr, Vec<u8>), Count>,
+    last_prune: Instant,
+}
+
+struct Count {
+    count: u32,
+    last: Instant,
+}
+
+impl RequestCounter {
+    pub(crate) fn new(window: Duration) -> Self {
+        let now = Instant::now();
+        let shards = (0..SHARDS)
+            .map(|_| {
+                Mutex::new(Shard {
+                    counts: HashMap::new(),
+                    last_prune: now,
+                })
+            })
+            .collect();
+
+        RequestCounter {
+            window,
+            shards,
+        }
+    }
+
+    /// Count a read request of `filename` from `ip` and return how many
+    /// requests of it were counted, including this one.
+    ///
+    /// The count starts over if the previous request of the same file from
+    /// the same IP is older than the window.
+    pub(crate) fn count(&self, ip: IpAddr, filename: &[u8]) -> u32 {
+        let key = (ip, filename.to_owned());
+        let mut shard = self.shard(&key).lock().unwrap();
+        let now = Instant::now();
+
+        // Expired counts are dropped at most once per window. Until then
+        // they start over when they are looked up.
+        if now.duration_since(shard.last_prune) >= self.window {
+            let window = self.window;
+            shard
+                .counts
+                .retain(|_, count| now.duration_since(count.last) <= window);
+            shard.last_prune = now;
+        }
+
+        if shard.counts.len() >= MAX_SHARD_ENTRIES
+            && !shard.counts.contains_key(&key)
+        {
+            let oldest = shard
+                .counts
+                .iter()
+                .min_by_key(|(_, count)| count.last)
+                .map(|(key, _)| key.clone());
+
+            if let Some(oldest) = oldest {
+                shard.counts.remove(&oldest);
+            }
+        }
+
+        let count = shard.counts.entry(key).or_insert(Count {
+            count: 0,
+            last: now,
+        });
+
+        if now.duration_since(count.last) > self.window {
+            count.count = 0;
+        }
+
+        count.count = count.count.saturating_add(1);
+        count.last = now;
+        count.count
+    }
+
+    /// Number of counted files.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().counts.len()).sum()
+    }
+
+    fn shard(&self, key: &(IpAddr, Vec<u8>)) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+}
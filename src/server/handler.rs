@@ -1,10 +1,217 @@
-use futures_lite::{AsyncRead, AsyncWrite};
+use futures_lite::{AsyncBufRead, AsyncRead, AsyncWrite};
+use std::any::Any;
+use std::fmt;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
+use super::{
+    BlockSource, CancellationToken, Direction, Priority, TransferOutcome,
+};
 use crate::packet;
 
+/// Parameters of a transfer after the options negotiation.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TransferContext {
+    pub client: SocketAddr,
+    /// Local address of the socket that serves the transfer. Its IP is
+    /// unspecified if the server listens on an unspecified address.
+    pub local_addr: SocketAddr,
+    pub path: PathBuf,
+    pub direction: Direction,
+    /// Options of the transfer.
+    pub options: NegotiatedOptions,
+    /// MAC address of the client, if it was found in the neighbor table.
+    ///
+    /// See [`TftpServerBuilder::lookup_client_mac`].
+    ///
+    /// [`TftpServerBuilder::lookup_client_mac`]: super::TftpServerBuilder::lookup_client_mac
+    pub client_mac: Option<MacAddr>,
+    /// How many times the client IP requested the file recently, including
+    /// this request. This is `None` for write requests, or if requests are
+    /// not counted.
+    ///
+    /// See [`TftpServerBuilder::count_requests`].
+    ///
+    /// [`TftpServerBuilder::count_requests`]: super::TftpServerBuilder::count_requests
+    pub request_count: Option<u32>,
+}
+
+/// Options of a transfer after the negotiation.
+///
+/// Unlike [`Opts`] of a request, these are the values that the transfer
+/// uses, whether the client requested the option or not.
+///
+/// [`Opts`]: crate::packet::Opts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NegotiatedOptions {
+    /// Block size (RFC2348). This is 512 if the option was not negotiated.
+    pub block_size: u16,
+    /// Retry timeout. It is the `timeout` option of the client (RFC2349)
+    /// within the limits of the server, or the timeout of the server if the
+    /// client did not request one.
+    pub timeout: Duration,
+    /// Window size (RFC7440). This is 1 for write requests, or if windows
+    /// are not negotiated.
+    ///
+    /// See [`TftpServerBuilder::max_window_size`].
+    ///
+    /// [`TftpServerBuilder::max_window_size`]: super::TftpServerBuilder::max_window_size
+    pub window_size: u16,
+    /// Size of the file, if it is known (RFC2349).
+    pub transfer_size: Option<u64>,
+}
+
+/// MAC address of a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = crate::Error;
+
+    /// Parse `aa:bb:cc:dd:ee:ff`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mac = [0u8; 6];
+        let mut parts = s.split(':');
+
+        for byte in mac.iter_mut() {
+            let part = parts.next().ok_or(crate::Error::InvalidMacAddr)?;
+            *byte = u8::from_str_radix(part, 16)
+                .map_err(|_| crate::Error::InvalidMacAddr)?;
+        }
+
+        match parts.next() {
+            Some(_) => Err(crate::Error::InvalidMacAddr),
+            None => Ok(MacAddr(mac)),
+        }
+    }
+}
+
+/// Parameters of a single transfer that override the ones of the server.
+///
+/// Fields that are `None` keep the value of the server. See
+/// [`Handler::transfer_params`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferParams {
+    /// Retry timeout. A timeout that the client negotiated takes
+    /// precedence, as with [`TftpServerBuilder::timeout`].
+    ///
+    /// [`TftpServerBuilder::timeout`]: super::TftpServerBuilder::timeout
+    pub timeout: Option<Duration>,
+    /// Timeout that is acknowledged to a client that requested the
+    /// `timeout` option (RFC2349), in place of the requested one, e.g. 1
+    /// second for small config files and 5 seconds for huge images. It is
+    /// rounded to whole seconds and kept within
+    /// [`TftpServerBuilder::min_client_timeout`] and
+    /// [`TftpServerBuilder::max_client_timeout`].
+    ///
+    /// Clients that did not request the option do not get it, because
+    /// RFC2347 forbids unrequested options, so [`timeout`](Self::timeout)
+    /// applies to them.
+    ///
+    /// [`TftpServerBuilder::min_client_timeout`]: super::TftpServerBuilder::min_client_timeout
+    /// [`TftpServerBuilder::max_client_timeout`]: super::TftpServerBuilder::max_client_timeout
+    pub client_timeout: Option<Duration>,
+    /// Maximum send retries of a block.
+    pub max_send_retries: Option<u32>,
+    /// Maximum rate of file data, in bytes per second.
+    pub max_bytes_per_sec: Option<u64>,
+    /// Size of the buffer of a write request, see
+    /// [`TftpServerBuilder::write_buffer_size`]. `0` writes every block
+    /// right away.
+    ///
+    /// [`TftpServerBuilder::write_buffer_size`]: super::TftpServerBuilder::write_buffer_size
+    pub write_buffer_size: Option<usize>,
+    /// Whether the `tsize` option is left out of the OACK, see
+    /// [`TftpServerBuilder::omit_transfer_size`].
+    ///
+    /// [`TftpServerBuilder::omit_transfer_size`]: super::TftpServerBuilder::omit_transfer_size
+    pub omit_transfer_size: Option<bool>,
+    /// Maximum block size. It can only lower the limit of
+    /// [`TftpServerBuilder::max_block_size`].
+    ///
+    /// [`TftpServerBuilder::max_block_size`]: super::TftpServerBuilder::max_block_size
+    pub max_block_size: Option<u16>,
+    /// Token that cancels the transfer, in addition to the token of
+    /// [`TftpServerBuilder::cancellation_token`].
+    ///
+    /// [`TftpServerBuilder::cancellation_token`]: super::TftpServerBuilder::cancellation_token
+    pub cancellation_token: Option<CancellationToken>,
+}
+
+/// Opaque state of a single transfer.
+///
+/// It is created by [`Handler::transfer_state`] and handed back to the
+/// later calls of the transfer, so the handler does not need to look it up
+/// by client address. The state is dropped when the transfer ends.
+#[derive(Default)]
+pub struct TransferState {
+    value: Option<Box<dyn Any + Send>>,
+}
+
+impl TransferState {
+    /// Create state that holds `value`.
+    pub fn new<T>(value: T) -> Self
+    where
+        T: Any + Send,
+    {
+        TransferState {
+            value: Some(Box::new(value)),
+        }
+    }
+
+    /// Whether the state holds no value.
+    pub fn is_empty(&self) -> bool {
+        self.value.is_none()
+    }
+
+    /// Value of the state, if it is a `T`.
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.value.as_ref()?.downcast_ref()
+    }
+
+    /// Mutable value of the state, if it is a `T`.
+    pub fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.value.as_mut()?.downcast_mut()
+    }
+
+    /// Take the value out of the state, if it is a `T`.
+    pub fn take<T: Any>(&mut self) -> Option<T> {
+        match self.value.take()?.downcast() {
+            Ok(value) => Some(*value),
+            Err(value) => {
+                self.value = Some(value);
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Debug for TransferState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TransferState")
+            .field("empty", &self.is_empty())
+            .finish()
+    }
+}
+
 /// Trait for implementing advance handlers.
+///
+/// The server calls the methods of the handler one at a time, so state
+/// behind `&mut self` needs no locking. To share a handler with the rest of
+/// the application, implement [`SharedHandler`] and pass an `Arc` of it,
+/// which is called from concurrent transfers in parallel.
+///
+/// [`SharedHandler`]: super::SharedHandler
 #[crate::async_trait]
 pub trait Handler: Send {
     type Reader: AsyncRead + Unpin + Send + 'static;
@@ -17,11 +224,250 @@ pub trait Handler: Send {
         path: &Path,
     ) -> Result<(Self::Reader, Option<u64>), packet::Error>;
 
+    /// Open `Reader` to serve a read request that was counted.
+    ///
+    /// This is called instead of [`read_req_open`](Self::read_req_open)
+    /// when requests are counted with [`count_requests`]. `count` is how
+    /// many times the IP of `client` requested `path` recently, including
+    /// this request, so a fallback can be served to a client that keeps
+    /// retrying, e.g. a PXE client that fails to boot. By default
+    /// [`read_req_open`](Self::read_req_open) is called.
+    ///
+    /// [`count_requests`]: super::TftpServerBuilder::count_requests
+    async fn read_req_open_counted(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        _count: u32,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.read_req_open(client, path).await
+    }
+
+    /// Open `Reader` to serve a read request, together with the state of
+    /// its transfer.
+    ///
+    /// `count` is the number of recent requests of [`count_requests`], if
+    /// requests are counted. Override it to create the state of the
+    /// transfer while the file is opened, e.g. to keep its metadata, instead
+    /// of in [`transfer_state`](Self::transfer_state). By default
+    /// [`read_req_open_counted`](Self::read_req_open_counted) or
+    /// [`read_req_open`](Self::read_req_open) is called, followed by
+    /// [`transfer_state`](Self::transfer_state).
+    ///
+    /// [`count_requests`]: super::TftpServerBuilder::count_requests
+    async fn read_req_open_with_state(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        count: Option<u32>,
+    ) -> Result<(Self::Reader, Option<u64>, TransferState), packet::Error> {
+        let (reader, size) = match count {
+            Some(count) => {
+                self.read_req_open_counted(client, path, count).await
+            }
+            None => self.read_req_open(client, path).await,
+        }?;
+        let state = self.transfer_state(client, path, Direction::Read).await;
+
+        Ok((reader, size, state))
+    }
+
+    /// Open [`BlockSource`] to serve a read request.
+    ///
+    /// This is tried before [`read_req_open`](Self::read_req_open), which is
+    /// used if `None` is returned. By default `None` is returned.
+    async fn read_req_open_blocks(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<Option<(Box<dyn BlockSource>, Option<u64>)>, packet::Error>
+    {
+        Ok(None)
+    }
+
+    /// Access `reader` as `AsyncBufRead`.
+    ///
+    /// If `Some` is returned, full blocks are sent directly from the buffer
+    /// of the reader, which saves a copy per block. Override it if
+    /// `Reader` implements `AsyncBufRead`:
+    ///
+    /// ```ignore
+    /// fn buf_reader(
+    ///     reader: &mut Self::Reader,
+    /// ) -> Option<&mut (dyn AsyncBufRead + Unpin + Send)> {
+    ///     Some(reader)
+    /// }
+    /// ```
+    ///
+    /// By default `None` is returned.
+    fn buf_reader(
+        _reader: &mut Self::Reader,
+    ) -> Option<&mut (dyn AsyncBufRead + Unpin + Send)> {
+        None
+    }
+
     /// Open `Writer` to serve a write request.
+    ///
+    /// `size` is the `tsize` option of the client (RFC2349), which can be
+    /// used to preallocate the file or to reject it if it does not fit.
     async fn write_req_open(
         &mut self,
         client: &SocketAddr,
         path: &Path,
         size: Option<u64>,
     ) -> Result<Self::Writer, packet::Error>;
+
+    /// Open `Writer` to serve a write request, together with the state of
+    /// its transfer.
+    ///
+    /// See [`read_req_open_with_state`](Self::read_req_open_with_state). By
+    /// default [`write_req_open`](Self::write_req_open) is called, followed
+    /// by [`transfer_state`](Self::transfer_state).
+    async fn write_req_open_with_state(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<(Self::Writer, TransferState), packet::Error> {
+        let writer = self.write_req_open(client, path, size).await?;
+        let state = self.transfer_state(client, path, Direction::Write).await;
+
+        Ok((writer, state))
+    }
+
+    /// Notification that a write request failed.
+    ///
+    /// This is called whenever an opened upload does not complete, e.g. on
+    /// timeout, an error of the client, a panic of another hook or a
+    /// restart without options, and `writer` holds the data that was
+    /// received so far. By default it is dropped.
+    async fn write_req_failed(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _writer: Self::Writer,
+        _state: &mut TransferState,
+    ) {
+    }
+
+    /// Priority of a transfer.
+    ///
+    /// This is called before the file is opened and is taken into account
+    /// only when [`max_concurrent_transfers`] is reached.
+    ///
+    /// [`max_concurrent_transfers`]: super::TftpServerBuilder::max_concurrent_transfers
+    async fn transfer_priority(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+    ) -> Priority {
+        Priority::Normal
+    }
+
+    /// Parameters of a transfer.
+    ///
+    /// This is called after the file is opened, so the parameters can
+    /// depend on it, e.g. tiny config files can get aggressive timeouts and
+    /// huge images relaxed ones. By default the parameters of the server
+    /// are used.
+    async fn transfer_params(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+        _state: &mut TransferState,
+    ) -> TransferParams {
+        TransferParams::default()
+    }
+
+    /// State of a transfer whose file was just opened.
+    ///
+    /// This is called right after the file is opened, before any other call
+    /// of the handler, unless the state is returned by
+    /// [`read_req_open_with_state`](Self::read_req_open_with_state) or
+    /// [`write_req_open_with_state`](Self::write_req_open_with_state). It is
+    /// handed to [`transfer_params`](Self::transfer_params),
+    /// [`transfer_started`](Self::transfer_started),
+    /// [`write_req_failed`](Self::write_req_failed) and
+    /// [`transfer_finished`](Self::transfer_finished), and dropped when the
+    /// transfer ends. By default it is empty.
+    async fn transfer_state(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+    ) -> TransferState {
+        TransferState::default()
+    }
+
+    /// Notification that the transfer started.
+    ///
+    /// This is called when the client replies for the first time, so the
+    /// options of `ctx` are final.
+    async fn transfer_started(
+        &mut self,
+        _ctx: &TransferContext,
+        _state: &mut TransferState,
+    ) {
+    }
+
+    /// Notification that the transfer finished.
+    ///
+    /// This is called for reads and writes once the transfer with the
+    /// client ends, and after [`write_req_failed`](Self::write_req_failed).
+    /// A transfer that is restarted from another port is finished as
+    /// `Cancelled`, and one whose handler panicked as `Panicked`. It is not
+    /// called for requests that fail before the transfer with the client
+    /// begins, e.g. because the file cannot be opened; `state` is only
+    /// dropped then.
+    async fn transfer_finished(
+        &mut self,
+        _ctx: &TransferContext,
+        _outcome: TransferOutcome,
+        _state: &mut TransferState,
+    ) {
+    }
+
+    /// Problems of the configuration of the handler, e.g. a served
+    /// directory that is missing or not accessible.
+    ///
+    /// This is called by [`TftpServerBuilder::validate`], so every problem
+    /// is reported at startup instead of on the first request that runs into
+    /// it. By default no problems are reported.
+    ///
+    /// [`TftpServerBuilder::validate`]: super::TftpServerBuilder::validate
+    fn validate(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Open `Writer` to serve a write request in `mail` mode.
+    ///
+    /// Filename of a mail request is the recipient. By default mail
+    /// requests are rejected. `IllegalOperation` is reported as an
+    /// unsupported transfer mode.
+    async fn mail_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _recipient: &str,
+    ) -> Result<Self::Writer, packet::Error> {
+        Err(packet::Error::IllegalOperation)
+    }
+
+    /// Copy of the handler that is called without the lock of the server.
+    ///
+    /// The server serializes calls of a handler with a lock, since methods
+    /// take `&mut self`. If `Some` is returned, every call is made on a new
+    /// copy instead, so calls of concurrent transfers run in parallel. This
+    /// is meant for handlers that share their state, e.g. [`Arc`] of a
+    /// [`SharedHandler`]. By default `None` is returned.
+    ///
+    /// [`Arc`]: std::sync::Arc
+    /// [`SharedHandler`]: super::SharedHandler
+    fn share(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
 }
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// How a file is produced from its compressed (or uncompressed) variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Codec {
+    #[cfg(feature = "gzip")]
+    GzipDecode,
+    #[cfg(feature = "gzip")]
+    GzipEncode,
+    #[cfg(feature = "zstd")]
+    ZstdDecode,
+}
+
+/// Uncompressed sizes of compressed variants, so a gzip file is
+/// decompressed to count its size only once. An entry is valid while the
+/// modification time and the length of the file stay the same.
+#[derive(Default)]
+pub(super) struct SizeCache(Mutex<HashMap<PathBuf, (SizeStamp, Option<u64>)>>);
+
+type SizeStamp = (Option<SystemTime>, u64);
+
+impl SizeCache {
+    /// Size of `file` at `path`, counted with `size` if it is not cached.
+    ///
+    /// The lock is not held while `size` runs.
+    fn get_or_count(
+        &self,
+        path: &Path,
+        file: &mut File,
+        size: fn(&mut File) -> io::Result<Option<u64>>,
+    ) -> io::Result<Option<u64>> {
+        let meta = file.metadata()?;
+        let stamp = (meta.modified().ok(), meta.len());
+
+        if let Some(&(cached, len)) = self.0.lock().unwrap().get(path) {
+            if cached == stamp {
+                return Ok(len);
+            }
+        }
+
+        let len = size(file)?;
+        self.0.lock().unwrap().insert(path.to_owned(), (stamp, len));
+
+        Ok(len)
+    }
+}
+
+/// Find a variant of `path` that exists on disk.
+pub(super) fn find_variant(
+    path: &Path,
+    decompress: bool,
+    compress: bool,
+) -> Option<(PathBuf, Codec)> {
+    let mut candidates = Vec::new();
+
+    if decompress {
+        #[cfg(feature = "gzip")]
+        candidates.push((with_extension(path, "gz"), Codec::GzipDecode));
+        #[cfg(feature = "zstd")]
+        candidates.push((with_extension(path, "zst"), Codec::ZstdDecode));
+    }
+
+    #[cfg(feature = "gzip")]
+    if compress && path.extension().is_some_and(|ext| ext == "gz") {
+        candidates.push((path.with_extension(""), Codec::GzipEncode));
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    let _ = compress;
+
+    candidates.into_iter().find(|(path, _)| path.is_file())
+}
+
+/// Open `path` and transcode it with `codec`.
+///
+/// The size of the result is returned if it is recorded in the file, or in
+/// `sizes`.
+pub(super) fn open(
+    path: &Path,
+    codec: Codec,
+    sizes: &SizeCache,
+) -> io::Result<(Box<dyn Read + Send>, Option<u64>)> {
+    let mut file = File::open(path)?;
+
+    match codec {
+        #[cfg(feature = "gzip")]
+        Codec::GzipDecode => {
+            let size = sizes.get_or_count(path, &mut file, gzip_size)?;
+            let reader =
+                flate2::read::MultiGzDecoder::new(BufReader::new(file));
+            Ok((Box::new(reader), size))
+        }
+        #[cfg(feature = "gzip")]
+        Codec::GzipEncode => {
+            let reader = flate2::read::GzEncoder::new(
+                BufReader::new(file),
+                flate2::Compression::default(),
+            );
+            Ok((Box::new(reader), None))
+        }
+        #[cfg(feature = "zstd")]
+        Codec::ZstdDecode => {
+            let size = sizes.get_or_count(path, &mut file, zstd_size)?;
+            let reader = ZstdDecoder::new(BufReader::new(file))?;
+            Ok((Box::new(reader), size))
+        }
+    }
+}
+
+fn with_extension(path: &Path, ext: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(ext);
+    path.into()
+}
+
+/// Uncompressed size of a gzip file.
+///
+/// The trailer of a gzip member holds only the size modulo 2^32 and files
+/// can hold multiple members, so the file is decompressed once to count
+/// its size. This also rejects corrupted files before the transfer starts.
+#[cfg(feature = "gzip")]
+fn gzip_size(file: &mut File) -> io::Result<Option<u64>> {
+    let mut decoder = flate2::read::MultiGzDecoder::new(BufReader::new(&*file));
+    let size = io::copy(&mut decoder, &mut io::sink())?;
+    file.seek(SeekFrom::Start(0))?;
+
+    Ok(Some(size))
+}
+
+/// Uncompressed size from the header of the first zstd frame.
+///
+/// A file can hold multiple frames, so the size is trusted only if the
+/// frame covers the whole file.
+#[cfg(feature = "zstd")]
+fn zstd_size(file: &mut File) -> io::Result<Option<u64>> {
+    let len = file.metadata()?.len();
+    let size = zstd_frame_size(&mut BufReader::new(&mut *file), len);
+    file.seek(SeekFrom::Start(0))?;
+
+    // Invalid files are reported by the decoder
+    Ok(size.ok().flatten())
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_frame_size(
+    reader: &mut BufReader<&mut File>,
+    file_len: u64,
+) -> io::Result<Option<u64>> {
+    let (frame, header_len) = ruzstd::frame::read_frame_header(&mut *reader)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let descriptor = &frame.header.descriptor;
+    let has_size = descriptor.frame_content_size_flag() != 0
+        || descriptor.single_segment_flag();
+
+    // Walk over the blocks to find where the frame ends
+    let mut frame_len = u64::from(header_len);
+
+    loop {
+        let mut head = [0u8; 3];
+        reader.read_exact(&mut head)?;
+
+        let head = u32::from_le_bytes([head[0], head[1], head[2], 0]);
+        let is_last = head & 1 == 1;
+        let is_rle = (head >> 1) & 3 == 1;
+
+        // RLE blocks hold a single byte
+        let content_len = if is_rle {
+            1
+        } else {
+            head >> 3
+        };
+
+        reader.seek_relative(i64::from(content_len))?;
+        frame_len += 3 + u64::from(content_len);
+
+        if is_last {
+            break;
+        }
+    }
+
+    if descriptor.content_checksum_flag() {
+        frame_len += 4;
+    }
+
+    if has_size && frame_len == file_len {
+        Ok(Some(frame.header.frame_content_size()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Decoder of zstd files that can hold multiple frames.
+#[cfg(feature = "zstd")]
+struct ZstdDecoder<R> {
+    source: R,
+    decoder: ruzstd::FrameDecoder,
+}
+
+#[cfg(feature = "zstd")]
+impl<R: io::BufRead> ZstdDecoder<R> {
+    fn new(mut source: R) -> io::Result<Self> {
+        let mut decoder = ruzstd::FrameDecoder::new();
+        decoder.init(&mut source).map_err(invalid_data)?;
+        Ok(ZstdDecoder {
+            source,
+            decoder,
+        })
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<R: io::BufRead> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use ruzstd::BlockDecodingStrategy;
+
+        loop {
+            while self.decoder.can_collect() < buf.len()
+                && !self.decoder.is_finished()
+            {
+                let needed = buf.len() - self.decoder.can_collect();
+
+                self.decoder
+                    .decode_blocks(
+                        &mut self.source,
+                        BlockDecodingStrategy::UptoBytes(needed),
+                    )
+                    .map_err(invalid_data)?;
+            }
+
+            let len = self.decoder.read(buf)?;
+
+            if len > 0 || buf.is_empty() {
+                return Ok(len);
+            }
+
+            // Frame is finished, continue with the next one
+            if self.source.fill_buf()?.is_empty() {
+                return Ok(0);
+            }
+
+            self.decoder.reset(&mut self.source).map_err(invalid_data)?;
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
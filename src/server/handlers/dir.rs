@@ -1,19 +1,125 @@
+use async_lock::{MutexGuardArc, OnceCell};
 use blocking::{unblock, Unblock};
-use log::trace;
+use bytes::Bytes;
+use futures_lite::io::{AsyncRead, AsyncWrite, AsyncWriteExt, Cursor};
+use futures_lite::ready;
+use log::{trace, warn};
+use std::cmp;
+use std::collections::HashMap;
 use std::fs::{self, File};
+use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
 use std::path::Component;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use super::compressed::{self, find_variant, Codec};
 use crate::error::{Error, Result};
 use crate::packet;
+use crate::utils::path_to_bytes;
 
 /// Handler that serves read requests for a directory.
+///
+/// Clones of the handler share the cache of preloaded files.
+#[derive(Clone)]
 pub struct DirHandler {
     dir: PathBuf,
     serve_rrq: bool,
     serve_wrq: bool,
+    backslash_separator: bool,
+    byte_ranges: bool,
+    // Biggest file that is shared, if reads are shared
+    share_reads: Option<u64>,
+    dir_listing: Option<PathBuf>,
+    min_free_space: u64,
+    partial_uploads: PartialUpload,
+    append_uploads: Vec<String>,
+    appending: Arc<AppendLocks>,
+    upload_path: Option<Arc<UploadPathFn>>,
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    decompress_variants: bool,
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    compress_variants: bool,
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    variant_sizes: Arc<compressed::SizeCache>,
+    cache: Arc<Mutex<HashMap<PathBuf, CachedFile>>>,
+    revalidate_cache: Option<Duration>,
+    shared: Arc<SharedFiles>,
+    // Watching stops when the last clone is dropped
+    #[cfg(feature = "watch")]
+    _watcher: Option<Arc<notify::RecommendedWatcher>>,
+}
+
+/// Reader of [`DirHandler`].
+pub struct DirReader(ReaderKind);
+
+/// Writer of [`DirHandler`].
+pub struct DirWriter {
+    file: Unblock<File>,
+    // Where the upload is stored, after `upload_path`
+    path: PathBuf,
+    // Uploads that append to the same file run one after another
+    append: Option<AppendLock>,
+}
+
+enum AppendLock {
+    Waiting(Pin<Box<dyn Future<Output = MutexGuardArc<()>> + Send>>),
+    Held {
+        _guard: MutexGuardArc<()>,
+    },
+}
+
+type UploadPathFn =
+    dyn Fn(&SocketAddr, &Path) -> Result<PathBuf, packet::Error> + Send + Sync;
+
+enum ReaderKind {
+    File(Unblock<File>),
+    Range(Unblock<io::Take<File>>),
+    Memory(Cursor<Bytes>),
+    Shared {
+        // Keeps the file shared until the transfer finishes
+        _file: Arc<SharedFile>,
+        content: Cursor<Bytes>,
+    },
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    Transcoded(Unblock<Box<dyn io::Read + Send>>),
+}
+
+/// Preloaded file.
+#[derive(Clone)]
+struct CachedFile {
+    content: Bytes,
+    // File that the content was read from, e.g. a compressed variant
+    source: PathBuf,
+    stamp: FileStamp,
+    // When the source was compared with `stamp` for the last time
+    checked: Instant,
+}
+
+/// Modification time and size of a file, which change with its content.
+#[derive(Clone, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+/// What [`DirHandler`] does with the file of a failed upload.
+#[derive(Clone)]
+pub enum PartialUpload {
+    /// Keep the file as it is.
+    Keep,
+    /// Delete the file.
+    Delete,
+    /// Rename the file to `<name>.part`.
+    RenamePart,
+    /// Call a function with the path of the file. It runs on a blocking
+    /// thread, so it can access the file system.
+    Callback(Arc<dyn Fn(&Path) + Send + Sync>),
 }
 
 pub enum DirHandlerMode {
@@ -55,14 +161,269 @@ impl DirHandler {
             dir,
             serve_rrq,
             serve_wrq,
+            backslash_separator: false,
+            byte_ranges: false,
+            share_reads: None,
+            dir_listing: None,
+            min_free_space: 0,
+            partial_uploads: PartialUpload::Keep,
+            append_uploads: Vec::new(),
+            appending: Arc::default(),
+            upload_path: None,
+            #[cfg(any(feature = "gzip", feature = "zstd"))]
+            decompress_variants: false,
+            #[cfg(any(feature = "gzip", feature = "zstd"))]
+            compress_variants: false,
+            #[cfg(any(feature = "gzip", feature = "zstd"))]
+            variant_sizes: Arc::default(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            revalidate_cache: None,
+            shared: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(feature = "watch")]
+            _watcher: None,
+        })
+    }
+
+    /// Treat `\` in requested paths as a path separator.
+    ///
+    /// Windows clients (e.g. Windows Deployment Services) request files such
+    /// as `boot\bcd`. With this option such requests are resolved in the same
+    /// way on every host OS.
+    pub fn backslash_separator(self) -> Self {
+        DirHandler {
+            backslash_separator: true,
+            ..self
+        }
+    }
+
+    /// Serve slices of files that are requested as `file?off=N&len=M`.
+    ///
+    /// This is useful for bootloaders that fetch pieces of a large image in
+    /// stages. Both parameters are optional, so `file?off=N` is served until
+    /// the end of the file and `file?len=M` from its start. Requests that do
+    /// not match the convention, or that name an existing file, are served as
+    /// usual.
+    pub fn byte_ranges(self) -> Self {
+        DirHandler {
+            byte_ranges: true,
+            ..self
+        }
+    }
+
+    /// Share a file between transfers that read it at the same time.
+    ///
+    /// The file is opened and read in memory once, and all concurrent
+    /// transfers are served from the same copy. The copy is dropped when
+    /// the last of them finishes. This avoids opening the same file hundreds
+    /// of times when many clients boot at once.
+    ///
+    /// Transfers that start while others are running get the content that
+    /// the first one read, even if the file changed in the meantime. Write
+    /// requests for the file end the sharing for new transfers.
+    ///
+    /// Only files of up to `max_size` bytes are kept in memory. Bigger
+    /// files are read from disk by every transfer.
+    pub fn share_reads(self, max_size: u64) -> Self {
+        DirHandler {
+            share_reads: Some(max_size),
+            ..self
+        }
+    }
+
+    /// Serve a listing of the directory when `name` is requested.
+    ///
+    /// Some recovery tools request a file such as `.dirlist` to discover
+    /// the available images. The listing is plain text with the name of
+    /// every regular file in the root of the directory, one per line and
+    /// sorted. Subdirectories are not listed. A file called `name` is
+    /// never served.
+    pub fn dir_listing<P>(self, name: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        DirHandler {
+            dir_listing: Some(name.into()),
+            ..self
+        }
+    }
+
+    /// Serve missing files from their compressed variant.
+    ///
+    /// If `file.img` is requested but only `file.img.gz` (with `gzip`
+    /// feature) or `file.img.zst` (with `zstd` feature) exists, it is
+    /// decompressed on the fly. The size of gzip variants is counted before
+    /// the first transfer, and again when the file changes. The size of zstd variants is reported to the
+    /// client only if it is recorded in a single frame.
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    pub fn decompress_variants(self) -> Self {
+        DirHandler {
+            decompress_variants: true,
+            ..self
+        }
+    }
+
+    /// Keep `bytes` of the file system free.
+    ///
+    /// Write requests that announce their size with `tsize` (RFC2349) are
+    /// rejected with `DiskFull` before the file is created if they would
+    /// leave less free space. Uploads without `tsize` are not checked.
+    pub fn min_free_space(self, bytes: u64) -> Self {
+        DirHandler {
+            min_free_space: bytes,
+            ..self
+        }
+    }
+
+    /// Set what is done with the file of an upload that fails.
+    ///
+    /// **Default:** [`PartialUpload::Keep`]
+    pub fn partial_uploads(self, policy: PartialUpload) -> Self {
+        DirHandler {
+            partial_uploads: policy,
+            ..self
+        }
+    }
+
+    /// Append uploads to files that match any of `patterns`.
+    ///
+    /// This is useful for devices that upload their logs to the same
+    /// filename again and again. Patterns are matched against the path
+    /// relative to the directory, where `*` matches any sequence of
+    /// characters and `?` a single character, e.g. `logs/*.log`.
+    ///
+    /// Appended files are not preallocated, and
+    /// [`partial_uploads`](Self::partial_uploads) is not applied to them,
+    /// because they hold data of earlier uploads. Concurrent uploads to the
+    /// same file run one after another, so their data is not interleaved:
+    /// an upload is acknowledged only after the previous one finished.
+    pub fn append_uploads<I, S>(self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        DirHandler {
+            append_uploads: patterns.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+
+    /// Store uploads under the path that `f` returns.
+    ///
+    /// `f` gets the client and the requested filename, and returns a path
+    /// relative to the directory, e.g. prefixed with the IP of the client
+    /// so devices that all upload `config.txt` do not clobber each other.
+    /// It can also reject the upload with an error. The returned path is
+    /// checked in the same way as requested filenames.
+    pub fn upload_path<F>(self, f: F) -> Self
+    where
+        F: Fn(&SocketAddr, &Path) -> Result<PathBuf, packet::Error>
+            + Send
+            + Sync
+            + 'static,
+    {
+        DirHandler {
+            upload_path: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Serve missing `.gz` files from their uncompressed variant.
+    ///
+    /// If `file.img.gz` is requested but only `file.img` exists, it is
+    /// compressed on the fly. The size of the result is not known in
+    /// advance, so it is never reported to the client.
+    #[cfg(feature = "gzip")]
+    pub fn compress_variants(self) -> Self {
+        DirHandler {
+            compress_variants: true,
+            ..self
+        }
+    }
+}
+
+impl DirHandler {
+    /// Load files in memory, so they are served without accessing the disk.
+    ///
+    /// Use this to warm up before a boot storm. `paths` are resolved in the
+    /// same way as requested filenames. Files stay in memory until they are
+    /// replaced by a write request or [`clear_cache`] is called.
+    ///
+    /// The server takes ownership of the handler, so keep a clone of it to
+    /// preload files while the server is running.
+    ///
+    /// [`clear_cache`]: Self::clear_cache
+    pub async fn preload<I, P>(&self, paths: I) -> Result<()>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        for path in paths {
+            let path =
+                self.secure_path(path.as_ref()).map_err(Error::Packet)?;
+            let file = self.load(&path).await?;
+
+            trace!("TFTP preloaded file: {}", path.display());
+            self.cache.lock().unwrap().insert(path, file);
+        }
+
+        Ok(())
+    }
+
+    /// Check preloaded files against the disk at most once per `interval`.
+    ///
+    /// When a file is requested and its modification time or size changed
+    /// since it was loaded, it is loaded again, and it is dropped from
+    /// memory if it is gone. Requests that arrive while a file is checked
+    /// get the old content, so a boot storm causes one `stat` per file and
+    /// interval. Unlike `watch` of the `watch` feature, this works on file
+    /// systems without change notifications, such as NFS.
+    ///
+    /// **Default:** Preloaded files are never checked
+    pub fn revalidate_cache(self, interval: Duration) -> Self {
+        DirHandler {
+            revalidate_cache: Some(interval),
+            ..self
+        }
+    }
+
+    /// Drop all preloaded files.
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Watch the directory and drop preloaded files as soon as they are
+    /// changed on disk, so they are never served stale.
+    ///
+    /// This uses inotify on Linux, FSEvents on macOS and the equivalent
+    /// mechanism of the other platforms.
+    #[cfg(feature = "watch")]
+    pub fn watch(self) -> Result<Self> {
+        use notify::{RecursiveMode, Watcher};
+
+        let cache = Arc::clone(&self.cache);
+
+        let mut watcher = notify::recommended_watcher(
+            move |event: notify::Result<notify::Event>| {
+                invalidate_cache(&cache, event)
+            },
+        )
+        .map_err(|e| Error::Watch(e.to_string()))?;
+
+        watcher
+            .watch(&self.dir, RecursiveMode::Recursive)
+            .map_err(|e| Error::Watch(e.to_string()))?;
+
+        Ok(DirHandler {
+            _watcher: Some(Arc::new(watcher)),
+            ..self
         })
     }
 }
 
 #[crate::async_trait]
 impl crate::server::Handler for DirHandler {
-    type Reader = Unblock<File>;
-    type Writer = Unblock<File>;
+    type Reader = DirReader;
+    type Writer = DirWriter;
 
     async fn read_req_open(
         &mut self,
@@ -73,25 +434,77 @@ impl crate::server::Handler for DirHandler {
             return Err(packet::Error::IllegalOperation);
         }
 
-        let path = secure_path(&self.dir, path)?;
+        let path = self.secure_path(path)?;
+
+        if self.is_dir_listing(&path) {
+            let dir = self.dir.clone();
+            let listing = unblock(move || list_dir(&dir)).await?;
+            let len = listing.len() as u64;
+
+            trace!("TFTP sending directory listing: {}", path.display());
+
+            let reader = ReaderKind::Memory(Cursor::new(Bytes::from(listing)));
+            return Ok((DirReader(reader), Some(len)));
+        }
+
+        if let Some((path, range)) = self.split_byte_range(&path) {
+            return self.read_byte_range(path, range).await;
+        }
+
+        if let Some(content) = self.cached(&path).await {
+            trace!("TFTP sending preloaded file: {}", path.display());
+
+            let len = content.len() as u64;
+            let reader = ReaderKind::Memory(Cursor::new(content));
+            return Ok((DirReader(reader), Some(len)));
+        }
+
+        #[cfg(any(feature = "gzip", feature = "zstd"))]
+        if let Some((variant, codec)) = self.find_variant(&path) {
+            let sizes = Arc::clone(&self.variant_sizes);
+            let (reader, len) =
+                unblock(move || compressed::open(&variant, codec, &sizes))
+                    .await?;
+            let reader = ReaderKind::Transcoded(Unblock::new(reader));
+
+            trace!("TFTP sending transcoded file: {}", path.display());
+
+            return Ok((DirReader(reader), len));
+        }
 
         // Send only regular files
         if !path.is_file() {
             return Err(packet::Error::FileNotFound);
         }
 
+        if let Some(max_size) = self.share_reads {
+            let file = SharedFile::get(&self.shared, &path);
+
+            if let Some(content) = file.content(max_size).await? {
+                let len = content.len() as u64;
+
+                trace!("TFTP sending shared file: {}", path.display());
+
+                let reader = ReaderKind::Shared {
+                    _file: file,
+                    content: Cursor::new(content),
+                };
+                return Ok((DirReader(reader), Some(len)));
+            }
+        }
+
         let path_clone = path.clone();
         let (file, len) = unblock(move || open_file_ro(path_clone)).await?;
-        let reader = Unblock::new(file);
+        let reader = ReaderKind::File(Unblock::new(file));
 
         trace!("TFTP sending file: {}", path.display());
 
-        Ok((reader, len))
+        Ok((DirReader(reader), len))
     }
 
     async fn write_req_open(
         &mut self,
-        _client: &SocketAddr,
+        client: &SocketAddr,
         path: &Path,
         size: Option<u64>,
     ) -> Result<Self::Writer, packet::Error> {
@@ -99,15 +512,388 @@ impl crate::server::Handler for DirHandler {
             return Err(packet::Error::IllegalOperation);
         }
 
-        let path = secure_path(&self.dir, path)?;
+        let path = match self.upload_path {
+            Some(ref upload_path) => {
+                self.secure_path(&upload_path(client, path)?)?
+            }
+            None => self.secure_path(path)?,
+        };
+
+        // Preloaded content is replaced
+        self.cache.lock().unwrap().remove(&path);
+        // New transfers must not get the old content
+        self.shared.lock().unwrap().remove(&path);
 
+        // Reject uploads that can not fit before truncating the file
+        if let Some(size) = size {
+            let dir = self.dir.clone();
+
+            let available = unblock(move || available_space(&dir)).await?;
+
+            if size.saturating_add(self.min_free_space) > available {
+                return Err(packet::Error::DiskFull);
+            }
+        }
+
+        let append = self.is_append(&path);
         let path_clone = path.clone();
-        let file = unblock(move || open_file_wo(path_clone, size)).await?;
-        let writer = Unblock::new(file);
+        let file =
+            unblock(move || open_file_wo(path_clone, size, append)).await?;
 
         trace!("TFTP receiving file: {}", path.display());
 
-        Ok(writer)
+        // Waiting here would hold the handler lock, the writer waits when
+        // it is flushed before the transfer starts
+        let append = append.then(|| {
+            let lock = append_lock(&self.appending, &path);
+            AppendLock::Waiting(Box::pin(async move { lock.lock_arc().await }))
+        });
+
+        Ok(DirWriter {
+            file: Unblock::new(file),
+            path,
+            append,
+        })
+    }
+
+    async fn write_req_failed(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        mut writer: Self::Writer,
+        _state: &mut crate::server::TransferState,
+    ) {
+        // Pending writes must finish before the file is moved
+        let _ = writer.close().await;
+        let path = writer.path;
+
+        if self.is_append(&path) {
+            return;
+        }
+
+        trace!("TFTP upload failed: {}", path.display());
+
+        let res = match self.partial_uploads.clone() {
+            PartialUpload::Keep => Ok(()),
+            PartialUpload::Delete => {
+                unblock(move || fs::remove_file(path)).await
+            }
+            PartialUpload::RenamePart => {
+                unblock(move || fs::rename(&path, part_path(&path))).await
+            }
+            PartialUpload::Callback(f) => {
+                unblock(move || f(&path)).await;
+                Ok(())
+            }
+        };
+
+        if let Err(e) = res {
+            warn!("Cleaning up partial upload failed: {}", e);
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let dir = self.dir.display();
+
+        match fs::metadata(&self.dir) {
+            Ok(meta) if meta.is_dir() => {}
+            Ok(_) => problems.push(format!("'{}' is not a directory", dir)),
+            Err(e) => {
+                problems.push(format!("'{}' is not accessible: {}", dir, e))
+            }
+        }
+
+        if self.serve_rrq {
+            if let Err(e) = fs::read_dir(&self.dir) {
+                problems.push(format!("'{}' is not readable: {}", dir, e));
+            }
+        }
+
+        if self.serve_wrq {
+            if let Err(e) = check_writable(&self.dir) {
+                problems.push(format!("'{}' is not writable: {}", dir, e));
+            }
+        }
+
+        if !self.serve_wrq {
+            if !self.append_uploads.is_empty() {
+                problems.push(
+                    "append_uploads is set, but uploads are not served"
+                        .to_string(),
+                );
+            }
+
+            if self.upload_path.is_some() {
+                problems.push(
+                    "upload_path is set, but uploads are not served"
+                        .to_string(),
+                );
+            }
+
+            if self.min_free_space > 0 {
+                problems.push(
+                    "min_free_space is set, but uploads are not served"
+                        .to_string(),
+                );
+            }
+        }
+
+        if let Some(ref name) = self.dir_listing {
+            if !self.serve_rrq {
+                problems.push(
+                    "dir_listing is set, but reads are not served".to_string(),
+                );
+            }
+
+            if self.secure_path(name).is_err() {
+                problems.push(format!(
+                    "dir_listing name '{}' can not be requested",
+                    name.display()
+                ));
+            }
+        }
+
+        problems
+    }
+}
+
+impl AsyncRead for DirReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.0 {
+            ReaderKind::File(ref mut file) => Pin::new(file).poll_read(cx, buf),
+            ReaderKind::Range(ref mut file) => {
+                Pin::new(file).poll_read(cx, buf)
+            }
+            ReaderKind::Memory(ref mut mem) => Pin::new(mem).poll_read(cx, buf),
+            ReaderKind::Shared {
+                ref mut content,
+                ..
+            } => Pin::new(content).poll_read(cx, buf),
+            #[cfg(any(feature = "gzip", feature = "zstd"))]
+            ReaderKind::Transcoded(ref mut reader) => {
+                Pin::new(reader).poll_read(cx, buf)
+            }
+        }
+    }
+}
+
+impl DirWriter {
+    /// Path where the upload is stored.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Wait until no other upload appends to the file.
+    fn poll_append_lock(&mut self, cx: &mut Context) -> Poll<()> {
+        if let Some(AppendLock::Waiting(ref mut lock)) = self.append {
+            let guard = ready!(lock.as_mut().poll(cx));
+            self.append = Some(AppendLock::Held {
+                _guard: guard,
+            });
+        }
+
+        Poll::Ready(())
+    }
+}
+
+impl AsyncWrite for DirWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.poll_append_lock(cx));
+        Pin::new(&mut self.file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        ready!(self.poll_append_lock(cx));
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        ready!(self.poll_append_lock(cx));
+        Pin::new(&mut self.file).poll_close(cx)
+    }
+}
+
+impl DirHandler {
+    /// File and byte range of `path`, if `path` does not exist and it follows
+    /// the convention of [`byte_ranges`].
+    ///
+    /// [`byte_ranges`]: Self::byte_ranges
+    fn split_byte_range(&self, path: &Path) -> Option<(PathBuf, ByteRange)> {
+        if !self.byte_ranges || path.exists() {
+            return None;
+        }
+
+        let (path, query) = split_query(path)?;
+        let range = ByteRange::parse(&query)?;
+
+        Some((path, range))
+    }
+
+    async fn read_byte_range(
+        &self,
+        path: PathBuf,
+        range: ByteRange,
+    ) -> Result<(DirReader, Option<u64>), packet::Error> {
+        if let Some(content) = self.cached(&path).await {
+            let (start, end) = range.resolve(content.len() as u64)?;
+
+            trace!(
+                "TFTP sending preloaded file: {} (bytes {}-{})",
+                path.display(),
+                start,
+                end
+            );
+
+            let content = content.slice(start as usize..end as usize);
+            let reader = ReaderKind::Memory(Cursor::new(content));
+            return Ok((DirReader(reader), Some(end - start)));
+        }
+
+        if !path.is_file() {
+            return Err(packet::Error::FileNotFound);
+        }
+
+        let path_clone = path.clone();
+        let (file, start, end) =
+            unblock(move || open_byte_range(path_clone, range)).await?;
+        let reader = ReaderKind::Range(Unblock::new(file));
+
+        trace!(
+            "TFTP sending file: {} (bytes {}-{})",
+            path.display(),
+            start,
+            end
+        );
+
+        Ok((DirReader(reader), Some(end - start)))
+    }
+
+    /// Variant that is served instead of `path`, if `path` does not exist.
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    fn find_variant(&self, path: &Path) -> Option<(PathBuf, Codec)> {
+        if path.exists() {
+            return None;
+        }
+
+        find_variant(path, self.decompress_variants, self.compress_variants)
+    }
+
+    /// Read `path`, or its compressed variant, in memory.
+    async fn load(&self, path: &Path) -> io::Result<CachedFile> {
+        #[cfg(any(feature = "gzip", feature = "zstd"))]
+        if let Some((variant, codec)) = self.find_variant(path) {
+            let sizes = Arc::clone(&self.variant_sizes);
+
+            return unblock(move || {
+                use std::io::Read;
+
+                let stamp = FileStamp::of(&variant)?;
+                let (mut reader, _) =
+                    compressed::open(&variant, codec, &sizes)?;
+                let mut content = Vec::new();
+                reader.read_to_end(&mut content)?;
+
+                Ok(CachedFile::new(content, variant, stamp))
+            })
+            .await;
+        }
+
+        let path = path.to_owned();
+
+        unblock(move || {
+            // Changes during the read are noticed by the next check
+            let stamp = FileStamp::of(&path)?;
+            let content = fs::read(&path)?;
+
+            Ok(CachedFile::new(content, path, stamp))
+        })
+        .await
+    }
+
+    /// Preloaded content of `path`, revalidated if it is due.
+    async fn cached(&self, path: &Path) -> Option<Bytes> {
+        let (source, stamp) = {
+            let mut cache = self.cache.lock().unwrap();
+            let file = cache.get_mut(path)?;
+
+            match self.revalidate_cache {
+                Some(interval) if file.checked.elapsed() >= interval => {
+                    // Concurrent requests get the content as it is
+                    file.checked = Instant::now();
+                    (file.source.clone(), file.stamp.clone())
+                }
+                _ => return Some(file.content.clone()),
+            }
+        };
+
+        let current = unblock(move || FileStamp::of(&source)).await.ok();
+
+        if current.as_ref() == Some(&stamp) {
+            return self
+                .cache
+                .lock()
+                .unwrap()
+                .get(path)
+                .map(|f| f.content.clone());
+        }
+
+        match self.load(path).await {
+            Ok(file) => {
+                trace!("TFTP reloaded preloaded file: {}", path.display());
+
+                let content = file.content.clone();
+                self.cache.lock().unwrap().insert(path.to_owned(), file);
+                Some(content)
+            }
+            Err(_) => {
+                trace!("TFTP preloaded file is gone: {}", path.display());
+
+                self.cache.lock().unwrap().remove(path);
+                None
+            }
+        }
+    }
+
+    /// Whether uploads to `path` are appended.
+    fn is_append(&self, path: &Path) -> bool {
+        let relative = match path.strip_prefix(&self.dir) {
+            Ok(relative) => relative.to_string_lossy(),
+            Err(_) => return false,
+        };
+
+        self.append_uploads
+            .iter()
+            .any(|pattern| matches_pattern(pattern, &relative))
+    }
+
+    fn is_dir_listing(&self, path: &Path) -> bool {
+        match self.dir_listing {
+            Some(ref name) => self.secure_path(name).as_deref() == Ok(path),
+            None => false,
+        }
+    }
+
+    fn secure_path(&self, path: &Path) -> Result<PathBuf, packet::Error> {
+        if self.backslash_separator {
+            secure_path(&self.dir, &replace_backslashes(path))
+        } else {
+            secure_path(&self.dir, path)
+        }
     }
 }
 
@@ -136,13 +922,410 @@ fn secure_path(
     Ok(restricted_dir.join(path))
 }
 
+#[cfg(feature = "watch")]
+fn invalidate_cache(
+    cache: &Mutex<HashMap<PathBuf, CachedFile>>,
+    event: notify::Result<notify::Event>,
+) {
+    let mut cache = cache.lock().unwrap();
+
+    let event = match event {
+        Ok(event) if event.need_rescan() => {
+            cache.clear();
+            return;
+        }
+        Ok(event) => event,
+        Err(e) => {
+            // Changes might be missed, so nothing can be trusted
+            warn!("Watching TFTP directory failed: {}", e);
+            cache.clear();
+            return;
+        }
+    };
+
+    if event.kind.is_access() {
+        return;
+    }
+
+    for path in &event.paths {
+        cache.retain(|cached, _| {
+            if cached.starts_with(path) {
+                trace!("TFTP preloaded file changed: {}", cached.display());
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+impl CachedFile {
+    fn new(content: Vec<u8>, source: PathBuf, stamp: FileStamp) -> Self {
+        CachedFile {
+            content: Bytes::from(content),
+            source,
+            stamp,
+            checked: Instant::now(),
+        }
+    }
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> io::Result<Self> {
+        let meta = fs::metadata(path)?;
+
+        Ok(FileStamp {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+        })
+    }
+}
+
+/// Locks of files that running uploads append to.
+type AppendLocks = Mutex<HashMap<PathBuf, Weak<async_lock::Mutex<()>>>>;
+
+/// Lock of the appended file `path`, shared by all its uploads.
+fn append_lock(locks: &AppendLocks, path: &Path) -> Arc<async_lock::Mutex<()>> {
+    let mut map = locks.lock().unwrap();
+
+    if let Some(lock) = map.get(path).and_then(Weak::upgrade) {
+        return lock;
+    }
+
+    // Locks of finished uploads are dropped
+    map.retain(|_, lock| lock.strong_count() > 0);
+
+    let lock = Arc::new(async_lock::Mutex::new(()));
+    map.insert(path.to_owned(), Arc::downgrade(&lock));
+    lock
+}
+
+/// Files that are read by running transfers.
+type SharedFiles = Mutex<HashMap<PathBuf, Weak<SharedFile>>>;
+
+/// Content of a file that is shared between transfers.
+struct SharedFile {
+    path: PathBuf,
+    // `None` if the file is too big to be shared
+    content: OnceCell<Option<Bytes>>,
+    files: Arc<SharedFiles>,
+}
+
+impl SharedFile {
+    /// Shared file of `path`. A new one is created if no transfer reads
+    /// `path`.
+    fn get(files: &Arc<SharedFiles>, path: &Path) -> Arc<SharedFile> {
+        let mut map = files.lock().unwrap();
+
+        if let Some(file) = map.get(path).and_then(Weak::upgrade) {
+            return file;
+        }
+
+        let file = Arc::new(SharedFile {
+            path: path.to_owned(),
+            content: OnceCell::new(),
+            files: Arc::clone(files),
+        });
+
+        map.insert(path.to_owned(), Arc::downgrade(&file));
+        file
+    }
+
+    /// Content of the file, or `None` if it is bigger than `max_size`. It
+    /// is read only by the first caller.
+    async fn content(&self, max_size: u64) -> io::Result<Option<Bytes>> {
+        self.content
+            .get_or_try_init(|| async {
+                let path = self.path.clone();
+
+                unblock(move || {
+                    use std::io::Read;
+
+                    // Read one more byte to tell if the file is too big
+                    let mut content = Vec::new();
+                    File::open(path)?
+                        .take(max_size.saturating_add(1))
+                        .read_to_end(&mut content)?;
+
+                    if content.len() as u64 > max_size {
+                        return Ok(None);
+                    }
+
+                    Ok(Some(Bytes::from(content)))
+                })
+                .await
+            })
+            .await
+            .cloned()
+    }
+}
+
+impl Drop for SharedFile {
+    fn drop(&mut self) {
+        let mut map = self.files.lock().unwrap();
+
+        // The entry might already belong to a newer shared file
+        if map.get(&self.path).is_some_and(|f| f.strong_count() == 0) {
+            map.remove(&self.path);
+        }
+    }
+}
+
+/// Slice of a file requested with `file?off=N&len=M`.
+#[derive(Debug, Clone, Copy)]
+struct ByteRange {
+    offset: u64,
+    len: Option<u64>,
+}
+
+impl ByteRange {
+    fn parse(query: &str) -> Option<ByteRange> {
+        let mut offset = None;
+        let mut len = None;
+
+        for param in query.split('&') {
+            let (name, value) = param.split_once('=')?;
+            let value = value.parse::<u64>().ok()?;
+
+            match name {
+                "off" if offset.is_none() => offset = Some(value),
+                "len" if len.is_none() => len = Some(value),
+                _ => return None,
+            }
+        }
+
+        if offset.is_none() && len.is_none() {
+            return None;
+        }
+
+        Some(ByteRange {
+            offset: offset.unwrap_or(0),
+            len,
+        })
+    }
+
+    /// Start and end of the range in a file of `file_len` bytes.
+    fn resolve(self, file_len: u64) -> Result<(u64, u64), packet::Error> {
+        if self.offset > file_len {
+            return Err(packet::Error::Msg(
+                "Offset is beyond the end of file".to_string(),
+            ));
+        }
+
+        let end = match self.len {
+            Some(len) => cmp::min(self.offset.saturating_add(len), file_len),
+            None => file_len,
+        };
+
+        Ok((self.offset, end))
+    }
+}
+
+/// Split `file?query` to its file and query parts.
+#[cfg(unix)]
+fn split_query(path: &Path) -> Option<(PathBuf, String)> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let bytes = path.as_os_str().as_bytes();
+    let pos = bytes.iter().rposition(|&c| c == b'?')?;
+    let query = std::str::from_utf8(&bytes[pos + 1..]).ok()?;
+
+    if query.contains('/') {
+        return None;
+    }
+
+    let path = Path::new(OsStr::from_bytes(&bytes[..pos]));
+    Some((path.to_owned(), query.to_owned()))
+}
+
+#[cfg(not(unix))]
+fn split_query(path: &Path) -> Option<(PathBuf, String)> {
+    let path = path.to_str()?;
+    let (path, query) = path.rsplit_once('?')?;
+
+    if query.contains(['/', '\\']) {
+        return None;
+    }
+
+    Some((path.into(), query.to_owned()))
+}
+
+#[cfg(unix)]
+fn replace_backslashes(path: &Path) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    let path = path
+        .as_os_str()
+        .as_bytes()
+        .iter()
+        .map(|&c| {
+            if c == b'\\' {
+                b'/'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    OsString::from_vec(path).into()
+}
+
+#[cfg(not(unix))]
+fn replace_backslashes(path: &Path) -> PathBuf {
+    path.to_string_lossy().replace('\\', "/").into()
+}
+
+/// Names of the regular files in `dir`, one per line.
+fn list_dir(dir: &Path) -> io::Result<Vec<u8>> {
+    let mut names = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+
+        // Symlinks are followed, as when the file is served
+        if entry.path().is_file() {
+            names.push(path_to_bytes(Path::new(&entry.file_name())));
+        }
+    }
+
+    names.sort();
+
+    let mut listing = Vec::new();
+
+    for name in names {
+        listing.extend_from_slice(&name);
+        listing.push(b'\n');
+    }
+
+    Ok(listing)
+}
+
 fn open_file_ro(path: PathBuf) -> io::Result<(File, Option<u64>)> {
     let file = File::open(path)?;
     let len = file.metadata().ok().map(|m| m.len());
     Ok((file, len))
 }
 
-fn open_file_wo(path: PathBuf, size: Option<u64>) -> io::Result<File> {
+fn open_byte_range(
+    path: PathBuf,
+    range: ByteRange,
+) -> Result<(io::Take<File>, u64, u64), packet::Error> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let (start, end) = range.resolve(len)?;
+
+    file.seek(SeekFrom::Start(start))?;
+    Ok((file.take(end - start), start, end))
+}
+
+/// Path of the file that keeps a partial upload of `path`.
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Space of the file system of `path` that is available to the process.
+#[cfg(unix)]
+fn available_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: `path` is NUL terminated and `stat` is only read on success
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        stat.assume_init()
+    };
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> io::Result<u64> {
+    Ok(u64::MAX)
+}
+
+/// Check that the process may create files in the directory `path`.
+#[cfg(unix)]
+fn check_writable(path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+
+    // SAFETY: `path` is NUL terminated
+    if unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_writable(path: &Path) -> io::Result<()> {
+    if fs::metadata(path)?.permissions().readonly() {
+        return Err(io::ErrorKind::PermissionDenied.into());
+    }
+
+    Ok(())
+}
+
+/// Match `name` against a pattern with `*` and `?` wildcards.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Position after the last `*` and the name position it matched up to
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` match one more character
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn open_file_wo(
+    path: PathBuf,
+    size: Option<u64>,
+    append: bool,
+) -> io::Result<File> {
+    if append {
+        return fs::OpenOptions::new().create(true).append(true).open(path);
+    }
+
     let file = File::create(path)?;
 
     if let Some(size) = size {
@@ -0,0 +1,83 @@
+use futures_lite::{ready, AsyncRead, AsyncWrite};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+type Open<T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send>>;
+
+/// Reader or writer that is opened on first poll.
+///
+/// This allows handlers to return from `read_req_open` and
+/// `write_req_open`, and release the handler lock of the server, before
+/// slow work such as a network round trip is done.
+pub(super) enum Lazy<T> {
+    Opening(Open<T>),
+    Open(T),
+    Failed,
+}
+
+impl<T> Lazy<T> {
+    pub(super) fn new<F>(open: F) -> Self
+    where
+        F: Future<Output = io::Result<T>> + Send + 'static,
+    {
+        Lazy::Opening(Box::pin(open))
+    }
+
+    fn poll_open(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut T>> {
+        if let Lazy::Opening(open) = self {
+            match ready!(open.as_mut().poll(cx)) {
+                Ok(inner) => *self = Lazy::Open(inner),
+                Err(e) => {
+                    *self = Lazy::Failed;
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+
+        match self {
+            Lazy::Open(inner) => Poll::Ready(Ok(inner)),
+            _ => Poll::Ready(Err(io::Error::other("File failed to open"))),
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Lazy<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let inner = ready!(self.get_mut().poll_open(cx))?;
+        Pin::new(inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Lazy<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let inner = ready!(self.get_mut().poll_open(cx))?;
+        Pin::new(inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        let inner = ready!(self.get_mut().poll_open(cx))?;
+        Pin::new(inner).poll_flush(cx)
+    }
+
+    // Empty files are opened too, so they are created
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        let inner = ready!(self.get_mut().poll_open(cx))?;
+        Pin::new(inner).poll_close(cx)
+    }
+}
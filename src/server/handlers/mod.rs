@@ -1,5 +1,14 @@
 //! Handlers for common use-cases.
 
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+mod compressed;
 mod dir;
+mod lazy;
+mod relay;
+#[cfg(feature = "tower")]
+mod service;
 
 pub use self::dir::*;
+pub use self::relay::*;
+#[cfg(feature = "tower")]
+pub use self::service::*;
@@ -0,0 +1,208 @@
+use bytes::Bytes;
+use futures_lite::io::{AsyncRead, Cursor, Sink};
+use log::trace;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use super::lazy::Lazy;
+use crate::client::{ReadStream, TftpClient};
+use crate::packet;
+use crate::utils::path_to_bytes;
+
+/// Handler that serves read requests from an upstream TFTP server.
+///
+/// Files are streamed to the client while they are downloaded. With
+/// [`cache`](Self::cache) files are kept in memory, so only the first
+/// request of a file reaches the upstream server. Clones of the handler
+/// share the cache.
+///
+/// The upstream request is sent when the transfer reads the file, so a
+/// slow upstream server does not hold the handler lock of the server. The
+/// size of relayed files is therefore not known when options are
+/// negotiated, and `tsize` is acknowledged only for cached files.
+///
+/// Write requests are rejected.
+#[derive(Clone)]
+pub struct RelayHandler {
+    client: TftpClient,
+    cache: Arc<Mutex<RelayCache>>,
+}
+
+/// Reader of [`RelayHandler`].
+pub struct RelayReader(ReaderKind);
+
+enum ReaderKind {
+    Cached(Cursor<Bytes>),
+    Upstream {
+        stream: Lazy<Box<ReadStream>>,
+        // Content is collected for the cache if it may fit
+        tee: Option<Tee>,
+    },
+}
+
+struct Tee {
+    cache: Arc<Mutex<RelayCache>>,
+    path: PathBuf,
+    content: Vec<u8>,
+}
+
+#[derive(Default)]
+struct RelayCache {
+    files: HashMap<PathBuf, Bytes>,
+    size: u64,
+    max_size: u64,
+}
+
+impl RelayHandler {
+    /// Create new handler that relays requests to `upstream`.
+    pub fn new(upstream: SocketAddr) -> Self {
+        RelayHandler::with_client(TftpClient::new(upstream))
+    }
+
+    /// Create new handler that relays requests with `client`.
+    ///
+    /// This allows to set the block size and timeouts towards the upstream
+    /// server.
+    pub fn with_client(client: TftpClient) -> Self {
+        RelayHandler {
+            client,
+            cache: Arc::new(Mutex::new(RelayCache::default())),
+        }
+    }
+
+    /// Cache downloaded files in memory, up to `max_bytes` in total.
+    ///
+    /// Files are cached after they are fully downloaded. When the cache is
+    /// full, new files are relayed without being cached.
+    ///
+    /// **Default:** Files are not cached
+    pub fn cache(self, max_bytes: u64) -> Self {
+        self.cache.lock().unwrap().max_size = max_bytes;
+        self
+    }
+
+    /// Drop the cached files, e.g. after they changed upstream.
+    pub fn clear_cache(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.files.clear();
+        cache.size = 0;
+    }
+}
+
+#[crate::async_trait]
+impl crate::server::Handler for RelayHandler {
+    type Reader = RelayReader;
+    type Writer = Sink;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        let tee = {
+            let cache = self.cache.lock().unwrap();
+
+            if let Some(content) = cache.files.get(path) {
+                trace!("Relay cache hit: {}", path.display());
+                let len = content.len() as u64;
+                let reader = ReaderKind::Cached(Cursor::new(content.clone()));
+                return Ok((RelayReader(reader), Some(len)));
+            }
+
+            if cache.size < cache.max_size {
+                Some(Tee {
+                    cache: self.cache.clone(),
+                    path: path.to_owned(),
+                    content: Vec::new(),
+                })
+            } else {
+                None
+            }
+        };
+
+        // Upstream is contacted by the transfer, outside of the handler lock
+        let client = self.client.clone();
+        let filename = path_to_bytes(path);
+        let stream = Lazy::new(async move {
+            match client.read(filename).await {
+                Ok(stream) => Ok(Box::new(stream)),
+                Err(e) => Err(io::Error::other(packet::Error::from(e))),
+            }
+        });
+
+        Ok((
+            RelayReader(ReaderKind::Upstream {
+                stream,
+                tee,
+            }),
+            None,
+        ))
+    }
+
+    async fn write_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        Err(packet::Error::IllegalOperation)
+    }
+}
+
+impl AsyncRead for RelayReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().0 {
+            ReaderKind::Cached(reader) => Pin::new(reader).poll_read(cx, buf),
+            ReaderKind::Upstream {
+                stream,
+                tee,
+            } => {
+                let len = match Pin::new(stream).poll_read(cx, buf) {
+                    Poll::Ready(Ok(len)) => len,
+                    res => return res,
+                };
+
+                if len == 0 {
+                    if let Some(tee) = tee.take() {
+                        tee.finish();
+                    }
+                } else if let Some(t) = tee {
+                    t.content.extend_from_slice(&buf[..len]);
+
+                    let max_size = t.cache.lock().unwrap().max_size;
+                    if t.content.len() as u64 > max_size {
+                        *tee = None;
+                    }
+                }
+
+                Poll::Ready(Ok(len))
+            }
+        }
+    }
+}
+
+impl Tee {
+    fn finish(self) {
+        let mut cache = self.cache.lock().unwrap();
+        let len = self.content.len() as u64;
+
+        if cache.size + len > cache.max_size
+            || cache.files.contains_key(&self.path)
+        {
+            return;
+        }
+
+        trace!("Relay cache insert: {}", self.path.display());
+        cache.size += len;
+        cache.files.insert(self.path, self.content.into());
+    }
+}
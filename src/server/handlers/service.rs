@@ -0,0 +1,174 @@
+use futures_lite::future::poll_fn;
+use futures_lite::{AsyncRead, AsyncWrite};
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tower_service::Service;
+
+use super::lazy::Lazy;
+use crate::packet;
+use crate::server::{Direction, Handler};
+
+/// Request that [`ServiceHandler`] passes to its service.
+#[derive(Debug, Clone)]
+pub struct TftpRequest {
+    pub client: SocketAddr,
+    pub path: PathBuf,
+    pub direction: Direction,
+    /// Size of the file that the client announced in a write request.
+    pub transfer_size: Option<u64>,
+}
+
+/// Response of the service of [`ServiceHandler`].
+pub enum TftpResponse {
+    /// Reader that serves a read request, and the size of the file if it
+    /// is known.
+    Read(Box<dyn AsyncRead + Unpin + Send>, Option<u64>),
+    /// Writer that serves a write request.
+    Write(Box<dyn AsyncWrite + Unpin + Send>),
+}
+
+impl fmt::Debug for TftpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TftpResponse::Read(_, size) => {
+                f.debug_tuple("Read").field(&"..").field(size).finish()
+            }
+            TftpResponse::Write(_) => {
+                f.debug_tuple("Write").field(&"..").finish()
+            }
+        }
+    }
+}
+
+/// Handler that serves requests with a `tower::Service`.
+///
+/// This allows to put tower middleware, such as rate limiting, load
+/// shedding or instrumentation, in front of a handler. Errors of the
+/// service are sent to the client: a [`packet::Error`] as it is, any other
+/// error as [`packet::Error::Msg`].
+///
+/// Every request calls a clone of the service when its transfer starts,
+/// so a slow service does not hold the handler lock of the server. The
+/// size of the file is therefore not known when options are negotiated,
+/// and the `tsize` option is not acknowledged.
+///
+/// Available with the `tower` feature.
+pub struct ServiceHandler<S> {
+    service: S,
+}
+
+impl<S> ServiceHandler<S> {
+    /// Create handler that calls `service` for every request.
+    pub fn new(service: S) -> Self {
+        ServiceHandler {
+            service,
+        }
+    }
+
+    /// Inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.service
+    }
+}
+
+impl<S> ServiceHandler<S>
+where
+    S: Service<TftpRequest, Response = TftpResponse> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    fn call(
+        &self,
+        req: TftpRequest,
+    ) -> impl Future<Output = Result<TftpResponse, packet::Error>> + Send {
+        let mut service = self.service.clone();
+
+        async move {
+            poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .map_err(service_error)?;
+
+            service.call(req).await.map_err(service_error)
+        }
+    }
+}
+
+fn service_error<E>(error: E) -> packet::Error
+where
+    E: Into<Box<dyn StdError + Send + Sync>>,
+{
+    match error.into().downcast::<packet::Error>() {
+        Ok(e) => *e,
+        Err(e) => packet::Error::Msg(e.to_string()),
+    }
+}
+
+fn io_error(error: packet::Error) -> io::Error {
+    io::Error::other(error)
+}
+
+#[crate::async_trait]
+impl<S> Handler for ServiceHandler<S>
+where
+    S: Service<TftpRequest, Response = TftpResponse> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Reader = Box<dyn AsyncRead + Unpin + Send>;
+    type Writer = Box<dyn AsyncWrite + Unpin + Send>;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        let req = TftpRequest {
+            client: *client,
+            path: path.to_owned(),
+            direction: Direction::Read,
+            transfer_size: None,
+        };
+        let call = self.call(req);
+
+        let reader = Lazy::new(async move {
+            match call.await.map_err(io_error)? {
+                TftpResponse::Read(reader, _) => Ok(reader),
+                TftpResponse::Write(_) => Err(io_error(packet::Error::Msg(
+                    "Service returned writer for read request".to_string(),
+                ))),
+            }
+        });
+
+        Ok((Box::new(reader), None))
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        let req = TftpRequest {
+            client: *client,
+            path: path.to_owned(),
+            direction: Direction::Write,
+            transfer_size: size,
+        };
+        let call = self.call(req);
+
+        let writer = Lazy::new(async move {
+            match call.await.map_err(io_error)? {
+                TftpResponse::Write(writer) => Ok(writer),
+                TftpResponse::Read(..) => Err(io_error(packet::Error::Msg(
+                    "Service returned reader for write request".to_string(),
+                ))),
+            }
+        });
+
+        Ok(Box::new(writer))
+    }
+}
@@ -0,0 +1,235 @@
+use bytes::BufMut;
+use nom::multi::{length_count, length_data};
+use nom::number::complete::{be_u16, be_u32, be_u64, be_u8};
+use nom::sequence::tuple;
+use nom::IResult;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+
+/// Version of the format of [`KeepState::export`].
+const FORMAT_VERSION: u8 = 1;
+
+/// Progress of the running read transfers of a server, so they can be
+/// resumed after the server is restarted.
+///
+/// Set it with [`TftpServerBuilder::keep_state`]. Before the server
+/// process is replaced, e.g. to upgrade it, the supervisor saves
+/// [`export`](Self::export) and passes it to
+/// [`TftpServerBuilder::resume_transfers`] of the new process, which binds
+/// the same transfer ports and continues after the last acknowledged
+/// block.
+///
+/// The old process must exit without cancelling its transfers, because
+/// cancelled transfers are terminated with an error. Write requests are not
+/// kept, since a [`Handler`] can not reopen a writer in the middle of a
+/// file.
+///
+/// Cloned values refer to the same state.
+///
+/// [`TftpServerBuilder::keep_state`]: super::TftpServerBuilder::keep_state
+/// [`TftpServerBuilder::resume_transfers`]: super::TftpServerBuilder::resume_transfers
+/// [`Handler`]: super::Handler
+#[derive(Debug, Clone, Default)]
+pub struct KeepState {
+    transfers: Arc<Mutex<HashMap<SocketAddr, Registered>>>,
+}
+
+/// Running transfer in [`KeepState`].
+#[derive(Debug)]
+struct Registered {
+    snapshot: TransferSnapshot,
+    // Shared with `Progress`, so ACKs do not lock the transfers
+    acked_blocks: Arc<AtomicU64>,
+}
+
+/// Progress of a read transfer.
+///
+/// See [`KeepState`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferSnapshot {
+    /// Address of the client.
+    pub client: SocketAddr,
+    /// Address of the transfer socket, which the client sends to.
+    pub local_addr: SocketAddr,
+    /// Filename that was opened, after it was resolved.
+    pub filename: Vec<u8>,
+    /// Negotiated block size.
+    pub block_size: u16,
+    /// Negotiated window size.
+    pub window_size: u16,
+    /// Retry timeout of the transfer.
+    pub timeout: Duration,
+    /// Number of blocks that the client acknowledged.
+    pub acked_blocks: u64,
+}
+
+/// Registration of a running transfer in [`KeepState`]. It is removed on
+/// drop.
+pub(crate) struct Progress {
+    state: KeepState,
+    client: SocketAddr,
+    acked_blocks: Arc<AtomicU64>,
+}
+
+impl KeepState {
+    /// Create a new state.
+    pub fn new() -> Self {
+        KeepState::default()
+    }
+
+    /// Progress of the running read transfers.
+    pub fn snapshots(&self) -> Vec<TransferSnapshot> {
+        let transfers = self.transfers.lock().unwrap();
+        transfers.values().map(Registered::snapshot).collect()
+    }
+
+    /// Encode the progress of the running read transfers.
+    pub fn export(&self) -> Vec<u8> {
+        let transfers = self.transfers.lock().unwrap();
+        let mut buf = Vec::new();
+
+        buf.put_u8(FORMAT_VERSION);
+        buf.put_u32(transfers.len() as u32);
+
+        for registered in transfers.values() {
+            registered.snapshot().encode(&mut buf);
+        }
+
+        buf
+    }
+
+    /// Decode the output of [`export`](Self::export).
+    pub fn import(data: &[u8]) -> Result<Vec<TransferSnapshot>> {
+        match parse_export(data) {
+            Ok((&[], snapshots)) => Ok(snapshots),
+            _ => Err(Error::InvalidTransferState),
+        }
+    }
+
+    /// Record the progress of the transfer of `snapshot`.
+    pub(crate) fn register(&self, snapshot: TransferSnapshot) -> Progress {
+        let client = snapshot.client;
+        let acked_blocks = Arc::new(AtomicU64::new(snapshot.acked_blocks));
+        let registered = Registered {
+            snapshot,
+            acked_blocks: acked_blocks.clone(),
+        };
+        self.transfers.lock().unwrap().insert(client, registered);
+
+        Progress {
+            state: self.clone(),
+            client,
+            acked_blocks,
+        }
+    }
+}
+
+impl Registered {
+    fn snapshot(&self) -> TransferSnapshot {
+        TransferSnapshot {
+            acked_blocks: self.acked_blocks.load(Ordering::Relaxed),
+            ..self.snapshot.clone()
+        }
+    }
+}
+
+impl TransferSnapshot {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_addr(&self.client, buf);
+        encode_addr(&self.local_addr, buf);
+        buf.put_u16(self.filename.len() as u16);
+        buf.put_slice(&self.filename);
+        buf.put_u16(self.block_size);
+        buf.put_u16(self.window_size);
+        buf.put_u64(self.timeout.as_millis() as u64);
+        buf.put_u64(self.acked_blocks);
+    }
+}
+
+impl Progress {
+    /// Record that `blocks` blocks are acknowledged.
+    pub(crate) fn acked(&self, blocks: u64) {
+        self.acked_blocks.store(blocks, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        let mut transfers = self.state.transfers.lock().unwrap();
+
+        // A newer transfer of the same client is kept
+        if let Some(registered) = transfers.get(&self.client) {
+            if Arc::ptr_eq(&registered.acked_blocks, &self.acked_blocks) {
+                transfers.remove(&self.client);
+            }
+        }
+    }
+}
+
+fn encode_addr(addr: &SocketAddr, buf: &mut Vec<u8>) {
+    match addr {
+        SocketAddr::V4(addr) => {
+            buf.put_u8(4);
+            buf.put_slice(&addr.ip().octets());
+            buf.put_u16(addr.port());
+        }
+        SocketAddr::V6(addr) => {
+            buf.put_u8(6);
+            buf.put_slice(&addr.ip().octets());
+            buf.put_u16(addr.port());
+            buf.put_u32(addr.scope_id());
+        }
+    }
+}
+
+fn parse_export(input: &[u8]) -> IResult<&[u8], Vec<TransferSnapshot>> {
+    let (input, _) = nom::bytes::complete::tag([FORMAT_VERSION])(input)?;
+    length_count(be_u32, parse_snapshot)(input)
+}
+
+fn parse_snapshot(input: &[u8]) -> IResult<&[u8], TransferSnapshot> {
+    let (input, (client, local_addr, filename)) =
+        tuple((parse_addr, parse_addr, length_data(be_u16)))(input)?;
+    let (input, (block_size, window_size, timeout, acked_blocks)) =
+        tuple((be_u16, be_u16, be_u64, be_u64))(input)?;
+
+    let snapshot = TransferSnapshot {
+        client,
+        local_addr,
+        filename: filename.to_vec(),
+        block_size,
+        window_size,
+        timeout: Duration::from_millis(timeout),
+        acked_blocks,
+    };
+
+    Ok((input, snapshot))
+}
+
+fn parse_addr(input: &[u8]) -> IResult<&[u8], SocketAddr> {
+    let (input, family) = be_u8(input)?;
+
+    match family {
+        4 => {
+            let (input, (ip, port)) = tuple((be_u32, be_u16))(input)?;
+            let addr = SocketAddrV4::new(Ipv4Addr::from(ip), port);
+            Ok((input, SocketAddr::V4(addr)))
+        }
+        6 => {
+            let (input, (hi, lo, port, scope_id)) =
+                tuple((be_u64, be_u64, be_u16, be_u32))(input)?;
+            let ip = Ipv6Addr::from((u128::from(hi) << 64) | u128::from(lo));
+            let addr = SocketAddrV6::new(ip, port, 0, scope_id);
+            Ok((input, SocketAddr::V6(addr)))
+        }
+        _ => Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Tag,
+        ))),
+    }
+}
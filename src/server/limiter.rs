@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Priority of a transfer.
+///
+/// When the limit of concurrent transfers is reached, pending transfers
+/// start in priority order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Limits the number of concurrent transfers.
+pub(crate) struct TransferLimiter {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    available: usize,
+    waiters: Vec<Waiter>,
+    next_id: u64,
+}
+
+struct Waiter {
+    priority: Priority,
+    // Waiters are served in FIFO order within the same priority
+    id: u64,
+    waker: Option<Waker>,
+    granted: bool,
+}
+
+/// Slot of a running transfer. It is released on drop.
+pub(crate) struct Permit {
+    limiter: Arc<TransferLimiter>,
+}
+
+pub(crate) struct Acquire {
+    limiter: Arc<TransferLimiter>,
+    priority: Priority,
+    id: Option<u64>,
+    done: bool,
+}
+
+impl TransferLimiter {
+    pub(crate) fn new(max: usize) -> Self {
+        TransferLimiter {
+            inner: Mutex::new(Inner {
+                available: max,
+                waiters: Vec::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    /// Wait until a transfer of `priority` is allowed to start.
+    pub(crate) fn acquire(self: &Arc<Self>, priority: Priority) -> Acquire {
+        Acquire {
+            limiter: Arc::clone(self),
+            priority,
+            id: None,
+            done: false,
+        }
+    }
+
+    fn release(&self) {
+        let mut inner = self.inner.lock().unwrap();
+
+        // Hand over the slot to the waiter with the highest priority
+        let next =
+            inner.waiters.iter_mut().filter(|w| !w.granted).max_by(|a, b| {
+                a.priority.cmp(&b.priority).then(b.id.cmp(&a.id))
+            });
+
+        match next {
+            Some(waiter) => {
+                waiter.granted = true;
+
+                if let Some(waker) = waiter.waker.take() {
+                    waker.wake();
+                }
+            }
+            None => inner.available += 1,
+        }
+    }
+}
+
+impl Future for Acquire {
+    type Output = Permit;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Permit> {
+        let mut inner = self.limiter.inner.lock().unwrap();
+
+        let granted = match self.id {
+            None if inner.available > 0 && inner.waiters.is_empty() => {
+                inner.available -= 1;
+                true
+            }
+            None => {
+                let id = inner.next_id;
+                inner.next_id += 1;
+
+                inner.waiters.push(Waiter {
+                    priority: self.priority,
+                    id,
+                    waker: Some(cx.waker().clone()),
+                    granted: false,
+                });
+
+                drop(inner);
+                self.id = Some(id);
+                return Poll::Pending;
+            }
+            Some(id) => {
+                let pos = inner
+                    .waiters
+                    .iter()
+                    .position(|w| w.id == id)
+                    .expect("waiter is missing");
+
+                if inner.waiters[pos].granted {
+                    inner.waiters.remove(pos);
+                    true
+                } else {
+                    inner.waiters[pos].waker = Some(cx.waker().clone());
+                    false
+                }
+            }
+        };
+
+        drop(inner);
+
+        if granted {
+            self.done = true;
+            Poll::Ready(Permit {
+                limiter: Arc::clone(&self.limiter),
+            })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        let id = match self.id {
+            Some(id) if !self.done => id,
+            _ => return,
+        };
+
+        let mut inner = self.limiter.inner.lock().unwrap();
+        let pos = match inner.waiters.iter().position(|w| w.id == id) {
+            Some(pos) => pos,
+            None => return,
+        };
+
+        let waiter = inner.waiters.remove(pos);
+        drop(inner);
+
+        // Slot was handed over to us, but we gave up. Pass it on.
+        if waiter.granted {
+            self.limiter.release();
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+/// Limits the number of pending handshakes, i.e. requests that did not
+/// receive the first reply of the client yet.
+pub(crate) struct HandshakeLimiter {
+    max: Option<usize>,
+    max_per_ip: Option<usize>,
+    pending: Mutex<PendingHandshakes>,
+}
+
+#[derive(Default)]
+struct PendingHandshakes {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Slot of a pending handshake. It is released on drop.
+pub(crate) struct Handshake {
+    limiter: Arc<HandshakeLimiter>,
+    ip: IpAddr,
+}
+
+impl HandshakeLimiter {
+    pub(crate) fn new(max: Option<usize>, max_per_ip: Option<usize>) -> Self {
+        HandshakeLimiter {
+            max,
+            max_per_ip,
+            pending: Mutex::new(PendingHandshakes::default()),
+        }
+    }
+
+    /// Start a handshake with `ip`, or return `None` if a limit is reached.
+    pub(crate) fn try_start(self: &Arc<Self>, ip: IpAddr) -> Option<Handshake> {
+        let mut pending = self.pending.lock().unwrap();
+
+        if self.max.is_some_and(|max| pending.total >= max) {
+            return None;
+        }
+
+        let per_ip = pending.per_ip.get(&ip).copied().unwrap_or(0);
+
+        if self.max_per_ip.is_some_and(|max| per_ip >= max) {
+            return None;
+        }
+
+        pending.per_ip.insert(ip, per_ip + 1);
+        pending.total += 1;
+
+        Some(Handshake {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+}
+
+impl Drop for Handshake {
+    fn drop(&mut self) {
+        let mut pending = self.limiter.pending.lock().unwrap();
+
+        pending.total -= 1;
+
+        if let Some(per_ip) = pending.per_ip.get_mut(&self.ip) {
+            *per_ip -= 1;
+
+            if *per_ip == 0 {
+                pending.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Limits the number of requests that are handled at the same time.
+pub(crate) struct RequestLimiter {
+    max: usize,
+    active: AtomicUsize,
+}
+
+/// Slot of an active request. It is released on drop.
+pub(crate) struct ActiveRequest {
+    limiter: Arc<RequestLimiter>,
+}
+
+impl RequestLimiter {
+    pub(crate) fn new(max: usize) -> Self {
+        RequestLimiter {
+            max,
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Start a request, or return `None` if the limit is reached.
+    pub(crate) fn try_start(self: &Arc<Self>) -> Option<ActiveRequest> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < self.max).then_some(active + 1)
+            })
+            .ok()?;
+
+        Some(ActiveRequest {
+            limiter: Arc::clone(self),
+        })
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.limiter.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Accounts the bytes that transfers hold in their buffers. Memory of the
+/// handler is not accounted.
+pub(crate) struct MemoryBudget {
+    max: usize,
+    used: AtomicUsize,
+}
+
+/// Bytes that a transfer holds. They are released on drop.
+pub(crate) struct Reservation {
+    budget: Arc<MemoryBudget>,
+    bytes: usize,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(max: usize) -> Self {
+        MemoryBudget {
+            max,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether transfers hold all the bytes of the budget.
+    pub(crate) fn exhausted(&self) -> bool {
+        self.used.load(Ordering::Acquire) >= self.max
+    }
+
+    /// Reserve `bytes`, even if they do not fit in the budget.
+    ///
+    /// Every transfer needs a buffer for at least one packet, otherwise it
+    /// can not make progress.
+    pub(crate) fn reserve(self: &Arc<Self>, bytes: usize) -> Reservation {
+        self.used.fetch_add(bytes, Ordering::AcqRel);
+
+        Reservation {
+            budget: Arc::clone(self),
+            bytes,
+        }
+    }
+}
+
+impl Reservation {
+    /// Resize the reservation to `bytes`, or less if they do not fit in the
+    /// budget, but never less than `min`. Returns the reserved bytes.
+    pub(crate) fn resize(&mut self, bytes: usize, min: usize) -> usize {
+        let budget = &self.budget;
+        let current = self.bytes;
+        let mut new = current;
+
+        budget
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                let others = used - current;
+                let available = budget.max.saturating_sub(others);
+                new = bytes.min(available).max(min);
+                Some(others + new)
+            })
+            .unwrap();
+
+        self.bytes = new;
+        new
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::AcqRel);
+    }
+}
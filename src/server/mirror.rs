@@ -0,0 +1,97 @@
+use async_executor::Executor;
+use async_lock::Semaphore;
+use futures_lite::io;
+use log::trace;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::client::TftpClient;
+use crate::packet::RwReq;
+
+/// What is mirrored of each read request.
+///
+/// See [`TftpServerBuilder::mirror`].
+///
+/// [`TftpServerBuilder::mirror`]: super::TftpServerBuilder::mirror
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorMode {
+    /// Send the request and cancel it after the first reply, so the
+    /// secondary server opens the file without transferring it.
+    Requests,
+    /// Download the whole file and discard it. Only one mirrored transfer
+    /// runs at a time; requests that arrive meanwhile are mirrored as
+    /// [`Requests`](Self::Requests).
+    Transfers,
+}
+
+/// Replays read requests to a secondary server.
+pub(crate) struct Mirror {
+    client: TftpClient,
+    mode: MirrorMode,
+    transfers: Arc<AtomicUsize>,
+    // Permits of the background tasks
+    tasks: Arc<Semaphore>,
+}
+
+impl Mirror {
+    /// Maximum number of requests that are mirrored at the same time.
+    pub(crate) const MAX_TASKS: usize = 32;
+
+    pub(crate) fn new(addr: SocketAddr, mode: MirrorMode) -> Self {
+        Mirror {
+            client: TftpClient::new(addr),
+            mode,
+            transfers: Arc::new(AtomicUsize::new(0)),
+            tasks: Arc::new(Semaphore::new(Self::MAX_TASKS)),
+        }
+    }
+
+    /// Mirror `req` in the background. Results are only logged, so the
+    /// secondary server never affects the client. The request is not
+    /// mirrored if [`MAX_TASKS`](Self::MAX_TASKS) are already running.
+    pub(crate) fn spawn(&self, ex: &Executor<'static>, req: &RwReq) {
+        let Some(permit) = self.tasks.try_acquire_arc() else {
+            trace!(
+                "RRQ not mirrored, too many mirrored requests (file: {})",
+                req.filename_lossy()
+            );
+            return;
+        };
+
+        let client = match req.opts.block_size {
+            Some(size) => self.client.clone().block_size(size),
+            None => self.client.clone(),
+        };
+        let filename = req.filename.clone();
+        let transfer = self.mode == MirrorMode::Transfers
+            && self
+                .transfers
+                .compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok();
+        let transfers = self.transfers.clone();
+
+        ex.spawn(async move {
+            let _permit = permit;
+            let res = match client.read(&filename).await {
+                Ok(stream) if transfer => {
+                    io::copy(stream, io::sink()).await.map(|_| ())
+                }
+                Ok(_) => Ok(()),
+                Err(e) => Err(io::Error::other(e)),
+            };
+
+            if transfer {
+                transfers.store(0, Ordering::SeqCst);
+            }
+
+            trace!(
+                "Mirrored RRQ (server: {}, file: {}, result: {:?})",
+                client.server(),
+                String::from_utf8_lossy(&filename),
+                res
+            );
+        })
+        .detach();
+    }
+}
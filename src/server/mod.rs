@@ -1,14 +1,47 @@
 //! Server side implementation.
 
+mod audit;
+mod authorizer;
+mod block_source;
 mod builder;
+mod cancel;
+pub(crate) mod counter;
 mod handler;
+mod keep_state;
+mod limiter;
+mod mirror;
+#[cfg(target_os = "linux")]
+pub(crate) mod neighbor;
+#[cfg(feature = "otel")]
+mod otel;
 mod read_req;
+mod rejections;
+mod resolver;
+mod restart;
+mod self_check;
 #[allow(clippy::module_inception)]
 mod server;
+mod shards;
+mod shared_handler;
+mod statsd;
 mod write_req;
 
 pub mod handlers;
 
+pub use self::audit::*;
+pub use self::authorizer::*;
+pub use self::block_source::*;
 pub use self::builder::*;
+pub use self::cancel::CancellationToken;
 pub use self::handler::*;
+pub use self::keep_state::{KeepState, TransferSnapshot};
+pub use self::limiter::Priority;
+pub use self::mirror::MirrorMode;
+#[cfg(feature = "otel")]
+pub use self::otel::*;
+pub use self::rejections::*;
+pub use self::resolver::*;
+pub use self::self_check::*;
 pub use self::server::*;
+pub use self::shared_handler::SharedHandler;
+pub use self::statsd::*;
@@ -0,0 +1,38 @@
+use blocking::unblock;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+
+use super::MacAddr;
+
+const ARP_TABLE: &str = "/proc/net/arp";
+
+/// Look up the MAC address of `ip` in the ARP table of the kernel.
+///
+/// Only IPv4 neighbors are listed in the table.
+pub(crate) async fn lookup_mac(ip: IpAddr) -> Option<MacAddr> {
+    let ip = match ip {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(ip) => ip.to_ipv4_mapped()?,
+    };
+
+    let table = unblock(|| fs::read_to_string(ARP_TABLE)).await.ok()?;
+    find_mac(&table, ip)
+}
+
+/// Find `ip` in the content of `/proc/net/arp`.
+pub(crate) fn find_mac(table: &str, ip: Ipv4Addr) -> Option<MacAddr> {
+    // IP address, HW type, Flags, HW address, Mask, Device
+    table.lines().skip(1).find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let entry_ip = fields.next()?.parse::<Ipv4Addr>().ok()?;
+        let flags = fields.nth(1)?;
+        let mac = fields.next()?;
+
+        // Incomplete entries have no flags
+        if entry_ip != ip || flags == "0x0" {
+            return None;
+        }
+
+        mac.parse().ok()
+    })
+}
@@ -0,0 +1,168 @@
+use opentelemetry::metrics::{Counter, Histogram, Unit};
+use opentelemetry::trace::{Span, SpanKind, Status, Tracer};
+use opentelemetry::{global, KeyValue};
+
+use super::{AuditRecord, AuditSink, Direction};
+
+/// Audit sink that exports requests to OpenTelemetry.
+///
+/// Every request is exported as a span, and as the `tftp.server.requests`,
+/// `tftp.server.transferred` and `tftp.server.duration` metrics. Restarted
+/// requests are counted in `tftp.server.restarts`, retransmitted packets
+/// in `tftp.server.retransmissions`, oversized datagrams of the client in
+/// `tftp.server.oversized_datagrams` and duplicate blocks of uploads in
+/// `tftp.server.duplicate_blocks`. Metrics have the
+/// `tftp.direction` and `tftp.outcome` attributes. The global tracer and
+/// meter providers are used, so they must be configured by the application.
+///
+/// Available with the `otel` feature.
+pub struct OtelAudit {
+    requests: Counter<u64>,
+    transferred: Counter<u64>,
+    duration: Histogram<f64>,
+    restarts: Counter<u64>,
+    retransmissions: Counter<u64>,
+    oversized_datagrams: Counter<u64>,
+    duplicate_blocks: Counter<u64>,
+}
+
+impl OtelAudit {
+    pub fn new() -> Self {
+        let meter = global::meter("async-tftp");
+
+        OtelAudit {
+            requests: meter
+                .u64_counter("tftp.server.requests")
+                .with_description("Number of served requests")
+                .init(),
+            transferred: meter
+                .u64_counter("tftp.server.transferred")
+                .with_description("Bytes of file data that were transferred")
+                .with_unit(Unit::new("By"))
+                .init(),
+            duration: meter
+                .f64_histogram("tftp.server.duration")
+                .with_description("Duration of requests")
+                .with_unit(Unit::new("s"))
+                .init(),
+            restarts: meter
+                .u64_counter("tftp.server.restarts")
+                .with_description(
+                    "Number of requests that restarted from another port",
+                )
+                .init(),
+            retransmissions: meter
+                .u64_counter("tftp.server.retransmissions")
+                .with_description("Number of retransmitted packets")
+                .init(),
+            oversized_datagrams: meter
+                .u64_counter("tftp.server.oversized_datagrams")
+                .with_description(
+                    "Number of datagrams that exceeded the block size",
+                )
+                .init(),
+            duplicate_blocks: meter
+                .u64_counter("tftp.server.duplicate_blocks")
+                .with_description(
+                    "Number of duplicates of already written blocks",
+                )
+                .init(),
+        }
+    }
+}
+
+impl Default for OtelAudit {
+    fn default() -> Self {
+        OtelAudit::new()
+    }
+}
+
+#[crate::async_trait]
+impl AuditSink for OtelAudit {
+    async fn record(&self, record: &AuditRecord) {
+        let direction = match record.direction {
+            Direction::Read => "read",
+            Direction::Write => "write",
+        };
+
+        let attrs = [
+            KeyValue::new("tftp.direction", direction),
+            KeyValue::new("tftp.outcome", record.outcome.as_str()),
+        ];
+
+        self.requests.add(1, &attrs);
+        self.transferred.add(record.transferred, &attrs);
+        self.duration.record(record.duration.as_secs_f64(), &attrs);
+
+        if record.restarted {
+            self.restarts.add(1, &attrs);
+        }
+
+        if record.retransmissions.total > 0 {
+            self.retransmissions.add(record.retransmissions.total, &attrs);
+        }
+
+        if record.oversized_datagrams > 0 {
+            self.oversized_datagrams.add(record.oversized_datagrams, &attrs);
+        }
+
+        if record.duplicate_blocks > 0 {
+            self.duplicate_blocks.add(record.duplicate_blocks, &attrs);
+        }
+
+        let tracer = global::tracer("async-tftp");
+        let name = match record.direction {
+            Direction::Read => "tftp read",
+            Direction::Write => "tftp write",
+        };
+
+        let mut span = tracer
+            .span_builder(name)
+            .with_kind(SpanKind::Server)
+            .with_start_time(record.time)
+            .with_attributes(vec![
+                KeyValue::new("client.address", record.client.ip().to_string()),
+                KeyValue::new("client.port", i64::from(record.client.port())),
+                KeyValue::new("tftp.filename", record.filename.clone()),
+                KeyValue::new("tftp.transferred", record.transferred as i64),
+                KeyValue::new("tftp.restarted", record.restarted),
+                KeyValue::new(
+                    "tftp.retransmissions",
+                    record.retransmissions.total as i64,
+                ),
+            ])
+            .start(&tracer);
+
+        if let Some(local_addr) = record.local_addr {
+            span.set_attribute(KeyValue::new(
+                "server.address",
+                local_addr.ip().to_string(),
+            ));
+            span.set_attribute(KeyValue::new(
+                "server.port",
+                i64::from(local_addr.port()),
+            ));
+        }
+
+        if let Some(ref options) = record.options {
+            span.set_attribute(KeyValue::new(
+                "tftp.block_size",
+                i64::from(options.block_size),
+            ));
+            span.set_attribute(KeyValue::new(
+                "tftp.window_size",
+                i64::from(options.window_size),
+            ));
+        }
+
+        if let Some(ref e) = record.error {
+            span.set_attribute(KeyValue::new(
+                "tftp.error_code",
+                i64::from(e.code()),
+            ));
+            span.set_status(Status::error(e.msg().to_owned()));
+        }
+
+        span.end_with_timestamp(record.time + record.duration);
+    }
+}
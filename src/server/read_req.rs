@@ -1,30 +1,81 @@
-use async_io::Async;
 use bytes::{BufMut, Bytes, BytesMut};
-use futures_lite::{AsyncRead, AsyncReadExt};
+use futures_lite::{
+    future, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt,
+};
 use log::trace;
 use std::cmp;
-use std::io;
-use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::collections::VecDeque;
+use std::io::{self, IoSlice};
+use std::net::SocketAddr;
 use std::slice;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::clock::Clock;
 use crate::error::{Error, Result};
-use crate::packet::{Opts, Packet, RwReq, PACKET_DATA_HEADER_LEN};
-use crate::server::{ServerConfig, DEFAULT_BLOCK_SIZE};
-use crate::utils::io_timeout;
+use crate::packet::{self, Opts, Packet, RwReq, PACKET_DATA_HEADER_LEN};
+use crate::server::keep_state::Progress;
+use crate::server::limiter::{Handshake, Reservation};
+use crate::server::{
+    encode_error, CancellationToken, ErrorMessageFn, Retransmissions,
+    ServerConfig, StartedNotify, TransferOutcome, DEFAULT_BLOCK_SIZE,
+};
+use crate::server::{BlockSource, TransferSnapshot};
+use crate::transport::Transport;
+use crate::utils::{io_timeout, is_conn_reset, throttle};
+
+/// Where the data of a read request comes from.
+pub(crate) enum Source<'r, R> {
+    Reader(&'r mut R),
+    /// Reader whose buffer is sent directly. It is taken when the transfer
+    /// starts.
+    BufReader(Option<&'r mut (dyn AsyncBufRead + Unpin + Send)>),
+    Blocks(Box<dyn BlockSource>),
+}
+
+/// Reply of the client to a sent packet.
+enum Reply {
+    Ack(u16),
+    /// Client terminated the transfer with an error.
+    Error(packet::Error),
+}
 
 pub(crate) struct ReadRequest<'r, R>
 where
     R: AsyncRead + Send,
 {
     peer: SocketAddr,
-    socket: Async<UdpSocket>,
-    reader: &'r mut R,
+    socket: Box<dyn Transport>,
+    source: Source<'r, R>,
     buffer: BytesMut,
+    // Bytes of `buffer` and of unacknowledged blocks
+    memory: Option<Reservation>,
     block_size: usize,
     timeout: Duration,
+    // Pending until the first reply of the client
+    handshake: Option<Handshake>,
+    handshake_timeout: Duration,
+    started: Option<StartedNotify>,
     max_send_retries: u32,
+    max_oack_retries: u32,
+    oack_timeout: Duration,
     oack_opts: Option<Opts>,
+    options_rejected: bool,
+    window_size: usize,
+    adaptive_window: bool,
+    window_packet_gap: Option<Duration>,
+    max_bytes_per_sec: Option<u64>,
+    fail_on_conn_reset: bool,
+    outcome: TransferOutcome,
+    retransmissions: Retransmissions,
+    error_messages: Option<Arc<ErrorMessageFn>>,
+    cancellation: Option<CancellationToken>,
+    clock: Arc<dyn Clock>,
+    transferred: u64,
+    // Blocks that the client acknowledged, including the ones before the
+    // transfer was resumed
+    acked: u64,
+    progress: Option<Progress>,
 }
 
 impl<'r, R> ReadRequest<'r, R>
@@ -32,12 +83,13 @@ where
     R: AsyncRead + Send + Unpin,
 {
     pub(crate) async fn init(
-        reader: &'r mut R,
+        source: Source<'r, R>,
         file_size: Option<u64>,
         peer: SocketAddr,
         req: &RwReq,
         config: ServerConfig,
-        local_ip: IpAddr,
+        socket: Box<dyn Transport>,
+        handshake: Option<Handshake>,
     ) -> Result<ReadRequest<'r, R>> {
         let oack_opts = build_oack_opts(&config, req, file_size);
 
@@ -53,51 +105,451 @@ where
             .map(|t| Duration::from_secs(u64::from(t)))
             .unwrap_or(config.timeout);
 
-        let addr = SocketAddr::new(local_ip, 0);
-        let socket = Async::<UdpSocket>::bind(addr).map_err(Error::Bind)?;
+        let window_size = oack_opts
+            .as_ref()
+            .and_then(|o| o.window_size)
+            .map(|w| w as usize)
+            .unwrap_or(1);
+
+        let mut read_req = ReadRequest::new(
+            source,
+            peer,
+            config,
+            socket,
+            block_size,
+            timeout,
+            window_size,
+        );
+        read_req.handshake = handshake;
+        read_req.oack_opts = oack_opts;
+
+        Ok(read_req)
+    }
+
+    /// Continue the transfer of `snapshot` after its acknowledged blocks,
+    /// without a handshake.
+    pub(crate) fn resume(
+        source: Source<'r, R>,
+        snapshot: &TransferSnapshot,
+        config: ServerConfig,
+        socket: Box<dyn Transport>,
+    ) -> ReadRequest<'r, R> {
+        let mut read_req = ReadRequest::new(
+            source,
+            snapshot.client,
+            config,
+            socket,
+            usize::from(snapshot.block_size),
+            snapshot.timeout,
+            usize::from(snapshot.window_size.max(1)),
+        );
+        read_req.acked = snapshot.acked_blocks;
+
+        read_req
+    }
+
+    fn new(
+        source: Source<'r, R>,
+        peer: SocketAddr,
+        config: ServerConfig,
+        socket: Box<dyn Transport>,
+        block_size: usize,
+        timeout: Duration,
+        window_size: usize,
+    ) -> ReadRequest<'r, R> {
+        let handshake_timeout = config.handshake_timeout.unwrap_or(timeout);
+        let oack_timeout = config.oack_timeout.unwrap_or(handshake_timeout);
+        let memory = config
+            .memory_budget
+            .as_ref()
+            .map(|budget| budget.reserve(PACKET_DATA_HEADER_LEN + block_size));
 
-        Ok(ReadRequest {
+        ReadRequest {
             peer,
             socket,
-            reader,
+            source,
             buffer: BytesMut::with_capacity(
                 PACKET_DATA_HEADER_LEN + block_size,
             ),
+            memory,
             block_size,
             timeout,
+            handshake: None,
+            handshake_timeout,
+            started: None,
             max_send_retries: config.max_send_retries,
-            oack_opts,
-        })
+            max_oack_retries: config
+                .max_oack_retries
+                .unwrap_or(config.max_send_retries),
+            oack_timeout,
+            oack_opts: None,
+            options_rejected: false,
+            window_size,
+            adaptive_window: config.adaptive_window,
+            window_packet_gap: config.window_packet_gap,
+            max_bytes_per_sec: config.max_bytes_per_sec,
+            fail_on_conn_reset: config.fail_on_conn_reset,
+            outcome: TransferOutcome::Completed,
+            retransmissions: Retransmissions::default(),
+            error_messages: config.error_messages,
+            cancellation: config.cancellation,
+            clock: config.clock,
+            transferred: 0,
+            acked: 0,
+            progress: None,
+        }
     }
 
-    pub(crate) async fn handle(&mut self) {
-        if let Err(e) = self.try_handle().await {
+    /// Serve the request. On failure the error is sent to the client
+    /// and returned.
+    ///
+    /// Every await point of `try_handle` is cancellation safe: when the
+    /// transfer is cancelled, the error is sent from the state it was
+    /// dropped in.
+    pub(crate) async fn handle(&mut self) -> Result<(), packet::Error> {
+        let res = match self.cancellation.clone() {
+            Some(token) => {
+                let cancelled = async move {
+                    token.cancelled().await;
+                    Err(Error::Cancelled)
+                };
+                future::or(cancelled, self.try_handle()).await
+            }
+            None => self.try_handle().await,
+        };
+
+        if let Err(e) = res {
             trace!("RRQ request failed (peer: {}, error: {})", &self.peer, &e);
 
-            Packet::Error(e.into()).encode(&mut self.buffer);
+            if self.outcome == TransferOutcome::Completed {
+                self.outcome = match e {
+                    Error::MaxSendRetriesReached(..) => {
+                        TransferOutcome::Timeout
+                    }
+                    Error::Cancelled => TransferOutcome::Cancelled,
+                    _ => TransferOutcome::Other,
+                };
+            }
+
+            let reason = e.wire_msg();
+            let e = packet::Error::from(e);
+
+            // Client terminated the transfer with an error
+            if self.outcome == TransferOutcome::ClientError {
+                return Err(e);
+            }
+
+            // Drop what a failed or cancelled block left behind
+            self.buffer.clear();
+            let messages = self.error_messages.as_deref();
+            encode_error(messages, &e, reason, &mut self.buffer);
             let buf = self.buffer.split().freeze();
             // Errors are never retransmitted.
             // We do not care if `send_to` resulted to an IO error.
             let _ = self.socket.send_to(&buf[..], self.peer).await;
+
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Address of the client.
+    pub(crate) fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Bytes of file data that the client acknowledged.
+    pub(crate) fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    /// Negotiated block size.
+    pub(crate) fn block_size(&self) -> u16 {
+        self.block_size as u16
+    }
+
+    /// Negotiated retry timeout.
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Local address of the transfer socket.
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Whether the client rejected the options of the OACK.
+    pub(crate) fn options_rejected(&self) -> bool {
+        self.options_rejected
+    }
+
+    /// Negotiated window size.
+    pub(crate) fn window_size(&self) -> u16 {
+        self.window_size as u16
+    }
+
+    /// How the transfer finished.
+    pub(crate) fn outcome(&self) -> TransferOutcome {
+        self.outcome
+    }
+
+    /// Packets that were retransmitted.
+    pub(crate) fn retransmissions(&self) -> &Retransmissions {
+        &self.retransmissions
+    }
+
+    /// Take back the socket, so the transfer can be restarted from the same
+    /// address.
+    pub(crate) fn into_socket(self) -> Box<dyn Transport> {
+        self.socket
+    }
+
+    /// Run `started` when the client replies for the first time.
+    pub(crate) fn on_started(&mut self, started: StartedNotify) {
+        self.started = Some(started);
+    }
+
+    /// Record the acknowledged blocks in `progress`.
+    pub(crate) fn track_progress(&mut self, progress: Progress) {
+        progress.acked(self.acked);
+        self.progress = Some(progress);
+    }
+
+    /// Handshake is completed with the first reply of the client.
+    async fn client_replied(&mut self) {
+        self.handshake = None;
+
+        if let Some(started) = self.started.take() {
+            started.await;
         }
     }
 
     async fn try_handle(&mut self) -> Result<()> {
-        let mut block_id: u16 = 0;
+        self.skip_acked().await?;
+
+        if self.window_size > 1 {
+            return self.serve_windowed().await;
+        }
+
+        if let Source::BufReader(ref mut reader) = self.source {
+            let reader = reader.take().expect("reader is already taken");
+            return self.serve_buf_reader(reader).await;
+        }
+
+        let mut block_id = self.acked as u16;
+        let mut index = self.acked;
 
         // Send file to client
         loop {
-            let is_last_block;
+            block_id = block_id.wrapping_add(1);
+            let buf = self.read_data(block_id, index).await?;
+            index += 1;
 
-            // Reclaim buffer
-            self.buffer.reserve(PACKET_DATA_HEADER_LEN + self.block_size);
+            // Send OACK after we manage to read the first block from reader.
+            //
+            // We do this because we want to give the developers the option to
+            // produce an error after they construct a reader.
+            self.send_oack().await?;
 
-            // Encode head of Data packet
-            block_id = block_id.wrapping_add(1);
-            Packet::encode_data_head(block_id, &mut self.buffer);
+            // Send Data packet
+            let len = buf.len() - PACKET_DATA_HEADER_LEN;
+            self.send(buf, block_id).await?;
+            self.transferred += len as u64;
+            self.blocks_acked(1);
+
+            if len < self.block_size {
+                break;
+            }
+
+            throttle(&*self.clock, self.max_bytes_per_sec, len).await;
+        }
+
+        trace!("RRQ request served (peer: {})", &self.peer);
+        Ok(())
+    }
+
+    /// Serve the request in windows of blocks, as described in RFC7440.
+    ///
+    /// Blocks are kept until they are acknowledged, because the client
+    /// acknowledges the last block it received in order and expects the
+    /// rest to be sent again.
+    async fn serve_windowed(&mut self) -> Result<()> {
+        // Blocks that are not acknowledged yet
+        let mut unacked = VecDeque::new();
+        // How many blocks at the front of `unacked` were already sent
+        let mut sent = 0;
+        let mut acked_id = self.acked as u16;
+        let mut index = self.acked;
+        let mut eof = false;
+        let mut window = self.window_size;
+        let mut retries = 0;
+
+        loop {
+            let limit = self.budget_window(window, unacked.len());
+
+            while unacked.len() < limit && !eof {
+                let block_id = acked_id.wrapping_add(unacked.len() as u16 + 1);
+                let buf = self.read_data(block_id, index).await?;
+                index += 1;
+
+                eof = buf.len() - PACKET_DATA_HEADER_LEN < self.block_size;
+                unacked.push_back(buf);
+            }
 
-            // Read block in self.buffer
-            let buf = unsafe {
+            if unacked.is_empty() {
+                break;
+            }
+
+            // Same as in `try_handle`, OACK is sent after the first block
+            // is read.
+            self.send_oack().await?;
+
+            let in_flight = cmp::min(unacked.len(), window);
+
+            for (i, buf) in unacked.iter().take(in_flight).enumerate() {
+                if let Some(gap) = self.window_packet_gap.filter(|_| i > 0) {
+                    self.clock.sleep(gap).await;
+                }
+
+                if i < sent {
+                    let block_id = acked_id.wrapping_add(i as u16 + 1);
+                    self.retransmissions.add(block_id);
+                }
+
+                self.socket.send_to(buf, self.peer).await?;
+
+                let len = buf.len() - PACKET_DATA_HEADER_LEN;
+                throttle(&*self.clock, self.max_bytes_per_sec, len).await;
+            }
+
+            sent = cmp::max(sent, in_flight);
+
+            let timeout = self.data_timeout();
+            let window_start = acked_id;
+            // ACK of the last acknowledged block asks for the window again
+            let in_window = |block_id: u16| {
+                let n = usize::from(block_id.wrapping_sub(window_start));
+                n <= in_flight
+            };
+
+            match self.recv_ack(timeout, in_window).await {
+                Ok(Reply::Ack(block_id)) if block_id == acked_id => {
+                    self.client_replied().await;
+                    trace!(
+                        "RRQ (peer: {}, block_id: {}) - Received duplicate ACK",
+                        &self.peer,
+                        block_id
+                    );
+
+                    // Window is sent again at once. Duplicates count as
+                    // retries, so they can not keep the transfer going.
+                    retries += 1;
+
+                    if retries > self.max_send_retries {
+                        return Err(Error::MaxSendRetriesReached(
+                            self.peer,
+                            acked_id.wrapping_add(1),
+                        ));
+                    }
+
+                    if self.adaptive_window {
+                        window = cmp::max(window / 2, 1);
+                    }
+                }
+                Ok(Reply::Ack(block_id)) => {
+                    self.client_replied().await;
+                    trace!(
+                        "RRQ (peer: {}, block_id: {}) - Received ACK",
+                        &self.peer,
+                        block_id
+                    );
+
+                    let n = usize::from(block_id.wrapping_sub(acked_id));
+
+                    for buf in unacked.drain(..n) {
+                        let len = buf.len() - PACKET_DATA_HEADER_LEN;
+                        self.transferred += len as u64;
+                    }
+
+                    acked_id = block_id;
+                    sent -= n;
+                    self.blocks_acked(n as u64);
+                    retries = 0;
+
+                    if self.adaptive_window {
+                        window = if n == in_flight {
+                            cmp::min(window + 1, self.window_size)
+                        } else {
+                            cmp::max(window / 2, 1)
+                        };
+                    }
+                }
+                Ok(Reply::Error(e)) => {
+                    trace!("RRQ (peer: {}) - Client error: {}", &self.peer, &e);
+
+                    self.outcome = TransferOutcome::ClientError;
+                    return Err(Error::Packet(e));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                    trace!(
+                        "RRQ (peer: {}, block_id: {}) - Timeout",
+                        &self.peer,
+                        acked_id.wrapping_add(1)
+                    );
+
+                    retries += 1;
+
+                    if retries > self.max_send_retries {
+                        return Err(Error::MaxSendRetriesReached(
+                            self.peer,
+                            acked_id.wrapping_add(1),
+                        ));
+                    }
+
+                    if self.adaptive_window {
+                        window = cmp::max(window / 2, 1);
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        trace!("RRQ request served (peer: {})", &self.peer);
+        Ok(())
+    }
+
+    /// Number of blocks, up to `window`, that fit in the memory budget.
+    /// The `held` blocks, and at least one, always fit.
+    fn budget_window(&mut self, window: usize, held: usize) -> usize {
+        let memory = match self.memory {
+            Some(ref mut memory) => memory,
+            None => return window,
+        };
+
+        let packet_len = PACKET_DATA_HEADER_LEN + self.block_size;
+        let min = cmp::max(held, 1) * packet_len;
+        let limit = memory.resize(window * packet_len, min) / packet_len;
+
+        if limit < window {
+            trace!(
+                "RRQ (peer: {}) - Window limited to {} blocks by memory budget",
+                &self.peer,
+                limit
+            );
+        }
+
+        limit
+    }
+
+    /// Read the block at `index` and encode it as DATA packet.
+    async fn read_data(&mut self, block_id: u16, index: u64) -> Result<Bytes> {
+        // Reclaim buffer
+        self.buffer.reserve(PACKET_DATA_HEADER_LEN + self.block_size);
+        Packet::encode_data_head_to(block_id, &mut self.buffer);
+
+        match self.source {
+            Source::Reader(_) => unsafe {
                 let uninit_buf = self.buffer.chunk_mut();
 
                 let data_buf = slice::from_raw_parts_mut(
@@ -106,44 +558,230 @@ where
                 );
 
                 let len = self.read_block(data_buf).await?;
-                is_last_block = len < self.block_size;
-
                 self.buffer.advance_mut(len);
-                self.buffer.split().freeze()
-            };
-
-            // Send OACK after we manage to read the first block from reader.
-            //
-            // We do this because we want to give the developers the option to
-            // produce an error after they construct a reader.
-            if let Some(opts) = self.oack_opts.take() {
-                trace!("RRQ OACK (peer: {}, opts: {:?}", &self.peer, &opts);
+            },
+            // Only windowed transfers copy from the buffer of the reader
+            Source::BufReader(ref mut reader) => {
+                let reader = reader.as_mut().expect("reader is already taken");
+                let mut len = 0;
+
+                while len < self.block_size {
+                    let chunk = match reader.fill_buf().await {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            self.outcome = TransferOutcome::HandlerIo;
+                            return Err(e.into());
+                        }
+                    };
+
+                    if chunk.is_empty() {
+                        break;
+                    }
 
-                let mut buf = BytesMut::new();
-                Packet::OAck(opts.to_owned()).encode(&mut buf);
+                    let n = cmp::min(chunk.len(), self.block_size - len);
+                    self.buffer.put_slice(&chunk[..n]);
+                    reader.consume(n);
+                    len += n;
+                }
+            }
+            Source::Blocks(ref mut blocks) => {
+                let data = match blocks.block(index, self.block_size).await {
+                    Ok(data) => data,
+                    Err(e) => return Err(self.handler_failed(e)),
+                };
+
+                if data.len() > self.block_size {
+                    return Err(self.handler_failed(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "block source returned oversized block",
+                    )));
+                }
 
-                self.send(buf.split().freeze(), 0).await?;
+                self.buffer.put_slice(&data);
             }
+        }
 
-            // Send Data packet
-            self.send(buf, block_id).await?;
+        Ok(self.buffer.split().freeze())
+    }
+
+    /// Same as `try_handle`, but full blocks are sent directly from the
+    /// buffer of `reader`.
+    async fn serve_buf_reader(
+        &mut self,
+        reader: &mut (dyn AsyncBufRead + Unpin + Send),
+    ) -> Result<()> {
+        let mut block_id = self.acked as u16;
+        let mut head = BytesMut::with_capacity(PACKET_DATA_HEADER_LEN);
+
+        loop {
+            block_id = block_id.wrapping_add(1);
+
+            let chunk =
+                reader.fill_buf().await.map_err(|e| self.handler_failed(e))?;
+
+            let len = if chunk.len() >= self.block_size {
+                let payload = &chunk[..self.block_size];
+
+                Packet::encode_data_head_to(block_id, &mut head);
+                let head = head.split();
+
+                self.send_oack().await?;
+                self.send_vectored(
+                    &[IoSlice::new(&head), IoSlice::new(payload)],
+                    block_id,
+                )
+                .await?;
+
+                reader.consume(self.block_size);
+                self.block_size
+            } else {
+                // Buffer holds less than a block, so we assemble it
+                self.buffer.reserve(PACKET_DATA_HEADER_LEN + self.block_size);
+                Packet::encode_data_head_to(block_id, &mut self.buffer);
+
+                let mut len = 0;
 
-            if is_last_block {
+                while len < self.block_size {
+                    let chunk = reader
+                        .fill_buf()
+                        .await
+                        .map_err(|e| self.handler_failed(e))?;
+
+                    if chunk.is_empty() {
+                        break;
+                    }
+
+                    let n = cmp::min(chunk.len(), self.block_size - len);
+                    self.buffer.put_slice(&chunk[..n]);
+                    reader.consume(n);
+                    len += n;
+                }
+
+                let buf = self.buffer.split().freeze();
+
+                self.send_oack().await?;
+                self.send(buf, block_id).await?;
+
+                len
+            };
+
+            self.transferred += len as u64;
+            self.blocks_acked(1);
+
+            if len < self.block_size {
                 break;
             }
+
+            throttle(&*self.clock, self.max_bytes_per_sec, len).await;
         }
 
         trace!("RRQ request served (peer: {})", &self.peer);
         Ok(())
     }
 
+    fn blocks_acked(&mut self, n: u64) {
+        self.acked += n;
+
+        if let Some(ref progress) = self.progress {
+            progress.acked(self.acked);
+        }
+    }
+
+    /// Skip the data of the blocks that were acknowledged before the
+    /// transfer was resumed.
+    async fn skip_acked(&mut self) -> Result<()> {
+        let len = self.acked * self.block_size as u64;
+
+        let res = match self.source {
+            _ if len == 0 => Ok(()),
+            Source::Reader(ref mut reader) => skip(&mut **reader, len).await,
+            Source::BufReader(ref mut reader) => {
+                let reader = reader.as_mut().expect("reader is already taken");
+                skip(&mut **reader, len).await
+            }
+            // Blocks are read by index
+            Source::Blocks(_) => Ok(()),
+        };
+
+        res.map_err(|e| self.handler_failed(e))
+    }
+
+    /// Send OACK, if there are options to acknowledge.
+    async fn send_oack(&mut self) -> Result<()> {
+        if let Some(opts) = self.oack_opts.take() {
+            trace!("RRQ OACK (peer: {}, opts: {:?}", &self.peer, &opts);
+
+            let mut buf = BytesMut::new();
+            Packet::OAck(opts.to_owned()).encode_to(&mut buf);
+
+            let buf = buf.split().freeze();
+
+            self.send_with(
+                &[IoSlice::new(&buf)],
+                0,
+                self.max_oack_retries,
+                self.oack_timeout,
+                true,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
     async fn send(&mut self, packet: Bytes, block_id: u16) -> Result<()> {
-        // Send packet until we receive an ack
-        for _ in 0..=self.max_send_retries {
-            self.socket.send_to(&packet[..], self.peer).await?;
+        self.send_vectored(&[IoSlice::new(&packet)], block_id).await
+    }
+
+    async fn send_vectored(
+        &mut self,
+        packet: &[IoSlice<'_>],
+        block_id: u16,
+    ) -> Result<()> {
+        let timeout = self.data_timeout();
 
-            match self.recv_ack(block_id).await {
-                Ok(_) => {
+        self.send_with(packet, block_id, self.max_send_retries, timeout, false)
+            .await
+    }
+
+    fn data_timeout(&self) -> Duration {
+        match self.handshake {
+            Some(_) => self.handshake_timeout,
+            None => self.timeout,
+        }
+    }
+
+    /// Send packet until we receive an ack, or retries are exhausted.
+    async fn send_with(
+        &mut self,
+        packet: &[IoSlice<'_>],
+        block_id: u16,
+        max_retries: u32,
+        timeout: Duration,
+        is_oack: bool,
+    ) -> Result<()> {
+        for attempt in 0..=max_retries {
+            if attempt > 0 {
+                self.retransmissions.add(block_id);
+            }
+
+            match packet {
+                [buf] => self.socket.send_to(buf, self.peer).await?,
+                _ => self.socket.send_to_vectored(packet, self.peer).await?,
+            };
+
+            match self.recv_ack(timeout, |id| id == block_id).await {
+                Ok(Reply::Error(e)) => {
+                    trace!("RRQ (peer: {}) - Client error: {}", &self.peer, &e);
+
+                    self.options_rejected =
+                        is_oack && e == packet::Error::OptionsNegotiationFailed;
+                    self.outcome = TransferOutcome::ClientError;
+
+                    return Err(Error::Packet(e));
+                }
+                Ok(Reply::Ack(_)) => {
+                    self.client_replied().await;
                     trace!(
                         "RRQ (peer: {}, block_id: {}) - Received ACK",
                         &self.peer,
@@ -166,17 +804,34 @@ where
         Err(Error::MaxSendRetriesReached(self.peer, block_id))
     }
 
-    async fn recv_ack(&mut self, block_id: u16) -> io::Result<()> {
+    /// Wait for an ACK of a block that `accept` returns true for.
+    async fn recv_ack(
+        &mut self,
+        timeout: Duration,
+        accept: impl Fn(u16) -> bool,
+    ) -> io::Result<Reply> {
         // We can not use `self` within `async_std::io::timeout` because not all
         // struct members implement `Sync`. So we borrow only what we need.
         let socket = &mut self.socket;
         let peer = self.peer;
+        let fail_on_conn_reset = self.fail_on_conn_reset;
 
-        io_timeout(self.timeout, async {
+        io_timeout(&*self.clock, timeout, async {
+            // Replies are ACKs and errors, whose messages may be longer
+            // than small blocks
             let mut buf = [0u8; 1024];
 
             loop {
-                let (len, recved_peer) = socket.recv_from(&mut buf[..]).await?;
+                let (len, recved_peer) = match socket
+                    .recv_from(&mut buf[..])
+                    .await
+                {
+                    Ok(x) => x,
+                    Err(ref e) if is_conn_reset(e) && !fail_on_conn_reset => {
+                        continue
+                    }
+                    Err(e) => return Err(e),
+                };
 
                 // if the packet do not come from the client we are serving, then ignore it
                 if recved_peer != peer {
@@ -184,32 +839,69 @@ where
                 }
 
                 // parse only valid Ack packets, the rest are ignored
-                if let Ok(Packet::Ack(recved_block_id)) =
-                    Packet::decode(&buf[..len])
-                {
-                    if recved_block_id == block_id {
-                        return Ok(());
+                // ACKs can not move the transfer backwards, so ACKs outside
+                // of the window are dropped without extending the timeout
+                match Packet::decode(&buf[..len]) {
+                    Ok(Packet::Ack(block_id)) if accept(block_id) => {
+                        return Ok(Reply::Ack(block_id));
                     }
+                    Ok(Packet::Ack(block_id)) => {
+                        trace!(
+                            "RRQ (peer: {}, block_id: {}) - Dropped ACK \
+                             outside of window",
+                            &peer,
+                            block_id
+                        );
+                    }
+                    Ok(Packet::Error(e)) => return Ok(Reply::Error(e)),
+                    _ => {}
                 }
             }
         })
-        .await?;
-
-        Ok(())
+        .await
     }
 
     async fn read_block(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let reader = match self.source {
+            Source::Reader(ref mut reader) => reader,
+            _ => unreachable!(),
+        };
         let mut len = 0;
 
         while len < buf.len() {
-            match self.reader.read(&mut buf[len..]).await? {
-                0 => break,
-                x => len += x,
+            match reader.read(&mut buf[len..]).await {
+                Ok(0) => break,
+                Ok(x) => len += x,
+                Err(e) => return Err(self.handler_failed(e)),
             }
         }
 
         Ok(len)
     }
+
+    /// Record that reading the file failed.
+    fn handler_failed(&mut self, e: io::Error) -> Error {
+        self.outcome = TransferOutcome::HandlerIo;
+        Error::Io(e)
+    }
+}
+
+/// Read and drop `len` bytes of `reader`.
+async fn skip<T>(reader: &mut T, len: u64) -> io::Result<()>
+where
+    T: AsyncRead + Unpin + ?Sized,
+{
+    let sink = futures_lite::io::sink();
+    let skipped = futures_lite::io::copy(reader.take(len), sink).await?;
+
+    if skipped < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "file is shorter than the resumed transfer",
+        ));
+    }
+
+    Ok(())
 }
 
 fn build_oack_opts(
@@ -227,12 +919,19 @@ fn build_oack_opts(
         };
     }
 
-    if !config.ignore_client_timeout {
-        opts.timeout = req.opts.timeout;
+    opts.timeout = config.client_timeout(req);
+
+    if let (Some(size), Some(limit)) =
+        (req.opts.window_size, config.max_window_size)
+    {
+        opts.window_size = Some(cmp::min(size, u64::from(limit)).max(1));
     }
 
-    if let (Some(0), Some(file_size)) = (req.opts.transfer_size, file_size) {
-        opts.transfer_size = Some(file_size);
+    match (req.opts.transfer_size, file_size) {
+        (Some(0), Some(file_size)) if !config.omit_transfer_size => {
+            opts.transfer_size = Some(file_size);
+        }
+        _ => {}
     }
 
     if opts == Opts::default() {
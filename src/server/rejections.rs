@@ -0,0 +1,229 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::{AuditRecord, AuditSink, TransferOutcome};
+use crate::packet;
+
+/// Shards of the counters by client IP, so concurrent requests of different
+/// clients rarely contend for the same lock.
+const SHARDS: usize = 16;
+
+/// Counters by client of each shard.
+const MAX_SHARD_CLIENTS: usize = RejectionStats::MAX_CLIENTS / SHARDS;
+
+/// Why a request was rejected before its transfer started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RejectionReason {
+    /// File was not found.
+    NotFound,
+    /// Access was denied, e.g. by [`Authorizer`].
+    ///
+    /// [`Authorizer`]: super::Authorizer
+    AccessDenied,
+    /// Options of the request were refused by the server, or the OACK by
+    /// the client.
+    BadOptions,
+    /// Transfer mode is not supported.
+    UnsupportedMode,
+    /// Request exceeded the size, filename length or number of options that
+    /// the server accepts.
+    OversizedRequest,
+    /// Request was ignored, because too many handshakes were pending.
+    Dropped,
+    /// Any other error.
+    Other,
+}
+
+impl RejectionReason {
+    /// Name of the reason, as used in logs and metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectionReason::NotFound => "not_found",
+            RejectionReason::AccessDenied => "access_denied",
+            RejectionReason::BadOptions => "bad_options",
+            RejectionReason::UnsupportedMode => "unsupported_mode",
+            RejectionReason::OversizedRequest => "oversized_request",
+            RejectionReason::Dropped => "dropped",
+            RejectionReason::Other => "other",
+        }
+    }
+
+    /// Number of reasons, i.e. of counters in [`RejectionStats`].
+    const COUNT: usize = 7;
+
+    /// Index of the counter of the reason, below [`COUNT`](Self::COUNT).
+    fn index(self) -> usize {
+        match self {
+            RejectionReason::NotFound => 0,
+            RejectionReason::AccessDenied => 1,
+            RejectionReason::BadOptions => 2,
+            RejectionReason::UnsupportedMode => 3,
+            RejectionReason::OversizedRequest => 4,
+            RejectionReason::Dropped => 5,
+            RejectionReason::Other => 6,
+        }
+    }
+
+    fn from_error(error: &packet::Error) -> Self {
+        match error {
+            packet::Error::FileNotFound => RejectionReason::NotFound,
+            packet::Error::PermissionDenied | packet::Error::NoSuchUser => {
+                RejectionReason::AccessDenied
+            }
+            packet::Error::OptionsNegotiationFailed => {
+                RejectionReason::BadOptions
+            }
+            _ => RejectionReason::Other,
+        }
+    }
+}
+
+impl std::fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Audit sink that counts requests that failed before their transfer
+/// started, by reason and client IP.
+///
+/// Besides requests that the server rejected, this counts requests that
+/// exceeded the limits of the server or were dropped by the limit of pending
+/// handshakes, and transfers that the client terminated by rejecting the
+/// OACK. Totals by reason are atomic and counters by client are sharded by
+/// IP, so counting does not serialize the requests of the server.
+///
+/// At most [`MAX_CLIENTS`](Self::MAX_CLIENTS) counters by client are kept.
+/// When they are full, the counter with the lowest count of its shard is
+/// evicted, so a flood of requests from spoofed addresses can not grow
+/// them without bounds.
+///
+/// Clones share the counters, so keep a clone to read them while the server
+/// runs:
+///
+/// ```ignore
+/// let stats = RejectionStats::new();
+/// let tftpd = TftpServerBuilder::with_dir_ro(".")?
+///     .audit(stats.clone())
+///     .build()
+///     .await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct RejectionStats {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug)]
+struct Counters {
+    // Indexed by `RejectionReason::index`
+    totals: [AtomicU64; RejectionReason::COUNT],
+    by_client: Vec<Mutex<HashMap<(IpAddr, RejectionReason), u64>>>,
+}
+
+impl RejectionStats {
+    /// Maximum number of client IP and reason pairs that are counted.
+    pub const MAX_CLIENTS: usize = 4096;
+
+    /// Create new sink with zero counters.
+    pub fn new() -> Self {
+        RejectionStats {
+            inner: Arc::new(Counters {
+                totals: Default::default(),
+                by_client: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            }),
+        }
+    }
+
+    /// Number of rejections for `reason`, of all clients.
+    pub fn count(&self, reason: RejectionReason) -> u64 {
+        self.inner.totals[reason.index()].load(Ordering::Relaxed)
+    }
+
+    /// Number of rejections for every client IP and reason.
+    pub fn by_client(&self) -> HashMap<(IpAddr, RejectionReason), u64> {
+        let mut counts = HashMap::new();
+
+        for shard in &self.inner.by_client {
+            counts.extend(shard.lock().unwrap().iter());
+        }
+
+        counts
+    }
+
+    /// Reset all counters.
+    ///
+    /// Rejections that are counted meanwhile may be kept in some counters
+    /// only.
+    pub fn reset(&self) {
+        for total in &self.inner.totals {
+            total.store(0, Ordering::Relaxed);
+        }
+
+        for shard in &self.inner.by_client {
+            shard.lock().unwrap().clear();
+        }
+    }
+
+    fn shard(
+        &self,
+        ip: IpAddr,
+    ) -> &Mutex<HashMap<(IpAddr, RejectionReason), u64>> {
+        let mut hasher = DefaultHasher::new();
+        ip.hash(&mut hasher);
+        &self.inner.by_client[hasher.finish() as usize % SHARDS]
+    }
+}
+
+impl Default for RejectionStats {
+    fn default() -> Self {
+        RejectionStats::new()
+    }
+}
+
+#[crate::async_trait]
+impl AuditSink for RejectionStats {
+    async fn record(&self, record: &AuditRecord) {
+        let reason = match (record.outcome, &record.error) {
+            (TransferOutcome::UnsupportedMode, _) => {
+                RejectionReason::UnsupportedMode
+            }
+            (TransferOutcome::OversizedRequest, _) => {
+                RejectionReason::OversizedRequest
+            }
+            (TransferOutcome::Dropped, _) => RejectionReason::Dropped,
+            (TransferOutcome::Rejected, Some(e)) => {
+                RejectionReason::from_error(e)
+            }
+            (
+                TransferOutcome::ClientError,
+                Some(packet::Error::OptionsNegotiationFailed),
+            ) => RejectionReason::BadOptions,
+            _ => return,
+        };
+
+        let ip = record.client.ip();
+
+        self.inner.totals[reason.index()].fetch_add(1, Ordering::Relaxed);
+
+        let mut shard = self.shard(ip).lock().unwrap();
+        let key = (ip, reason);
+
+        if !shard.contains_key(&key) && shard.len() >= MAX_SHARD_CLIENTS {
+            let evicted = shard
+                .iter()
+                .min_by_key(|(_, &count)| count)
+                .map(|(&key, _)| key);
+
+            if let Some(evicted) = evicted {
+                shard.remove(&evicted);
+            }
+        }
+
+        *shard.entry(key).or_default() += 1;
+    }
+}
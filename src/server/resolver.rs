@@ -0,0 +1,27 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::packet;
+
+/// Trait for integrating with DHCP/PXE provisioning.
+///
+/// The resolver is consulted for every read request after [`Authorizer`],
+/// and it decides which file is actually served to the client, e.g. by
+/// looking up the lease of the client IP in a DHCP database. Return
+/// `Ok(None)` to serve the requested file, or an error to reply with it.
+///
+/// The resolved path is passed to [`Handler`] and reported in
+/// [`TransferContext::path`]. Audit records keep the requested filename.
+///
+/// [`Authorizer`]: super::Authorizer
+/// [`Handler`]: super::Handler
+/// [`TransferContext::path`]: super::TransferContext::path
+#[crate::async_trait]
+pub trait BootSessionResolver: Send + Sync {
+    /// Resolve the file that `client` gets when it requests `path`.
+    async fn resolve(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<Option<PathBuf>, packet::Error>;
+}
@@ -0,0 +1,100 @@
+use async_lock::Semaphore;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Detects clients that abandon a read request and retry it from a new
+/// port.
+pub(crate) struct RestartDetector {
+    window: Duration,
+    reads: Mutex<HashMap<SocketAddr, ActiveRead>>,
+}
+
+struct ActiveRead {
+    filename: Vec<u8>,
+    received: Instant,
+    cancel: Arc<Semaphore>,
+}
+
+/// Registration of a running read request. It is removed on drop.
+pub(crate) struct Read {
+    detector: Arc<RestartDetector>,
+    peer: SocketAddr,
+    cancel: Arc<Semaphore>,
+}
+
+impl RestartDetector {
+    pub(crate) fn new(window: Duration) -> Self {
+        RestartDetector {
+            window,
+            reads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register a read request of `filename` from `peer`.
+    ///
+    /// If the same IP requested the same file from another port within the
+    /// window, that request is cancelled and `true` is returned.
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        peer: SocketAddr,
+        filename: &[u8],
+    ) -> (Read, bool) {
+        let mut reads = self.reads.lock().unwrap();
+        let now = Instant::now();
+
+        let abandoned = reads.iter().find(|(addr, read)| {
+            addr.ip() == peer.ip()
+                && **addr != peer
+                && read.filename == filename
+                && now.duration_since(read.received) <= self.window
+        });
+
+        let restarted = match abandoned {
+            Some((_, read)) => {
+                read.cancel.add_permits(1);
+                true
+            }
+            None => false,
+        };
+
+        // No permits until the request is cancelled
+        let cancel = Arc::new(Semaphore::new(0));
+
+        reads.insert(
+            peer,
+            ActiveRead {
+                filename: filename.to_owned(),
+                received: now,
+                cancel: Arc::clone(&cancel),
+            },
+        );
+
+        let read = Read {
+            detector: Arc::clone(self),
+            peer,
+            cancel,
+        };
+
+        (read, restarted)
+    }
+}
+
+impl Read {
+    /// Resolves when the client restarts the request from another port.
+    pub(crate) fn cancelled(&self) -> impl Future<Output = ()> {
+        let cancel = Arc::clone(&self.cancel);
+
+        async move {
+            cancel.acquire_arc().await;
+        }
+    }
+}
+
+impl Drop for Read {
+    fn drop(&mut self) {
+        self.detector.reads.lock().unwrap().remove(&self.peer);
+    }
+}
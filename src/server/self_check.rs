@@ -0,0 +1,173 @@
+use futures_lite::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use super::{Handler, TftpServer};
+use crate::client::TftpClient;
+use crate::error::{Error, Result};
+
+/// Content that the write request of [`SelfCheck`] uploads.
+const SELF_CHECK_DATA: &[u8] = b"async-tftp self-check\n";
+
+/// Probe that checks a server end to end over loopback, e.g. for health
+/// checks of orchestrated deployments.
+///
+/// It is created with [`TftpServer::self_check`] before the server is
+/// consumed by [`TftpServer::serve`], and can be run any time while it is
+/// served. Each run reads [`read_file`](Self::read_file) and uploads a few
+/// bytes to [`write_file`](Self::write_file), so the handler must be
+/// prepared for them.
+#[derive(Debug, Clone)]
+pub struct SelfCheck {
+    addr: SocketAddr,
+    read_file: String,
+    write_file: String,
+    timeout: Duration,
+}
+
+/// Result of a [`SelfCheck`] run.
+#[derive(Debug)]
+pub struct SelfCheckReport {
+    /// Result of the read request.
+    pub read: ProbeReport,
+    /// Result of the write request.
+    pub write: ProbeReport,
+}
+
+/// Result of a single request of [`SelfCheck`].
+#[derive(Debug)]
+pub struct ProbeReport {
+    /// Requested filename.
+    pub filename: String,
+    /// Time until the transfer finished or failed.
+    pub duration: Duration,
+    /// Bytes that were transferred.
+    pub transferred: u64,
+    /// Result of the transfer.
+    pub result: Result<()>,
+}
+
+impl<H: 'static> TftpServer<H>
+where
+    H: Handler,
+{
+    /// Create a probe that checks this server over loopback.
+    ///
+    /// See [`SelfCheck`].
+    pub fn self_check(&self) -> Result<SelfCheck> {
+        let listen_addr = self.listen_addr()?;
+
+        // A wildcard address is reached through loopback
+        let ip = match listen_addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => {
+                IpAddr::V4(Ipv4Addr::LOCALHOST)
+            }
+            IpAddr::V6(ip) if ip.is_unspecified() => {
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            }
+            ip => ip,
+        };
+
+        Ok(SelfCheck {
+            addr: SocketAddr::new(ip, listen_addr.port()),
+            read_file: "self-check".to_string(),
+            write_file: "self-check".to_string(),
+            timeout: Duration::from_secs(1),
+        })
+    }
+}
+
+impl SelfCheck {
+    /// Set the file that is read.
+    ///
+    /// **Default:** `self-check`
+    pub fn read_file<S: Into<String>>(self, filename: S) -> Self {
+        SelfCheck {
+            read_file: filename.into(),
+            ..self
+        }
+    }
+
+    /// Set the file that is written.
+    ///
+    /// **Default:** `self-check`
+    pub fn write_file<S: Into<String>>(self, filename: S) -> Self {
+        SelfCheck {
+            write_file: filename.into(),
+            ..self
+        }
+    }
+
+    /// Set retry timeout of the requests. Each packet is retried three
+    /// times.
+    ///
+    /// **Default:** 1 second
+    pub fn timeout(self, timeout: Duration) -> Self {
+        SelfCheck {
+            timeout,
+            ..self
+        }
+    }
+
+    /// Address that the probe sends its requests to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Read and write a file, and report the results.
+    pub async fn run(&self) -> SelfCheckReport {
+        let client =
+            TftpClient::new(self.addr).timeout(self.timeout).max_retries(3);
+
+        let started = Instant::now();
+        let res = client.read_to_vec(self.read_file.as_str()).await;
+
+        let read = ProbeReport {
+            filename: self.read_file.clone(),
+            duration: started.elapsed(),
+            transferred: res.as_ref().map_or(0, |data| data.len() as u64),
+            result: res.map(|_| ()),
+        };
+
+        let started = Instant::now();
+        let len = SELF_CHECK_DATA.len() as u64;
+        let res = client
+            .write(
+                self.write_file.as_str(),
+                Cursor::new(SELF_CHECK_DATA),
+                Some(len),
+            )
+            .await;
+
+        let write = ProbeReport {
+            filename: self.write_file.clone(),
+            duration: started.elapsed(),
+            transferred: *res.as_ref().unwrap_or(&0),
+            result: res.map(|_| ()),
+        };
+
+        SelfCheckReport {
+            read,
+            write,
+        }
+    }
+}
+
+impl SelfCheckReport {
+    /// Whether both requests succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.read.result.is_ok() && self.write.result.is_ok()
+    }
+
+    /// Whether the server answered both requests, even if with an error.
+    pub fn responded(&self) -> bool {
+        self.read.responded() && self.write.responded()
+    }
+}
+
+impl ProbeReport {
+    /// Whether the server answered the request, even if with an error.
+    pub fn responded(&self) -> bool {
+        matches!(self.result, Ok(()) | Err(Error::Remote(_)))
+    }
+}
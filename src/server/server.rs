@@ -1,67 +1,330 @@
 use async_executor::Executor;
 use async_io::Async;
-use async_lock::Mutex;
-use log::trace;
-use std::collections::HashSet;
+use async_lock::{Mutex, MutexGuard, RwLock};
+use bytes::BufMut;
+use futures_lite::{future, FutureExt};
+use log::{error, trace};
+use std::any::Any;
+use std::cmp;
+use std::collections::HashMap;
 use std::future::Future;
+use std::io;
 use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
+use super::counter::RequestCounter;
+use super::limiter::{
+    ActiveRequest, Handshake, HandshakeLimiter, MemoryBudget, Permit,
+    RequestLimiter, TransferLimiter,
+};
+use super::mirror::Mirror;
 use super::read_req::*;
+use super::restart::RestartDetector;
+use super::shards::Shards;
 use super::write_req::*;
-use super::Handler;
+use super::{
+    AuditRecord, AuditSink, Authorizer, BlockSizePolicy, BootSessionResolver,
+    CancellationToken, Direction, DuplicateBlocks, Handler, KeepState, MacAddr,
+    NegotiatedOptions, OversizedDatagrams, RefusedRecords, Retransmissions,
+    TransferContext, TransferOutcome, TransferParams, TransferSnapshot,
+    TransferState,
+};
+use crate::clock::Clock;
 use crate::error::*;
-use crate::packet::{Packet, RwReq};
+use crate::packet::{self, Mode, Opts, Packet, RwReq};
+use crate::transport::Transport;
+use crate::utils::{bind_with, is_conn_reset, path_to_bytes, SocketOpts};
+
+/// Attempts to bind a random transfer port before giving up.
+const RANDOM_PORT_ATTEMPTS: usize = 32;
 
 /// TFTP server.
 pub struct TftpServer<H>
 where
     H: Handler,
 {
-    pub(crate) socket: Async<UdpSocket>,
-    pub(crate) handler: Arc<Mutex<H>>,
-    pub(crate) reqs_in_progress: Arc<Mutex<HashSet<SocketAddr>>>,
-    pub(crate) ex: Executor<'static>,
+    pub(crate) socket: Arc<dyn Transport>,
+    // One per shard with `TftpServerBuilder::shard_handlers`
+    pub(crate) handlers: Vec<Arc<HandlerLock<H>>>,
+    // One per shard, so shards do not contend for the same lock
+    pub(crate) reqs: Vec<Arc<Mutex<ReqMap>>>,
+    // Shared with the request tasks that spawn mirrored requests
+    pub(crate) ex: Arc<Executor<'static>>,
+    pub(crate) shards: Option<Shards>,
+    // Read locked by every request task
+    pub(crate) running: Arc<RwLock<()>>,
     pub(crate) config: ServerConfig,
-    pub(crate) local_ip: IpAddr,
+    pub(crate) transfer_addr: TransferAddr,
+    // Transfers of a previous server that are resumed when serving starts
+    pub(crate) resumed: Vec<TransferSnapshot>,
+    // ERROR packet of shed requests, encoded once
+    pub(crate) busy_reply: Vec<u8>,
+    pub(crate) refused: RefusedRecords,
+}
+
+pub(crate) type ErrorMessageFn =
+    dyn Fn(&packet::Error) -> Option<String> + Send + Sync;
+
+/// Encode ERROR packet of `error`, with the message that `messages`
+/// returns for it. Otherwise `reason` replaces the default message of the
+/// code.
+pub(crate) fn encode_error<B: BufMut>(
+    messages: Option<&ErrorMessageFn>,
+    error: &packet::Error,
+    reason: Option<&str>,
+    buf: &mut B,
+) {
+    match messages.and_then(|f| f(error)) {
+        Some(msg) => error.encode_with_msg(&msg, buf),
+        None => match reason {
+            Some(msg) => error.encode_with_msg(msg, buf),
+            None => Packet::Error(error.clone()).encode_to(buf),
+        },
+    }
+}
+
+pub(crate) type SocketHookFn =
+    dyn Fn(&socket2::Socket, SocketAddr) -> io::Result<()> + Send + Sync;
+
+/// ERROR packet that answers shed requests.
+pub(crate) fn busy_reply(messages: Option<&ErrorMessageFn>) -> Vec<u8> {
+    let mut data = Vec::new();
+    let error = packet::Error::Msg("Server busy".to_string());
+    encode_error(messages, &error, None, &mut data);
+    data
+}
+
+/// Where the sockets of transfers are bound.
+#[derive(Clone)]
+pub(crate) struct TransferAddr {
+    // Port is 0, unless replies are sent from the listening port
+    pub(crate) addr: SocketAddr,
+    // Ports are drawn randomly from this range, if port is 0
+    pub(crate) ports: Option<(u16, u16)>,
+    // Set on every socket, before the socket hook
+    pub(crate) opts: SocketOpts,
+    // Sets up every socket before it is bound
+    pub(crate) socket_hook: Option<Arc<SocketHookFn>>,
+    // Listening transport that binds the sockets instead, if it is not UDP
+    pub(crate) transport: Option<Arc<dyn Transport>>,
 }
 
 #[derive(Clone)]
 pub(crate) struct ServerConfig {
     pub(crate) timeout: Duration,
     pub(crate) block_size_limit: Option<u16>,
+    pub(crate) min_block_size: Option<(u16, BlockSizePolicy)>,
     pub(crate) max_send_retries: u32,
+    pub(crate) max_bytes_per_sec: Option<u64>,
+    pub(crate) write_buffer_size: usize,
+    pub(crate) legacy_params: Option<TransferParams>,
+    pub(crate) max_oack_retries: Option<u32>,
+    pub(crate) oack_timeout: Option<Duration>,
+    pub(crate) max_window_size: Option<u16>,
+    pub(crate) adaptive_window: bool,
+    pub(crate) window_packet_gap: Option<Duration>,
     pub(crate) ignore_client_timeout: bool,
+    pub(crate) min_client_timeout: Option<Duration>,
+    pub(crate) max_client_timeout: Option<Duration>,
+    // Acknowledged instead of the timeout that the client requested
+    pub(crate) client_timeout_override: Option<Duration>,
     pub(crate) ignore_client_block_size: bool,
+    pub(crate) omit_transfer_size: bool,
+    pub(crate) max_filename_len: Option<usize>,
+    pub(crate) max_request_options: Option<usize>,
+    pub(crate) max_request_size: Option<usize>,
+    pub(crate) max_write_size: Option<u64>,
+    pub(crate) authorizer: Option<Arc<dyn Authorizer>>,
+    pub(crate) resolver: Option<Arc<dyn BootSessionResolver>>,
+    pub(crate) audit: Option<Arc<dyn AuditSink>>,
+    pub(crate) transfer_limiter: Option<Arc<TransferLimiter>>,
+    pub(crate) handshake_limiter: Option<Arc<HandshakeLimiter>>,
+    pub(crate) request_limiter: Option<Arc<RequestLimiter>>,
+    pub(crate) memory_budget: Option<Arc<MemoryBudget>>,
+    pub(crate) handshake_timeout: Option<Duration>,
+    pub(crate) restart_detector: Option<Arc<RestartDetector>>,
+    pub(crate) request_counter: Option<Arc<RequestCounter>>,
+    pub(crate) duplicate_request_window: Option<Duration>,
+    pub(crate) downgrade_rejected_options: bool,
+    pub(crate) lookup_client_mac: bool,
+    pub(crate) oversized_datagrams: OversizedDatagrams,
+    pub(crate) duplicate_blocks: DuplicateBlocks,
+    // ICMP "port unreachable" of the client fails a transfer
+    pub(crate) fail_on_conn_reset: bool,
+    pub(crate) error_messages: Option<Arc<ErrorMessageFn>>,
+    pub(crate) mirror: Option<Arc<Mirror>>,
+    pub(crate) keep_state: Option<KeepState>,
+    pub(crate) cancellation: Option<CancellationToken>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl ServerConfig {
+    /// Timeout that the client requested, clamped to the configured limits,
+    /// in seconds.
+    pub(crate) fn client_timeout(&self, req: &RwReq) -> Option<u8> {
+        if self.ignore_client_timeout {
+            return None;
+        }
+
+        let mut timeout = req.opts.timeout?;
+
+        if let Some(handler) = self.client_timeout_override {
+            // Rounded to the nearest whole second
+            let secs = (handler.as_millis() + 500) / 1000;
+            timeout = secs.clamp(1, 255) as u8;
+        }
+
+        if let Some(min) = self.min_client_timeout {
+            // Rounded up to whole seconds
+            let secs = min.as_secs() + u64::from(min.subsec_nanos() > 0);
+            timeout = cmp::max(u64::from(timeout), secs).min(255) as u8;
+        }
+
+        if let Some(max) = self.max_client_timeout {
+            let secs = cmp::max(max.as_secs(), 1).min(255) as u8;
+            timeout = cmp::min(timeout, secs);
+        }
+
+        Some(timeout)
+    }
 }
 
 pub(crate) const DEFAULT_BLOCK_SIZE: usize = 512;
 
+/// Future that notifies the handler that a transfer started.
+pub(crate) type StartedNotify = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Outcome of a request whose transfer was started.
+struct Transfer {
+    local_addr: Option<SocketAddr>,
+    transferred: u64,
+    result: Result<(), packet::Error>,
+    outcome: TransferOutcome,
+    retransmissions: Retransmissions,
+    oversized_datagrams: u64,
+    duplicate_blocks: u64,
+    options: Option<NegotiatedOptions>,
+}
+
+/// Request of a client that is in progress or finished recently.
+pub(crate) struct ReqEntry {
+    data: Vec<u8>,
+    finished: Option<Instant>,
+}
+
+/// Requests by client address.
+pub(crate) type ReqMap = HashMap<SocketAddr, ReqEntry>;
+
+/// Request information that is needed after the request is finished.
+struct ReqInfo {
+    peer: SocketAddr,
+    direction: Direction,
+    filename: String,
+    restarted: bool,
+    // Released when the request is finished
+    active: Option<ActiveRequest>,
+}
+
 impl<H: 'static> TftpServer<H>
 where
     H: Handler,
 {
     /// Returns the listenning socket address.
+    ///
+    /// If the server was bound to port 0, this is the port that the OS
+    /// assigned, so it is known before [`serve`](Self::serve) is polled.
     pub fn listen_addr(&self) -> Result<SocketAddr> {
-        Ok(self.socket.get_ref().local_addr()?)
+        Ok(self.socket.local_addr()?)
     }
 
     /// Consume and start the server.
     pub async fn serve(self) -> Result<()> {
+        self.serve_until(future::pending()).await
+    }
+
+    /// Consume and start the server, until `shutdown` resolves.
+    ///
+    /// When `shutdown` resolves, no more requests are accepted and this
+    /// resolves once every transfer finished, so nothing of the server is
+    /// left running. Transfers are not cancelled, cancel the token of
+    /// [`TftpServerBuilder::cancellation_token`] to abort them.
+    ///
+    /// [`TftpServerBuilder::cancellation_token`]: super::TftpServerBuilder::cancellation_token
+    pub async fn serve_until<F>(mut self, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        // Shards are stopped when serving stops
+        let _shards = match self.shards {
+            Some(ref shards) => Some(shards.start()?),
+            None => None,
+        };
+
+        let resumed = std::mem::take(&mut self.resumed);
+
         self.ex
             .run(async {
-                let mut buf = [0u8; 4096];
+                self.resume_transfers(resumed).await;
 
-                loop {
-                    let (len, peer) = self.socket.recv_from(&mut buf).await?;
-                    self.handle_req_packet(peer, &buf[..len]).await;
+                let cancelled = async {
+                    match self.config.cancellation {
+                        Some(ref token) => token.cancelled().await,
+                        None => future::pending().await,
+                    }
+                    trace!("Server cancelled");
+                    Ok(())
+                };
+                let shutdown = async {
+                    shutdown.await;
+                    trace!("Server shut down");
+                    Ok(())
+                };
+                let refused = async {
+                    if let Some(ref audit) = self.config.audit {
+                        self.refused.run(&**audit).await;
+                    }
+                    future::pending().await
+                };
+                let stopped = future::or(cancelled, shutdown);
+                future::or(self.recv_reqs(), future::or(refused, stopped))
+                    .await?;
+
+                if let Some(ref audit) = self.config.audit {
+                    self.refused.flush(&**audit).await;
                 }
+
+                // Wait until the transfers are finished
+                let _ = self.running.write().await;
+                Ok(())
             })
             .await
     }
 
+    async fn recv_reqs(&self) -> Result<()> {
+        let mut buf = [0u8; 4096];
+
+        loop {
+            let (len, peer) = match self.socket.recv_from(&mut buf).await {
+                Ok(x) => x,
+                Err(ref e) if is_conn_reset(e) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.handle_req_packet(peer, &buf[..len]).await;
+        }
+    }
+
     async fn handle_req_packet(&self, peer: SocketAddr, data: &[u8]) {
+        if self.config.max_request_size.is_some_and(|max| data.len() > max) {
+            trace!("Request too big (peer: {}, size: {})", &peer, data.len());
+            self.reject_req(peer, data);
+            return;
+        }
+
         let packet = match Packet::decode(data) {
             Ok(p @ Packet::Rrq(_)) => p,
             Ok(p @ Packet::Wrq(_)) => p,
@@ -71,122 +334,1498 @@ where
             Err(_) => return,
         };
 
-        if !self.reqs_in_progress.lock().await.insert(peer) {
-            // Ignore pending requests
+        let (direction, req) = match packet {
+            Packet::Rrq(ref req) => (Direction::Read, req),
+            Packet::Wrq(ref req) => (Direction::Write, req),
+            _ => unreachable!(),
+        };
+
+        if self
+            .config
+            .max_filename_len
+            .is_some_and(|max| req.filename.len() > max)
+        {
+            trace!("Filename too long (peer: {}, req: {:?})", &peer, req);
+            self.reject_req(peer, data);
             return;
         }
 
+        if self
+            .config
+            .max_request_options
+            .is_some_and(|max| count_req_opts(data) > max)
+        {
+            trace!("Too many options (peer: {}, req: {:?})", &peer, req);
+            self.reject_req(peer, data);
+            return;
+        }
+
+        let mut reqs = self.reqs(peer.ip()).lock().await;
+
+        match reqs.get(&peer) {
+            // Ignore pending requests
+            Some(entry) if entry.finished.is_none() => return,
+            // Ignore retransmissions of finished requests
+            Some(entry) if self.is_duplicate(entry, data) => {
+                trace!("Duplicate request (peer: {}, req: {:?})", &peer, req);
+                return;
+            }
+            _ => {}
+        }
+
+        let over_budget = self
+            .config
+            .memory_budget
+            .as_ref()
+            .is_some_and(|budget| budget.exhausted());
+
+        let active = match self.config.request_limiter {
+            Some(ref limiter) => limiter.try_start().map(Some),
+            None => Some(None),
+        };
+
+        let active = match active.filter(|_| !over_budget) {
+            Some(active) => active,
+            None => {
+                trace!("Server busy, shedding request (peer: {})", &peer);
+
+                // Retransmissions are ignored like those of finished requests
+                if self.config.duplicate_request_window.is_some() {
+                    reqs.insert(
+                        peer,
+                        ReqEntry {
+                            data: data.to_owned(),
+                            finished: Some(Instant::now()),
+                        },
+                    );
+                }
+                drop(reqs);
+                self.shed_req(peer, direction, req).await;
+                return;
+            }
+        };
+
+        let handshake = match self.config.handshake_limiter {
+            Some(ref limiter) => match limiter.try_start(peer.ip()) {
+                Some(handshake) => Some(handshake),
+                None => {
+                    trace!("Too many pending handshakes (peer: {})", &peer);
+                    drop(reqs);
+                    self.audit_refused(AuditRecord::refused(
+                        peer,
+                        direction,
+                        req.filename_lossy().into_owned(),
+                        None,
+                        TransferOutcome::Dropped,
+                    ));
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        reqs.insert(
+            peer,
+            ReqEntry {
+                data: data.to_owned(),
+                finished: None,
+            },
+        );
+        drop(reqs);
+
         match packet {
-            Packet::Rrq(req) => self.handle_rrq(peer, req),
-            Packet::Wrq(req) => self.handle_wrq(peer, req),
+            Packet::Rrq(req) => self.handle_rrq(peer, req, handshake, active),
+            Packet::Wrq(req) => self.handle_wrq(peer, req, handshake, active),
             _ => unreachable!(),
         }
     }
 
-    fn handle_rrq(&self, peer: SocketAddr, req: RwReq) {
+    /// Shard of the requests of `ip`, or 0 if the server is not sharded.
+    fn shard(&self, ip: IpAddr) -> usize {
+        self.shards.as_ref().map_or(0, |shards| shards.index(ip))
+    }
+
+    /// Requests of the shard of `ip`.
+    fn reqs(&self, ip: IpAddr) -> &Arc<Mutex<ReqMap>> {
+        &self.reqs[self.shard(ip) % self.reqs.len()]
+    }
+
+    /// Handler of the shard of `ip`.
+    fn handler(&self, ip: IpAddr) -> &Arc<HandlerLock<H>> {
+        &self.handlers[self.shard(ip) % self.handlers.len()]
+    }
+
+    /// Whether `data` is a retransmission of a request that finished within
+    /// the duplicate request window.
+    fn is_duplicate(&self, entry: &ReqEntry, data: &[u8]) -> bool {
+        let (window, finished) =
+            match (self.config.duplicate_request_window, entry.finished) {
+                (Some(window), Some(finished)) => (window, finished),
+                _ => return false,
+            };
+
+        finished.elapsed() < window && entry.data == data
+    }
+
+    /// Answer a request that exceeds the limits of the server with an
+    /// error, without decoding it.
+    fn reject_req(&self, peer: SocketAddr, data: &[u8]) {
+        let error = packet::Error::IllegalOperation;
+        let transfer_addr = self.transfer_addr.clone();
+        let messages = self.config.error_messages.clone();
+        let audit = self.config.audit.clone();
+        let (direction, filename) = req_summary(data);
+
+        // Serving stops after the task releases it
+        let running = self.running.try_read_arc();
+
+        self.ex
+            .spawn(async move {
+                let sent = send_error(
+                    error.clone(),
+                    None,
+                    peer,
+                    &transfer_addr,
+                    messages,
+                );
+                if let Err(e) = sent.await {
+                    trace!("Failed to send error to peer {}: {}", &peer, &e);
+                }
+
+                if let Some(audit) = audit {
+                    let outcome = TransferOutcome::OversizedRequest;
+                    let record = AuditRecord::refused(
+                        peer,
+                        direction,
+                        filename,
+                        Some(error),
+                        outcome,
+                    );
+                    audit.record(&record).await;
+                }
+
+                drop(running);
+            })
+            .detach();
+    }
+
+    /// Pass `record` of a request that was not handled to the audit sink.
+    fn audit_refused(&self, record: AuditRecord) {
+        if self.config.audit.is_some() {
+            self.refused.push(record);
+        }
+    }
+
+    /// Answer a request with "Server busy" error, without handling it.
+    ///
+    /// The reply is sent from the listening socket, so shedding a request
+    /// costs no task and no socket. It is dropped if the socket can not
+    /// send it immediately.
+    async fn shed_req(
+        &self,
+        peer: SocketAddr,
+        direction: Direction,
+        req: &RwReq,
+    ) {
+        let sent = self.socket.send_to(&self.busy_reply, peer);
+        match future::poll_once(sent).await {
+            Some(Ok(_)) => {}
+            Some(Err(e)) => {
+                trace!("Failed to send error to peer {}: {}", &peer, &e);
+            }
+            None => trace!("Busy reply to peer {} dropped", &peer),
+        }
+
+        self.audit_refused(AuditRecord::refused(
+            peer,
+            direction,
+            req.filename_lossy().into_owned(),
+            Some(packet::Error::Msg("Server busy".to_string())),
+            TransferOutcome::Shed,
+        ));
+    }
+
+    fn handle_rrq(
+        &self,
+        peer: SocketAddr,
+        mut req: RwReq,
+        handshake: Option<Handshake>,
+        active: Option<ActiveRequest>,
+    ) {
         trace!("RRQ recieved (peer: {}, req: {:?})", &peer, &req);
 
-        let handler = Arc::clone(&self.handler);
+        let handler = Arc::clone(self.handler(peer.ip()));
+        // Notified outside of the request future too, which can be cut short
+        let finish = FinishNotify::new(Arc::clone(&handler));
+        let unfinished = Arc::clone(&finish);
         let config = self.config.clone();
-        let local_ip = self.local_ip;
+        let transfer_addr = self.transfer_addr.clone();
+        // Weak, so the tasks are dropped with the executor
+        let ex = Arc::downgrade(&self.ex);
+        let (read, restarted) = match self.config.restart_detector {
+            Some(ref detector) => {
+                let (read, restarted) = detector.start(peer, &req.filename);
+                (Some(read), restarted)
+            }
+            None => (None, false),
+        };
+
+        if restarted {
+            trace!("RRQ restarted from new port (peer: {})", &peer);
+        }
+
+        let info = ReqInfo {
+            peer,
+            direction: Direction::Read,
+            filename: req.filename_lossy().into_owned(),
+            restarted,
+            active,
+        };
 
         // Prepare request future
         let req_fut = async move {
-            let (mut reader, size) = handler
-                .lock()
-                .await
-                .read_req_open(&peer, req.filename.as_ref())
-                .await
-                .map_err(Error::Packet)?;
+            // Mail mode is defined only for write requests
+            if req.mode == Mode::Mail {
+                return Err(Error::UnsupportedMode);
+            }
 
-            let mut read_req = ReadRequest::init(
-                &mut reader,
-                size,
-                peer,
-                &req,
-                config,
-                local_ip,
-            )
-            .await?;
+            check_min_block_size(&config, &mut req)?;
+            let authorized = authorize(&config, &peer, &req, Direction::Read);
+            cancellable(&config, authorized).await??;
+
+            // Denied requests are not counted
+            let request_count = config
+                .request_counter
+                .as_ref()
+                .map(|counter| counter.count(peer.ip(), &req.filename));
+
+            // Only authorized requests reach the secondary server
+            if let (Some(mirror), Some(ex)) = (&config.mirror, ex.upgrade()) {
+                mirror.spawn(&ex, &req);
+            }
 
-            read_req.handle().await;
+            cancellable(&config, resolve(&config, &peer, &mut req)).await??;
 
-            Ok(())
+            let permit =
+                acquire_permit(&config, &handler, &peer, &req, Direction::Read);
+            let _permit = cancellable(&config, permit).await?;
+
+            let mut socket = bind_socket(&transfer_addr, peer)?;
+            let mut handshake = handshake;
+            let client_mac =
+                cancellable(&config, client_mac(&config, &peer)).await?;
+
+            loop {
+                let mut reader = None;
+                let source = open_read_source(
+                    &handler,
+                    &config,
+                    &peer,
+                    &req,
+                    request_count,
+                    &mut reader,
+                );
+                let (source, size, state, req_config) =
+                    cancellable(&config, source).await??;
+
+                let mut read_req = ReadRequest::init(
+                    source,
+                    size,
+                    peer,
+                    &req,
+                    req_config,
+                    socket,
+                    handshake.take(),
+                )
+                .await?;
+
+                let local_addr = read_req.local_addr()?;
+
+                let options = NegotiatedOptions {
+                    block_size: read_req.block_size(),
+                    timeout: read_req.timeout(),
+                    window_size: read_req.window_size(),
+                    transfer_size: size,
+                };
+
+                let ctx = TransferContext {
+                    client: peer,
+                    local_addr,
+                    path: req.filename_path().into_owned(),
+                    direction: Direction::Read,
+                    options,
+                    client_mac,
+                    request_count,
+                };
+                let state = Arc::new(Mutex::new(state));
+                read_req.on_started(notify_started(
+                    Arc::clone(&handler),
+                    ctx.clone(),
+                    Arc::clone(&state),
+                ));
+                finish.insert(ctx, Arc::clone(&state)).await;
+                keep_progress(&config, &mut read_req, &req, local_addr);
+
+                let result = read_req.handle().await;
+
+                if read_req.options_rejected()
+                    && config.downgrade_rejected_options
+                {
+                    trace!("RRQ restarted without options (peer: {})", &peer);
+
+                    req.opts = Opts::default();
+                    socket = read_req.into_socket();
+                    finish.restarted().await;
+                    continue;
+                }
+
+                let outcome = read_req.outcome();
+                finish.finished(outcome).await;
+
+                return Ok(Transfer {
+                    local_addr: Some(local_addr),
+                    transferred: read_req.transferred(),
+                    result,
+                    outcome,
+                    retransmissions: read_req.retransmissions().clone(),
+                    oversized_datagrams: 0,
+                    duplicate_blocks: 0,
+                    options: Some(options),
+                });
+            }
         };
 
-        let reqs_in_progress = Arc::clone(&self.reqs_in_progress);
+        // Boxed, so the wrappers do not copy it on the stack
+        let req_fut = Box::pin(req_fut);
 
-        // Run request future in a new task
-        self.ex
-            .spawn(run_req(req_fut, peer, reqs_in_progress, local_ip))
-            .detach();
+        // Abort the request if the client restarts it from another port
+        let transfer_addr = self.transfer_addr.clone();
+        let messages = self.config.error_messages.clone();
+        let req_fut = async move {
+            let read = match read {
+                Some(read) => read,
+                None => return unfinished.run(req_fut).await,
+            };
+
+            let restarted = async {
+                read.cancelled().await;
+
+                let e = packet::Error::Msg(
+                    "Transfer restarted from another port".to_string(),
+                );
+
+                if let Err(e) =
+                    send_error(e.clone(), None, peer, &transfer_addr, messages)
+                        .await
+                {
+                    trace!("Failed to send error to peer {}: {}", &peer, &e);
+                }
+
+                Ok(Transfer {
+                    local_addr: None,
+                    transferred: 0,
+                    result: Err(e),
+                    outcome: TransferOutcome::Cancelled,
+                    retransmissions: Retransmissions::default(),
+                    oversized_datagrams: 0,
+                    duplicate_blocks: 0,
+                    options: None,
+                })
+            };
+
+            unfinished.run(future::or(req_fut, restarted)).await
+        };
+
+        self.spawn_req(req_fut, info);
     }
 
-    fn handle_wrq(&self, peer: SocketAddr, req: RwReq) {
+    /// Continue the read transfers that a previous server recorded in its
+    /// [`KeepState`].
+    async fn resume_transfers(&self, snapshots: Vec<TransferSnapshot>) {
+        for snapshot in snapshots {
+            let mut reqs = self.reqs(snapshot.client.ip()).lock().await;
+
+            // Requests of the client are ignored while it is resumed
+            reqs.insert(
+                snapshot.client,
+                ReqEntry {
+                    data: Vec::new(),
+                    finished: None,
+                },
+            );
+
+            drop(reqs);
+            self.handle_resumed(snapshot);
+        }
+    }
+
+    fn handle_resumed(&self, snapshot: TransferSnapshot) {
+        let peer = snapshot.client;
+        trace!("RRQ resumed (peer: {}, snapshot: {:?})", &peer, &snapshot);
+
+        let handler = Arc::clone(self.handler(peer.ip()));
+        // Notified outside of the request future too, which can be cut short
+        let finish = FinishNotify::new(Arc::clone(&handler));
+        let unfinished = Arc::clone(&finish);
+        let config = self.config.clone();
+        let transfer_addr = self.transfer_addr.clone();
+
+        // Transfers without negotiated options get the legacy parameters
+        let mut req = RwReq {
+            filename: snapshot.filename.clone(),
+            mode: Mode::Octet,
+            opts: Opts::default(),
+        };
+        if usize::from(snapshot.block_size) != DEFAULT_BLOCK_SIZE {
+            req.opts.block_size = Some(snapshot.block_size);
+        }
+        if snapshot.window_size > 1 {
+            req.opts.window_size = Some(u64::from(snapshot.window_size));
+        }
+
+        let info = ReqInfo {
+            peer,
+            direction: Direction::Read,
+            filename: req.filename_lossy().into_owned(),
+            restarted: false,
+            active: None,
+        };
+
+        let req_fut = async move {
+            let permit =
+                acquire_permit(&config, &handler, &peer, &req, Direction::Read);
+            let _permit = cancellable(&config, permit).await?;
+
+            let socket =
+                bind_resumed_socket(&transfer_addr, snapshot.local_addr, peer)?;
+            let client_mac =
+                cancellable(&config, client_mac(&config, &peer)).await?;
+
+            let mut reader = None;
+            let source = open_read_source(
+                &handler,
+                &config,
+                &peer,
+                &req,
+                None,
+                &mut reader,
+            );
+            let (source, size, state, req_config) =
+                cancellable(&config, source).await??;
+
+            let mut read_req =
+                ReadRequest::resume(source, &snapshot, req_config, socket);
+            let local_addr = read_req.local_addr()?;
+
+            let options = NegotiatedOptions {
+                block_size: read_req.block_size(),
+                timeout: read_req.timeout(),
+                window_size: read_req.window_size(),
+                transfer_size: size,
+            };
+
+            let ctx = TransferContext {
+                client: peer,
+                local_addr,
+                path: req.filename_path().into_owned(),
+                direction: Direction::Read,
+                options,
+                client_mac,
+                request_count: None,
+            };
+            let state = Arc::new(Mutex::new(state));
+            read_req.on_started(notify_started(
+                Arc::clone(&handler),
+                ctx.clone(),
+                Arc::clone(&state),
+            ));
+            finish.insert(ctx, state).await;
+            keep_progress(&config, &mut read_req, &req, local_addr);
+
+            let result = read_req.handle().await;
+            let outcome = read_req.outcome();
+            finish.finished(outcome).await;
+
+            Ok(Transfer {
+                local_addr: Some(local_addr),
+                transferred: read_req.transferred(),
+                result,
+                outcome,
+                retransmissions: read_req.retransmissions().clone(),
+                oversized_datagrams: 0,
+                duplicate_blocks: 0,
+                options: Some(options),
+            })
+        };
+        let req_fut = async move { unfinished.run(req_fut).await };
+
+        self.spawn_req(req_fut, info);
+    }
+
+    fn handle_wrq(
+        &self,
+        peer: SocketAddr,
+        mut req: RwReq,
+        handshake: Option<Handshake>,
+        active: Option<ActiveRequest>,
+    ) {
         trace!("WRQ recieved (peer: {}, req: {:?})", &peer, &req);
 
-        let handler = Arc::clone(&self.handler);
+        let handler = Arc::clone(self.handler(peer.ip()));
+        // Notified outside of the request future too, which can be cut short
+        let finish = FinishNotify::new(Arc::clone(&handler));
+        let unfinished = Arc::clone(&finish);
         let config = self.config.clone();
-        let local_ip = self.local_ip;
+        let transfer_addr = self.transfer_addr.clone();
+        let info = ReqInfo {
+            peer,
+            direction: Direction::Write,
+            filename: req.filename_lossy().into_owned(),
+            restarted: false,
+            active,
+        };
 
         // Prepare request future
         let req_fut = async move {
-            let mut writer = handler
-                .lock()
-                .await
-                .write_req_open(
-                    &peer,
-                    req.filename.as_ref(),
-                    req.opts.transfer_size,
-                )
-                .await
-                .map_err(Error::Packet)?;
+            check_min_block_size(&config, &mut req)?;
+            check_write_size(&config, &req)?;
+            let authorized = authorize(&config, &peer, &req, Direction::Write);
+            cancellable(&config, authorized).await??;
+
+            let permit = acquire_permit(
+                &config,
+                &handler,
+                &peer,
+                &req,
+                Direction::Write,
+            );
+            let _permit = cancellable(&config, permit).await?;
+
+            let mut socket = bind_socket(&transfer_addr, peer)?;
+            let mut handshake = handshake;
+            let client_mac =
+                cancellable(&config, client_mac(&config, &peer)).await?;
+            let mut guard = WriterGuard::new(&handler, peer, &req);
+
+            let transfer = AssertUnwindSafe(async {
+                loop {
+                    let writer = open_writer(&handler, &config, &peer, &req);
+                    let (writer, state, req_config) =
+                        cancellable(&config, writer).await??;
+                    let state = Arc::new(Mutex::new(state));
+                    let writer = guard.insert(writer, Arc::clone(&state));
 
-            let mut write_req =
-                WriteRequest::init(&mut writer, peer, &req, config, local_ip)
+                    let mut write_req = WriteRequest::init(
+                        writer,
+                        peer,
+                        &req,
+                        req_config,
+                        socket,
+                        handshake.take(),
+                    )
                     .await?;
 
-            write_req.handle().await;
+                    let local_addr = write_req.local_addr()?;
 
-            Ok(())
+                    let options = NegotiatedOptions {
+                        block_size: write_req.block_size(),
+                        timeout: write_req.timeout(),
+                        window_size: 1,
+                        transfer_size: req.opts.transfer_size,
+                    };
+
+                    let ctx = TransferContext {
+                        client: peer,
+                        local_addr,
+                        path: req.filename_path().into_owned(),
+                        direction: Direction::Write,
+                        options,
+                        client_mac,
+                        request_count: None,
+                    };
+                    write_req.on_started(notify_started(
+                        Arc::clone(&handler),
+                        ctx.clone(),
+                        Arc::clone(&state),
+                    ));
+                    finish.insert(ctx, Arc::clone(&state)).await;
+
+                    let result = write_req.handle().await;
+
+                    if write_req.options_rejected()
+                        && config.downgrade_rejected_options
+                    {
+                        trace!(
+                            "WRQ restarted without options (peer: {})",
+                            &peer
+                        );
+
+                        socket = write_req.into_socket();
+                        // Writer of the rejected attempt is dropped, so the
+                        // handler cleans up before the file is opened again
+                        guard.failed().await;
+                        finish.restarted().await;
+
+                        req.opts = Opts::default();
+                        continue;
+                    }
+
+                    let transfer = Transfer {
+                        local_addr: Some(local_addr),
+                        transferred: write_req.transferred(),
+                        result,
+                        outcome: write_req.outcome(),
+                        retransmissions: write_req.retransmissions().clone(),
+                        oversized_datagrams: write_req.oversized_datagrams(),
+                        duplicate_blocks: write_req.duplicate_blocks(),
+                        options: Some(options),
+                    };
+                    drop(write_req);
+
+                    match transfer.result {
+                        Ok(_) => guard.completed(),
+                        Err(_) => guard.failed().await,
+                    }
+
+                    let outcome = transfer.outcome;
+                    finish.finished(outcome).await;
+
+                    return Ok(transfer);
+                }
+            })
+            .catch_unwind()
+            .await;
+
+            // Every other exit, including a panic, fails the writer
+            guard.failed().await;
+
+            match transfer {
+                Ok(transfer) => transfer,
+                Err(panic) => panic::resume_unwind(panic),
+            }
+        };
+        let req_fut = async move { unfinished.run(req_fut).await };
+
+        self.spawn_req(req_fut, info);
+    }
+
+    fn spawn_req<F>(&self, req_fut: F, info: ReqInfo)
+    where
+        F: Future<Output = Result<Transfer>> + Send + 'static,
+    {
+        let reqs = Arc::clone(self.reqs(info.peer.ip()));
+        let duplicate_window = self.config.duplicate_request_window;
+        let audit = self.config.audit.clone();
+        let transfer_addr = self.transfer_addr.clone();
+        let messages = self.config.error_messages.clone();
+
+        let ex = match self.shards {
+            Some(ref shards) => shards.executor(info.peer.ip()),
+            None => &self.ex,
         };
 
-        let reqs_in_progress = Arc::clone(&self.reqs_in_progress);
+        // Serving stops after the task releases it
+        let running = self.running.try_read_arc();
 
         // Run request future in a new task
-        self.ex
-            .spawn(run_req(req_fut, peer, reqs_in_progress, local_ip))
-            .detach();
+        ex.spawn(async move {
+            run_req(
+                req_fut,
+                info,
+                reqs,
+                duplicate_window,
+                audit,
+                transfer_addr,
+                messages,
+            )
+            .await;
+            drop(running);
+        })
+        .detach();
+    }
+}
+
+/// Number of options in a valid RRQ/WRQ packet.
+///
+/// After the opcode, every option is a NUL terminated name and value,
+/// that follow the NUL terminated filename and mode.
+fn count_req_opts(data: &[u8]) -> usize {
+    let nuls = data[2..].iter().filter(|&&c| c == b'\0').count();
+    nuls.saturating_sub(2) / 2
+}
+
+/// Apply the minimum block size policy to the options of `req`.
+fn check_min_block_size(config: &ServerConfig, req: &mut RwReq) -> Result<()> {
+    let (min, policy) = match config.min_block_size {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    match req.opts.block_size {
+        Some(size) if size < min => match policy {
+            BlockSizePolicy::Reject => {
+                Err(Error::Packet(packet::Error::OptionsNegotiationFailed))
+            }
+            BlockSizePolicy::Ignore => {
+                req.opts.block_size = None;
+                Ok(())
+            }
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Reject uploads that announce more data than the server accepts.
+fn check_write_size(config: &ServerConfig, req: &RwReq) -> Result<()> {
+    match (req.opts.transfer_size, config.max_write_size) {
+        (Some(size), Some(max)) if size > max => {
+            Err(Error::Packet(packet::Error::DiskFull))
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn authorize(
+    config: &ServerConfig,
+    peer: &SocketAddr,
+    req: &RwReq,
+    direction: Direction,
+) -> Result<()> {
+    match config.authorizer {
+        Some(ref authorizer) => authorizer
+            .authorize(peer, &req.filename_path(), direction)
+            .await
+            .map_err(Error::Packet),
+        None => Ok(()),
+    }
+}
+
+/// MAC address of `peer`, if it is looked up.
+async fn client_mac(
+    config: &ServerConfig,
+    peer: &SocketAddr,
+) -> Option<MacAddr> {
+    if !config.lookup_client_mac {
+        return None;
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let mac = super::neighbor::lookup_mac(peer.ip()).await;
+        trace!("Client MAC (peer: {}, mac: {:?})", peer, mac);
+        mac
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = peer;
+        None
+    }
+}
+
+/// Replace the filename of `req` with the one that the resolver returns.
+async fn resolve(
+    config: &ServerConfig,
+    peer: &SocketAddr,
+    req: &mut RwReq,
+) -> Result<()> {
+    let resolver = match config.resolver {
+        Some(ref resolver) => resolver,
+        None => return Ok(()),
+    };
+
+    let path = resolver
+        .resolve(peer, &req.filename_path())
+        .await
+        .map_err(Error::Packet)?;
+
+    if let Some(path) = path {
+        trace!("RRQ resolved (peer: {}, path: {})", peer, path.display());
+        req.filename = path_to_bytes(&path);
+    }
+
+    Ok(())
+}
+
+/// Handler of a shard, whose calls are serialized by a lock unless the
+/// handler can be shared, see [`Handler::share`].
+pub(crate) struct HandlerLock<H> {
+    handler: Mutex<H>,
+    shared: bool,
+}
+
+impl<H: Handler> HandlerLock<H> {
+    pub(crate) fn new(handler: H) -> Self {
+        HandlerLock {
+            shared: handler.share().is_some(),
+            handler: Mutex::new(handler),
+        }
+    }
+
+    /// Handler for the next calls. The lock is held until the calls are
+    /// done, unless the handler is shared, which only takes the lock to
+    /// copy it.
+    async fn lock(&self) -> HandlerGuard<'_, H> {
+        let handler = self.handler.lock().await;
+
+        match self.shared.then(|| handler.share()).flatten() {
+            Some(handler) => HandlerGuard::Shared(handler),
+            None => HandlerGuard::Locked(handler),
+        }
+    }
+}
+
+enum HandlerGuard<'a, H> {
+    Locked(MutexGuard<'a, H>),
+    Shared(H),
+}
+
+impl<H> Deref for HandlerGuard<'_, H> {
+    type Target = H;
+
+    fn deref(&self) -> &H {
+        match self {
+            HandlerGuard::Locked(handler) => handler,
+            HandlerGuard::Shared(handler) => handler,
+        }
+    }
+}
+
+impl<H> DerefMut for HandlerGuard<'_, H> {
+    fn deref_mut(&mut self) -> &mut H {
+        match self {
+            HandlerGuard::Locked(handler) => handler,
+            HandlerGuard::Shared(handler) => handler,
+        }
+    }
+}
+
+/// Wait for a transfer slot, if concurrent transfers are limited.
+async fn acquire_permit<H: Handler>(
+    config: &ServerConfig,
+    handler: &HandlerLock<H>,
+    peer: &SocketAddr,
+    req: &RwReq,
+    direction: Direction,
+) -> Option<Permit> {
+    let limiter = config.transfer_limiter.as_ref()?;
+
+    let priority = handler
+        .lock()
+        .await
+        .transfer_priority(peer, &req.filename_path(), direction)
+        .await;
+
+    Some(limiter.acquire(priority).await)
+}
+
+/// Open the source of a read request and the state of its transfer. A
+/// reader is stored in `reader`, so the source can borrow it.
+async fn open_read_source<'r, H: Handler>(
+    handler: &HandlerLock<H>,
+    config: &ServerConfig,
+    peer: &SocketAddr,
+    req: &RwReq,
+    request_count: Option<u32>,
+    reader: &'r mut Option<H::Reader>,
+) -> Result<(Source<'r, H::Reader>, Option<u64>, TransferState, ServerConfig)> {
+    let mut handler = handler.lock().await;
+    let path = req.filename_path();
+
+    if let Some((blocks, size)) = handler
+        .read_req_open_blocks(peer, &path)
+        .await
+        .map_err(Error::Packet)?
+    {
+        let mut state =
+            handler.transfer_state(peer, &path, Direction::Read).await;
+        let config = transfer_config(
+            &mut *handler,
+            config,
+            peer,
+            req,
+            Direction::Read,
+            &mut state,
+        )
+        .await;
+
+        return Ok((Source::Blocks(blocks), size, state, config));
+    }
+
+    let (r, size, mut state) = handler
+        .read_req_open_with_state(peer, &path, request_count)
+        .await
+        .map_err(Error::Packet)?;
+    let config = transfer_config(
+        &mut *handler,
+        config,
+        peer,
+        req,
+        Direction::Read,
+        &mut state,
+    )
+    .await;
+
+    let r = reader.insert(r);
+
+    if H::buf_reader(r).is_some() {
+        Ok((Source::BufReader(H::buf_reader(r)), size, state, config))
+    } else {
+        Ok((Source::Reader(r), size, state, config))
+    }
+}
+
+/// Open the writer of a write request, the state of its transfer and its
+/// configuration.
+async fn open_writer<H: Handler>(
+    handler: &HandlerLock<H>,
+    config: &ServerConfig,
+    peer: &SocketAddr,
+    req: &RwReq,
+) -> Result<(H::Writer, TransferState, ServerConfig)> {
+    let mut handler = handler.lock().await;
+
+    let path = req.filename_path();
+
+    let (writer, mut state) = match req.mode {
+        // Handlers without mail support reject it as an illegal operation
+        Mode::Mail => {
+            let writer = handler
+                .mail_req_open(peer, &req.filename_lossy())
+                .await
+                .map_err(|e| match e {
+                    packet::Error::IllegalOperation => Error::UnsupportedMode,
+                    e => Error::Packet(e),
+                })?;
+            let state =
+                handler.transfer_state(peer, &path, Direction::Write).await;
+
+            (writer, state)
+        }
+        _ => handler
+            .write_req_open_with_state(peer, &path, req.opts.transfer_size)
+            .await
+            .map_err(Error::Packet)?,
+    };
+    let config = transfer_config(
+        &mut *handler,
+        config,
+        peer,
+        req,
+        Direction::Write,
+        &mut state,
+    )
+    .await;
+
+    Ok((writer, state, config))
+}
+
+/// Writer of a write request, that the handler cleans up with
+/// [`Handler::write_req_failed`] unless the transfer completed.
+struct WriterGuard<'a, H: Handler> {
+    handler: &'a HandlerLock<H>,
+    peer: SocketAddr,
+    path: PathBuf,
+    writer: Option<(H::Writer, Arc<Mutex<TransferState>>)>,
+}
+
+impl<'a, H: Handler> WriterGuard<'a, H> {
+    fn new(handler: &'a HandlerLock<H>, peer: SocketAddr, req: &RwReq) -> Self {
+        WriterGuard {
+            handler,
+            peer,
+            path: req.filename_path().into_owned(),
+            writer: None,
+        }
+    }
+
+    /// Keep the writer of an attempt until it completes or fails.
+    fn insert(
+        &mut self,
+        writer: H::Writer,
+        state: Arc<Mutex<TransferState>>,
+    ) -> &mut H::Writer {
+        &mut self.writer.insert((writer, state)).0
+    }
+
+    /// Drop the writer of a completed transfer.
+    fn completed(&mut self) {
+        self.writer = None;
+    }
+
+    /// Let the handler clean up the writer, if one is kept.
+    async fn failed(&mut self) {
+        let (writer, state) = match self.writer.take() {
+            Some(x) => x,
+            None => return,
+        };
+        let mut state = state.lock().await;
+
+        self.handler
+            .lock()
+            .await
+            .write_req_failed(&self.peer, &self.path, writer, &mut state)
+            .await;
     }
 }
 
+/// Server configuration with the parameters that the handler returned for
+/// the transfer of `req`.
+///
+/// Called with the handler lock that opened the transfer, so the handler is
+/// locked once per transfer.
+async fn transfer_config<H: Handler>(
+    handler: &mut H,
+    config: &ServerConfig,
+    peer: &SocketAddr,
+    req: &RwReq,
+    direction: Direction,
+    state: &mut TransferState,
+) -> ServerConfig {
+    let params = handler
+        .transfer_params(peer, &req.filename_path(), direction, state)
+        .await;
+
+    let mut config = config.clone();
+
+    // Clients without options are usually RFC1350-only boot ROMs
+    if req.opts == Opts::default() {
+        if let Some(legacy) = config.legacy_params.clone() {
+            apply_params(&mut config, &legacy);
+        }
+    }
+
+    apply_params(&mut config, &params);
+    config
+}
+
+/// Override the parameters of `config` that are set in `params`.
+fn apply_params(config: &mut ServerConfig, params: &TransferParams) {
+    if let Some(timeout) = params.timeout {
+        config.timeout = timeout;
+    }
+
+    if let Some(retries) = params.max_send_retries {
+        config.max_send_retries = retries;
+    }
+
+    if let Some(timeout) = params.client_timeout {
+        config.client_timeout_override = Some(timeout);
+    }
+
+    if let Some(rate) = params.max_bytes_per_sec {
+        config.max_bytes_per_sec = Some(rate);
+    }
+
+    if let Some(size) = params.write_buffer_size {
+        config.write_buffer_size = size;
+    }
+
+    if let Some(omit) = params.omit_transfer_size {
+        config.omit_transfer_size = omit;
+    }
+
+    if let Some(size) = params.max_block_size {
+        config.block_size_limit = Some(match config.block_size_limit {
+            Some(limit) => cmp::min(limit, size),
+            None => size,
+        });
+    }
+
+    // Transfer is cancelled by either token
+    if let Some(ref token) = params.cancellation_token {
+        config.cancellation = Some(match config.cancellation {
+            Some(ref server) => {
+                let child = server.child_token();
+                token.add_child(&child);
+                child
+            }
+            None => token.clone(),
+        });
+    }
+}
+
+/// Run `fut`, unless the transfers of `config` are cancelled first.
+async fn cancellable<T>(
+    config: &ServerConfig,
+    fut: impl Future<Output = T>,
+) -> Result<T> {
+    let token = match config.cancellation {
+        Some(ref token) => token,
+        None => return Ok(fut.await),
+    };
+
+    let cancelled = async {
+        token.cancelled().await;
+        Err(Error::Cancelled)
+    };
+
+    // Cancellation wins if both are ready
+    future::or(cancelled, async { Ok(fut.await) }).await
+}
+
+/// Transfer whose handler is notified with [`Handler::transfer_finished`]
+/// once, however the transfer ends.
+struct FinishNotify<H> {
+    handler: Arc<HandlerLock<H>>,
+    transfer: Mutex<Option<(TransferContext, Arc<Mutex<TransferState>>)>>,
+}
+
+impl<H: Handler> FinishNotify<H> {
+    fn new(handler: Arc<HandlerLock<H>>) -> Arc<Self> {
+        Arc::new(FinishNotify {
+            handler,
+            transfer: Mutex::new(None),
+        })
+    }
+
+    /// Keep the transfer of `ctx` until it finishes.
+    async fn insert(
+        &self,
+        ctx: TransferContext,
+        state: Arc<Mutex<TransferState>>,
+    ) {
+        *self.transfer.lock().await = Some((ctx, state));
+    }
+
+    /// Drop the attempt that is restarted without options, which does not
+    /// finish the transfer.
+    async fn restarted(&self) {
+        *self.transfer.lock().await = None;
+    }
+
+    /// Let the handler know that the transfer finished with `outcome`,
+    /// unless it was already notified.
+    async fn finished(&self, outcome: TransferOutcome) {
+        let (ctx, state) = match self.transfer.lock().await.take() {
+            Some(x) => x,
+            None => return,
+        };
+        let mut state = state.lock().await;
+
+        self.handler
+            .lock()
+            .await
+            .transfer_finished(&ctx, outcome, &mut state)
+            .await;
+    }
+
+    /// Run `req_fut` and notify the handler of a transfer that it left
+    /// unfinished, because it was cut short by a restart or panicked.
+    async fn run(
+        &self,
+        req_fut: impl Future<Output = Result<Transfer>>,
+    ) -> Result<Transfer> {
+        let res = AssertUnwindSafe(req_fut).catch_unwind().await;
+
+        match res {
+            Ok(transfer) => {
+                self.finished(TransferOutcome::Cancelled).await;
+                transfer
+            }
+            Err(panic) => {
+                self.finished(TransferOutcome::Panicked).await;
+                panic::resume_unwind(panic)
+            }
+        }
+    }
+}
+
+fn notify_started<H: Handler + 'static>(
+    handler: Arc<HandlerLock<H>>,
+    ctx: TransferContext,
+    state: Arc<Mutex<TransferState>>,
+) -> StartedNotify {
+    Box::pin(async move {
+        let mut state = state.lock().await;
+        handler.lock().await.transfer_started(&ctx, &mut state).await;
+    })
+}
+
+/// Record the progress of `read_req` in the kept state of the server.
+fn keep_progress<R>(
+    config: &ServerConfig,
+    read_req: &mut ReadRequest<'_, R>,
+    req: &RwReq,
+    local_addr: SocketAddr,
+) where
+    R: futures_lite::AsyncRead + Send + Unpin,
+{
+    let keep_state = match config.keep_state {
+        Some(ref keep_state) => keep_state,
+        None => return,
+    };
+
+    let progress = keep_state.register(TransferSnapshot {
+        client: read_req.peer(),
+        local_addr,
+        filename: req.filename.clone(),
+        block_size: read_req.block_size(),
+        window_size: read_req.window_size(),
+        timeout: read_req.timeout(),
+        acked_blocks: 0,
+    });
+    read_req.track_progress(progress);
+}
+
+/// Bind a socket for a transfer or an error reply.
+///
+/// If `local_addr` has a port, it is shared with the listening socket and
+/// the socket is connected to `peer`, so the packets of `peer` are
+/// delivered to it.
+fn bind_socket(
+    transfer_addr: &TransferAddr,
+    peer: SocketAddr,
+) -> Result<Box<dyn Transport>> {
+    if let Some(ref transport) = transfer_addr.transport {
+        return transport.bind_transfer(peer).map_err(Error::Bind);
+    }
+
+    let local_addr = transfer_addr.addr;
+
+    if local_addr.port() != 0 {
+        let socket = bind_hooked(transfer_addr, local_addr, peer, true)
+            .map_err(Error::Bind)?;
+        socket.connect(peer).map_err(Error::Bind)?;
+        return into_transport(socket);
+    }
+
+    let (first, last) = match transfer_addr.ports {
+        Some(ports) => ports,
+        None => {
+            let socket = bind_hooked(transfer_addr, local_addr, peer, false)
+                .map_err(Error::Bind)?;
+            return into_transport(socket);
+        }
+    };
+
+    let mut res = Err(io::ErrorKind::AddrInUse.into());
+
+    // Ports in use are skipped, as long as attempts are left
+    for _ in 0..RANDOM_PORT_ATTEMPTS {
+        let port = random_port(first, last).map_err(Error::Bind)?;
+        let addr = SocketAddr::new(local_addr.ip(), port);
+        res = bind_hooked(transfer_addr, addr, peer, false);
+
+        match res {
+            Err(ref e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            _ => break,
+        }
+    }
+
+    into_transport(res.map_err(Error::Bind)?)
+}
+
+fn into_transport(socket: UdpSocket) -> Result<Box<dyn Transport>> {
+    let socket = Async::new(socket).map_err(Error::Bind)?;
+    Ok(Box::new(socket))
+}
+
+/// Bind the socket of a resumed transfer to the address it had, so the
+/// client keeps sending to it.
+fn bind_resumed_socket(
+    transfer_addr: &TransferAddr,
+    local_addr: SocketAddr,
+    peer: SocketAddr,
+) -> Result<Box<dyn Transport>> {
+    // Replies are sent from the listening port
+    if transfer_addr.addr.port() != 0 || transfer_addr.transport.is_some() {
+        return bind_socket(transfer_addr, peer);
+    }
+
+    let socket = bind_hooked(transfer_addr, local_addr, peer, false)
+        .map_err(Error::Bind)?;
+    into_transport(socket)
+}
+
+/// Bind a socket to `addr`, after its options are set and the socket hook
+/// of the builder set it up for `peer`.
+fn bind_hooked(
+    transfer_addr: &TransferAddr,
+    addr: SocketAddr,
+    peer: SocketAddr,
+    reuse_port: bool,
+) -> io::Result<UdpSocket> {
+    bind_with(addr, reuse_port, |socket| {
+        transfer_addr.opts.apply(socket)?;
+
+        match transfer_addr.socket_hook {
+            Some(ref hook) => hook(socket, peer),
+            None => Ok(()),
+        }
+    })
+}
+
+/// Draw a port from `first..=last` with the CSPRNG of the OS.
+fn random_port(first: u16, last: u16) -> io::Result<u16> {
+    let mut buf = [0u8; 4];
+    getrandom::getrandom(&mut buf).map_err(io::Error::from)?;
+
+    let len = u32::from(last - first) + 1;
+    Ok(first + (u32::from_ne_bytes(buf) % len) as u16)
+}
+
 async fn send_error(
-    error: Error,
+    error: packet::Error,
+    reason: Option<&str>,
     peer: SocketAddr,
-    local_ip: IpAddr,
+    transfer_addr: &TransferAddr,
+    messages: Option<Arc<ErrorMessageFn>>,
 ) -> Result<()> {
-    let addr: SocketAddr = SocketAddr::new(local_ip, 0);
-    let socket = Async::<UdpSocket>::bind(addr).map_err(Error::Bind)?;
+    let socket = bind_socket(transfer_addr, peer)?;
 
-    let data = Packet::Error(error.into()).to_bytes();
+    let mut data = Vec::new();
+    encode_error(messages.as_deref(), &error, reason, &mut data);
     socket.send_to(&data[..], peer).await?;
 
     Ok(())
 }
 
-async fn run_req(
-    req_fut: impl Future<Output = Result<()>>,
+/// Send `e` to the client of a request that failed before its transfer,
+/// with `reason` as message if it has no code of its own.
+async fn failed_transfer(
+    e: packet::Error,
+    reason: Option<&str>,
+    outcome: TransferOutcome,
     peer: SocketAddr,
-    reqs_in_progress: Arc<Mutex<HashSet<SocketAddr>>>,
-    local_ip: IpAddr,
+    transfer_addr: &TransferAddr,
+    messages: Option<Arc<ErrorMessageFn>>,
+) -> Transfer {
+    let sent = send_error(e.clone(), reason, peer, transfer_addr, messages);
+    if let Err(e) = sent.await {
+        trace!("Failed to send error to peer {}: {}", &peer, &e);
+    }
+
+    Transfer {
+        local_addr: None,
+        transferred: 0,
+        result: Err(e),
+        outcome,
+        retransmissions: Retransmissions::default(),
+        oversized_datagrams: 0,
+        duplicate_blocks: 0,
+        options: None,
+    }
+}
+
+/// Direction and filename of the request in `data`, which may be too big
+/// to decode.
+fn req_summary(data: &[u8]) -> (Direction, String) {
+    let direction = match data.get(..2) {
+        Some([0, 2]) => Direction::Write,
+        _ => Direction::Read,
+    };
+    let filename = data.get(2..).unwrap_or_default();
+    let end = filename.iter().position(|&b| b == 0);
+    let filename = &filename[..end.unwrap_or(filename.len())];
+
+    (direction, String::from_utf8_lossy(filename).into_owned())
+}
+
+/// Message of a panic payload, if it is a string.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(msg) => msg,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown panic", |msg| msg.as_str()),
+    }
+}
+
+async fn run_req(
+    req_fut: impl Future<Output = Result<Transfer>>,
+    info: ReqInfo,
+    reqs: Arc<Mutex<ReqMap>>,
+    duplicate_window: Option<Duration>,
+    audit: Option<Arc<dyn AuditSink>>,
+    transfer_addr: TransferAddr,
+    messages: Option<Arc<ErrorMessageFn>>,
 ) {
-    if let Err(e) = req_fut.await {
-        trace!("Request failed (peer: {}, error: {}", &peer, &e);
+    let peer = info.peer;
+    let time = SystemTime::now();
+    let started = Instant::now();
+
+    // A panic of the handler must not kill the task silently
+    let res = AssertUnwindSafe(req_fut).catch_unwind().await;
+
+    let transfer = match res {
+        Ok(Ok(transfer)) => transfer,
+        Ok(Err(e)) => {
+            trace!("Request failed (peer: {}, error: {}", &peer, &e);
+
+            let outcome = match e {
+                Error::Packet(_) => TransferOutcome::Rejected,
+                Error::UnsupportedMode => TransferOutcome::UnsupportedMode,
+                Error::Cancelled => TransferOutcome::Cancelled,
+                _ => TransferOutcome::Other,
+            };
+
+            let reason = e.wire_msg();
+            let e = packet::Error::from(e);
+            failed_transfer(e, reason, outcome, peer, &transfer_addr, messages)
+                .await
+        }
+        Err(panic) => {
+            error!(
+                "Request panicked (peer: {}, direction: {:?}, file: {}, \
+                 panic: {})",
+                &peer,
+                info.direction,
+                &info.filename,
+                panic_message(&*panic)
+            );
 
-        if let Err(e) = send_error(e, peer, local_ip).await {
-            trace!("Failed to send error to peer {}: {}", &peer, &e);
+            let e = packet::Error::Msg("Internal server error".to_string());
+            let outcome = TransferOutcome::Panicked;
+            failed_transfer(e, None, outcome, peer, &transfer_addr, messages)
+                .await
+        }
+    };
+
+    trace!("Request finished (peer: {}, outcome: {})", &peer, transfer.outcome);
+
+    if let Some(audit) = audit {
+        let record = AuditRecord {
+            time,
+            duration: started.elapsed(),
+            client: peer,
+            local_addr: transfer.local_addr,
+            direction: info.direction,
+            options: transfer.options,
+            filename: info.filename,
+            transferred: transfer.transferred,
+            error: transfer.result.err(),
+            outcome: transfer.outcome,
+            retransmissions: transfer.retransmissions,
+            oversized_datagrams: transfer.oversized_datagrams,
+            duplicate_blocks: transfer.duplicate_blocks,
+            restarted: info.restarted,
+        };
+
+        audit.record(&record).await;
+    }
+
+    let mut reqs = reqs.lock().await;
+
+    match duplicate_window {
+        Some(window) => {
+            let now = Instant::now();
+
+            // Forget requests that are finished for longer than `window`
+            reqs.retain(|_, entry| {
+                entry.finished.is_none_or(|finished| {
+                    now.duration_since(finished) < window
+                })
+            });
+
+            if let Some(entry) = reqs.get_mut(&peer) {
+                entry.finished = Some(now);
+            }
+        }
+        None => {
+            reqs.remove(&peer);
         }
     }
 
-    reqs_in_progress.lock().await.remove(&peer);
+    drop(info.active);
 }
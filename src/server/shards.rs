@@ -0,0 +1,164 @@
+use async_executor::Executor;
+use futures_lite::future;
+use log::trace;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::thread;
+
+/// Executors that run request tasks, each on its own thread.
+pub(crate) struct Shards {
+    executors: Vec<Arc<Executor<'static>>>,
+    pin_to_cores: bool,
+}
+
+/// Threads of running shards. They are stopped on drop.
+pub(crate) struct RunningShards {
+    stop: Arc<Stop>,
+}
+
+/// Signal that stops the threads of the shards.
+struct Stop {
+    inner: Mutex<StopInner>,
+}
+
+struct StopInner {
+    stopped: bool,
+    // Waker of each shard thread
+    wakers: Vec<Option<Waker>>,
+}
+
+impl Shards {
+    pub(crate) fn new(executors: usize, pin_to_cores: bool) -> Self {
+        Shards {
+            executors: (0..executors)
+                .map(|_| Arc::new(Executor::new()))
+                .collect(),
+            pin_to_cores,
+        }
+    }
+
+    /// Number of shards.
+    pub(crate) fn len(&self) -> usize {
+        self.executors.len()
+    }
+
+    /// Shard of the requests of `ip`.
+    ///
+    /// All requests of a client run on the same shard.
+    pub(crate) fn index(&self, ip: IpAddr) -> usize {
+        let mut hasher = DefaultHasher::new();
+        ip.hash(&mut hasher);
+
+        (hasher.finish() % self.executors.len() as u64) as usize
+    }
+
+    /// Executor of the requests of `ip`.
+    pub(crate) fn executor(&self, ip: IpAddr) -> &Executor<'static> {
+        &self.executors[self.index(ip)]
+    }
+
+    /// Run every executor on its own thread, until the returned value is
+    /// dropped.
+    pub(crate) fn start(&self) -> io::Result<RunningShards> {
+        let stop = Arc::new(Stop {
+            inner: Mutex::new(StopInner {
+                stopped: false,
+                wakers: vec![None; self.executors.len()],
+            }),
+        });
+        let running = RunningShards {
+            stop: Arc::clone(&stop),
+        };
+
+        for (index, ex) in self.executors.iter().enumerate() {
+            let ex = Arc::clone(ex);
+            let stop = Arc::clone(&stop);
+            let pin_to_cores = self.pin_to_cores;
+
+            thread::Builder::new()
+                .name(format!("tftp-shard-{}", index))
+                .spawn(move || {
+                    if pin_to_cores {
+                        if let Err(e) = pin_to_core(index) {
+                            trace!("Failed to pin shard {}: {}", index, &e);
+                        }
+                    }
+
+                    future::block_on(ex.run(stop.wait(index)));
+                })?;
+        }
+
+        Ok(running)
+    }
+}
+
+impl Stop {
+    async fn wait(&self, index: usize) {
+        future::poll_fn(|cx| {
+            let mut inner = self.inner.lock().unwrap();
+
+            if inner.stopped {
+                return Poll::Ready(());
+            }
+
+            inner.wakers[index] = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl Drop for RunningShards {
+    fn drop(&mut self) {
+        let mut inner = self.stop.inner.lock().unwrap();
+        inner.stopped = true;
+
+        for waker in inner.wakers.iter_mut().filter_map(Option::take) {
+            waker.wake();
+        }
+    }
+}
+
+/// Pin the current thread to the `index`-th CPU that it is allowed to run on.
+#[cfg(target_os = "linux")]
+fn pin_to_core(index: usize) -> io::Result<()> {
+    use std::mem;
+
+    // SAFETY: `cpu_set_t` is a plain bit set, so all zeroes is a valid empty
+    // set. `size` is the size of the sets that the calls read and write, and
+    // the CPU indexes are below `CPU_SETSIZE`.
+    unsafe {
+        let size = mem::size_of::<libc::cpu_set_t>();
+        let mut allowed: libc::cpu_set_t = mem::zeroed();
+
+        if libc::sched_getaffinity(0, size, &mut allowed) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let cpus: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &allowed))
+            .collect();
+
+        if cpus.is_empty() {
+            return Ok(());
+        }
+
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(cpus[index % cpus.len()], &mut set);
+
+        if libc::sched_setaffinity(0, size, &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_index: usize) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,349 @@
+use futures_lite::{AsyncBufRead, AsyncRead, AsyncWrite};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{
+    BlockSource, Direction, Handler, Priority, TransferContext,
+    TransferOutcome, TransferParams, TransferState,
+};
+use crate::packet;
+
+/// Handler that is shared through an [`Arc`].
+///
+/// Methods take `&self`, so state such as caches or limits needs interior
+/// mutability, e.g. `Mutex` or atomics. In return `Arc<H>`, and
+/// `Arc<dyn SharedHandler<Reader = R, Writer = W>>`, implement [`Handler`]
+/// and can be passed to [`TftpServerBuilder::with_handler`]. A clone of the
+/// `Arc` can be kept to inspect the state or to share it with another
+/// server.
+///
+/// Methods have the same meaning as the ones of [`Handler`]. The server
+/// calls them without a lock, so calls of concurrent transfers run in
+/// parallel. Readers of a trait object are never accessed as
+/// `AsyncBufRead`.
+///
+/// [`TftpServerBuilder::with_handler`]: super::TftpServerBuilder::with_handler
+#[crate::async_trait]
+pub trait SharedHandler: Send + Sync {
+    type Reader: AsyncRead + Unpin + Send + 'static;
+    type Writer: AsyncWrite + Unpin + Send + 'static;
+
+    /// See [`Handler::read_req_open`].
+    async fn read_req_open(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error>;
+
+    /// See [`Handler::read_req_open_counted`].
+    async fn read_req_open_counted(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+        _count: u32,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.read_req_open(client, path).await
+    }
+
+    /// See [`Handler::read_req_open_with_state`].
+    async fn read_req_open_with_state(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+        count: Option<u32>,
+    ) -> Result<(Self::Reader, Option<u64>, TransferState), packet::Error> {
+        let (reader, size) = match count {
+            Some(count) => {
+                self.read_req_open_counted(client, path, count).await
+            }
+            None => self.read_req_open(client, path).await,
+        }?;
+        let state = self.transfer_state(client, path, Direction::Read).await;
+
+        Ok((reader, size, state))
+    }
+
+    /// See [`Handler::read_req_open_blocks`].
+    async fn read_req_open_blocks(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<Option<(Box<dyn BlockSource>, Option<u64>)>, packet::Error>
+    {
+        Ok(None)
+    }
+
+    /// See [`Handler::buf_reader`].
+    fn buf_reader(
+        _reader: &mut Self::Reader,
+    ) -> Option<&mut (dyn AsyncBufRead + Unpin + Send)>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// See [`Handler::write_req_open`].
+    async fn write_req_open(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error>;
+
+    /// See [`Handler::write_req_open_with_state`].
+    async fn write_req_open_with_state(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<(Self::Writer, TransferState), packet::Error> {
+        let writer = self.write_req_open(client, path, size).await?;
+        let state = self.transfer_state(client, path, Direction::Write).await;
+
+        Ok((writer, state))
+    }
+
+    /// See [`Handler::write_req_failed`].
+    async fn write_req_failed(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _writer: Self::Writer,
+        _state: &mut TransferState,
+    ) {
+    }
+
+    /// See [`Handler::transfer_priority`].
+    async fn transfer_priority(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+    ) -> Priority {
+        Priority::Normal
+    }
+
+    /// See [`Handler::transfer_params`].
+    async fn transfer_params(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+        _state: &mut TransferState,
+    ) -> TransferParams {
+        TransferParams::default()
+    }
+
+    /// See [`Handler::transfer_state`].
+    async fn transfer_state(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+    ) -> TransferState {
+        TransferState::default()
+    }
+
+    /// See [`Handler::transfer_started`].
+    async fn transfer_started(
+        &self,
+        _ctx: &TransferContext,
+        _state: &mut TransferState,
+    ) {
+    }
+
+    /// See [`Handler::transfer_finished`].
+    async fn transfer_finished(
+        &self,
+        _ctx: &TransferContext,
+        _outcome: TransferOutcome,
+        _state: &mut TransferState,
+    ) {
+    }
+
+    /// See [`Handler::validate`].
+    fn validate(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// See [`Handler::mail_req_open`].
+    async fn mail_req_open(
+        &self,
+        _client: &SocketAddr,
+        _recipient: &str,
+    ) -> Result<Self::Writer, packet::Error> {
+        Err(packet::Error::IllegalOperation)
+    }
+}
+
+/// [`SharedHandler::buf_reader`] of handlers that are not trait objects.
+pub trait SharedBufReader: SharedHandler {
+    fn shared_buf_reader(
+        reader: &mut Self::Reader,
+    ) -> Option<&mut (dyn AsyncBufRead + Unpin + Send)>;
+}
+
+impl<H: SharedHandler> SharedBufReader for H {
+    fn shared_buf_reader(
+        reader: &mut Self::Reader,
+    ) -> Option<&mut (dyn AsyncBufRead + Unpin + Send)> {
+        H::buf_reader(reader)
+    }
+}
+
+impl<R, W> SharedBufReader for dyn SharedHandler<Reader = R, Writer = W>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    fn shared_buf_reader(
+        _reader: &mut Self::Reader,
+    ) -> Option<&mut (dyn AsyncBufRead + Unpin + Send)> {
+        None
+    }
+}
+
+#[crate::async_trait]
+impl<H> Handler for Arc<H>
+where
+    H: SharedBufReader + ?Sized,
+{
+    type Reader = H::Reader;
+    type Writer = H::Writer;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        SharedHandler::read_req_open(&**self, client, path).await
+    }
+
+    async fn read_req_open_counted(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        count: u32,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        SharedHandler::read_req_open_counted(&**self, client, path, count).await
+    }
+
+    async fn read_req_open_with_state(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        count: Option<u32>,
+    ) -> Result<(Self::Reader, Option<u64>, TransferState), packet::Error> {
+        SharedHandler::read_req_open_with_state(&**self, client, path, count)
+            .await
+    }
+
+    async fn read_req_open_blocks(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<Option<(Box<dyn BlockSource>, Option<u64>)>, packet::Error>
+    {
+        SharedHandler::read_req_open_blocks(&**self, client, path).await
+    }
+
+    fn buf_reader(
+        reader: &mut Self::Reader,
+    ) -> Option<&mut (dyn AsyncBufRead + Unpin + Send)> {
+        H::shared_buf_reader(reader)
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        SharedHandler::write_req_open(&**self, client, path, size).await
+    }
+
+    async fn write_req_open_with_state(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<(Self::Writer, TransferState), packet::Error> {
+        SharedHandler::write_req_open_with_state(&**self, client, path, size)
+            .await
+    }
+
+    async fn write_req_failed(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        writer: Self::Writer,
+        state: &mut TransferState,
+    ) {
+        SharedHandler::write_req_failed(&**self, client, path, writer, state)
+            .await
+    }
+
+    async fn transfer_priority(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        direction: Direction,
+    ) -> Priority {
+        SharedHandler::transfer_priority(&**self, client, path, direction).await
+    }
+
+    async fn transfer_params(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        direction: Direction,
+        state: &mut TransferState,
+    ) -> TransferParams {
+        SharedHandler::transfer_params(&**self, client, path, direction, state)
+            .await
+    }
+
+    async fn transfer_state(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        direction: Direction,
+    ) -> TransferState {
+        SharedHandler::transfer_state(&**self, client, path, direction).await
+    }
+
+    async fn transfer_started(
+        &mut self,
+        ctx: &TransferContext,
+        state: &mut TransferState,
+    ) {
+        SharedHandler::transfer_started(&**self, ctx, state).await
+    }
+
+    async fn transfer_finished(
+        &mut self,
+        ctx: &TransferContext,
+        outcome: TransferOutcome,
+        state: &mut TransferState,
+    ) {
+        SharedHandler::transfer_finished(&**self, ctx, outcome, state).await
+    }
+
+    fn validate(&self) -> Vec<String> {
+        SharedHandler::validate(&**self)
+    }
+
+    async fn mail_req_open(
+        &mut self,
+        client: &SocketAddr,
+        recipient: &str,
+    ) -> Result<Self::Writer, packet::Error> {
+        SharedHandler::mail_req_open(&**self, client, recipient).await
+    }
+
+    fn share(&self) -> Option<Self> {
+        Some(Arc::clone(self))
+    }
+}
@@ -0,0 +1,140 @@
+use async_io::Async;
+use log::warn;
+use std::fmt::Write;
+use std::net::{SocketAddr, UdpSocket};
+
+use super::{AuditRecord, AuditSink, Direction};
+use crate::error::{Error, Result};
+
+/// Audit sink that sends request metrics to a statsd server.
+///
+/// For every request the `requests` and `transferred` counters and the
+/// `duration` timing (in milliseconds) are sent in a single datagram.
+/// Restarted requests also increment the `restarts` counter, retransmitted
+/// packets the `retransmissions` counter, oversized datagrams of the
+/// client the `oversized_datagrams` counter, and duplicate blocks of
+/// uploads the `duplicate_blocks` counter.
+///
+/// By default, direction and [outcome](super::TransferOutcome) of the
+/// request are appended to the metric names (e.g.
+/// `tftp.requests.read.completed`). With
+/// [`dogstatsd_tags`](Self::dogstatsd_tags) they are sent as tags instead.
+pub struct StatsdAudit {
+    socket: Async<UdpSocket>,
+    addr: SocketAddr,
+    prefix: String,
+    dogstatsd_tags: bool,
+}
+
+impl StatsdAudit {
+    /// Create sink that sends metrics to `addr`.
+    pub fn new(addr: SocketAddr) -> Result<Self> {
+        let bind_addr: SocketAddr = match addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+
+        let socket =
+            Async::<UdpSocket>::bind(bind_addr).map_err(Error::Bind)?;
+
+        Ok(StatsdAudit {
+            socket,
+            addr,
+            prefix: "tftp".to_owned(),
+            dogstatsd_tags: false,
+        })
+    }
+
+    /// Set prefix of metric names.
+    ///
+    /// **Default:** `tftp`
+    pub fn prefix(self, prefix: &str) -> Self {
+        StatsdAudit {
+            prefix: prefix.to_owned(),
+            ..self
+        }
+    }
+
+    /// Send direction and result as DogStatsD tags.
+    pub fn dogstatsd_tags(self) -> Self {
+        StatsdAudit {
+            dogstatsd_tags: true,
+            ..self
+        }
+    }
+
+    fn encode(&self, record: &AuditRecord) -> String {
+        let direction = match record.direction {
+            Direction::Read => "read",
+            Direction::Write => "write",
+        };
+
+        let outcome = record.outcome.as_str();
+
+        let mut metrics = vec![
+            ("requests", 1, "c"),
+            ("transferred", record.transferred, "c"),
+            ("duration", record.duration.as_millis() as u64, "ms"),
+        ];
+
+        if record.restarted {
+            metrics.push(("restarts", 1, "c"));
+        }
+
+        if record.retransmissions.total > 0 {
+            metrics.push((
+                "retransmissions",
+                record.retransmissions.total,
+                "c",
+            ));
+        }
+
+        if record.oversized_datagrams > 0 {
+            metrics.push((
+                "oversized_datagrams",
+                record.oversized_datagrams,
+                "c",
+            ));
+        }
+
+        if record.duplicate_blocks > 0 {
+            metrics.push(("duplicate_blocks", record.duplicate_blocks, "c"));
+        }
+
+        let mut buf = String::new();
+
+        // Writing in a `String` never fails
+        for (name, value, kind) in &metrics {
+            if !buf.is_empty() {
+                buf.push('\n');
+            }
+
+            if self.dogstatsd_tags {
+                let _ = write!(
+                    buf,
+                    "{}.{}:{}|{}|#direction:{},outcome:{}",
+                    self.prefix, name, value, kind, direction, outcome
+                );
+            } else {
+                let _ = write!(
+                    buf,
+                    "{}.{}.{}.{}:{}|{}",
+                    self.prefix, name, direction, outcome, value, kind
+                );
+            }
+        }
+
+        buf
+    }
+}
+
+#[crate::async_trait]
+impl AuditSink for StatsdAudit {
+    async fn record(&self, record: &AuditRecord) {
+        let buf = self.encode(record);
+
+        if let Err(e) = self.socket.send_to(buf.as_bytes(), self.addr).await {
+            warn!("Failed to send metrics to {}: {}", self.addr, e);
+        }
+    }
+}
@@ -1,23 +1,46 @@
-use async_io::Async;
 use bytes::{Buf, Bytes, BytesMut};
-use futures_lite::{AsyncWrite, AsyncWriteExt};
+use futures_lite::{future, AsyncWrite, AsyncWriteExt};
 use log::trace;
 use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
 use std::io;
-use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::clock::Clock;
 use crate::error::{Error, Result};
-use crate::packet::{Opts, Packet, RwReq, PACKET_DATA_HEADER_LEN};
-use crate::server::{ServerConfig, DEFAULT_BLOCK_SIZE};
-use crate::utils::io_timeout;
+use crate::packet::{
+    self, Opts, Packet, PacketType, RwReq, PACKET_DATA_HEADER_LEN,
+};
+use crate::server::limiter::{Handshake, Reservation};
+use crate::server::{
+    encode_error, CancellationToken, DuplicateBlocks, ErrorMessageFn,
+    OversizedDatagrams, Retransmissions, ServerConfig, StartedNotify,
+    TransferOutcome, DEFAULT_BLOCK_SIZE,
+};
+use crate::transport::Transport;
+use crate::utils::{io_timeout, is_conn_reset, is_msg_size, throttle};
+
+/// Reply of the client to a sent ACK or OACK.
+enum Reply {
+    Data(Bytes),
+    /// Client terminated the transfer with an error.
+    Error(packet::Error),
+    /// Client sent a datagram bigger than the block size.
+    Oversized,
+    /// Client sent a duplicate of the written block with different data.
+    DuplicateMismatch,
+}
 
 pub(crate) struct WriteRequest<'w, W>
 where
     W: AsyncWrite + Send,
 {
     peer: SocketAddr,
-    socket: Async<UdpSocket>,
+    socket: Box<dyn Transport>,
     writer: &'w mut W,
     // BytesMut reclaims memory only if it is continuous.
     // Because we always need to keep the previous ACK, we can not use
@@ -25,10 +48,38 @@ where
     // So we keep previous ACK in `ack` buffer.
     buffer: BytesMut,
     ack: BytesMut,
+    // Bytes of `buffer`, released when the transfer is finished
+    _memory: Option<Reservation>,
+    // Blocks that are not written yet, see `write_buffer_size`
+    write_buffer: Vec<u8>,
+    write_buffer_size: usize,
     block_size: usize,
     timeout: Duration,
+    // Pending until the first reply of the client
+    handshake: Option<Handshake>,
+    handshake_timeout: Duration,
+    started: Option<StartedNotify>,
     max_retries: u32,
+    max_oack_retries: u32,
+    oack_timeout: Duration,
     oack_opts: Option<Opts>,
+    options_rejected: bool,
+    max_bytes_per_sec: Option<u64>,
+    max_write_size: Option<u64>,
+    oversized_policy: OversizedDatagrams,
+    oversized_datagrams: u64,
+    duplicate_policy: DuplicateBlocks,
+    duplicate_blocks: u64,
+    fail_on_conn_reset: bool,
+    // Last written block and digest of its data, if duplicates are checked
+    written_block: Option<u16>,
+    written_digest: Option<u64>,
+    outcome: TransferOutcome,
+    retransmissions: Retransmissions,
+    error_messages: Option<Arc<ErrorMessageFn>>,
+    cancellation: Option<CancellationToken>,
+    clock: Arc<dyn Clock>,
+    transferred: u64,
 }
 
 impl<'w, W> WriteRequest<'w, W>
@@ -40,7 +91,8 @@ where
         peer: SocketAddr,
         req: &RwReq,
         config: ServerConfig,
-        local_ip: IpAddr,
+        socket: Box<dyn Transport>,
+        handshake: Option<Handshake>,
     ) -> Result<WriteRequest<'w, W>> {
         let oack_opts = build_oack_opts(&config, req);
 
@@ -56,8 +108,18 @@ where
             .map(|t| Duration::from_secs(u64::from(t)))
             .unwrap_or(config.timeout);
 
-        let addr = SocketAddr::new(local_ip, 0);
-        let socket = Async::<UdpSocket>::bind(addr).map_err(Error::Bind)?;
+        let handshake_timeout = config.handshake_timeout.unwrap_or(timeout);
+        let oack_timeout = config.oack_timeout.unwrap_or(handshake_timeout);
+        // DATA packet and one more byte to detect oversized datagrams,
+        // and the write buffer
+        let memory = config.memory_budget.as_ref().map(|budget| {
+            budget.reserve(
+                PACKET_DATA_HEADER_LEN
+                    + block_size
+                    + 1
+                    + config.write_buffer_size,
+            )
+        });
 
         Ok(WriteRequest {
             peer,
@@ -65,59 +127,317 @@ where
             writer,
             buffer: BytesMut::new(),
             ack: BytesMut::new(),
+            _memory: memory,
+            write_buffer: Vec::with_capacity(config.write_buffer_size),
+            write_buffer_size: config.write_buffer_size,
             block_size,
             timeout,
+            handshake,
+            handshake_timeout,
+            started: None,
             max_retries: config.max_send_retries,
+            max_oack_retries: config
+                .max_oack_retries
+                .unwrap_or(config.max_send_retries),
+            oack_timeout,
             oack_opts,
+            options_rejected: false,
+            max_bytes_per_sec: config.max_bytes_per_sec,
+            max_write_size: config.max_write_size,
+            oversized_policy: config.oversized_datagrams,
+            oversized_datagrams: 0,
+            duplicate_policy: config.duplicate_blocks,
+            duplicate_blocks: 0,
+            fail_on_conn_reset: config.fail_on_conn_reset,
+            written_block: None,
+            written_digest: None,
+            outcome: TransferOutcome::Completed,
+            retransmissions: Retransmissions::default(),
+            error_messages: config.error_messages,
+            cancellation: config.cancellation,
+            clock: config.clock,
+            transferred: 0,
         })
     }
 
-    pub(crate) async fn handle(&mut self) {
-        if let Err(e) = self.try_handle().await {
+    /// Serve the request. On failure the error is sent to the client
+    /// and returned.
+    ///
+    /// Every await point of `try_handle` is cancellation safe: when the
+    /// transfer is cancelled, the error is sent from the state it was
+    /// dropped in.
+    pub(crate) async fn handle(&mut self) -> Result<(), packet::Error> {
+        let res = match self.cancellation.clone() {
+            Some(token) => {
+                let cancelled = async move {
+                    token.cancelled().await;
+                    Err(Error::Cancelled)
+                };
+                future::or(cancelled, self.try_handle()).await
+            }
+            None => self.try_handle().await,
+        };
+
+        if let Err(e) = res {
             trace!("WRQ request failed (peer: {}, error: {}", self.peer, &e);
 
-            Packet::Error(e.into()).encode(&mut self.buffer);
+            if self.outcome == TransferOutcome::Completed {
+                self.outcome = match e {
+                    Error::MaxSendRetriesReached(..) => {
+                        TransferOutcome::Timeout
+                    }
+                    Error::Cancelled => TransferOutcome::Cancelled,
+                    _ => TransferOutcome::Other,
+                };
+            }
+
+            let reason = e.wire_msg();
+            let e = packet::Error::from(e);
+
+            // Client terminated the transfer with an error
+            if self.outcome == TransferOutcome::ClientError {
+                return Err(e);
+            }
+
+            // Drop what a failed or cancelled block left behind
+            self.buffer.clear();
+            let messages = self.error_messages.as_deref();
+            encode_error(messages, &e, reason, &mut self.buffer);
             let buf = self.buffer.split().freeze();
             // Errors are never retransmitted.
             // We do not care if `send_to` resulted to an IO error.
             let _ = self.socket.send_to(&buf[..], self.peer).await;
+
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Bytes of file data that were written.
+    pub(crate) fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    /// Negotiated block size.
+    pub(crate) fn block_size(&self) -> u16 {
+        self.block_size as u16
+    }
+
+    /// Negotiated retry timeout.
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Local address of the transfer socket.
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Whether the client rejected the options of the OACK.
+    pub(crate) fn options_rejected(&self) -> bool {
+        self.options_rejected
+    }
+
+    /// How the transfer finished.
+    pub(crate) fn outcome(&self) -> TransferOutcome {
+        self.outcome
+    }
+
+    /// Packets that were retransmitted.
+    pub(crate) fn retransmissions(&self) -> &Retransmissions {
+        &self.retransmissions
+    }
+
+    /// Datagrams of the client that exceeded the block size.
+    pub(crate) fn oversized_datagrams(&self) -> u64 {
+        self.oversized_datagrams
+    }
+
+    /// Duplicates of written blocks that were acknowledged again.
+    pub(crate) fn duplicate_blocks(&self) -> u64 {
+        self.duplicate_blocks
+    }
+
+    /// Take back the socket, so the transfer can be restarted from the same
+    /// address.
+    pub(crate) fn into_socket(self) -> Box<dyn Transport> {
+        self.socket
+    }
+
+    /// Run `started` when the client replies for the first time.
+    pub(crate) fn on_started(&mut self, started: StartedNotify) {
+        self.started = Some(started);
+    }
+
+    /// Handshake is completed with the first reply of the client.
+    async fn client_replied(&mut self) {
+        self.handshake = None;
+
+        if let Some(started) = self.started.take() {
+            started.await;
         }
     }
 
     async fn try_handle(&mut self) -> Result<()> {
         let mut block_id: u16 = 0;
 
-        // Send first Ack/OAck
-        match self.oack_opts.take() {
-            Some(opts) => Packet::OAck(opts).encode(&mut self.ack),
-            None => Packet::Ack(0).encode(&mut self.ack),
+        // Writers that open lazily fail before the first ACK
+        if let Err(e) = self.writer.flush().await {
+            self.outcome = TransferOutcome::HandlerIo;
+            return Err(e.into());
         }
 
+        // Send first Ack/OAck
+        let mut oack_sent = match self.oack_opts.take() {
+            Some(opts) => {
+                Packet::OAck(opts).encode_to(&mut self.ack);
+                true
+            }
+            None => {
+                Packet::Ack(0).encode_to(&mut self.ack);
+                false
+            }
+        };
+
         self.socket.send_to(&self.ack, self.peer).await?;
 
         loop {
+            // OACK is retransmitted until the first data block arrives
+            let is_oack = oack_sent;
+            let (max_retries, timeout) = if oack_sent {
+                oack_sent = false;
+                (self.max_oack_retries, self.oack_timeout)
+            } else {
+                (self.max_retries, self.recv_timeout())
+            };
+
             // Recv data
             block_id = block_id.wrapping_add(1);
-            let data = self.recv_data(block_id).await?;
+            let data =
+                self.recv_data(block_id, max_retries, timeout, is_oack).await?;
+
+            let last = data.len() < self.block_size;
 
             // Write data to file
-            self.writer.write_all(&data[..]).await?;
+            if self.write_buffer_size == 0 {
+                self.write(&data).await?;
+            } else {
+                self.write_buffer.extend_from_slice(&data);
+
+                if last || self.write_buffer.len() >= self.write_buffer_size {
+                    let buffer = mem::take(&mut self.write_buffer);
+                    let res = self.write(&buffer).await;
+
+                    // Keep the allocation for the next chunk
+                    self.write_buffer = buffer;
+                    self.write_buffer.clear();
+                    res?;
+                }
+            }
+
+            self.written_block = Some(block_id);
+            if self.duplicate_policy == DuplicateBlocks::RejectMismatch {
+                self.written_digest = Some(digest(&data));
+            }
 
-            if data.len() < self.block_size {
+            if last {
                 break;
             }
         }
 
+        // Data is written before the transfer counts as completed
+        if let Err(e) = self.writer.close().await {
+            self.outcome = TransferOutcome::HandlerIo;
+            return Err(e.into());
+        }
+
         Ok(())
     }
 
-    async fn recv_data(&mut self, block_id: u16) -> Result<Bytes> {
-        for _ in 0..=self.max_retries {
-            match self.recv_data_block(block_id).await {
-                Ok(data) => {
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if let Err(e) = self.writer.write_all(data).await {
+            self.outcome = TransferOutcome::HandlerIo;
+            return Err(e.into());
+        }
+
+        self.transferred += data.len() as u64;
+        Ok(())
+    }
+
+    fn recv_timeout(&self) -> Duration {
+        match self.handshake {
+            Some(_) => self.handshake_timeout,
+            None => self.timeout,
+        }
+    }
+
+    async fn recv_data(
+        &mut self,
+        block_id: u16,
+        max_retries: u32,
+        timeout: Duration,
+        is_oack: bool,
+    ) -> Result<Bytes> {
+        for _ in 0..=max_retries {
+            match self.recv_data_block(block_id, timeout).await {
+                Ok(Reply::Error(e)) => {
+                    trace!("WRQ (peer: {}) - Client error: {}", &self.peer, &e);
+
+                    self.options_rejected =
+                        is_oack && e == packet::Error::OptionsNegotiationFailed;
+                    self.outcome = TransferOutcome::ClientError;
+
+                    return Err(Error::Packet(e));
+                }
+                Ok(Reply::Oversized) => {
+                    trace!("WRQ (peer: {}) - Oversized datagram", &self.peer);
+
+                    self.oversized_datagrams += 1;
+                    self.outcome = TransferOutcome::OversizedDatagram;
+
+                    return Err(Error::OversizedDatagram);
+                }
+                Ok(Reply::DuplicateMismatch) => {
+                    trace!(
+                        "WRQ (peer: {}) - Duplicate block differs",
+                        &self.peer
+                    );
+
+                    self.outcome = TransferOutcome::DuplicateMismatch;
+
+                    return Err(Error::DuplicateMismatch);
+                }
+                Ok(Reply::Data(data)) => {
+                    self.client_replied().await;
+
+                    // Client can send more than it announced with `tsize`.
+                    // Checked before the ACK, so the client does not take
+                    // the last block as written.
+                    if let Some(max) = self.max_write_size {
+                        let received = self.transferred
+                            + self.write_buffer.len() as u64
+                            + data.len() as u64;
+
+                        if received > max {
+                            return Err(Error::Packet(packet::Error::DiskFull));
+                        }
+                    }
+
+                    // Delay the ACK of a full block to keep the data rate
+                    // under the limit
+                    if data.len() == self.block_size {
+                        throttle(
+                            &*self.clock,
+                            self.max_bytes_per_sec,
+                            data.len(),
+                        )
+                        .await;
+                    }
+
                     // Data received, send ACK
                     self.ack.clear();
-                    Packet::Ack(block_id).encode(&mut self.ack);
+                    Packet::Ack(block_id).encode_to(&mut self.ack);
 
                     self.socket.send_to(&self.ack, self.peer).await?;
                     return Ok(data);
@@ -125,6 +445,7 @@ where
                 Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
                     // On timeout reply with the previous ACK packet
                     self.socket.send_to(&self.ack, self.peer).await?;
+                    self.retransmissions.add(block_id.wrapping_sub(1));
                     continue;
                 }
                 Err(e) => return Err(e.into()),
@@ -134,38 +455,144 @@ where
         Err(Error::MaxSendRetriesReached(self.peer, block_id))
     }
 
-    async fn recv_data_block(&mut self, block_id: u16) -> io::Result<Bytes> {
+    async fn recv_data_block(
+        &mut self,
+        block_id: u16,
+        timeout: Duration,
+    ) -> io::Result<Reply> {
         let socket = &mut self.socket;
         let peer = self.peer;
+        let max_len = PACKET_DATA_HEADER_LEN + self.block_size;
+        let policy = self.oversized_policy;
+        let oversized = &mut self.oversized_datagrams;
+        let ack = &self.ack;
+        let written_block = self.written_block;
+        let written_digest = self.written_digest;
+        let duplicates = &mut self.duplicate_blocks;
+        let retransmissions = &mut self.retransmissions;
+        let fail_on_conn_reset = self.fail_on_conn_reset;
 
-        self.buffer.resize(PACKET_DATA_HEADER_LEN + self.block_size, 0);
+        // One more byte detects datagrams that exceed the block size
+        self.buffer.resize(max_len + 1, 0);
         let mut buf = self.buffer.split();
 
-        io_timeout(self.timeout, async move {
+        io_timeout(&*self.clock, timeout, async move {
             loop {
-                let (len, recved_peer) = socket.recv_from(&mut buf[..]).await?;
+                let (len, recved_peer) = match socket
+                    .recv_from(&mut buf[..])
+                    .await
+                {
+                    Ok(x) => x,
+                    Err(ref e) if is_conn_reset(e) && !fail_on_conn_reset => {
+                        continue
+                    }
+                    // Transport failed the receive of a datagram that does
+                    // not fit, instead of truncating it
+                    Err(ref e) if is_msg_size(e) => match policy {
+                        OversizedDatagrams::Reject => {
+                            return Ok(Reply::Oversized)
+                        }
+                        OversizedDatagrams::Ignore => {
+                            *oversized += 1;
+                            continue;
+                        }
+                    },
+                    Err(e) => return Err(e),
+                };
 
                 if recved_peer != peer {
                     continue;
                 }
 
-                if let Ok(Packet::Data(recved_block_id, _)) =
-                    Packet::decode(&buf[..len])
-                {
-                    if recved_block_id == block_id {
+                if len > max_len {
+                    match packet_type(&buf[..len]) {
+                        Some(PacketType::Data) => match policy {
+                            OversizedDatagrams::Reject => {
+                                return Ok(Reply::Oversized)
+                            }
+                            OversizedDatagrams::Ignore => {
+                                *oversized += 1;
+                                continue;
+                            }
+                        },
+                        // Only DATA is limited to the block size. Messages
+                        // of errors may be longer and are cut off.
+                        Some(PacketType::Error) => {
+                            if let Some(error) = truncated_error(&buf[..len]) {
+                                return Ok(Reply::Error(error));
+                            }
+                        }
+                        _ => {}
+                    }
+
+                    continue;
+                }
+
+                match Packet::decode(&buf[..len]) {
+                    Ok(Packet::Data(recved_block_id, _))
+                        if recved_block_id == block_id =>
+                    {
                         buf.truncate(len);
                         buf.advance(PACKET_DATA_HEADER_LEN);
                         break;
                     }
+                    // Our ACK of the written block was lost or delayed, so
+                    // the client sent it again. It is not written again,
+                    // and it does not extend the timeout of the next block.
+                    Ok(Packet::Data(recved_block_id, data))
+                        if Some(recved_block_id) == written_block =>
+                    {
+                        trace!(
+                            "WRQ (peer: {}) - Duplicate block {}",
+                            peer,
+                            recved_block_id
+                        );
+
+                        *duplicates += 1;
+
+                        if let Some(written) = written_digest {
+                            if digest(data) != written {
+                                return Ok(Reply::DuplicateMismatch);
+                            }
+                        }
+
+                        socket.send_to(ack, peer).await?;
+                        retransmissions.add(recved_block_id);
+                    }
+                    Ok(Packet::Error(e)) => return Ok(Reply::Error(e)),
+                    _ => {}
                 }
             }
 
-            Ok(buf.freeze())
+            Ok(Reply::Data(buf.freeze()))
         })
         .await
     }
 }
 
+/// Type of the packet in `buf`, if it starts with a known opcode.
+fn packet_type(buf: &[u8]) -> Option<PacketType> {
+    let opcode = buf.get(..2)?;
+    PacketType::from_u16(u16::from_be_bytes([opcode[0], opcode[1]]))
+}
+
+/// Error of an ERROR packet that is cut off at the end of `buf`, with the
+/// start of its message.
+fn truncated_error(buf: &[u8]) -> Option<packet::Error> {
+    let code = buf.get(2..4)?;
+    let code = u16::from_be_bytes([code[0], code[1]]);
+    let msg = buf[4..].split(|b| *b == 0).next().unwrap_or_default();
+
+    Some(packet::Error::from_code(code, Some(&String::from_utf8_lossy(msg))))
+}
+
+/// Digest of the data of a block, to detect mismatching duplicates.
+fn digest(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
 fn build_oack_opts(config: &ServerConfig, req: &RwReq) -> Option<Opts> {
     let mut opts = Opts::default();
 
@@ -177,11 +604,11 @@ fn build_oack_opts(config: &ServerConfig, req: &RwReq) -> Option<Opts> {
         };
     }
 
-    if !config.ignore_client_timeout {
-        opts.timeout = req.opts.timeout;
-    }
+    opts.timeout = config.client_timeout(req);
 
-    opts.transfer_size = req.opts.transfer_size;
+    if !config.omit_transfer_size {
+        opts.transfer_size = req.opts.transfer_size;
+    }
 
     if opts == Opts::default() {
         None
@@ -0,0 +1,113 @@
+use futures_lite::future;
+use std::net::SocketAddr;
+use std::thread::{self, JoinHandle};
+
+use super::MemoryNetwork;
+use crate::client::TftpClient;
+use crate::error::Result;
+use crate::server::{CancellationToken, Handler, TftpServerBuilder};
+
+/// Address of the server of [`loopback_pair`] on its network.
+const SERVER_ADDR: ([u8; 4], u16) = ([10, 0, 0, 1], 69);
+
+/// Address of the client of [`loopback_pair`] on its network.
+const CLIENT_IP: [u8; 4] = [10, 0, 0, 2];
+
+/// Server of [`loopback_pair`] that runs on its own thread.
+///
+/// The server is stopped on drop.
+#[derive(Debug)]
+pub struct LoopbackServer {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+    token: CancellationToken,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+/// Start the server of `builder` on a [`MemoryNetwork`] and create a client
+/// of it.
+///
+/// Datagrams never touch the network stack of the OS, so tests do not
+/// depend on free ports or firewalls. This allows end-to-end tests of a
+/// handler in a few lines:
+///
+/// ```ignore
+/// let builder = TftpServerBuilder::with_handler(MockHandler::new());
+/// let (client, _server) = loopback_pair(builder).await?;
+///
+/// let data = client.read_to_vec("kernel").await?;
+/// ```
+///
+/// The transport and the cancellation token of `builder` are replaced. More
+/// clients can join the network of [`LoopbackServer::network`].
+pub async fn loopback_pair<H>(
+    builder: TftpServerBuilder<H>,
+) -> Result<(TftpClient, LoopbackServer)>
+where
+    H: Handler + 'static,
+{
+    let network = MemoryNetwork::new();
+    let token = CancellationToken::new();
+    let server = builder
+        .transport(network.bind(SocketAddr::from(SERVER_ADDR))?)
+        .cancellation_token(token.clone())
+        .build()
+        .await?;
+    let addr = server.listen_addr()?;
+
+    let thread = thread::Builder::new()
+        .name("tftp-loopback".to_string())
+        .spawn(move || future::block_on(server.serve()))?;
+
+    let client = TftpClient::new(addr)
+        .transport(network.bind(SocketAddr::from((CLIENT_IP, 0)))?);
+    let server = LoopbackServer {
+        addr,
+        network,
+        token,
+        thread: Some(thread),
+    };
+
+    Ok((client, server))
+}
+
+impl LoopbackServer {
+    /// Address that the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Network that the server is reachable on.
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    /// Stop the server and return the result of serving.
+    ///
+    /// Running transfers are cancelled.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.token.cancel();
+
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(res)) => res,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LoopbackServer {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.token.cancel();
+            return;
+        }
+
+        // Errors can only be observed with `shutdown`
+        let _ = self.stop();
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+
+use crate::transport::Transport;
+
+/// Datagrams that a socket holds before new ones are dropped, like the
+/// receive buffer of a UDP socket.
+const QUEUE_LIMIT: usize = 1024;
+
+/// First port that is assigned to sockets that are bound to port 0.
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// Network of in-memory datagram sockets.
+///
+/// Datagrams are delivered instantly and in order, without touching the
+/// network stack of the OS. Datagrams to addresses without a socket are
+/// dropped, like with UDP.
+///
+/// # Example
+///
+/// ```ignore
+/// let network = MemoryNetwork::new();
+/// let server = TftpServerBuilder::with_handler(handler)
+///     .transport(network.bind("10.0.0.1:69".parse()?)?)
+///     .build()
+///     .await?;
+/// let client = TftpClient::new(server.listen_addr()?)
+///     .transport(network.bind("10.0.0.2:0".parse()?)?);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryNetwork {
+    inner: Arc<Mutex<Network>>,
+}
+
+#[derive(Debug, Default)]
+struct Network {
+    sockets: HashMap<SocketAddr, Weak<Mailbox>>,
+    next_port: u16,
+}
+
+#[derive(Debug, Default)]
+struct Mailbox {
+    state: Mutex<MailboxState>,
+}
+
+#[derive(Debug, Default)]
+struct MailboxState {
+    datagrams: VecDeque<(Vec<u8>, SocketAddr)>,
+    waker: Option<Waker>,
+}
+
+/// Socket of a [`MemoryNetwork`].
+///
+/// The address is released on drop.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    mailbox: Arc<Mailbox>,
+}
+
+impl MemoryNetwork {
+    /// Create an empty network.
+    pub fn new() -> Self {
+        MemoryNetwork::default()
+    }
+
+    /// Bind a socket to `addr`. If its port is 0, a free port is assigned.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<MemoryTransport> {
+        let mut network = self.inner.lock().unwrap();
+        network.sockets.retain(|_, mailbox| mailbox.strong_count() > 0);
+
+        let addr = match addr.port() {
+            0 => network.free_addr(addr.ip())?,
+            _ if network.sockets.contains_key(&addr) => {
+                return Err(io::ErrorKind::AddrInUse.into())
+            }
+            _ => addr,
+        };
+
+        let mailbox = Arc::new(Mailbox::default());
+        network.sockets.insert(addr, Arc::downgrade(&mailbox));
+
+        Ok(MemoryTransport {
+            network: self.clone(),
+            addr,
+            mailbox,
+        })
+    }
+
+    fn deliver(&self, data: &[u8], from: SocketAddr, to: SocketAddr) {
+        let mailbox = self.inner.lock().unwrap().sockets.get(&to).cloned();
+
+        if let Some(mailbox) = mailbox.and_then(|m| m.upgrade()) {
+            let mut state = mailbox.state.lock().unwrap();
+
+            if state.datagrams.len() < QUEUE_LIMIT {
+                state.datagrams.push_back((data.to_vec(), from));
+            }
+
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Network {
+    fn free_addr(&mut self, ip: IpAddr) -> io::Result<SocketAddr> {
+        let ports = u32::from(u16::MAX - FIRST_EPHEMERAL_PORT) + 1;
+
+        for _ in 0..ports {
+            let port = FIRST_EPHEMERAL_PORT + self.next_port;
+            self.next_port = ((u32::from(self.next_port) + 1) % ports) as u16;
+
+            let addr = SocketAddr::new(ip, port);
+            if !self.sockets.contains_key(&addr) {
+                return Ok(addr);
+            }
+        }
+
+        Err(io::ErrorKind::AddrInUse.into())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn poll_send_to(
+        &self,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        self.network.deliver(buf, self.addr, target);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let mut state = self.mailbox.state.lock().unwrap();
+
+        match state.datagrams.pop_front() {
+            Some((data, from)) => {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                Poll::Ready(Ok((len, from)))
+            }
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn bind_transfer(
+        &self,
+        _peer: SocketAddr,
+    ) -> io::Result<Box<dyn Transport>> {
+        let addr = SocketAddr::new(self.addr.ip(), 0);
+        Ok(Box::new(self.network.bind(addr)?))
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        let mut network = self.network.inner.lock().unwrap();
+        network.sockets.remove(&self.addr);
+    }
+}
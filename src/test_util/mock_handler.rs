@@ -0,0 +1,177 @@
+use futures_lite::io::{AsyncWrite, Cursor};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use crate::packet;
+use crate::server::{Direction, Handler};
+
+/// Handler with programmable responses.
+///
+/// Read requests are served from files that are set with
+/// [`file`](Self::file), and the data of write requests is kept in memory.
+/// Requests of unknown files are answered with
+/// [`packet::Error::FileNotFound`].
+///
+/// Clones share their state, so a test can keep a clone to inspect the
+/// handler after it is moved into the server.
+#[derive(Clone, Default)]
+pub struct MockHandler {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    files: HashMap<PathBuf, Vec<u8>>,
+    errors: HashMap<PathBuf, packet::Error>,
+    written: HashMap<PathBuf, Vec<u8>>,
+    requests: Vec<MockRequest>,
+}
+
+/// Request that reached a [`MockHandler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockRequest {
+    pub client: SocketAddr,
+    pub path: PathBuf,
+    pub direction: Direction,
+}
+
+/// Writer of [`MockHandler`].
+pub struct MockWriter {
+    state: Arc<Mutex<MockState>>,
+    path: PathBuf,
+}
+
+impl MockHandler {
+    pub fn new() -> Self {
+        MockHandler::default()
+    }
+
+    /// Serve `content` for read requests of `path`.
+    pub fn file<P, C>(self, path: P, content: C) -> Self
+    where
+        P: Into<PathBuf>,
+        C: Into<Vec<u8>>,
+    {
+        self.lock().files.insert(path.into(), content.into());
+        self
+    }
+
+    /// Answer requests of `path` with `error`, in both directions.
+    pub fn error<P>(self, path: P, error: packet::Error) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.lock().errors.insert(path.into(), error);
+        self
+    }
+
+    /// Data that write requests stored for `path`.
+    pub fn written<P>(&self, path: P) -> Option<Vec<u8>>
+    where
+        P: AsRef<Path>,
+    {
+        self.lock().written.get(path.as_ref()).cloned()
+    }
+
+    /// Requests that reached the handler, in order.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.lock().requests.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Record request and return the error that is set for its path.
+    fn request(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+        direction: Direction,
+    ) -> Result<(), packet::Error> {
+        let mut state = self.lock();
+
+        state.requests.push(MockRequest {
+            client: *client,
+            path: path.to_owned(),
+            direction,
+        });
+
+        match state.errors.get(path) {
+            Some(e) => Err(e.clone()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[crate::async_trait]
+impl Handler for MockHandler {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = MockWriter;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.request(client, path, Direction::Read)?;
+
+        match self.lock().files.get(path) {
+            Some(content) => {
+                let size = content.len() as u64;
+                Ok((Cursor::new(content.clone()), Some(size)))
+            }
+            None => Err(packet::Error::FileNotFound),
+        }
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.request(client, path, Direction::Write)?;
+        self.lock().written.insert(path.to_owned(), Vec::new());
+
+        Ok(MockWriter {
+            state: Arc::clone(&self.state),
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl AsyncWrite for MockWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(written) = state.written.get_mut(&self.path) {
+            written.extend_from_slice(buf);
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
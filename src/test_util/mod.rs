@@ -0,0 +1,15 @@
+//! Utilities for testing code that builds on this crate.
+//!
+//! Available with the `test-util` feature.
+
+mod loopback;
+mod memory;
+mod mock_handler;
+mod pcap;
+mod request;
+
+pub use self::loopback::*;
+pub use self::memory::*;
+pub use self::mock_handler::*;
+pub use self::pcap::*;
+pub use self::request::*;
@@ -0,0 +1,420 @@
+use async_io::Async;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::clock::SystemClock;
+use crate::packet::PacketType;
+use crate::utils::io_timeout;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const IPPROTO_UDP: u8 = 17;
+
+/// TFTP session that was captured in a pcap file.
+///
+/// Only UDP datagrams are kept. IP fragments and packets of other protocols
+/// are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    packets: Vec<CapturedPacket>,
+}
+
+/// UDP datagram of a [`Capture`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedPacket {
+    /// Time since the first packet of the capture.
+    pub time: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+impl Capture {
+    /// Read capture from a pcap file.
+    pub fn open<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Capture::parse(&fs::read(path)?)
+    }
+
+    /// Parse capture from the content of a pcap file.
+    ///
+    /// Ethernet, Linux cooked, BSD loopback and raw IP link types are
+    /// supported.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let header =
+            data.get(..24).ok_or_else(|| invalid("truncated header"))?;
+
+        let (big_endian, nanos) = match header[..4] {
+            [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+            [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+            _ => return Err(invalid("not a pcap file")),
+        };
+
+        let read_u32 = |buf: &[u8]| {
+            let bytes = [buf[0], buf[1], buf[2], buf[3]];
+
+            if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        };
+
+        let linktype = read_u32(&header[20..]) & 0xffff;
+        let mut rest = &data[24..];
+        let mut packets = Vec::new();
+        let mut start = None;
+
+        while !rest.is_empty() {
+            let record =
+                rest.get(..16).ok_or_else(|| invalid("truncated record"))?;
+            let len = read_u32(&record[8..]) as usize;
+            let frame = rest
+                .get(16..16 + len)
+                .ok_or_else(|| invalid("truncated record"))?;
+            rest = &rest[16 + len..];
+
+            let secs = u64::from(read_u32(record));
+            let frac = read_u32(&record[4..]);
+            let time = match nanos {
+                true => Duration::new(secs, frac),
+                false => {
+                    Duration::new(secs, 0) + Duration::from_micros(frac.into())
+                }
+            };
+            let start = *start.get_or_insert(time);
+
+            if let Some((src, dst, data)) = parse_frame(linktype, frame) {
+                packets.push(CapturedPacket {
+                    time: time.saturating_sub(start),
+                    src,
+                    dst,
+                    data: data.to_owned(),
+                });
+            }
+        }
+
+        Ok(Capture {
+            packets,
+        })
+    }
+
+    /// All UDP datagrams of the capture, in order.
+    pub fn packets(&self) -> &[CapturedPacket] {
+        &self.packets
+    }
+
+    /// Addresses that sent a RRQ or WRQ.
+    pub fn clients(&self) -> Vec<SocketAddr> {
+        let mut clients = Vec::new();
+
+        for packet in &self.packets {
+            if is_request(&packet.data) && !clients.contains(&packet.src) {
+                clients.push(packet.src);
+            }
+        }
+
+        clients
+    }
+
+    /// Datagrams that the server sent, in order.
+    pub fn server_packets(&self) -> Vec<&CapturedPacket> {
+        let clients = self.clients();
+
+        self.packets.iter().filter(|p| clients.contains(&p.dst)).collect()
+    }
+}
+
+/// Replay the client side of `capture` against the server at `server`.
+///
+/// Every client of the capture gets its own socket. Requests are sent to
+/// `server`, and the rest of the client packets to the transfer socket of
+/// the server. Before each client packet, the server packets that preceded
+/// it in the capture are awaited for up to `wait` each, so timeouts of the
+/// captured session are replayed too.
+///
+/// Returns the datagrams that the server sent, in order.
+pub async fn replay_client(
+    capture: &Capture,
+    server: SocketAddr,
+    wait: Duration,
+) -> io::Result<Vec<Vec<u8>>> {
+    let clients = capture.clients();
+    let mut sockets = HashMap::new();
+
+    for client in &clients {
+        let socket = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0))?;
+        sockets.insert(*client, socket);
+    }
+
+    // Captured server addresses, mapped to the addresses of the replay
+    let mut servers: HashMap<SocketAddr, SocketAddr> = HashMap::new();
+    let mut replies = Vec::new();
+    let mut pending = Vec::new();
+
+    for packet in capture.packets() {
+        if clients.contains(&packet.dst) {
+            pending.push(packet);
+            continue;
+        }
+
+        let socket = match sockets.get(&packet.src) {
+            Some(socket) => socket,
+            None => continue,
+        };
+
+        // Receive what the server sent before this packet
+        for expected in pending.drain(..) {
+            let socket = &sockets[&expected.dst];
+
+            if let Some((data, from)) = recv_reply(socket, wait).await? {
+                servers.entry(expected.src).or_insert(from);
+                replies.push(data);
+            }
+        }
+
+        let dst = match is_request(&packet.data) {
+            true => server,
+            false => match servers.get(&packet.dst) {
+                Some(dst) => *dst,
+                // Server did not answer in the replay
+                None => continue,
+            },
+        };
+
+        socket.send_to(&packet.data, dst).await?;
+    }
+
+    for expected in pending {
+        let socket = &sockets[&expected.dst];
+
+        if let Some((data, _)) = recv_reply(socket, wait).await? {
+            replies.push(data);
+        }
+    }
+
+    Ok(replies)
+}
+
+/// Replay the server side of `capture` against a client.
+///
+/// `listener` stands for the address that received the requests of the
+/// capture, so the client under test sends its request there. Every
+/// transfer address of the captured server gets its own socket. Before each
+/// server packet, the client packets that preceded it in the capture are
+/// awaited for up to `wait` each, and server packets are sent to the
+/// address that the matching request came from.
+///
+/// Returns the datagrams that the client sent, in order.
+pub async fn replay_server(
+    capture: &Capture,
+    listener: &Async<UdpSocket>,
+    wait: Duration,
+) -> io::Result<Vec<Vec<u8>>> {
+    let clients = capture.clients();
+
+    // Captured transfer addresses of the server, and their sockets
+    let mut sockets: HashMap<SocketAddr, Async<UdpSocket>> = HashMap::new();
+    // Captured client addresses, mapped to the addresses of the replay
+    let mut peers: HashMap<SocketAddr, SocketAddr> = HashMap::new();
+    let mut requests = Vec::new();
+    let mut pending = Vec::new();
+
+    for packet in capture.packets() {
+        if clients.contains(&packet.src) {
+            pending.push(packet);
+            continue;
+        }
+
+        if !clients.contains(&packet.dst) {
+            continue;
+        }
+
+        // Receive what the client sent before this packet
+        for expected in pending.drain(..) {
+            let socket = match is_request(&expected.data) {
+                true => listener,
+                false => match sockets.get(&expected.dst) {
+                    Some(socket) => socket,
+                    // Client sent to a transfer socket that does not exist
+                    None => continue,
+                },
+            };
+
+            if let Some((data, from)) = recv_reply(socket, wait).await? {
+                peers.entry(expected.src).or_insert(from);
+                requests.push(data);
+            }
+        }
+
+        let dst = match peers.get(&packet.dst) {
+            Some(dst) => *dst,
+            // Client did not send its request in the replay
+            None => continue,
+        };
+
+        let socket = match sockets.entry(packet.src) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let ip = listener.get_ref().local_addr()?.ip();
+                entry.insert(Async::<UdpSocket>::bind((ip, 0))?)
+            }
+        };
+
+        socket.send_to(&packet.data, dst).await?;
+    }
+
+    for expected in pending {
+        let socket = match is_request(&expected.data) {
+            true => listener,
+            false => match sockets.get(&expected.dst) {
+                Some(socket) => socket,
+                None => continue,
+            },
+        };
+
+        if let Some((data, _)) = recv_reply(socket, wait).await? {
+            requests.push(data);
+        }
+    }
+
+    Ok(requests)
+}
+
+/// Receive a datagram, or `None` if nothing arrives within `wait`.
+async fn recv_reply(
+    socket: &Async<UdpSocket>,
+    wait: Duration,
+) -> io::Result<Option<(Vec<u8>, SocketAddr)>> {
+    let mut buf = vec![0u8; 65536];
+
+    match io_timeout(&SystemClock, wait, socket.recv_from(&mut buf)).await {
+        Ok((len, from)) => {
+            buf.truncate(len);
+            Ok(Some((buf, from)))
+        }
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_request(data: &[u8]) -> bool {
+    matches!(
+        be16(data, 0).and_then(PacketType::from_u16),
+        Some(PacketType::Rrq) | Some(PacketType::Wrq)
+    )
+}
+
+fn be16(buf: &[u8], at: usize) -> Option<u16> {
+    let bytes = buf.get(at..at + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("pcap: {}", msg))
+}
+
+/// Returns source, destination and payload of a UDP datagram.
+fn parse_frame(
+    linktype: u32,
+    frame: &[u8],
+) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (ethertype, ip) = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = be16(frame, 12)?;
+            let mut ip = &frame[14..];
+
+            if ethertype == ETHERTYPE_VLAN {
+                ethertype = be16(ip, 2)?;
+                ip = &ip[4..];
+            }
+
+            (ethertype, ip)
+        }
+        LINKTYPE_LINUX_SLL => (be16(frame, 14)?, &frame[16..]),
+        LINKTYPE_NULL => {
+            let ip = frame.get(4..)?;
+
+            match ip.first()? >> 4 {
+                4 => (ETHERTYPE_IPV4, ip),
+                6 => (ETHERTYPE_IPV6, ip),
+                _ => return None,
+            }
+        }
+        LINKTYPE_RAW => match frame.first()? >> 4 {
+            4 => (ETHERTYPE_IPV4, frame),
+            6 => (ETHERTYPE_IPV6, frame),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let (src_ip, dst_ip, udp) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_len = usize::from(ip.first()? & 0x0f) * 4;
+            let total_len = usize::from(be16(ip, 2)?);
+            let flags_offset = be16(ip, 6)?;
+
+            // More fragments flag or fragment offset
+            if *ip.get(9)? != IPPROTO_UDP || flags_offset & 0x3fff != 0 {
+                return None;
+            }
+
+            let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            let udp = ip.get(header_len..total_len)?;
+
+            (
+                IpAddr::from(Ipv4Addr::from(src)),
+                IpAddr::from(Ipv4Addr::from(dst)),
+                udp,
+            )
+        }
+        ETHERTYPE_IPV6 => {
+            // Extension headers are not supported
+            if *ip.get(6)? != IPPROTO_UDP {
+                return None;
+            }
+
+            let payload_len = usize::from(be16(ip, 4)?);
+            let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            let udp = ip.get(40..40 + payload_len)?;
+
+            (
+                IpAddr::from(Ipv6Addr::from(src)),
+                IpAddr::from(Ipv6Addr::from(dst)),
+                udp,
+            )
+        }
+        _ => return None,
+    };
+
+    let src_port = be16(udp, 0)?;
+    let dst_port = be16(udp, 2)?;
+    let len = usize::from(be16(udp, 4)?);
+    let data = udp.get(8..len)?;
+
+    Some((
+        SocketAddr::new(src_ip, src_port),
+        SocketAddr::new(dst_ip, dst_port),
+        data,
+    ))
+}
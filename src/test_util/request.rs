@@ -0,0 +1,77 @@
+#[cfg(feature = "bytes-api")]
+use bytes::Bytes;
+
+#[cfg(feature = "bytes-api")]
+use crate::packet::Packet;
+use crate::packet::{Mode, Opts, RwReq};
+
+/// Builder of RRQ and WRQ packets.
+///
+/// Mode of the request is `octet`, unless it is set with
+/// [`mode`](Self::mode). Options are sent only if they are set.
+#[derive(Debug)]
+pub struct RequestBuilder {
+    req: RwReq,
+}
+
+impl RequestBuilder {
+    pub fn new<F>(filename: F) -> Self
+    where
+        F: AsRef<[u8]>,
+    {
+        RequestBuilder {
+            req: RwReq {
+                filename: filename.as_ref().to_owned(),
+                mode: Mode::Octet,
+                opts: Opts::default(),
+            },
+        }
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.req.mode = mode;
+        self
+    }
+
+    /// Request `blksize` option (RFC2348).
+    pub fn block_size(mut self, size: u16) -> Self {
+        self.req.opts.block_size = Some(size);
+        self
+    }
+
+    /// Request `timeout` option (RFC2349).
+    pub fn timeout(mut self, secs: u8) -> Self {
+        self.req.opts.timeout = Some(secs);
+        self
+    }
+
+    /// Request `tsize` option (RFC2349). Read requests send 0 to ask for
+    /// the size of the file.
+    pub fn transfer_size(mut self, size: u64) -> Self {
+        self.req.opts.transfer_size = Some(size);
+        self
+    }
+
+    /// Request `windowsize` option (RFC7440).
+    pub fn window_size(mut self, size: u64) -> Self {
+        self.req.opts.window_size = Some(size);
+        self
+    }
+
+    /// Build the request without encoding it.
+    pub fn build(self) -> RwReq {
+        self.req
+    }
+
+    /// Encode the request as RRQ packet.
+    #[cfg(feature = "bytes-api")]
+    pub fn rrq(self) -> Bytes {
+        Packet::Rrq(self.req).to_vec().into()
+    }
+
+    /// Encode the request as WRQ packet.
+    #[cfg(feature = "bytes-api")]
+    pub fn wrq(self) -> Bytes {
+        Packet::Wrq(self.req).to_vec().into()
+    }
+}
@@ -0,0 +1,89 @@
+use async_channel::Sender;
+use futures_lite::future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{OwnedPacket, Packet};
+use crate::server::{AuditRecord, TftpServerBuilder, TransferOutcome};
+use crate::test_util::RequestBuilder;
+
+fn builder(
+    tx: Sender<AuditRecord>,
+    window_size: u16,
+) -> TftpServerBuilder<MemHandler> {
+    TftpServerBuilder::with_handler(MemHandler::new(content(512 * 10)))
+        .timeout(Duration::from_millis(100))
+        .max_send_retries(3)
+        .max_window_size(window_size)
+        .audit(ChannelSink(tx))
+}
+
+/// Acknowledge block 2, then keep acknowledging old blocks. The transfer
+/// must not rewind, and must time out as if the client was silent.
+async fn rewind(addr: SocketAddr, window_size: Option<u64>) {
+    let mut req = RequestBuilder::new("test");
+    if let Some(window_size) = window_size {
+        req = req.window_size(window_size);
+    }
+    let mut client = RawClient::rrq_with(addr, req).await;
+
+    loop {
+        let packet = client.recv_packet(Duration::from_secs(5)).await;
+
+        match packet.expect("no packet received") {
+            OwnedPacket::OAck(_) => client.send(Packet::Ack(0)).await,
+            OwnedPacket::Data(1, _) if window_size.is_none() => {
+                client.send(Packet::Ack(1)).await
+            }
+            OwnedPacket::Data(2, _) => {
+                client.send(Packet::Ack(2)).await;
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    loop {
+        for id in [0, 1, 2] {
+            client.send(Packet::Ack(id)).await;
+        }
+
+        // Only blocks after the acknowledged one are sent
+        match client.recv_packet(Duration::from_millis(10)).await {
+            Some(OwnedPacket::Data(id, _)) => {
+                assert!(id > 2, "rewound to {}", id)
+            }
+            Some(packet) => panic!("unexpected packet: {:?}", packet),
+            None => {}
+        }
+    }
+}
+
+fn check_regression(window_size: u16) {
+    let (tx, rx) = async_channel::unbounded();
+    let opt = Some(u64::from(window_size)).filter(|&w| w > 1);
+
+    run_with_server(builder(tx, window_size), |addr| async move {
+        let record = future::or(async { rx.recv().await.ok() }, async {
+            rewind(addr, opt).await;
+            None
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(record.outcome, TransferOutcome::Timeout);
+        assert_eq!(record.transferred, 512 * 2);
+    });
+}
+
+#[test]
+fn lockstep_ack_regression() {
+    check_regression(1);
+}
+
+#[test]
+fn windowed_ack_regression() {
+    check_regression(4);
+}
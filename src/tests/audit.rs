@@ -0,0 +1,283 @@
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, UNIX_EPOCH};
+use tempfile::tempdir;
+
+use super::client::TestClient;
+use super::faults::{Faults, FaultySocket};
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, Mode, Opts, Packet};
+use crate::server::{
+    AuditRecord, AuditSink, Direction, JsonLinesAudit, NegotiatedOptions,
+    Retransmissions, StatsdAudit, TftpServerBuilder, TransferOutcome,
+};
+use crate::test_util::RequestBuilder;
+
+#[test]
+fn records_are_sent_to_sink() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(content(1000));
+    let builder =
+        TftpServerBuilder::with_handler(handler).audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let client_addr = socket.local_addr().unwrap();
+        let mut client = TestClient::new(socket, addr);
+        client.read("test", Opts::default()).await.unwrap();
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.client, client_addr);
+        assert!(record.local_addr.is_some_and(|a| a != addr));
+        assert_eq!(record.direction, Direction::Read);
+        assert_eq!(record.filename, "test");
+        assert_eq!(record.transferred, 1000);
+        assert_eq!(record.error, None);
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(
+            record.options,
+            Some(NegotiatedOptions {
+                block_size: 512,
+                timeout: Duration::from_secs(3),
+                window_size: 1,
+                transfer_size: Some(1000),
+            })
+        );
+
+        let req = RequestBuilder::new("mailbox").mode(Mode::Mail).build();
+        request(addr, Packet::Rrq(req)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.filename, "mailbox");
+        assert_eq!(record.local_addr, None);
+        assert_eq!(record.options, None);
+        assert_eq!(record.transferred, 0);
+        assert_eq!(record.error, Some(packet::Error::IllegalOperation));
+        assert_eq!(record.outcome, TransferOutcome::UnsupportedMode);
+    });
+}
+
+fn record(filename: &str, error: Option<packet::Error>) -> AuditRecord {
+    AuditRecord {
+        time: UNIX_EPOCH + Duration::from_millis(1_600_000_000_123),
+        duration: Duration::from_millis(42),
+        client: "10.0.0.1:1234".parse::<SocketAddr>().unwrap(),
+        local_addr: None,
+        direction: Direction::Write,
+        options: None,
+        filename: filename.to_string(),
+        transferred: 512,
+        outcome: match error {
+            Some(_) => TransferOutcome::HandlerIo,
+            None => TransferOutcome::Completed,
+        },
+        error,
+        restarted: false,
+        retransmissions: Retransmissions::default(),
+        oversized_datagrams: 0,
+        duplicate_blocks: 0,
+    }
+}
+
+#[test]
+fn failure_outcomes() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(content(1000));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_millis(100))
+        .max_send_retries(0)
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(500);
+
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert!(client.recv(wait).await.is_some());
+        client.error(packet::Error::Msg("abort".to_string())).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::ClientError);
+        assert_eq!(record.error, Some(packet::Error::Msg("abort".to_string())));
+        assert_eq!(record.transferred, 0);
+
+        // Errors of the client are not answered
+        assert!(client.recv(wait).await.is_none());
+
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert!(client.recv(wait).await.is_some());
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Timeout);
+    });
+}
+
+#[test]
+fn retransmissions_are_recorded() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(content(1000));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_millis(200))
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_secs(1);
+
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(wait).await, Some((1, 512)));
+        client.ack().await;
+
+        // Block 2 is acknowledged after it is sent again
+        assert_eq!(client.recv(wait).await, Some((2, 488)));
+        assert_eq!(client.recv(wait).await, Some((2, 488)));
+        client.finish().await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(
+            record.retransmissions,
+            Retransmissions {
+                total: 1,
+                blocks: vec![(2, 1)],
+            }
+        );
+    });
+}
+
+#[test]
+fn retransmitted_blocks_are_capped() {
+    let mut retransmissions = Retransmissions::default();
+
+    for block_id in 0..1000 {
+        retransmissions.add(block_id);
+        retransmissions.add(block_id);
+    }
+
+    assert_eq!(retransmissions.total, 2000);
+    assert_eq!(retransmissions.blocks.len(), Retransmissions::MAX_BLOCKS);
+    assert_eq!(retransmissions.blocks[0], (0, 2));
+    assert_eq!(retransmissions.blocks.last(), Some(&(63, 2)));
+}
+
+#[test]
+fn json_line() {
+    assert_eq!(
+        record("a\"b\\c\n", None).to_json_line(),
+        "{\"time\":1600000000.123,\"duration_ms\":42,\
+         \"client\":\"10.0.0.1:1234\",\"direction\":\"write\",\
+         \"filename\":\"a\\\"b\\\\c\\n\",\"transferred\":512,\
+         \"outcome\":\"completed\",\"result\":\"ok\"}\n"
+    );
+
+    assert_eq!(
+        record("a", Some(packet::Error::DiskFull)).to_json_line(),
+        "{\"time\":1600000000.123,\"duration_ms\":42,\
+         \"client\":\"10.0.0.1:1234\",\"direction\":\"write\",\
+         \"filename\":\"a\",\"transferred\":512,\
+         \"outcome\":\"handler_io\",\"result\":\"error\",\"error_code\":3,\"error\":\"Disk is full\"}\n"
+    );
+
+    let mut served = record("a", None);
+    served.local_addr = Some("10.0.0.2:4321".parse().unwrap());
+    assert_eq!(
+        served.to_json_line(),
+        "{\"time\":1600000000.123,\"duration_ms\":42,\
+         \"client\":\"10.0.0.1:1234\",\"direction\":\"write\",\
+         \"filename\":\"a\",\"transferred\":512,\
+         \"local_addr\":\"10.0.0.2:4321\",\"outcome\":\"completed\",\
+         \"result\":\"ok\"}\n"
+    );
+
+    served.options = Some(NegotiatedOptions {
+        block_size: 1024,
+        timeout: Duration::from_millis(1500),
+        window_size: 4,
+        transfer_size: Some(512),
+    });
+    assert_eq!(
+        served.to_json_line(),
+        "{\"time\":1600000000.123,\"duration_ms\":42,\
+         \"client\":\"10.0.0.1:1234\",\"direction\":\"write\",\
+         \"filename\":\"a\",\"transferred\":512,\
+         \"local_addr\":\"10.0.0.2:4321\",\"block_size\":1024,\
+         \"timeout_ms\":1500,\"window_size\":4,\"transfer_size\":512,\
+         \"outcome\":\"completed\",\"result\":\"ok\"}\n"
+    );
+
+    let mut lossy = record("a", None);
+    lossy.retransmissions.add(0);
+    lossy.retransmissions.add(3);
+    lossy.retransmissions.add(3);
+    assert_eq!(
+        lossy.to_json_line(),
+        "{\"time\":1600000000.123,\"duration_ms\":42,\
+         \"client\":\"10.0.0.1:1234\",\"direction\":\"write\",\
+         \"filename\":\"a\",\"transferred\":512,\"retransmissions\":3,\
+         \"retransmitted_blocks\":[[0,1],[3,2]],\"outcome\":\"completed\",\
+         \"result\":\"ok\"}\n"
+    );
+
+    let mut restarted = record("a", None);
+    restarted.restarted = true;
+    assert_eq!(
+        restarted.to_json_line(),
+        "{\"time\":1600000000.123,\"duration_ms\":42,\
+         \"client\":\"10.0.0.1:1234\",\"direction\":\"write\",\
+         \"filename\":\"a\",\"transferred\":512,\"restarted\":true,\
+         \"outcome\":\"completed\",\"result\":\"ok\"}\n"
+    );
+}
+
+#[test]
+fn json_lines_file_reopen() {
+    let tmp = tempdir().unwrap();
+    let path = tmp.path().join("audit.log");
+    let rotated = tmp.path().join("audit.log.1");
+
+    let audit = JsonLinesAudit::open(&path).unwrap();
+
+    futures_lite::future::block_on(async {
+        audit.record(&record("a", None)).await;
+
+        fs::rename(&path, &rotated).unwrap();
+        audit.reopen().unwrap();
+
+        audit.record(&record("b", None)).await;
+    });
+
+    let old = fs::read_to_string(&rotated).unwrap();
+    let new = fs::read_to_string(&path).unwrap();
+
+    assert_eq!(old, record("a", None).to_json_line());
+    assert_eq!(new, record("b", None).to_json_line());
+}
+
+#[test]
+fn statsd() {
+    let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let addr = collector.local_addr().unwrap();
+
+    let plain = StatsdAudit::new(addr).unwrap();
+    let tagged = StatsdAudit::new(addr).unwrap().prefix("pxe").dogstatsd_tags();
+    let mut buf = [0u8; 1024];
+
+    futures_lite::future::block_on(plain.record(&record("a", None)));
+    let len = collector.recv(&mut buf).unwrap();
+    assert_eq!(
+        &buf[..len],
+        b"tftp.requests.write.completed:1|c\n\
+          tftp.transferred.write.completed:512|c\n\
+          tftp.duration.write.completed:42|ms"
+    );
+
+    let error = Some(packet::Error::DiskFull);
+    futures_lite::future::block_on(tagged.record(&record("a", error)));
+    let len = collector.recv(&mut buf).unwrap();
+    assert_eq!(
+        &buf[..len],
+        b"pxe.requests:1|c|#direction:write,outcome:handler_io\n\
+          pxe.transferred:512|c|#direction:write,outcome:handler_io\n\
+          pxe.duration:42|ms|#direction:write,outcome:handler_io"
+    );
+}
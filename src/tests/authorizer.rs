@@ -0,0 +1,56 @@
+use std::net::SocketAddr;
+use std::path::Path;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, Packet, RwReq};
+use crate::server::{Authorizer, Direction, TftpServerBuilder};
+use crate::test_util::RequestBuilder;
+
+/// Allows reading everything except `secret`, denies writing.
+struct ReadOnly;
+
+#[crate::async_trait]
+impl Authorizer for ReadOnly {
+    async fn authorize(
+        &self,
+        _client: &SocketAddr,
+        path: &Path,
+        direction: Direction,
+    ) -> Result<(), packet::Error> {
+        match direction {
+            Direction::Read if path == Path::new("secret") => {
+                Err(packet::Error::FileNotFound)
+            }
+            Direction::Read => Ok(()),
+            Direction::Write => Err(packet::Error::PermissionDenied),
+        }
+    }
+}
+
+fn req(filename: &str) -> RwReq {
+    RequestBuilder::new(filename).build()
+}
+
+#[test]
+fn authorizer() {
+    let handler = MemHandler::new(content(10));
+    let builder = TftpServerBuilder::with_handler(handler).authorizer(ReadOnly);
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, Packet::Rrq(req("public"))).await;
+        assert!(matches!(Packet::decode(&reply), Ok(Packet::Data(1, _))));
+
+        let reply = request(addr, Packet::Rrq(req("secret"))).await;
+        assert!(matches!(
+            Packet::decode(&reply),
+            Ok(Packet::Error(packet::Error::FileNotFound))
+        ));
+
+        let reply = request(addr, Packet::Wrq(req("public"))).await;
+        assert!(matches!(
+            Packet::decode(&reply),
+            Ok(Packet::Error(packet::Error::PermissionDenied))
+        ));
+    });
+}
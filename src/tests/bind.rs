@@ -0,0 +1,210 @@
+use futures_lite::future::block_on;
+#[cfg(target_os = "linux")]
+use socket2::SockRef;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::{content, run_with_server, RawClient};
+use crate::error::Error;
+use crate::server::TftpServerBuilder;
+
+#[test]
+fn port_zero_reports_assigned_port() {
+    let tftpd = block_on(
+        TftpServerBuilder::with_handler(MemHandler::new(content(100)))
+            .bind("127.0.0.1:0".parse().unwrap())
+            .build(),
+    )
+    .unwrap();
+
+    // Known before the server is polled
+    let addr = tftpd.listen_addr().unwrap();
+    assert_eq!(addr.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+    assert_ne!(addr.port(), 0);
+
+    thread::spawn(move || block_on(tftpd.serve()));
+
+    let peer = block_on(async {
+        let mut client = RawClient::rrq(addr, "test").await;
+        let block = client.recv(Duration::from_secs(5)).await;
+        assert!(matches!(block, Some((1, _))), "no block received");
+        client.server_addr().unwrap()
+    });
+
+    // Transfer socket is bound on the IP of the listening socket
+    assert_eq!(peer.ip(), addr.ip());
+    assert_ne!(peer.port(), addr.port());
+}
+
+#[test]
+fn listen_addr_in_use() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let res = block_on(
+        TftpServerBuilder::with_handler(MemHandler::new(Vec::new()))
+            .bind(addr)
+            .build(),
+    );
+
+    let e = match res {
+        Err(Error::BindListen(e)) => e,
+        _ => panic!("listening address was bound twice"),
+    };
+
+    assert_eq!(e.addr, addr);
+    assert_eq!(e.source.kind(), io::ErrorKind::AddrInUse);
+    assert!(e.hint().is_some());
+
+    let error = Error::BindListen(e);
+    assert!(error.to_string().contains(&addr.to_string()));
+
+    // Source is the error of the OS, not the same message again
+    let source = std::error::Error::source(&error).unwrap();
+    assert!(source.downcast_ref::<io::Error>().is_some());
+}
+
+#[test]
+fn listen_addr_not_available() {
+    // TEST-NET-1 is never assigned to the host
+    let addr: SocketAddr = "192.0.2.1:0".parse().unwrap();
+
+    let res = block_on(
+        TftpServerBuilder::with_handler(MemHandler::new(Vec::new()))
+            .bind(addr)
+            .build(),
+    );
+
+    match res {
+        Err(Error::BindListen(e)) => {
+            assert_eq!(e.addr, addr);
+            assert_eq!(e.source.kind(), io::ErrorKind::AddrNotAvailable);
+            assert!(e.hint().is_some());
+        }
+        _ => panic!("unassigned address was bound"),
+    }
+}
+
+#[test]
+fn transfer_socket_hook() {
+    let peers = Arc::new(Mutex::new(Vec::new()));
+    let hook_peers = Arc::clone(&peers);
+
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(100)))
+            .transfer_socket_hook(move |socket, peer| {
+                hook_peers.lock().unwrap().push(peer);
+                socket.set_recv_buffer_size(64 * 1024)
+            });
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(Duration::from_secs(5)).await, Some((1, 100)));
+        client.finish().await;
+    });
+
+    let peers = peers.lock().unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+}
+
+#[test]
+fn transfer_socket_hook_fails() {
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(100)))
+            .transfer_socket_hook(|_, _| Err(io::Error::other("hook failed")));
+
+    run_with_server(builder, |addr| async move {
+        // Without a socket the error can not be sent either
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(Duration::from_millis(500)).await, None);
+    });
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn fwmark() {
+    let marks = Arc::new(Mutex::new(Vec::new()));
+    let hook_marks = Arc::clone(&marks);
+
+    let res = block_on(
+        TftpServerBuilder::with_handler(MemHandler::new(content(100)))
+            .bind("127.0.0.1:0".parse().unwrap())
+            .fwmark(0x45)
+            .transfer_socket_hook(move |socket, _| {
+                hook_marks.lock().unwrap().push(socket.mark()?);
+                Ok(())
+            })
+            .build(),
+    );
+
+    let tftpd = match res {
+        Ok(tftpd) => tftpd,
+        // Needs CAP_NET_ADMIN
+        Err(Error::Bind(e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+            return
+        }
+        Err(e) => panic!("failed to build server: {}", e),
+    };
+
+    let socket = SockRef::from(tftpd.socket.udp_socket().unwrap());
+    assert_eq!(socket.mark().unwrap(), 0x45);
+
+    let addr = tftpd.listen_addr().unwrap();
+    thread::spawn(move || block_on(tftpd.serve()));
+
+    block_on(async {
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(Duration::from_secs(5)).await, Some((1, 100)));
+        client.finish().await;
+    });
+
+    assert_eq!(*marks.lock().unwrap(), [0x45]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn bind_device() {
+    let devices = Arc::new(Mutex::new(Vec::new()));
+    let hook_devices = Arc::clone(&devices);
+
+    let res = block_on(
+        TftpServerBuilder::with_handler(MemHandler::new(content(100)))
+            .bind("127.0.0.1:0".parse().unwrap())
+            .bind_device("lo")
+            .transfer_socket_hook(move |socket, _| {
+                hook_devices.lock().unwrap().push(socket.device()?);
+                Ok(())
+            })
+            .build(),
+    );
+
+    let tftpd = match res {
+        Ok(tftpd) => tftpd,
+        // Needs CAP_NET_RAW
+        Err(Error::Bind(e)) if e.kind() == io::ErrorKind::PermissionDenied => {
+            return
+        }
+        Err(e) => panic!("failed to build server: {}", e),
+    };
+
+    let socket = SockRef::from(tftpd.socket.udp_socket().unwrap());
+    assert_eq!(socket.device().unwrap().as_deref(), Some(&b"lo"[..]));
+
+    let addr = tftpd.listen_addr().unwrap();
+    thread::spawn(move || block_on(tftpd.serve()));
+
+    block_on(async {
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(Duration::from_secs(5)).await, Some((1, 100)));
+        client.finish().await;
+    });
+
+    // Transfer sockets are bound to the device of the listening socket
+    let devices = devices.lock().unwrap();
+    assert_eq!(*devices, [Some(b"lo".to_vec())]);
+}
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, Opts, OwnedPacket, Packet};
+use crate::server::{
+    BlockSizePolicy, TftpServerBuilder, TransferOutcome, TransferParams,
+};
+use crate::test_util::RequestBuilder;
+
+fn builder(policy: BlockSizePolicy) -> TftpServerBuilder<MemHandler> {
+    TftpServerBuilder::with_handler(MemHandler::new(content(2000)))
+        .min_block_size(256, policy)
+}
+
+fn rrq(block_size: u16) -> Packet<'static> {
+    Packet::Rrq(RequestBuilder::new("test").block_size(block_size).build())
+}
+
+#[test]
+fn min_block_size_reject() {
+    run_with_server(builder(BlockSizePolicy::Reject), |addr| async move {
+        let reply = request(addr, rrq(8)).await;
+        assert!(matches!(
+            Packet::decode(&reply).unwrap(),
+            Packet::Error(packet::Error::OptionsNegotiationFailed)
+        ));
+
+        let reply = request(addr, rrq(256)).await;
+        assert!(matches!(
+            Packet::decode(&reply).unwrap(),
+            Packet::OAck(Opts {
+                block_size: Some(256),
+                ..
+            })
+        ));
+    });
+}
+
+#[test]
+fn min_block_size_ignore() {
+    run_with_server(builder(BlockSizePolicy::Ignore), |addr| async move {
+        // Option is ignored, so there is nothing to acknowledge
+        let reply = request(addr, rrq(8)).await;
+        assert!(matches!(
+            Packet::decode(&reply).unwrap(),
+            Packet::Data(1, data) if data.len() == 512
+        ));
+
+        let reply = request(addr, rrq(1024)).await;
+        assert!(matches!(
+            Packet::decode(&reply).unwrap(),
+            Packet::OAck(Opts {
+                block_size: Some(1024),
+                ..
+            })
+        ));
+    });
+}
+
+fn oack_block_size(reply: &[u8]) -> Option<u16> {
+    match Packet::decode(reply).unwrap() {
+        Packet::OAck(opts) => opts.block_size,
+        packet => panic!("unexpected packet: {:?}", packet),
+    }
+}
+
+#[test]
+fn max_block_size() {
+    let builder = builder(BlockSizePolicy::Reject).max_block_size(1428);
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, rrq(1468)).await;
+        assert_eq!(oack_block_size(&reply), Some(1428));
+
+        let reply = request(addr, rrq(1024)).await;
+        assert_eq!(oack_block_size(&reply), Some(1024));
+
+        // Minimum is checked against the request of the client
+        let reply = request(addr, rrq(8)).await;
+        assert!(matches!(
+            Packet::decode(&reply).unwrap(),
+            Packet::Error(packet::Error::OptionsNegotiationFailed)
+        ));
+    });
+}
+
+#[test]
+fn max_block_size_of_handler() {
+    let params = TransferParams {
+        max_block_size: Some(1024),
+        ..TransferParams::default()
+    };
+
+    for (limit, expected) in
+        [(Some(1428), 1024), (Some(600), 600), (None, 1024)]
+    {
+        let handler =
+            MemHandler::new(content(2000)).with_params(params.clone());
+        let mut builder = TftpServerBuilder::with_handler(handler);
+        if let Some(limit) = limit {
+            builder = builder.max_block_size(limit);
+        }
+
+        run_with_server(builder, |addr| async move {
+            let reply = request(addr, rrq(1468)).await;
+            assert_eq!(oack_block_size(&reply), Some(expected));
+        });
+    }
+}
+
+#[test]
+fn small_blocks_hold_little_memory() {
+    // Budget of two DATA packets of the default block size
+    let handler = MemHandler::new(content(100));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .max_window_size(4)
+        .memory_budget(2 * 516);
+
+    run_with_server(builder, |addr| async move {
+        let req = RequestBuilder::new("test").block_size(8).window_size(4);
+        let mut client = RawClient::rrq_with(addr, req).await;
+
+        let oack = client.recv_packet(Duration::from_secs(1)).await;
+        assert!(matches!(oack, Some(OwnedPacket::OAck(_))));
+        client.send(Packet::Ack(0)).await;
+
+        // Whole window fits in the budget
+        for id in 1..=4 {
+            let block = client.recv(Duration::from_millis(500)).await;
+            assert_eq!(block, Some((id, 8)));
+        }
+    });
+}
+
+#[test]
+fn long_error_of_small_blocks_client() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(content(100));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_millis(100))
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::bind();
+        let req = RequestBuilder::new("test").block_size(8).build();
+        client.send_to(addr, Packet::Rrq(req)).await;
+
+        let oack = client.recv_packet(Duration::from_secs(1)).await;
+        assert!(matches!(oack, Some(OwnedPacket::OAck(_))));
+
+        // Error is longer than a block and its header
+        let message = "x".repeat(500);
+        let error = packet::Error::Msg(message.clone());
+        client.send(Packet::Error(error)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::ClientError);
+        assert_eq!(record.error, Some(packet::Error::Msg(message)));
+    });
+}
@@ -0,0 +1,132 @@
+use bytes::Bytes;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::client::TestClient;
+use super::faults::{Faults, FaultySocket};
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::packet::{self, Opts};
+use crate::server::{BlockSource, Handler, TftpServerBuilder};
+
+/// Source that serves a buffer and logs the requested blocks.
+struct MemBlocks {
+    content: Bytes,
+    requested: Arc<Mutex<Vec<(u64, usize)>>>,
+}
+
+#[crate::async_trait]
+impl BlockSource for MemBlocks {
+    async fn block(&mut self, index: u64, len: usize) -> io::Result<Bytes> {
+        self.requested.lock().unwrap().push((index, len));
+
+        let start = (index as usize * len).min(self.content.len());
+        let end = (start + len).min(self.content.len());
+        Ok(self.content.slice(start..end))
+    }
+}
+
+/// Handler that serves `blocks` with a `BlockSource` and everything else
+/// with `MemHandler`.
+struct BlocksHandler {
+    inner: MemHandler,
+    content: Bytes,
+    requested: Arc<Mutex<Vec<(u64, usize)>>>,
+}
+
+#[crate::async_trait]
+impl Handler for BlocksHandler {
+    type Reader = <MemHandler as Handler>::Reader;
+    type Writer = MemWriter;
+
+    async fn read_req_open_blocks(
+        &mut self,
+        _client: &SocketAddr,
+        path: &Path,
+    ) -> Result<Option<(Box<dyn BlockSource>, Option<u64>)>, packet::Error>
+    {
+        if path != Path::new("blocks") {
+            return Ok(None);
+        }
+
+        let blocks = MemBlocks {
+            content: self.content.clone(),
+            requested: self.requested.clone(),
+        };
+        let len = self.content.len() as u64;
+
+        Ok(Some((Box::new(blocks), Some(len))))
+    }
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.inner.read_req_open(client, path).await
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.inner.write_req_open(client, path, size).await
+    }
+}
+
+#[test]
+fn read_from_block_source() {
+    let requested = Arc::new(Mutex::new(Vec::new()));
+    let handler = BlocksHandler {
+        inner: MemHandler::new(content(100)),
+        content: Bytes::from(content(1024 * 3)),
+        requested: requested.clone(),
+    };
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+
+        let opts = Opts {
+            block_size: Some(1024),
+            ..Opts::default()
+        };
+        let (data, _) = client.read("blocks", opts).await.unwrap();
+        assert_eq!(data, content(1024 * 3));
+
+        // Size is a multiple of the block size, so an empty block ends it
+        let expected = vec![(0, 1024), (1, 1024), (2, 1024), (3, 1024)];
+        assert_eq!(*requested.lock().unwrap(), expected);
+
+        // Fall back to the reader
+        let (data, _) = client.read("other", Opts::default()).await.unwrap();
+        assert_eq!(data, content(100));
+    });
+}
+
+#[test]
+fn empty_block_source() {
+    let requested = Arc::new(Mutex::new(Vec::new()));
+    let handler = BlocksHandler {
+        inner: MemHandler::new(content(100)),
+        content: Bytes::new(),
+        requested: requested.clone(),
+    };
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+
+        let (data, _) = client.read("blocks", Opts::default()).await.unwrap();
+        assert!(data.is_empty());
+
+        // Only the first block is read, and it ends the transfer
+        assert_eq!(*requested.lock().unwrap(), vec![(0, 512)]);
+    });
+}
@@ -0,0 +1,88 @@
+use futures_lite::io::{AsyncBufRead, BufReader, Cursor};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use super::client::TestClient;
+use super::faults::{Faults, FaultySocket};
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::packet::{self, Opts};
+use crate::server::{Handler, TftpServerBuilder};
+
+/// Handler that serves `content` with a `BufReader` of `capacity`.
+struct BufHandler {
+    inner: MemHandler,
+    content: Vec<u8>,
+    capacity: usize,
+}
+
+#[crate::async_trait]
+impl Handler for BufHandler {
+    type Reader = BufReader<Cursor<Vec<u8>>>;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        let reader = Cursor::new(self.content.clone());
+        let len = self.content.len() as u64;
+        Ok((BufReader::with_capacity(self.capacity, reader), Some(len)))
+    }
+
+    fn buf_reader(
+        reader: &mut Self::Reader,
+    ) -> Option<&mut (dyn AsyncBufRead + Unpin + Send)> {
+        Some(reader)
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.inner.write_req_open(client, path, size).await
+    }
+}
+
+fn check_read(file_size: usize, capacity: usize, opts: Opts) {
+    let handler = BufHandler {
+        inner: MemHandler::new(Vec::new()),
+        content: content(file_size),
+        capacity,
+    };
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+
+        let (data, _) = client.read("test", opts).await.unwrap();
+        assert_eq!(data, content(file_size));
+    });
+}
+
+#[test]
+fn read_from_buf_reader() {
+    for &size in &[0, 1, 511, 512, 512 * 10, 512 * 10 + 123] {
+        // Every block fits in the buffer
+        check_read(size, 4096, Opts::default());
+        // Blocks are split between refills of the buffer
+        check_read(size, 700, Opts::default());
+        check_read(size, 100, Opts::default());
+    }
+}
+
+#[test]
+fn read_from_buf_reader_with_options() {
+    let opts = Opts {
+        block_size: Some(1024),
+        transfer_size: Some(0),
+        ..Opts::default()
+    };
+
+    check_read(1024 * 3, 4096, opts.clone());
+    check_read(1024 * 3 + 1, 1500, opts);
+}
@@ -0,0 +1,234 @@
+use async_executor::Executor;
+use futures_lite::future::{self, block_on};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::packet::{self, Packet};
+use crate::server::{
+    AuditRecord, AuditSink, CancellationToken, Handler, TftpServerBuilder,
+    TransferOutcome, TransferParams,
+};
+use crate::test_util::RequestBuilder;
+
+const WAIT: Duration = Duration::from_secs(5);
+
+fn cancelled() -> Packet<'static> {
+    Packet::Error(packet::Error::Msg("Transfer cancelled".to_string()))
+}
+
+#[test]
+fn server_token_stops_server() {
+    let token = CancellationToken::new();
+    let (tx, rx) = async_channel::unbounded();
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(2000)))
+            .bind("127.0.0.1:0".parse().unwrap())
+            .max_concurrent_transfers(1)
+            .cancellation_token(token.clone())
+            .audit(ChannelSink(tx));
+    let ex = Executor::new();
+
+    block_on(ex.run(async {
+        let tftpd = builder.build().await.unwrap();
+        let addr = tftpd.listen_addr().unwrap();
+        let server = ex.spawn(tftpd.serve());
+
+        let mut running = RawClient::rrq(addr, "test").await;
+        assert!(running.recv(WAIT).await.is_some());
+
+        // Waits for the transfer slot
+        let mut pending = RawClient::rrq(addr, "test").await;
+        async_io::Timer::after(Duration::from_millis(100)).await;
+
+        token.cancel();
+        running.expect(cancelled()).await;
+        pending.expect(cancelled()).await;
+
+        server.await.unwrap();
+
+        for _ in 0..2 {
+            let record = rx.recv().await.unwrap();
+            assert_eq!(record.outcome, TransferOutcome::Cancelled);
+        }
+    }));
+}
+
+#[test]
+fn transfer_token_cancels_transfer() {
+    let token = CancellationToken::new();
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(content(2000)).with_params(TransferParams {
+        cancellation_token: Some(token.clone()),
+        ..TransferParams::default()
+    });
+    let builder = TftpServerBuilder::with_handler(handler)
+        .cancellation_token(CancellationToken::new())
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert!(client.recv(WAIT).await.is_some());
+
+        token.cancel();
+        client.expect(cancelled()).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Cancelled);
+        assert_eq!(
+            record.error,
+            Some(packet::Error::Msg("Transfer cancelled".to_string()))
+        );
+    });
+}
+
+/// Handler whose files never open.
+struct BlockedHandler;
+
+#[crate::async_trait]
+impl Handler for BlockedHandler {
+    type Reader = <MemHandler as Handler>::Reader;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        future::pending().await
+    }
+
+    async fn write_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        future::pending().await
+    }
+}
+
+#[test]
+fn server_token_cancels_blocked_open() {
+    let token = CancellationToken::new();
+    let (tx, rx) = async_channel::unbounded();
+    let builder = TftpServerBuilder::with_handler(BlockedHandler)
+        .cancellation_token(token.clone())
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let req = RequestBuilder::new("test");
+        let mut client = RawClient::wrq_with(addr, req).await;
+
+        // Handler is opening the file
+        async_io::Timer::after(Duration::from_millis(100)).await;
+
+        token.cancel();
+        client.expect(cancelled()).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Cancelled);
+    });
+}
+
+#[test]
+fn child_token_is_cancelled_with_parent() {
+    let parent = CancellationToken::new();
+    let child = parent.child_token();
+
+    child.cancel();
+    assert!(!parent.is_cancelled());
+
+    let child = parent.child_token();
+    parent.cancel();
+    assert!(child.is_cancelled());
+    block_on(child.cancelled());
+
+    // Children of cancelled tokens start cancelled
+    assert!(parent.child_token().is_cancelled());
+}
+
+#[test]
+fn serve_until_waits_for_transfers() {
+    let (tx, rx) = async_channel::unbounded();
+    let (shutdown_tx, shutdown_rx) = async_channel::bounded::<()>(1);
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(2000)))
+            .bind("127.0.0.1:0".parse().unwrap())
+            .audit(ChannelSink(tx));
+    let ex = Executor::new();
+
+    block_on(ex.run(async {
+        let tftpd = builder.build().await.unwrap();
+        let addr = tftpd.listen_addr().unwrap();
+        let server = ex.spawn(tftpd.serve_until(async move {
+            let _ = shutdown_rx.recv().await;
+        }));
+
+        let mut running = RawClient::rrq(addr, "test").await;
+        assert!(running.recv(WAIT).await.is_some());
+
+        shutdown_tx.send(()).await.unwrap();
+        async_io::Timer::after(Duration::from_millis(100)).await;
+
+        // New requests are not accepted
+        let mut rejected = RawClient::rrq(addr, "test").await;
+        let short = Duration::from_millis(200);
+        assert_eq!(rejected.recv(short).await, None);
+        assert!(!server.is_finished());
+
+        // Running transfer is not cancelled
+        running.finish().await;
+        server.await.unwrap();
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.transferred, 2000);
+        assert!(rx.try_recv().is_err());
+    }));
+}
+
+/// Sink that takes a while to record.
+struct SlowSink(ChannelSink);
+
+#[crate::async_trait]
+impl AuditSink for SlowSink {
+    async fn record(&self, record: &AuditRecord) {
+        async_io::Timer::after(Duration::from_millis(200)).await;
+        self.0.record(record).await;
+    }
+}
+
+#[test]
+fn serve_until_waits_for_rejections() {
+    let (tx, rx) = async_channel::unbounded();
+    let (shutdown_tx, shutdown_rx) = async_channel::bounded::<()>(1);
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(2000)))
+            .bind("127.0.0.1:0".parse().unwrap())
+            .max_request_size(64)
+            .audit(SlowSink(ChannelSink(tx)));
+    let ex = Executor::new();
+
+    block_on(ex.run(async {
+        let tftpd = builder.build().await.unwrap();
+        let addr = tftpd.listen_addr().unwrap();
+        let server = ex.spawn(tftpd.serve_until(async move {
+            let _ = shutdown_rx.recv().await;
+        }));
+
+        let oversized = RequestBuilder::new([b'a'; 100]).build();
+        let mut client = RawClient::bind();
+        client.send_to(addr, Packet::Rrq(oversized)).await;
+        assert!(client.recv_packet(WAIT).await.is_some());
+
+        // Rejection is still being recorded
+        shutdown_tx.send(()).await.unwrap();
+        server.await.unwrap();
+
+        let record = rx.try_recv().unwrap();
+        assert_eq!(record.outcome, TransferOutcome::OversizedRequest);
+    }));
+}
@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use super::faults::FaultySocket;
+use crate::clock::SystemClock;
+use crate::packet::{self, Mode, Opts, Packet, RwReq};
+use crate::utils::io_timeout;
+
+/// Minimal TFTP client that is used to drive the server in tests.
+///
+/// It runs over a [`FaultySocket`] so the retransmission logic of the
+/// server can be exercised.
+pub struct TestClient {
+    socket: FaultySocket,
+    server: SocketAddr,
+    timeout: Duration,
+    max_retries: u32,
+}
+
+/// What the client observed during a transfer.
+#[derive(Debug, Default)]
+pub struct TransferLog {
+    /// Received OACK, if any.
+    pub oack: Option<Opts>,
+    /// How many times each DATA block (or ACK on uploads) was received.
+    pub blocks: BTreeMap<u16, usize>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("TFTP protocol error: {0}")]
+    Tftp(packet::Error),
+    #[error("Max retries reached")]
+    MaxRetriesReached,
+}
+
+impl TestClient {
+    pub fn new(socket: FaultySocket, server: SocketAddr) -> Self {
+        TestClient {
+            socket,
+            server,
+            timeout: Duration::from_millis(100),
+            max_retries: 50,
+        }
+    }
+
+    pub fn socket(&self) -> &FaultySocket {
+        &self.socket
+    }
+
+    /// Download `filename` and return its content.
+    pub async fn read(
+        &mut self,
+        filename: &str,
+        opts: Opts,
+    ) -> Result<(Vec<u8>, TransferLog), ClientError> {
+        let mut log = TransferLog::default();
+        let mut data = Vec::new();
+        let mut block_size = 512;
+        let mut block_id: u16 = 0;
+
+        let req = Packet::Rrq(RwReq {
+            filename: filename.as_bytes().to_vec(),
+            mode: Mode::Octet,
+            opts,
+        });
+
+        let mut last_sent = req.to_vec();
+        let mut peer = None;
+        let mut retries = 0;
+
+        self.socket.send_to(&last_sent, self.server).await?;
+        let mut deadline = Instant::now() + self.timeout;
+
+        loop {
+            let mut buf = [0u8; 65536];
+
+            let (len, from) = match self.recv(&mut buf, deadline).await {
+                Ok(x) => x,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                    retries += 1;
+                    if retries > self.max_retries {
+                        return Err(ClientError::MaxRetriesReached);
+                    }
+
+                    let to = peer.unwrap_or(self.server);
+                    self.socket.send_to(&last_sent, to).await?;
+                    deadline = Instant::now() + self.timeout;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            // Transfer is locked to the first peer that replied
+            match peer {
+                Some(peer) if peer != from => continue,
+                Some(_) => {}
+                None => peer = Some(from),
+            }
+
+            match Packet::decode(&buf[..len]) {
+                Ok(Packet::OAck(opts)) if block_id == 0 => {
+                    if let Some(size) = opts.block_size {
+                        block_size = usize::from(size);
+                    }
+
+                    log.oack = Some(opts);
+                    last_sent = Packet::Ack(0).to_vec();
+                    self.socket.send_to(&last_sent, from).await?;
+                    deadline = Instant::now() + self.timeout;
+                }
+                Ok(Packet::Data(id, payload)) => {
+                    *log.blocks.entry(id).or_default() += 1;
+
+                    if id == block_id.wrapping_add(1) {
+                        retries = 0;
+                        block_id = id;
+                        data.extend_from_slice(payload);
+
+                        last_sent = Packet::Ack(id).to_vec();
+                        self.socket.send_to(&last_sent, from).await?;
+                        deadline = Instant::now() + self.timeout;
+
+                        if payload.len() < block_size {
+                            return Ok((data, log));
+                        }
+                    } else if id == block_id {
+                        // Our ACK was probably lost, send it again
+                        self.socket.send_to(&last_sent, from).await?;
+                    }
+                }
+                Ok(Packet::Error(e)) => return Err(ClientError::Tftp(e)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Upload `content` as `filename`.
+    pub async fn write(
+        &mut self,
+        filename: &str,
+        opts: Opts,
+        content: &[u8],
+    ) -> Result<TransferLog, ClientError> {
+        let mut log = TransferLog::default();
+        let mut block_size = 512;
+        let mut block_id: u16 = 0;
+        let mut offset = 0;
+        let mut last_block_sent = false;
+
+        let req = Packet::Wrq(RwReq {
+            filename: filename.as_bytes().to_vec(),
+            mode: Mode::Octet,
+            opts,
+        });
+
+        let mut last_sent = req.to_vec();
+        let mut peer = None;
+        let mut retries = 0;
+
+        self.socket.send_to(&last_sent, self.server).await?;
+        let mut deadline = Instant::now() + self.timeout;
+
+        loop {
+            let mut buf = [0u8; 65536];
+
+            let (len, from) = match self.recv(&mut buf, deadline).await {
+                Ok(x) => x,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                    retries += 1;
+                    if retries > self.max_retries {
+                        return Err(ClientError::MaxRetriesReached);
+                    }
+
+                    let to = peer.unwrap_or(self.server);
+                    self.socket.send_to(&last_sent, to).await?;
+                    deadline = Instant::now() + self.timeout;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            match peer {
+                Some(peer) if peer != from => continue,
+                Some(_) => {}
+                None => peer = Some(from),
+            }
+
+            let acked = match Packet::decode(&buf[..len]) {
+                Ok(Packet::OAck(opts)) if block_id == 0 => {
+                    if let Some(size) = opts.block_size {
+                        block_size = usize::from(size);
+                    }
+
+                    log.oack = Some(opts);
+                    true
+                }
+                Ok(Packet::Ack(id)) => {
+                    *log.blocks.entry(id).or_default() += 1;
+                    id == block_id
+                }
+                Ok(Packet::Error(e)) => return Err(ClientError::Tftp(e)),
+                _ => false,
+            };
+
+            if !acked {
+                continue;
+            }
+
+            if last_block_sent {
+                return Ok(log);
+            }
+
+            retries = 0;
+            block_id = block_id.wrapping_add(1);
+
+            let end = (offset + block_size).min(content.len());
+            last_sent = Packet::Data(block_id, &content[offset..end]).to_vec();
+            last_block_sent = end - offset < block_size;
+            offset = end;
+
+            self.socket.send_to(&last_sent, from).await?;
+            deadline = Instant::now() + self.timeout;
+        }
+    }
+
+    async fn recv(
+        &mut self,
+        buf: &mut [u8],
+        deadline: Instant,
+    ) -> io::Result<(usize, SocketAddr)> {
+        let socket = &mut self.socket;
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        io_timeout(&SystemClock, timeout, socket.recv_from(buf)).await
+    }
+}
+
+impl TransferLog {
+    /// Blocks that were received more than once.
+    pub fn retransmitted_blocks(&self) -> Vec<u16> {
+        self.blocks
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Total number of datagrams that were received more than once.
+    pub fn retransmissions(&self) -> usize {
+        self.blocks.values().map(|count| count - 1).sum()
+    }
+}
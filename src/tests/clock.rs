@@ -0,0 +1,108 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::clock::{Clock, Sleep};
+
+/// Clock that moves forward only when [`MockClock::advance`] is called.
+#[derive(Clone, Default)]
+pub struct MockClock {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Time elapsed since the creation of the clock
+    now: Duration,
+    sleepers: Vec<Sleeper>,
+    next_id: u64,
+}
+
+struct Sleeper {
+    deadline: Duration,
+    waker: Option<Waker>,
+    id: u64,
+}
+
+struct MockSleep {
+    inner: Arc<Mutex<Inner>>,
+    deadline: Duration,
+    id: u64,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        MockClock::default()
+    }
+
+    /// Move time forward and wake up every sleep that expired.
+    pub fn advance(&self, dur: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.now += dur;
+
+        let now = inner.now;
+        for sleeper in inner.sleepers.iter_mut() {
+            if sleeper.deadline <= now {
+                if let Some(waker) = sleeper.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    /// Number of sleeps that are still waiting for their deadline.
+    pub fn pending_sleeps(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.sleepers.iter().filter(|s| s.deadline > inner.now).count()
+    }
+}
+
+impl Clock for MockClock {
+    fn sleep(&self, dur: Duration) -> Sleep {
+        let mut inner = self.inner.lock().unwrap();
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let deadline = inner.now + dur;
+
+        inner.sleepers.push(Sleeper {
+            deadline,
+            waker: None,
+            id,
+        });
+
+        Box::pin(MockSleep {
+            inner: self.inner.clone(),
+            deadline,
+            id,
+        })
+    }
+}
+
+impl Future for MockSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.now >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        let id = self.id;
+        if let Some(sleeper) = inner.sleepers.iter_mut().find(|s| s.id == id) {
+            sleeper.waker = Some(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for MockSleep {
+    fn drop(&mut self) {
+        let id = self.id;
+        self.inner.lock().unwrap().sleepers.retain(|s| s.id != id);
+    }
+}
@@ -0,0 +1,75 @@
+use bytes::BytesMut;
+
+use crate::codec::TftpCodec;
+use crate::error::Error;
+use crate::packet::{self, OwnedPacket, Packet};
+
+#[test]
+fn round_trip() {
+    let mut codec = TftpCodec::new();
+    let mut buf = BytesMut::new();
+
+    codec.encode(&Packet::Data(7, b"abc"), &mut buf).unwrap();
+    assert_eq!(&buf[..], b"\x00\x03\x00\x07abc");
+
+    let packet = codec.decode(&mut buf).unwrap();
+    assert!(matches!(packet, Some(OwnedPacket::Data(7, ref d)) if d == b"abc"));
+    assert!(buf.is_empty());
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+
+    let error = Packet::Error(packet::Error::FileNotFound);
+    codec.encode(&error, &mut buf).unwrap();
+    let packet = codec.decode_eof(&mut buf).unwrap();
+    assert!(matches!(
+        packet,
+        Some(OwnedPacket::Error(packet::Error::FileNotFound))
+    ));
+}
+
+#[test]
+fn invalid_datagram_is_consumed() {
+    let mut codec = TftpCodec::new();
+    let mut buf = BytesMut::from(&b"\x00\x09junk"[..]);
+
+    assert!(matches!(codec.decode(&mut buf), Err(Error::InvalidPacket)));
+    assert!(buf.is_empty());
+}
+
+#[cfg(feature = "tokio-codec")]
+#[test]
+fn tokio_util_codec() {
+    use tokio_util::codec::{Decoder, Encoder};
+
+    let mut codec = TftpCodec::new();
+    let mut buf = BytesMut::new();
+
+    Encoder::encode(&mut codec, OwnedPacket::Ack(3), &mut buf).unwrap();
+    assert_eq!(&buf[..], b"\x00\x04\x00\x03");
+    let packet = Decoder::decode(&mut codec, &mut buf).unwrap();
+    assert!(matches!(packet, Some(OwnedPacket::Ack(3))));
+
+    Encoder::encode(&mut codec, Packet::Data(1, b"abc"), &mut buf).unwrap();
+    let packet = Decoder::decode_eof(&mut codec, &mut buf).unwrap();
+    assert!(matches!(packet, Some(OwnedPacket::Data(1, ref d)) if d == b"abc"));
+    assert!(Decoder::decode(&mut codec, &mut buf).unwrap().is_none());
+}
+
+#[cfg(feature = "asynchronous-codec")]
+#[test]
+fn asynchronous_codec() {
+    use asynchronous_codec::{Decoder, Encoder};
+
+    let mut codec = TftpCodec::new();
+    let mut buf = BytesMut::new();
+
+    let data = OwnedPacket::Data(1, b"abc".to_vec());
+    Encoder::encode(&mut codec, data, &mut buf).unwrap();
+    assert_eq!(&buf[..], b"\x00\x03\x00\x01abc");
+    let packet = Decoder::decode(&mut codec, &mut buf).unwrap();
+    assert!(matches!(packet, Some(OwnedPacket::Data(1, ref d)) if d == b"abc"));
+
+    buf.extend_from_slice(b"\x00\x09junk");
+    let result = Decoder::decode(&mut codec, &mut buf);
+    assert!(matches!(result, Err(Error::InvalidPacket)));
+    assert!(buf.is_empty());
+}
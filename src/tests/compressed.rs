@@ -0,0 +1,190 @@
+#![cfg(any(feature = "gzip", feature = "zstd"))]
+
+use futures_lite::future::block_on;
+use std::fs;
+use tempfile::tempdir;
+
+use super::utils::*;
+use crate::packet;
+use crate::server::handlers::{DirHandler, DirHandlerMode};
+
+#[cfg(feature = "gzip")]
+fn gzip(data: &[u8]) -> Vec<u8> {
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[cfg(feature = "gzip")]
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    let mut content = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut content).unwrap();
+    content
+}
+
+/// Single segment zstd frame of raw blocks.
+#[cfg(feature = "zstd")]
+fn zstd_frame(blocks: &[&[u8]]) -> Vec<u8> {
+    let size: usize = blocks.iter().map(|b| b.len()).sum();
+    assert!(size < 256);
+
+    // Magic number, single segment flag and 1 byte content size
+    let mut frame = vec![0x28, 0xb5, 0x2f, 0xfd, 0x20, size as u8];
+
+    for (i, block) in blocks.iter().enumerate() {
+        let is_last = (i == blocks.len() - 1) as u32;
+        let head = (block.len() as u32) << 3 | is_last;
+        frame.extend_from_slice(&head.to_le_bytes()[..3]);
+        frame.extend_from_slice(block);
+    }
+
+    frame
+}
+
+#[test]
+fn uncompressed_file_is_preferred() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("file"), b"plain").unwrap();
+    fs::write(tmp.path().join("file.gz"), b"not gzip").unwrap();
+    fs::write(tmp.path().join("file.zst"), b"not zstd").unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .decompress_variants();
+
+    assert_eq!(
+        handler_read(&mut handler, "file"),
+        Ok((b"plain".to_vec(), Some(5)))
+    );
+}
+
+#[test]
+fn variants_are_disabled_by_default() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("file.gz"), b"").unwrap();
+    fs::write(tmp.path().join("file.zst"), b"").unwrap();
+
+    let mut handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly).unwrap();
+
+    assert_eq!(
+        handler_read(&mut handler, "file"),
+        Err(packet::Error::FileNotFound)
+    );
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn decompress_gzip() {
+    let tmp = tempdir().unwrap();
+    let content = b"kernel".repeat(1000);
+    fs::write(tmp.path().join("kernel.gz"), gzip(&content)).unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .decompress_variants();
+
+    assert_eq!(
+        handler_read(&mut handler, "kernel"),
+        Ok((content.clone(), Some(content.len() as u64)))
+    );
+
+    // Multiple members
+    let mut members = gzip(b"first ");
+    members.extend(gzip(b"second"));
+    fs::write(tmp.path().join("initrd.gz"), members).unwrap();
+
+    assert_eq!(
+        handler_read(&mut handler, "initrd"),
+        Ok((b"first second".to_vec(), Some(12)))
+    );
+
+    // Cached size is counted again when the file changes
+    let content = b"kernel".repeat(2000);
+    fs::write(tmp.path().join("kernel.gz"), gzip(&content)).unwrap();
+    assert_eq!(
+        handler_read(&mut handler, "kernel"),
+        Ok((content.clone(), Some(content.len() as u64)))
+    );
+
+    // Corrupted files are rejected before the transfer
+    let truncated = gzip(&content);
+    let truncated = &truncated[..truncated.len() / 2];
+    fs::write(tmp.path().join("bad.gz"), truncated).unwrap();
+    assert!(handler_read(&mut handler, "bad").is_err());
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn compress_gzip() {
+    let tmp = tempdir().unwrap();
+    let content = b"kernel".repeat(1000);
+    fs::write(tmp.path().join("kernel"), &content).unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .compress_variants();
+
+    let (compressed, size) = handler_read(&mut handler, "kernel.gz").unwrap();
+    assert_eq!(gunzip(&compressed), content);
+    assert_eq!(size, None);
+
+    // Existing `.gz` files are served as they are
+    fs::write(tmp.path().join("initrd.gz"), b"initrd").unwrap();
+    assert_eq!(
+        handler_read(&mut handler, "initrd.gz"),
+        Ok((b"initrd".to_vec(), Some(6)))
+    );
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn decompress_zstd() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("kernel.zst"), zstd_frame(&[b"ker", b"nel"]))
+        .unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .decompress_variants();
+
+    assert_eq!(
+        handler_read(&mut handler, "kernel"),
+        Ok((b"kernel".to_vec(), Some(6)))
+    );
+
+    // Multiple frames
+    let mut frames = zstd_frame(&[b"first "]);
+    frames.extend(zstd_frame(&[b"second"]));
+    fs::write(tmp.path().join("initrd.zst"), frames).unwrap();
+
+    assert_eq!(
+        handler_read(&mut handler, "initrd"),
+        Ok((b"first second".to_vec(), None))
+    );
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn preload_zstd() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("kernel.zst"), zstd_frame(&[b"kernel"])).unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .decompress_variants();
+
+    block_on(handler.preload(&["kernel"])).unwrap();
+    fs::remove_file(tmp.path().join("kernel.zst")).unwrap();
+
+    assert_eq!(
+        handler_read(&mut handler, "kernel"),
+        Ok((b"kernel".to_vec(), Some(6)))
+    );
+}
@@ -0,0 +1,388 @@
+#![cfg(feature = "conformance-tests")]
+#![cfg(unix)]
+
+//! Interoperability tests against well known TFTP clients and servers.
+//!
+//! Each test is skipped if its peer is not installed, unless the peer is
+//! listed in `TFTP_CONFORMANCE_REQUIRE`, e.g. `tftp-hpa,busybox`.
+
+use async_io::Timer;
+use blocking::unblock;
+use futures_lite::future::block_on;
+use futures_lite::io::Cursor;
+use std::env;
+use std::fs;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tempfile::tempdir;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::server::TftpServerBuilder;
+
+/// Path of the server of tftp-hpa.
+const TFTPD_HPA: &str = "/usr/sbin/in.tftpd";
+
+/// Whether a missing peer fails the test instead of skipping it.
+fn is_required(name: &str) -> bool {
+    env::var("TFTP_CONFORMANCE_REQUIRE")
+        .map(|names| names.split(',').any(|n| n.trim() == name))
+        .unwrap_or(false)
+}
+
+/// Whether the test of `name` can run.
+fn check_installed(name: &str, installed: bool) -> bool {
+    if !installed {
+        assert!(!is_required(name), "{} is required but not installed", name);
+        eprintln!("{} is not installed, skipping", name);
+    }
+
+    installed
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Client {
+    /// `tftp` of tftp-hpa
+    TftpHpa,
+    Curl,
+    Busybox,
+}
+
+impl Client {
+    fn name(self) -> &'static str {
+        match self {
+            Client::TftpHpa => "tftp-hpa",
+            Client::Curl => "curl",
+            Client::Busybox => "busybox",
+        }
+    }
+
+    fn is_installed(self) -> bool {
+        let mut cmd = match self {
+            Client::TftpHpa => Command::new("tftp"),
+            Client::Curl => Command::new("curl"),
+            Client::Busybox => Command::new("busybox"),
+        };
+
+        match self {
+            Client::TftpHpa => cmd.arg("-V"),
+            Client::Curl => cmd.arg("--version"),
+            Client::Busybox => cmd.arg("tftp").arg("--help"),
+        };
+
+        let output =
+            match cmd.stdin(Stdio::null()).stderr(Stdio::null()).output() {
+                Ok(output) => output,
+                Err(_) => return false,
+            };
+
+        match self {
+            Client::TftpHpa => true,
+            Client::Curl => {
+                let version = String::from_utf8_lossy(&output.stdout);
+
+                // curl 7.88 fails every TFTP transfer with
+                // `getpeername() failed with errno 107`.
+                if version.starts_with("curl 7.88.") {
+                    return false;
+                }
+
+                // curl can be built without TFTP support
+                version.split_whitespace().any(|proto| proto == "tftp")
+            }
+            // busybox can be built without `tftp` applet
+            Client::Busybox => output.status.success(),
+        }
+    }
+
+    fn supports_block_size(self) -> bool {
+        !matches!(self, Client::TftpHpa)
+    }
+
+    fn get(
+        self,
+        server: SocketAddr,
+        filename: &str,
+        local: &Path,
+        block_size: Option<u16>,
+    ) -> Command {
+        let mut cmd = self.command();
+
+        match self {
+            Client::TftpHpa => {
+                cmd.arg("-m")
+                    .arg("binary")
+                    .arg(server.ip().to_string())
+                    .arg(server.port().to_string())
+                    .arg("-c")
+                    .arg("get")
+                    .arg(filename)
+                    .arg(local);
+            }
+            Client::Curl => {
+                if let Some(block_size) = block_size {
+                    cmd.arg("--tftp-blksize").arg(block_size.to_string());
+                }
+
+                cmd.arg("-o").arg(local).arg(url(server, filename));
+            }
+            Client::Busybox => {
+                if let Some(block_size) = block_size {
+                    cmd.arg("-b").arg(block_size.to_string());
+                }
+
+                cmd.arg("-g")
+                    .arg("-r")
+                    .arg(filename)
+                    .arg("-l")
+                    .arg(local)
+                    .arg(server.ip().to_string())
+                    .arg(server.port().to_string());
+            }
+        }
+
+        cmd
+    }
+
+    fn put(
+        self,
+        server: SocketAddr,
+        filename: &str,
+        local: &Path,
+        block_size: Option<u16>,
+    ) -> Command {
+        let mut cmd = self.command();
+
+        match self {
+            Client::TftpHpa => {
+                cmd.arg("-m")
+                    .arg("binary")
+                    .arg(server.ip().to_string())
+                    .arg(server.port().to_string())
+                    .arg("-c")
+                    .arg("put")
+                    .arg(local)
+                    .arg(filename);
+            }
+            Client::Curl => {
+                if let Some(block_size) = block_size {
+                    cmd.arg("--tftp-blksize").arg(block_size.to_string());
+                }
+
+                cmd.arg("-T").arg(local).arg(url(server, filename));
+            }
+            Client::Busybox => {
+                if let Some(block_size) = block_size {
+                    cmd.arg("-b").arg(block_size.to_string());
+                }
+
+                cmd.arg("-p")
+                    .arg("-l")
+                    .arg(local)
+                    .arg("-r")
+                    .arg(filename)
+                    .arg(server.ip().to_string())
+                    .arg(server.port().to_string());
+            }
+        }
+
+        cmd
+    }
+
+    fn command(self) -> Command {
+        let mut cmd = match self {
+            Client::TftpHpa => Command::new("tftp"),
+            Client::Curl => {
+                let mut cmd = Command::new("curl");
+                cmd.arg("--silent").arg("--show-error");
+                cmd
+            }
+            Client::Busybox => {
+                let mut cmd = Command::new("busybox");
+                cmd.arg("tftp");
+                cmd
+            }
+        };
+
+        cmd.stdin(Stdio::null()).stdout(Stdio::null());
+        cmd
+    }
+}
+
+fn url(server: SocketAddr, filename: &str) -> String {
+    format!("tftp://{}/{}", server, filename)
+}
+
+fn builder(handler: MemHandler) -> TftpServerBuilder<MemHandler> {
+    TftpServerBuilder::with_handler(handler).timeout(Duration::from_secs(1))
+}
+
+fn check_read(client: Client, file_size: usize, block_size: Option<u16>) {
+    if !check_installed(client.name(), client.is_installed()) {
+        return;
+    }
+
+    if block_size.is_some() && !client.supports_block_size() {
+        eprintln!("{:?} does not support blksize, skipping", client);
+        return;
+    }
+
+    let handler = MemHandler::new(content(file_size));
+
+    run_with_server(builder(handler), |addr| async move {
+        let tmp = tempdir().unwrap();
+        let local = tmp.path().join("data");
+
+        let mut cmd = client.get(addr, "test", &local, block_size);
+        let status = unblock(move || cmd.status()).await.unwrap();
+        assert!(status.success(), "{:?} failed: {}", client, status);
+
+        assert_eq!(fs::read(&local).unwrap(), content(file_size));
+    });
+}
+
+fn check_write(client: Client, file_size: usize, block_size: Option<u16>) {
+    if !check_installed(client.name(), client.is_installed()) {
+        return;
+    }
+
+    if block_size.is_some() && !client.supports_block_size() {
+        eprintln!("{:?} does not support blksize, skipping", client);
+        return;
+    }
+
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+
+    run_with_server(builder(handler), |addr| async move {
+        let tmp = tempdir().unwrap();
+        let local = tmp.path().join("data");
+        fs::write(&local, content(file_size)).unwrap();
+
+        let mut cmd = client.put(addr, "test", &local, block_size);
+        let status = unblock(move || cmd.status()).await.unwrap();
+        assert!(status.success(), "{:?} failed: {}", client, status);
+
+        wait_until(Duration::from_secs(1), || {
+            *written.lock().unwrap() == content(file_size)
+        })
+        .await;
+    });
+}
+
+fn check_client(client: Client) {
+    for &size in &[0, 1, 511, 512, 512 * 20 + 123] {
+        check_read(client, size, None);
+        check_write(client, size, None);
+    }
+
+    for &size in &[1023, 1024, 1024 * 20 + 123] {
+        check_read(client, size, Some(1024));
+        check_write(client, size, Some(1024));
+    }
+}
+
+#[test]
+fn tftp_hpa() {
+    check_client(Client::TftpHpa);
+}
+
+#[test]
+fn curl() {
+    check_client(Client::Curl);
+}
+
+#[test]
+fn busybox() {
+    check_client(Client::Busybox);
+}
+
+/// `in.tftpd` of tftp-hpa that serves `dir`, killed on drop.
+struct TftpdHpa {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl TftpdHpa {
+    fn spawn(dir: &Path) -> TftpdHpa {
+        // Free port for the server
+        let addr = UdpSocket::bind("127.0.0.1:0")
+            .and_then(|socket| socket.local_addr())
+            .unwrap();
+
+        // Filenames are relative to `dir`. `--secure` would need root for
+        // chroot, and `--permissive` allows files of any mode.
+        let child = Command::new(TFTPD_HPA)
+            .arg("--foreground")
+            .arg("--address")
+            .arg(addr.to_string())
+            .arg("--create")
+            .arg("--permissive")
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to run in.tftpd");
+
+        TftpdHpa {
+            child,
+            addr,
+        }
+    }
+}
+
+impl Drop for TftpdHpa {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn client_against_tftpd_hpa() {
+    let installed = Path::new(TFTPD_HPA).exists();
+    if !check_installed("tftpd-hpa", installed) {
+        return;
+    }
+
+    let tmp = tempdir().unwrap();
+    let tftpd = TftpdHpa::spawn(tmp.path());
+
+    for &(size, block_size) in
+        &[(0, None), (512 * 20 + 123, None), (1024 * 20 + 123, Some(1024))]
+    {
+        fs::write(tmp.path().join("download"), content(size)).unwrap();
+
+        let mut client = TftpClient::new(tftpd.addr);
+        if let Some(block_size) = block_size {
+            client = client.block_size(block_size);
+        }
+
+        let data = block_on(async {
+            // The server may still be starting up
+            let mut res = client.read_to_vec("download").await;
+            for _ in 0..10 {
+                if res.is_ok() {
+                    break;
+                }
+                Timer::after(Duration::from_millis(100)).await;
+                res = client.read_to_vec("download").await;
+            }
+            res
+        })
+        .expect("download failed");
+        assert_eq!(data, content(size));
+
+        let sent = block_on(client.write(
+            "upload",
+            Cursor::new(content(size)),
+            Some(size as u64),
+        ))
+        .expect("upload failed");
+        assert_eq!(sent, size as u64);
+        assert_eq!(fs::read(tmp.path().join("upload")).unwrap(), content(size));
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{OwnedPacket, Packet};
+use crate::server::TftpServerBuilder;
+
+const FILE_SIZE: usize = 512 * 3 + 10;
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Client disappears for a while, so the retransmissions of the server
+/// trigger ICMP "port unreachable". On Windows this is reported as
+/// `WSAECONNRESET` on the transfer socket and it must not abort the
+/// transfer.
+#[test]
+fn transfer_survives_port_unreachable() {
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_millis(20))
+        .max_send_retries(50);
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+        let client_addr = client.local_addr();
+        assert_eq!(client.recv(WAIT).await, Some((1, 512)));
+
+        // Close client's port while server retransmits
+        drop(client);
+        async_io::Timer::after(Duration::from_millis(100)).await;
+
+        let mut client = RawClient::bind_to(client_addr);
+        let mut data = Vec::new();
+        let mut block_id = 0;
+
+        loop {
+            let packet = client.recv_packet(WAIT).await;
+
+            match packet.expect("no packet received") {
+                OwnedPacket::Data(id, payload) if id == block_id + 1 => {
+                    block_id = id;
+                    data.extend_from_slice(&payload);
+                    client.send(Packet::Ack(id)).await;
+
+                    if payload.len() < 512 {
+                        break;
+                    }
+                }
+                // Retransmission of the current block
+                OwnedPacket::Data(..) => {}
+                packet => panic!("unexpected packet: {:?}", packet),
+            }
+        }
+
+        assert_eq!(data, content(FILE_SIZE));
+    });
+}
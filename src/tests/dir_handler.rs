@@ -0,0 +1,607 @@
+use futures_lite::future::{self, block_on};
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::tempdir;
+
+use super::client::{ClientError, TestClient};
+use super::faults::{Faults, FaultySocket};
+use super::utils::*;
+
+use crate::packet;
+use crate::packet::Opts;
+use crate::server::handlers::{DirHandler, DirHandlerMode, PartialUpload};
+use crate::server::{Handler, TftpServerBuilder, TransferState};
+
+fn read(handler: &mut DirHandler, path: &str) -> Result<(), packet::Error> {
+    handler_read(handler, path).map(|_| ())
+}
+
+#[test]
+fn backslash_separator() {
+    let tmp = tempdir().unwrap();
+    fs::create_dir(tmp.path().join("boot")).unwrap();
+    fs::write(tmp.path().join("boot").join("bcd"), b"bcd").unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .backslash_separator();
+
+    assert_eq!(read(&mut handler, "boot\\bcd"), Ok(()));
+    assert_eq!(read(&mut handler, "\\boot\\bcd"), Ok(()));
+    assert_eq!(read(&mut handler, "boot/bcd"), Ok(()));
+    assert_eq!(
+        read(&mut handler, "boot\\..\\..\\bcd"),
+        Err(packet::Error::PermissionDenied)
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn backslash_is_not_separator_by_default() {
+    let tmp = tempdir().unwrap();
+    fs::create_dir(tmp.path().join("boot")).unwrap();
+    fs::write(tmp.path().join("boot").join("bcd"), b"bcd").unwrap();
+
+    let mut handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly).unwrap();
+
+    assert_eq!(read(&mut handler, "boot/bcd"), Ok(()));
+    assert_eq!(
+        read(&mut handler, "boot\\bcd"),
+        Err(packet::Error::FileNotFound)
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn non_utf8_filename() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use crate::test_util::RequestBuilder;
+
+    let tmp = tempdir().unwrap();
+    let filename = OsStr::from_bytes(b"caf\xe9");
+    fs::write(tmp.path().join(filename), b"latin-1").unwrap();
+
+    let req = RequestBuilder::new(b"caf\xe9").build();
+
+    let mut handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly).unwrap();
+
+    let res = block_on(
+        handler.read_req_open(&handler_client(), &req.filename_path()),
+    );
+    assert!(res.is_ok());
+}
+
+fn read_content(handler: &mut DirHandler, path: &str) -> Vec<u8> {
+    handler_read(handler, path).unwrap().0
+}
+
+#[test]
+fn preload() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("kernel"), b"kernel").unwrap();
+
+    let mut handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::ReadWrite).unwrap();
+    let app_handle = handler.clone();
+
+    block_on(app_handle.preload(&["kernel"])).unwrap();
+
+    // Served from memory
+    fs::remove_file(tmp.path().join("kernel")).unwrap();
+    assert_eq!(read_content(&mut handler, "kernel"), b"kernel");
+    assert_eq!(read_content(&mut handler, "/kernel"), b"kernel");
+
+    // Write requests replace preloaded content
+    block_on(async {
+        let mut writer = handler
+            .write_req_open(&handler_client(), Path::new("kernel"), None)
+            .await
+            .unwrap();
+        writer.write_all(b"new kernel").await.unwrap();
+        writer.flush().await.unwrap();
+    });
+    assert_eq!(read_content(&mut handler, "kernel"), b"new kernel");
+
+    assert!(block_on(app_handle.preload(&["missing"])).is_err());
+    assert!(block_on(app_handle.preload(&["../kernel"])).is_err());
+
+    app_handle.clear_cache();
+}
+
+#[test]
+fn revalidate_cache() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("kernel"), b"kernel").unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .revalidate_cache(Duration::ZERO);
+    let mut stale = handler.clone().revalidate_cache(Duration::from_secs(3600));
+
+    block_on(handler.preload(&["kernel"])).unwrap();
+
+    // Size changes even if mtime has a coarse resolution
+    fs::write(tmp.path().join("kernel"), b"new kernel").unwrap();
+    assert_eq!(read_content(&mut stale, "kernel"), b"kernel");
+    assert_eq!(read_content(&mut handler, "kernel"), b"new kernel");
+
+    // Clones share the reloaded content
+    assert_eq!(read_content(&mut stale, "kernel"), b"new kernel");
+
+    fs::remove_file(tmp.path().join("kernel")).unwrap();
+    assert_eq!(read(&mut handler, "kernel"), Err(packet::Error::FileNotFound));
+}
+
+fn read_range(
+    handler: &mut DirHandler,
+    path: &str,
+) -> Result<Vec<u8>, packet::Error> {
+    let (content, size) = handler_read(handler, path)?;
+    assert_eq!(size, Some(content.len() as u64));
+    Ok(content)
+}
+
+#[test]
+fn byte_ranges() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("image"), b"0123456789").unwrap();
+    fs::write(tmp.path().join("odd?off=1"), b"odd").unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .byte_ranges();
+
+    assert_eq!(
+        read_range(&mut handler, "image?off=2&len=3"),
+        Ok(b"234".to_vec())
+    );
+    assert_eq!(
+        read_range(&mut handler, "image?len=3&off=2"),
+        Ok(b"234".to_vec())
+    );
+    assert_eq!(read_range(&mut handler, "image?off=7"), Ok(b"789".to_vec()));
+    assert_eq!(read_range(&mut handler, "image?len=2"), Ok(b"01".to_vec()));
+    assert_eq!(
+        read_range(&mut handler, "image?off=8&len=100"),
+        Ok(b"89".to_vec())
+    );
+    assert_eq!(read_range(&mut handler, "image?off=10"), Ok(b"".to_vec()));
+    assert!(read_range(&mut handler, "image?off=11").is_err());
+
+    // Existing files are served as they are
+    assert_eq!(read_range(&mut handler, "odd?off=1"), Ok(b"odd".to_vec()));
+
+    // Anything else is a regular filename
+    let not_found = Err(packet::Error::FileNotFound);
+    assert_eq!(read_range(&mut handler, "image?off=1&foo=2"), not_found);
+    assert_eq!(read_range(&mut handler, "image?off=1&off=2"), not_found);
+    assert_eq!(read_range(&mut handler, "image?off=-1"), not_found);
+    assert_eq!(read_range(&mut handler, "image?"), not_found);
+    assert_eq!(read_range(&mut handler, "missing?off=1"), not_found);
+    assert_eq!(
+        read_range(&mut handler, "../image?off=1"),
+        Err(packet::Error::PermissionDenied)
+    );
+
+    // Preloaded files
+    block_on(handler.preload(&["image"])).unwrap();
+    fs::remove_file(tmp.path().join("image")).unwrap();
+    assert_eq!(
+        read_range(&mut handler, "image?off=2&len=3"),
+        Ok(b"234".to_vec())
+    );
+}
+
+#[test]
+fn dir_listing() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("vmlinuz"), b"kernel").unwrap();
+    fs::write(tmp.path().join("initrd.img"), b"initrd").unwrap();
+    fs::create_dir(tmp.path().join("pxelinux.cfg")).unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .dir_listing(".dirlist");
+
+    let listing = b"initrd.img\nvmlinuz\n";
+    assert_eq!(read_content(&mut handler, ".dirlist"), listing);
+    assert_eq!(read_content(&mut handler, "/.dirlist"), listing);
+
+    let mut handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly).unwrap();
+    assert_eq!(
+        read(&mut handler, ".dirlist"),
+        Err(packet::Error::FileNotFound)
+    );
+}
+
+#[test]
+fn byte_ranges_are_disabled_by_default() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("image"), b"0123456789").unwrap();
+
+    let mut handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly).unwrap();
+
+    assert_eq!(
+        read(&mut handler, "image?off=2"),
+        Err(packet::Error::FileNotFound)
+    );
+}
+
+#[test]
+fn upload_bigger_than_free_space() {
+    let tmp = tempdir().unwrap();
+    let mut handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::WriteOnly).unwrap();
+
+    let res = block_on(handler.write_req_open(
+        &handler_client(),
+        Path::new("image"),
+        Some(u64::MAX),
+    ));
+    assert!(matches!(res, Err(packet::Error::DiskFull)));
+    assert!(!tmp.path().join("image").exists());
+
+    // Announced size is preallocated
+    block_on(handler.write_req_open(
+        &handler_client(),
+        Path::new("image"),
+        Some(100),
+    ))
+    .unwrap();
+    assert_eq!(fs::metadata(tmp.path().join("image")).unwrap().len(), 100);
+}
+
+#[test]
+fn min_free_space() {
+    let tmp = tempdir().unwrap();
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::WriteOnly)
+        .unwrap()
+        .min_free_space(u64::MAX);
+
+    let res = block_on(handler.write_req_open(
+        &handler_client(),
+        Path::new("log"),
+        Some(1),
+    ));
+    assert!(matches!(res, Err(packet::Error::DiskFull)));
+
+    // Size of the upload is not known
+    block_on(handler.write_req_open(&handler_client(), Path::new("log"), None))
+        .unwrap();
+}
+
+/// Write `data` to `path` and report the upload as failed.
+fn fail_upload(handler: &mut DirHandler, path: &str, data: &[u8]) {
+    block_on(async {
+        let mut writer = handler
+            .write_req_open(&handler_client(), Path::new(path), None)
+            .await
+            .unwrap();
+        writer.write_all(data).await.unwrap();
+
+        let mut state = TransferState::default();
+        handler
+            .write_req_failed(
+                &handler_client(),
+                Path::new(path),
+                writer,
+                &mut state,
+            )
+            .await;
+    });
+}
+
+#[test]
+fn partial_uploads() {
+    let tmp = tempdir().unwrap();
+    let handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::WriteOnly).unwrap();
+
+    let mut keep = handler.clone();
+    fail_upload(&mut keep, "kept", b"part");
+    assert_eq!(fs::read(tmp.path().join("kept")).unwrap(), b"part");
+
+    let mut delete = handler.clone().partial_uploads(PartialUpload::Delete);
+    fail_upload(&mut delete, "deleted", b"part");
+    assert!(!tmp.path().join("deleted").exists());
+
+    let mut rename = handler.clone().partial_uploads(PartialUpload::RenamePart);
+    fail_upload(&mut rename, "renamed", b"part");
+    assert!(!tmp.path().join("renamed").exists());
+    assert_eq!(fs::read(tmp.path().join("renamed.part")).unwrap(), b"part");
+
+    let failed = Arc::new(Mutex::new(Vec::<PathBuf>::new()));
+    let failed_clone = failed.clone();
+    let mut callback =
+        handler.partial_uploads(PartialUpload::Callback(Arc::new(
+            move |path: &Path| failed_clone.lock().unwrap().push(path.into()),
+        )));
+    fail_upload(&mut callback, "reported", b"part");
+    assert_eq!(*failed.lock().unwrap(), [tmp.path().join("reported")]);
+}
+
+#[test]
+fn failed_upload_is_deleted() {
+    let tmp = tempdir().unwrap();
+    let handler = DirHandler::new(tmp.path(), DirHandlerMode::WriteOnly)
+        .unwrap()
+        .partial_uploads(PartialUpload::Delete);
+    let builder = TftpServerBuilder::with_handler(handler).max_write_size(1000);
+    let path = tmp.path().join("upload");
+
+    run_with_server(builder, |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+
+        let res = client.write("upload", Opts::default(), &content(1500)).await;
+        assert!(matches!(res, Err(ClientError::Tftp(packet::Error::DiskFull))));
+
+        wait_until(Duration::from_secs(5), || !path.exists()).await;
+    });
+}
+
+fn upload(handler: &mut DirHandler, path: &str, data: &[u8]) {
+    block_on(async {
+        let mut writer = handler
+            .write_req_open(
+                &handler_client(),
+                Path::new(path),
+                Some(data.len() as u64),
+            )
+            .await
+            .unwrap();
+        writer.write_all(data).await.unwrap();
+        writer.flush().await.unwrap();
+    });
+}
+
+#[test]
+fn append_uploads() {
+    let tmp = tempdir().unwrap();
+    fs::create_dir(tmp.path().join("logs")).unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::WriteOnly)
+        .unwrap()
+        .append_uploads(["logs/*.log", "dev?.txt"])
+        .partial_uploads(PartialUpload::Delete);
+
+    upload(&mut handler, "logs/boot.log", b"first\n");
+    upload(&mut handler, "logs/boot.log", b"second\n");
+    assert_eq!(
+        fs::read(tmp.path().join("logs/boot.log")).unwrap(),
+        b"first\nsecond\n"
+    );
+
+    upload(&mut handler, "dev1.txt", b"first\n");
+    upload(&mut handler, "dev1.txt", b"second\n");
+    assert_eq!(
+        fs::read(tmp.path().join("dev1.txt")).unwrap(),
+        b"first\nsecond\n"
+    );
+
+    // Other files are truncated
+    upload(&mut handler, "logs/boot.txt", b"first\n");
+    upload(&mut handler, "logs/boot.txt", b"second\n");
+    assert_eq!(
+        fs::read(tmp.path().join("logs/boot.txt")).unwrap(),
+        b"second\n"
+    );
+
+    // Failed uploads do not delete earlier logs
+    fail_upload(&mut handler, "logs/boot.log", b"third");
+    assert_eq!(
+        fs::read(tmp.path().join("logs/boot.log")).unwrap(),
+        b"first\nsecond\nthird"
+    );
+}
+
+#[test]
+fn concurrent_appends_are_serialized() {
+    let tmp = tempdir().unwrap();
+    let handler = DirHandler::new(tmp.path(), DirHandlerMode::WriteOnly)
+        .unwrap()
+        .append_uploads(["*.log"]);
+    let builder = TftpServerBuilder::with_handler(handler);
+    let path = tmp.path().join("boot.log");
+    let log_path = path.clone();
+
+    run_with_server(builder, |addr| async move {
+        let upload = |fill: u8| async move {
+            let socket = FaultySocket::bind(Faults::none()).unwrap();
+            let mut client = TestClient::new(socket, addr);
+            let data = vec![fill; 2000];
+            client.write("boot.log", Opts::default(), &data).await.unwrap();
+        };
+
+        future::zip(Box::pin(upload(b'a')), Box::pin(upload(b'b'))).await;
+
+        // Last block is written after it is acknowledged
+        let written = || fs::metadata(&log_path).is_ok_and(|m| m.len() == 4000);
+        wait_until(Duration::from_secs(5), written).await;
+    });
+
+    // Uploads are not interleaved
+    let log = fs::read(&path).unwrap();
+    let (a, b) = (vec![b'a'; 2000], vec![b'b'; 2000]);
+    assert!(
+        log == [&a[..], &b[..]].concat() || log == [&b[..], &a[..]].concat()
+    );
+}
+
+#[test]
+fn upload_path() {
+    let tmp = tempdir().unwrap();
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::WriteOnly)
+        .unwrap()
+        .upload_path(|client, path| match path.to_str() {
+            Some("forbidden") => Err(packet::Error::PermissionDenied),
+            Some("escape") => Ok("../escape".into()),
+            _ => Ok(Path::new(&client.ip().to_string()).join(path)),
+        })
+        .partial_uploads(PartialUpload::RenamePart);
+    fs::create_dir(tmp.path().join("127.0.0.1")).unwrap();
+
+    upload(&mut handler, "config.txt", b"config");
+    assert_eq!(
+        fs::read(tmp.path().join("127.0.0.1/config.txt")).unwrap(),
+        b"config"
+    );
+    assert!(!tmp.path().join("config.txt").exists());
+
+    // Cleanup applies to the rewritten path
+    fail_upload(&mut handler, "config.txt", b"part");
+    assert_eq!(
+        fs::read(tmp.path().join("127.0.0.1/config.txt.part")).unwrap(),
+        b"part"
+    );
+
+    for path in ["forbidden", "escape"] {
+        let res = block_on(handler.write_req_open(
+            &handler_client(),
+            Path::new(path),
+            None,
+        ));
+        assert!(matches!(res, Err(packet::Error::PermissionDenied)));
+    }
+}
+
+#[test]
+fn share_reads() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("kernel"), b"old").unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadWrite)
+        .unwrap()
+        .share_reads(1024);
+
+    let open = |handler: &mut DirHandler| {
+        block_on(handler.read_req_open(&handler_client(), Path::new("kernel")))
+            .unwrap()
+    };
+
+    let (mut first, size) = open(&mut handler);
+    assert_eq!(size, Some(3));
+
+    // Running transfer keeps the file shared
+    fs::write(tmp.path().join("kernel"), b"new").unwrap();
+    let (mut second, _) = open(&mut handler);
+
+    let mut content = Vec::new();
+    block_on(first.read_to_end(&mut content)).unwrap();
+    assert_eq!(content, b"old");
+    drop(first);
+
+    content.clear();
+    block_on(second.read_to_end(&mut content)).unwrap();
+    assert_eq!(content, b"old");
+
+    // Write requests end the sharing, even with running transfers
+    block_on(async {
+        let mut writer = handler
+            .write_req_open(&handler_client(), Path::new("kernel"), None)
+            .await
+            .unwrap();
+        writer.write_all(b"newer").await.unwrap();
+        writer.flush().await.unwrap();
+    });
+    assert_eq!(read_content(&mut handler, "kernel"), b"newer");
+    drop(second);
+
+    // Content is dropped after the last transfer
+    fs::write(tmp.path().join("kernel"), b"newest").unwrap();
+    assert_eq!(read_content(&mut handler, "kernel"), b"newest");
+}
+
+#[test]
+fn share_reads_max_size() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("kernel"), b"old").unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .share_reads(2);
+
+    let (mut first, size) =
+        block_on(handler.read_req_open(&handler_client(), Path::new("kernel")))
+            .unwrap();
+    assert_eq!(size, Some(3));
+
+    // File is too big to be shared, so every transfer reads it from disk
+    fs::write(tmp.path().join("kernel"), b"new").unwrap();
+    assert_eq!(read_content(&mut handler, "kernel"), b"new");
+
+    let mut content = Vec::new();
+    block_on(first.read_to_end(&mut content)).unwrap();
+    assert_eq!(content, b"new");
+}
+
+#[test]
+#[cfg(feature = "watch")]
+fn watch_invalidates_preloaded_files() {
+    use std::time::Duration;
+
+    use super::utils::wait_until;
+
+    let tmp = tempdir().unwrap();
+    fs::create_dir(tmp.path().join("boot")).unwrap();
+    fs::write(tmp.path().join("boot").join("kernel"), b"old").unwrap();
+    fs::write(tmp.path().join("initrd"), b"initrd").unwrap();
+
+    let mut handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .watch()
+        .unwrap();
+
+    block_on(handler.preload(&["boot/kernel", "initrd"])).unwrap();
+
+    fs::write(tmp.path().join("boot").join("kernel"), b"new").unwrap();
+
+    block_on(wait_until(Duration::from_secs(5), || {
+        read_content(&mut handler, "boot/kernel") == b"new"
+    }));
+
+    // Removed files are dropped too
+    fs::remove_file(tmp.path().join("initrd")).unwrap();
+    block_on(wait_until(Duration::from_secs(5), || {
+        read(&mut handler, "initrd") == Err(packet::Error::FileNotFound)
+    }));
+}
+
+#[test]
+fn validate() {
+    let tmp = tempdir().unwrap();
+
+    let builder = TftpServerBuilder::with_dir_ro(tmp.path()).unwrap();
+    assert!(builder.validate().is_ok());
+
+    let handler = DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly)
+        .unwrap()
+        .append_uploads(["*.log"])
+        .dir_listing("../listing");
+    let builder = TftpServerBuilder::with_handler(handler)
+        .max_block_size(1024)
+        .min_block_size(1428, crate::server::BlockSizePolicy::Reject);
+
+    // The directory disappears after the handler is created
+    fs::remove_dir(tmp.path()).unwrap();
+
+    let problems = match builder.validate() {
+        Err(crate::Error::InvalidConfig(problems)) => problems,
+        _ => panic!("configuration is valid"),
+    };
+
+    // Missing directory, not readable, append_uploads, dir_listing name
+    // and block sizes
+    assert_eq!(problems.len(), 5, "{:?}", problems);
+    assert!(problems[0].contains("is not accessible"));
+    assert!(problems[4].contains("min_block_size"));
+}
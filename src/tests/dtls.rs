@@ -0,0 +1,347 @@
+#![cfg(feature = "dtls")]
+
+use futures_lite::future::{self, block_on};
+use futures_lite::io::Cursor;
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslConnector, SslMethod};
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509NameBuilder, X509};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::clock::SystemClock;
+use crate::packet::Packet;
+use crate::server::{Handler, TftpServerBuilder};
+use crate::test_util::{MemoryNetwork, MemoryTransport, RequestBuilder};
+use crate::transport::{DtlsTransport, Transport};
+use crate::utils::io_timeout;
+
+/// Self-signed certificate of `localhost` and its acceptor and connector.
+fn tls() -> (SslAcceptorBuilder, SslConnector) {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_nid(Nid::COMMONNAME, "localhost").unwrap();
+    let name = name.build();
+
+    let mut cert = X509::builder().unwrap();
+    cert.set_version(2).unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    cert.set_serial_number(&serial).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns("localhost")
+        .build(&cert.x509v3_context(None, None))
+        .unwrap();
+    cert.append_extension(san).unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+    let cert = cert.build();
+
+    let mut acceptor =
+        SslAcceptor::mozilla_intermediate(SslMethod::dtls()).unwrap();
+    acceptor.set_private_key(&key).unwrap();
+    acceptor.set_certificate(&cert).unwrap();
+
+    let mut connector = SslConnector::builder(SslMethod::dtls()).unwrap();
+    connector.cert_store_mut().add_cert(cert).unwrap();
+
+    (acceptor, connector.build())
+}
+
+/// Serve the server that `builder` produces over DTLS on a
+/// [`MemoryNetwork`], run `f` with the network, the connector of clients
+/// and the server address, and return its output.
+fn run_with_dtls_server<H, F, Fut, T>(builder: TftpServerBuilder<H>, f: F) -> T
+where
+    H: Handler + 'static,
+    F: FnOnce(MemoryNetwork, SslConnector, SocketAddr) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let network = MemoryNetwork::new();
+    let (acceptor, connector) = tls();
+    let socket = network.bind("10.0.0.1:69".parse().unwrap()).unwrap();
+    let transport = DtlsTransport::server(socket, acceptor).unwrap();
+
+    run_with_transport(builder, transport, |addr| f(network, connector, addr))
+}
+
+#[test]
+fn transfers_over_dtls() {
+    let handler = MemHandler::new(content(20000));
+    let written = handler.written();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_dtls_server(builder, |network, connector, addr| async move {
+        let transport = DtlsTransport::client(
+            memory_client(&network),
+            connector,
+            "localhost",
+        );
+        let client =
+            TftpClient::new(addr).block_size(8192).transport(transport);
+
+        let data = client.read_to_vec("test").await.unwrap();
+        assert_eq!(data, content(20000));
+
+        let sent = client
+            .write("upload", Cursor::new(content(1500)), Some(1500))
+            .await
+            .unwrap();
+        assert_eq!(sent, 1500);
+
+        wait_until(Duration::from_secs(1), || {
+            *written.lock().unwrap() == content(1500)
+        })
+        .await;
+    });
+}
+
+#[test]
+fn plaintext_requests_are_not_served() {
+    let handler = MemHandler::new(content(1000));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_dtls_server(builder, |network, _, addr| async move {
+        let socket = memory_client(&network);
+        let rrq = Packet::Rrq(RequestBuilder::new("test").build());
+        let socket: &dyn Transport = &socket;
+        socket.send_to(&rrq.to_vec(), addr).await.unwrap();
+
+        let mut buf = [0; 1024];
+        let wait = Duration::from_millis(200);
+        let reply =
+            io_timeout(&SystemClock, wait, socket.recv_from(&mut buf)).await;
+        assert!(reply.is_err());
+    });
+}
+
+#[test]
+fn certificate_is_verified() {
+    let handler = MemHandler::new(content(1000));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_dtls_server(builder, |network, connector, addr| async move {
+        let transport =
+            DtlsTransport::client(memory_client(&network), connector, "other");
+        let client = TftpClient::new(addr)
+            .timeout(Duration::from_millis(200))
+            .max_retries(1)
+            .transport(transport);
+
+        assert!(client.read_to_vec("test").await.is_err());
+    });
+}
+
+/// Transport that drops the first datagrams that it sends.
+struct Lossy {
+    network: MemoryNetwork,
+    socket: MemoryTransport,
+    drop: Arc<AtomicUsize>,
+}
+
+impl Transport for Lossy {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        let dropped = self
+            .drop
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                n.checked_sub(1)
+            })
+            .is_ok();
+
+        match dropped {
+            true => Poll::Ready(Ok(buf.len())),
+            false => self.socket.poll_send_to(cx, buf, target),
+        }
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        self.socket.poll_recv_from(cx, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn bind_transfer(
+        &self,
+        _peer: SocketAddr,
+    ) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(Lossy {
+            network: self.network.clone(),
+            socket: memory_client(&self.network),
+            drop: Arc::clone(&self.drop),
+        }))
+    }
+}
+
+#[test]
+fn lost_handshake_is_sent_again() {
+    let handler = MemHandler::new(content(1000));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_dtls_server(builder, |network, connector, addr| async move {
+        let lossy = Lossy {
+            socket: memory_client(&network),
+            network,
+            drop: Arc::new(AtomicUsize::new(1)),
+        };
+        let transport = DtlsTransport::client(lossy, connector, "localhost");
+        let client = TftpClient::new(addr)
+            .timeout(Duration::from_millis(1500))
+            .transport(transport);
+
+        let data = client.read_to_vec("test").await.unwrap();
+        assert_eq!(data, content(1000));
+    });
+}
+
+/// First datagram of a client handshake.
+async fn client_hello(
+    network: &MemoryNetwork,
+    connector: SslConnector,
+) -> Vec<u8> {
+    let capture = memory_client(network);
+    let target = capture.local_addr().unwrap();
+    let client =
+        DtlsTransport::client(memory_client(network), connector, "localhost");
+    let client: &dyn Transport = &client;
+    client.send_to(b"test", target).await.unwrap();
+
+    let capture: &dyn Transport = &capture;
+    let mut buf = [0; 2048];
+    let (len, _) = capture.recv_from(&mut buf).await.unwrap();
+    buf[..len].to_vec()
+}
+
+#[test]
+fn handshakes_without_cookie_keep_no_session() {
+    let handler = MemHandler::new(content(1000));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_dtls_server(builder, |network, connector, addr| async move {
+        let hello = client_hello(&network, connector.clone()).await;
+
+        // More peers than sessions, e.g. with spoofed addresses
+        for _ in 0..1100 {
+            let socket = memory_client(&network);
+            let socket: &dyn Transport = &socket;
+            socket.send_to(&hello, addr).await.unwrap();
+
+            // HelloVerifyRequest
+            let mut buf = [0; 2048];
+            let (len, _) = socket.recv_from(&mut buf).await.unwrap();
+            assert!(len < hello.len());
+            assert_eq!((buf[0], buf[13]), (22, 3));
+        }
+
+        let transport = DtlsTransport::client(
+            memory_client(&network),
+            connector,
+            "localhost",
+        );
+        let client = TftpClient::new(addr).transport(transport);
+
+        let data = client.read_to_vec("test").await.unwrap();
+        assert_eq!(data, content(1000));
+    });
+}
+
+/// Send `data` from `client` until `server` receives it.
+async fn deliver(
+    server: &dyn Transport,
+    client: &dyn Transport,
+    data: &[u8],
+) -> (Vec<u8>, SocketAddr) {
+    let addr = server.local_addr().unwrap();
+    client.send_to(data, addr).await.unwrap();
+
+    // Handshakes only progress while the client receives
+    let client_recv = async {
+        let mut buf = [0; 1024];
+        loop {
+            let wait = Duration::from_millis(100);
+            let _ = io_timeout(&SystemClock, wait, client.recv_from(&mut buf))
+                .await;
+            client.send_to(data, addr).await.unwrap();
+        }
+    };
+    let server_recv = async {
+        let mut buf = [0; 1024];
+        let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+        (buf[..len].to_vec(), peer)
+    };
+
+    future::or(server_recv, client_recv).await
+}
+
+#[test]
+fn replaced_session_keeps_its_transfers() {
+    block_on(async {
+        let network = MemoryNetwork::new();
+        let (acceptor, connector) = tls();
+        let socket = network.bind("10.0.0.1:69".parse().unwrap()).unwrap();
+        let server = DtlsTransport::server(socket, acceptor).unwrap();
+        let peer: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+
+        let old = DtlsTransport::client(
+            network.bind(peer).unwrap(),
+            connector.clone(),
+            "localhost",
+        );
+        assert_eq!(
+            deliver(&server, &old, b"old").await,
+            (b"old".to_vec(), peer)
+        );
+        let old_transfer = server.bind_transfer(peer).unwrap();
+        drop(old);
+
+        // New client on the same address replaces the session
+        let new = DtlsTransport::client(
+            network.bind(peer).unwrap(),
+            connector,
+            "localhost",
+        );
+        assert_eq!(
+            deliver(&server, &new, b"new").await,
+            (b"new".to_vec(), peer)
+        );
+        let new_transfer = server.bind_transfer(peer).unwrap();
+        drop(old_transfer);
+
+        // Datagrams of the new session still go to its transfer
+        let new: &dyn Transport = &new;
+        new.send_to(b"data", server.local_addr().unwrap()).await.unwrap();
+        let mut buf = [0; 1024];
+        let wait = Duration::from_secs(1);
+        let received = new_transfer.recv_from(&mut buf);
+        let (len, _) = io_timeout(&SystemClock, wait, received).await.unwrap();
+        assert_eq!(&buf[..len], b"data");
+    });
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::Packet;
+use crate::server::{DuplicateBlocks, TftpServerBuilder, TransferOutcome};
+
+#[test]
+fn duplicate_block_is_reacked() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_secs(10))
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::wrq(addr, "upload").await;
+        client.expect(Packet::Ack(0)).await;
+        let data = content(612);
+
+        let block = Packet::Data(1, &data[..512]);
+        client.send(block.clone()).await;
+        client.expect(Packet::Ack(1)).await;
+
+        // Replied right away, long before the timeout
+        client.send(block).await;
+        client.expect(Packet::Ack(1)).await;
+
+        client.send(Packet::Data(2, &data[512..])).await;
+        client.expect(Packet::Ack(2)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.duplicate_blocks, 1);
+        assert_eq!(record.retransmissions.blocks, vec![(1, 1)]);
+    });
+
+    assert_eq!(*written.lock().unwrap(), content(612));
+}
+
+#[test]
+fn mismatching_duplicate_is_rejected() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let builder = TftpServerBuilder::with_handler(handler)
+        .duplicate_blocks(DuplicateBlocks::RejectMismatch)
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::wrq(addr, "upload").await;
+        client.expect(Packet::Ack(0)).await;
+        let data = content(1024);
+
+        let block = Packet::Data(1, &data[..512]);
+        client.send(block.clone()).await;
+        client.expect(Packet::Ack(1)).await;
+
+        // Same data is acknowledged again
+        client.send(block).await;
+        client.expect(Packet::Ack(1)).await;
+
+        client.send(Packet::Data(1, &data[512..])).await;
+        let reply = client.recv_bytes(Duration::from_secs(5)).await;
+        assert_eq!(
+            reply.unwrap(),
+            b"\x00\x05\x00\x04Duplicate block differs from written data\x00"
+        );
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::DuplicateMismatch);
+        assert_eq!(record.duplicate_blocks, 2);
+    });
+
+    assert_eq!(*written.lock().unwrap(), content(512));
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::server::TftpServerBuilder;
+
+#[test]
+fn duplicate_requests_are_ignored() {
+    let handler = MemHandler::new(content(100));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .duplicate_request_window(Duration::from_millis(500));
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(200);
+
+        // Pending requests are always ignored
+        let mut client = RawClient::rrq(addr, "kernel").await;
+        client.rrq_again(addr, "kernel").await;
+        assert!(client.recv(wait).await.is_some());
+        client.finish().await;
+        assert!(client.recv(wait).await.is_none());
+
+        // Wait until the server finishes the request
+        async_io::Timer::after(Duration::from_millis(100)).await;
+
+        // Retransmission that arrives after the transfer finished
+        client.rrq_again(addr, "kernel").await;
+        assert!(client.recv(wait).await.is_none());
+
+        // Other requests of the same client are served
+        client.rrq_again(addr, "initrd").await;
+        assert!(client.recv(wait).await.is_some());
+        client.finish().await;
+        async_io::Timer::after(Duration::from_millis(100)).await;
+
+        // Requests are served again after the window
+        async_io::Timer::after(Duration::from_millis(600)).await;
+        client.rrq_again(addr, "initrd").await;
+        assert!(client.recv(wait).await.is_some());
+        client.finish().await;
+    });
+}
+
+#[test]
+fn duplicate_requests_are_served_by_default() {
+    let handler = MemHandler::new(content(100));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(200);
+
+        let mut client = RawClient::rrq(addr, "kernel").await;
+        assert!(client.recv(wait).await.is_some());
+        client.finish().await;
+
+        // Wait until the server finishes the request
+        async_io::Timer::after(Duration::from_millis(100)).await;
+
+        client.rrq_again(addr, "kernel").await;
+        assert!(client.recv(wait).await.is_some());
+        client.finish().await;
+    });
+}
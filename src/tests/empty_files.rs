@@ -0,0 +1,166 @@
+use async_channel::Receiver;
+use futures_lite::io::{self, Cursor};
+use std::fs;
+use std::time::Duration;
+use tempfile::tempdir;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet::Packet;
+use crate::server::handlers::{DirHandler, DirHandlerMode};
+use crate::server::{AuditRecord, TftpServerBuilder, TransferOutcome};
+use crate::test_util::RequestBuilder;
+
+fn builder(
+    handler: MemHandler,
+) -> (TftpServerBuilder<MemHandler>, Receiver<AuditRecord>) {
+    let (tx, rx) = async_channel::unbounded();
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_millis(200))
+        .audit(ChannelSink(tx));
+
+    (builder, rx)
+}
+
+fn empty() -> RequestBuilder {
+    RequestBuilder::new("empty")
+}
+
+#[test]
+fn empty_file_read() {
+    let (builder, rx) = builder(MemHandler::new(Vec::new()));
+
+    run_with_server(builder, |addr| async move {
+        // Single empty DATA block
+        let mut client = RawClient::rrq(addr, "empty").await;
+        client.expect(Packet::Data(1, &[])).await;
+        client.send(Packet::Ack(1)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.transferred, 0);
+        assert!(record.retransmissions.blocks.is_empty());
+
+        // Transfer ended with the final ACK
+        assert!(client.is_idle(Duration::from_millis(500)).await);
+    });
+}
+
+#[test]
+fn empty_file_read_with_options() {
+    let (builder, rx) = builder(MemHandler::new(Vec::new()));
+    let builder = builder.max_window_size(4);
+
+    run_with_server(builder, |addr| async move {
+        let req = empty().block_size(1024).transfer_size(0).window_size(4);
+        let req = req.build();
+        let opts = req.opts.clone();
+
+        let mut client = RawClient::request(addr, Packet::Rrq(req)).await;
+        client.expect(Packet::OAck(opts)).await;
+        client.send(Packet::Ack(0)).await;
+        client.expect(Packet::Data(1, &[])).await;
+        client.send(Packet::Ack(1)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.transferred, 0);
+        assert!(client.is_idle(Duration::from_millis(500)).await);
+    });
+}
+
+#[test]
+fn empty_file_read_final_ack_lost() {
+    let (builder, rx) = builder(MemHandler::new(Vec::new()));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "empty").await;
+        client.expect(Packet::Data(1, &[])).await;
+
+        // The empty block is sent again until it is acknowledged
+        client.expect(Packet::Data(1, &[])).await;
+        client.send(Packet::Ack(1)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.retransmissions.blocks, vec![(1, 1)]);
+    });
+}
+
+#[test]
+fn empty_upload() {
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let writes = handler.writes();
+    let (builder, rx) = builder(handler);
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::wrq_with(addr, empty()).await;
+        client.expect(Packet::Ack(0)).await;
+        client.send(Packet::Data(1, &[])).await;
+        client.expect(Packet::Ack(1)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.transferred, 0);
+
+        // Duplicate of the empty block after the transfer ended
+        client.send(Packet::Data(1, &[])).await;
+        assert!(client.is_idle(Duration::from_millis(500)).await);
+    });
+
+    assert!(written.lock().unwrap().is_empty());
+    assert!(writes.lock().unwrap().is_empty());
+}
+
+#[test]
+fn empty_upload_with_options() {
+    let (builder, rx) = builder(MemHandler::new(Vec::new()));
+    let builder = builder.write_buffer_size(4096);
+
+    run_with_server(builder, |addr| async move {
+        let req = empty().block_size(1024).transfer_size(0).build();
+        let opts = req.opts.clone();
+
+        let mut client = RawClient::request(addr, Packet::Wrq(req)).await;
+        client.expect(Packet::OAck(opts)).await;
+        client.send(Packet::Data(1, &[])).await;
+        client.expect(Packet::Ack(1)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.transferred, 0);
+    });
+}
+
+#[test]
+fn empty_files_of_dir_handler() {
+    let tmp = tempdir().unwrap();
+    fs::write(tmp.path().join("marker"), b"").unwrap();
+
+    let handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::ReadWrite).unwrap();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+
+        let stream = client.read("marker").await.unwrap();
+        assert_eq!(stream.transfer_size(), Some(0));
+        drop(stream);
+        assert_eq!(client.read_to_vec("marker").await.unwrap(), b"");
+
+        let sent = client
+            .write("uploaded", Cursor::new(Vec::new()), Some(0))
+            .await
+            .unwrap();
+        assert_eq!(sent, 0);
+
+        let sent = client.write("streamed", io::empty(), None).await.unwrap();
+        assert_eq!(sent, 0);
+    });
+
+    assert_eq!(fs::read(tmp.path().join("uploaded")).unwrap(), b"");
+    assert_eq!(fs::read(tmp.path().join("streamed")).unwrap(), b"");
+}
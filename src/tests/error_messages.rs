@@ -0,0 +1,53 @@
+use std::fs;
+use tempfile::tempdir;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, Mode, Packet};
+use crate::server::TftpServerBuilder;
+use crate::test_util::RequestBuilder;
+
+fn rrq(filename: &str, mode: Mode) -> Packet<'static> {
+    Packet::Rrq(RequestBuilder::new(filename).mode(mode).build())
+}
+
+#[test]
+fn custom_error_messages() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("exists"), content(10)).unwrap();
+
+    let builder = TftpServerBuilder::with_dir_ro(dir.path())
+        .unwrap()
+        .error_messages(|e| match e {
+            packet::Error::FileNotFound => Some("No boot file".to_string()),
+            _ => None,
+        });
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, rrq("missing", Mode::Octet)).await;
+        assert_eq!(reply, b"\x00\x05\x00\x01No boot file\x00");
+
+        // Other errors keep their messages
+        let reply = request(addr, rrq("exists", Mode::Mail)).await;
+        assert_eq!(
+            reply,
+            b"\x00\x05\x00\x04Transfer mode is not supported\x00"
+        );
+    });
+}
+
+#[test]
+fn blank_error_messages() {
+    let handler = MemHandler::new(content(10));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .max_filename_len(8)
+        .blank_error_messages();
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, rrq("test", Mode::Mail)).await;
+        assert_eq!(reply, b"\x00\x05\x00\x04\x00");
+
+        let reply = request(addr, rrq("long-filename", Mode::Octet)).await;
+        assert_eq!(reply, b"\x00\x05\x00\x04\x00");
+    });
+}
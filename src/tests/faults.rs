@@ -0,0 +1,243 @@
+use async_io::{Async, Timer};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use crate::clock::SystemClock;
+use crate::transport::Transport;
+use crate::utils::io_timeout;
+
+const REORDER_HOLD: Duration = Duration::from_millis(10);
+
+/// Faults that are injected in the datagrams of a [`FaultySocket`].
+///
+/// Each probability is in the range of `0.0..=1.0` and is applied
+/// independently to sent and received datagrams.
+#[derive(Debug, Clone)]
+pub struct Faults {
+    /// Probability of a datagram to be lost.
+    pub loss: f64,
+    /// Probability of a datagram to be delivered twice.
+    pub duplicate: f64,
+    /// Probability of a datagram to be held back and delivered after
+    /// the next one.
+    pub reorder: f64,
+    /// Latency is uniformly distributed in this range.
+    pub latency: (Duration, Duration),
+    /// Seed of the random generator, so failures can be reproduced.
+    pub seed: u64,
+}
+
+/// Counters of the faults that were actually injected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub lost: usize,
+    pub duplicated: usize,
+    pub reordered: usize,
+}
+
+/// Socket wrapper that simulates an unreliable network.
+pub struct FaultySocket {
+    socket: Box<dyn Transport>,
+    faults: Faults,
+    rng: SmallRng,
+    stats: FaultStats,
+    // Received datagrams that are ready to be delivered
+    rx_ready: VecDeque<(Vec<u8>, SocketAddr)>,
+    // Received datagram that is held back to be reordered
+    rx_held: Option<(Vec<u8>, SocketAddr)>,
+    // Sent datagram that is held back to be reordered
+    tx_held: Option<(Vec<u8>, SocketAddr)>,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults {
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            latency: (Duration::ZERO, Duration::ZERO),
+            seed: 0,
+        }
+    }
+}
+
+impl Faults {
+    pub fn none() -> Self {
+        Faults::default()
+    }
+
+    pub fn loss(self, loss: f64) -> Self {
+        Faults {
+            loss,
+            ..self
+        }
+    }
+
+    pub fn duplicate(self, duplicate: f64) -> Self {
+        Faults {
+            duplicate,
+            ..self
+        }
+    }
+
+    pub fn reorder(self, reorder: f64) -> Self {
+        Faults {
+            reorder,
+            ..self
+        }
+    }
+
+    pub fn latency(self, min: Duration, max: Duration) -> Self {
+        Faults {
+            latency: (min, max),
+            ..self
+        }
+    }
+
+    pub fn seed(self, seed: u64) -> Self {
+        Faults {
+            seed,
+            ..self
+        }
+    }
+}
+
+impl FaultySocket {
+    /// Bind a UDP socket on loopback.
+    pub fn bind(faults: Faults) -> io::Result<Self> {
+        let socket = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0))?;
+        Ok(FaultySocket::new(socket, faults))
+    }
+
+    /// Inject `faults` in the datagrams of `socket`, e.g. a socket of a
+    /// [`MemoryNetwork`](crate::test_util::MemoryNetwork).
+    pub fn new<T: Transport + 'static>(socket: T, faults: Faults) -> Self {
+        FaultySocket {
+            socket: Box::new(socket),
+            rng: SmallRng::seed_from_u64(faults.seed),
+            faults,
+            stats: FaultStats::default(),
+            rx_ready: VecDeque::new(),
+            rx_held: None,
+            tx_held: None,
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn stats(&self) -> &FaultStats {
+        &self.stats
+    }
+
+    pub async fn send_to(
+        &mut self,
+        buf: &[u8],
+        addr: SocketAddr,
+    ) -> io::Result<()> {
+        if self.roll(self.faults.loss) {
+            self.stats.lost += 1;
+            return Ok(());
+        }
+
+        if self.tx_held.is_none() && self.roll(self.faults.reorder) {
+            self.stats.reordered += 1;
+            self.tx_held = Some((buf.to_vec(), addr));
+            return Ok(());
+        }
+
+        self.delay().await;
+        self.socket.send_to(buf, addr).await?;
+
+        if self.roll(self.faults.duplicate) {
+            self.stats.duplicated += 1;
+            self.socket.send_to(buf, addr).await?;
+        }
+
+        if let Some((held, addr)) = self.tx_held.take() {
+            self.socket.send_to(&held, addr).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr)> {
+        while self.rx_ready.is_empty() {
+            let mut data = vec![0u8; buf.len()];
+
+            let (len, peer) = match self.rx_held {
+                // Reordered datagram is delivered after the next one or
+                // after a while if nothing else arrives.
+                Some(_) => {
+                    match io_timeout(
+                        &SystemClock,
+                        REORDER_HOLD,
+                        self.socket.recv_from(&mut data),
+                    )
+                    .await
+                    {
+                        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                            let held = self.rx_held.take().unwrap();
+                            self.rx_ready.push_back(held);
+                            break;
+                        }
+                        res => res?,
+                    }
+                }
+                None => self.socket.recv_from(&mut data).await?,
+            };
+            data.truncate(len);
+
+            if self.roll(self.faults.loss) {
+                self.stats.lost += 1;
+                continue;
+            }
+
+            if self.rx_held.is_none() && self.roll(self.faults.reorder) {
+                self.stats.reordered += 1;
+                self.rx_held = Some((data, peer));
+                continue;
+            }
+
+            if self.roll(self.faults.duplicate) {
+                self.stats.duplicated += 1;
+                self.rx_ready.push_back((data.clone(), peer));
+            }
+
+            self.rx_ready.push_back((data, peer));
+
+            if let Some(held) = self.rx_held.take() {
+                self.rx_ready.push_back(held);
+            }
+        }
+
+        self.delay().await;
+
+        let (data, peer) = self.rx_ready.pop_front().unwrap();
+        buf[..data.len()].copy_from_slice(&data);
+
+        Ok((data.len(), peer))
+    }
+
+    async fn delay(&mut self) {
+        let (min, max) = self.faults.latency;
+
+        if max > min {
+            Timer::after(self.rng.gen_range(min..max)).await;
+        } else if min > Duration::ZERO {
+            Timer::after(min).await;
+        }
+    }
+
+    fn roll(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.rng.gen_bool(probability.min(1.0))
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::server::TftpServerBuilder;
+
+const FILE_SIZE: usize = 512 * 3 + 10;
+
+#[test]
+fn pending_handshakes_per_ip() {
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_secs(3))
+        .max_pending_handshakes_per_ip(1);
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(200);
+
+        let mut first = RawClient::rrq(addr, "test").await;
+        assert!(first.recv(wait).await.is_some());
+
+        // `first` did not reply yet, so requests of the same IP are dropped
+        let mut dropped = RawClient::rrq(addr, "test").await;
+        assert!(dropped.recv(wait).await.is_none());
+
+        // The handshake of `first` is completed with its first ACK
+        first.ack().await;
+        assert!(first.recv(wait).await.is_some());
+
+        let mut second = RawClient::rrq(addr, "test").await;
+        assert!(second.recv(wait).await.is_some());
+
+        first.finish().await;
+        second.finish().await;
+    });
+}
+
+#[test]
+fn pending_handshakes_timeout() {
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_secs(3))
+        .max_send_retries(1)
+        .max_pending_handshakes(1)
+        .handshake_timeout(Duration::from_millis(50));
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(200);
+
+        let mut abandoned = RawClient::rrq(addr, "test").await;
+        assert!(abandoned.recv(wait).await.is_some());
+
+        let mut dropped = RawClient::rrq(addr, "test").await;
+        assert!(dropped.recv(wait).await.is_none());
+
+        // Unanswered handshake is abandoned after the retries, long before
+        // the regular timeout expires
+        let mut next = RawClient::rrq(addr, "test").await;
+        assert!(next.recv(wait).await.is_some());
+
+        next.finish().await;
+    });
+}
@@ -0,0 +1,233 @@
+//! Files bigger than 4 GiB.
+//!
+//! The read and write paths keep `tsize`, the write limit, the transferred
+//! bytes and the acknowledged blocks in 64 bits, and roll block numbers
+//! over after 65535. These tests check that nothing is truncated to 32 bits
+//! and that data after a rollover lands at the right offset. They use sparse
+//! files and 64 KiB datagrams, so they run only on Linux.
+#![cfg(target_os = "linux")]
+
+use futures_lite::io::{self, AsyncReadExt, AsyncWrite};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tempfile::tempdir;
+
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet::{self, Opts, Packet};
+use crate::server::handlers::{DirHandler, DirHandlerMode};
+use crate::server::{Handler, TftpServerBuilder, TransferOutcome};
+use crate::test_util::RequestBuilder;
+
+/// Bigger than 4 GiB, and not a multiple of any block size.
+const SIZE: u64 = (1 << 32) + (1 << 20) + 123;
+
+/// Bytes that are written at the start, around the 4 GiB boundary and at
+/// the end of the sparse file. The rest of the file reads as zeros.
+const MARKERS: [(u64, &[u8]); 3] =
+    [(0, b"head"), ((1 << 32) - 2, b"4gib"), (SIZE - 4, b"tail")];
+
+/// Create a sparse file of `SIZE` bytes with `MARKERS`.
+fn sparse_file(dir: &Path) {
+    let mut file = File::create(dir.join("huge.img")).unwrap();
+    file.set_len(SIZE).unwrap();
+
+    for (offset, marker) in MARKERS {
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(marker).unwrap();
+    }
+}
+
+/// Expected content of the sparse file at `offset`.
+fn expected(offset: u64, len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    let end = offset + len as u64;
+
+    for (start, marker) in MARKERS {
+        for (i, byte) in marker.iter().enumerate() {
+            let pos = start + i as u64;
+
+            if (offset..end).contains(&pos) {
+                data[(pos - offset) as usize] = *byte;
+            }
+        }
+    }
+
+    data
+}
+
+fn rrq(filename: &str) -> Packet<'static> {
+    Packet::Rrq(RequestBuilder::new(filename).transfer_size(0).build())
+}
+
+fn wrq(transfer_size: u64) -> Packet<'static> {
+    let req = RequestBuilder::new("upload").transfer_size(transfer_size);
+    Packet::Wrq(req.build())
+}
+
+/// Handler that counts the bytes of uploads, instead of storing them.
+struct CountingHandler(Arc<AtomicU64>);
+
+struct CountingWriter(Arc<AtomicU64>);
+
+#[crate::async_trait]
+impl Handler for CountingHandler {
+    type Reader = io::Empty;
+    type Writer = CountingWriter;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        Err(packet::Error::FileNotFound)
+    }
+
+    async fn write_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        Ok(CountingWriter(self.0.clone()))
+    }
+}
+
+impl AsyncWrite for CountingWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.0.fetch_add(buf.len() as u64, Ordering::SeqCst);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn huge_transfer_sizes() {
+    let tmp = tempdir().unwrap();
+    sparse_file(tmp.path());
+
+    let handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly).unwrap();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, rrq("huge.img")).await;
+        assert_eq!(
+            Packet::decode(&reply).unwrap(),
+            Packet::OAck(Opts {
+                transfer_size: Some(SIZE),
+                ..Opts::default()
+            })
+        );
+    });
+
+    let handler = CountingHandler(Arc::new(AtomicU64::new(0)));
+    let builder = TftpServerBuilder::with_handler(handler).max_write_size(SIZE);
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, wrq(SIZE)).await;
+        assert_eq!(
+            Packet::decode(&reply).unwrap(),
+            Packet::OAck(Opts {
+                transfer_size: Some(SIZE),
+                ..Opts::default()
+            })
+        );
+
+        // Limit is not truncated to 32 bits
+        let reply = request(addr, wrq(SIZE + 1)).await;
+        assert_eq!(
+            Packet::decode(&reply).unwrap(),
+            Packet::Error(packet::Error::DiskFull)
+        );
+    });
+}
+
+#[test]
+#[ignore]
+fn huge_file_read() {
+    let tmp = tempdir().unwrap();
+    sparse_file(tmp.path());
+
+    let (tx, rx) = async_channel::unbounded();
+    let handler =
+        DirHandler::new(tmp.path(), DirHandlerMode::ReadOnly).unwrap();
+    let builder = TftpServerBuilder::with_handler(handler)
+        .max_block_size(65464)
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr).block_size(65464);
+        let mut stream = client.read("huge.img").await.unwrap();
+        assert_eq!(stream.transfer_size(), Some(SIZE));
+
+        let mut buf = vec![0u8; 1 << 20];
+        let mut offset = 0u64;
+
+        loop {
+            let len = stream.read(&mut buf).await.unwrap();
+            if len == 0 {
+                break;
+            }
+
+            // Block numbers roll over, so misplaced data is detected
+            assert!(buf[..len] == expected(offset, len)[..], "at {}", offset);
+            offset += len as u64;
+        }
+
+        assert_eq!(offset, SIZE);
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.transferred, SIZE);
+    });
+}
+
+#[test]
+#[ignore]
+fn huge_file_write() {
+    let written = Arc::new(AtomicU64::new(0));
+    let (tx, rx) = async_channel::unbounded();
+    let handler = CountingHandler(written.clone());
+    let builder = TftpServerBuilder::with_handler(handler)
+        .max_block_size(65464)
+        .audit(ChannelSink(tx));
+
+    let sent = run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr).block_size(65464);
+        let reader = io::repeat(0).take(SIZE);
+        let sent = client.write("upload", reader, Some(SIZE)).await.unwrap();
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.transferred, SIZE);
+
+        sent
+    });
+
+    assert_eq!(sent, SIZE);
+    assert_eq!(written.load(Ordering::SeqCst), SIZE);
+}
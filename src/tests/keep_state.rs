@@ -0,0 +1,90 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::error::Error;
+use crate::server::{KeepState, TftpServerBuilder, TransferSnapshot};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+#[test]
+fn resume_after_restart() {
+    let state = KeepState::new();
+    let builder = TftpServerBuilder::with_handler(MemHandler::new(content(
+        4 * 512 + 100,
+    )))
+    .keep_state(state.clone());
+
+    // The first server is dropped after the client acknowledged two blocks
+    let (mut client, exported) = run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+
+        for id in 1..=2 {
+            assert_eq!(client.recv(WAIT).await, Some((id, 512)));
+            client.ack().await;
+        }
+        assert_eq!(client.recv(WAIT).await, Some((3, 512)));
+
+        wait_until(WAIT, || {
+            state.snapshots().iter().any(|s| s.acked_blocks == 2)
+        })
+        .await;
+
+        (client, state.export())
+    });
+
+    let snapshots = KeepState::import(&exported).unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].local_addr, client.server_addr().unwrap());
+    assert_eq!(snapshots[0].filename, b"test");
+
+    let builder = TftpServerBuilder::with_handler(MemHandler::new(content(
+        4 * 512 + 100,
+    )))
+    .resume_transfers(snapshots);
+
+    run_with_server(builder, |_| async move {
+        assert_eq!(client.recv(WAIT).await, Some((3, 512)));
+        client.ack().await;
+        assert_eq!(client.recv(WAIT).await, Some((4, 512)));
+        client.ack().await;
+        assert_eq!(client.recv(WAIT).await, Some((5, 100)));
+        client.ack().await;
+    });
+}
+
+#[test]
+fn export_import() {
+    let snapshot = TransferSnapshot {
+        client: "[fe80::1%2]:2000".parse::<SocketAddr>().unwrap(),
+        local_addr: "[::]:40000".parse().unwrap(),
+        filename: b"pxelinux.0".to_vec(),
+        block_size: 1468,
+        window_size: 8,
+        timeout: Duration::from_millis(1500),
+        acked_blocks: 70_000,
+    };
+
+    let state = KeepState::new();
+    assert!(KeepState::import(&state.export()).unwrap().is_empty());
+
+    let progress = state.register(snapshot.clone());
+    let exported = state.export();
+    assert_eq!(KeepState::import(&exported).unwrap(), vec![snapshot.clone()]);
+
+    progress.acked(70_001);
+    assert_eq!(state.snapshots()[0].acked_blocks, 70_001);
+
+    // Finished transfers are forgotten, but not a newer one of the client
+    let newer = state.register(snapshot.clone());
+    drop(progress);
+    assert_eq!(state.snapshots(), vec![snapshot]);
+    drop(newer);
+    assert!(state.snapshots().is_empty());
+
+    assert!(matches!(
+        KeepState::import(&exported[..exported.len() - 1]),
+        Err(Error::InvalidTransferState)
+    ));
+}
@@ -0,0 +1,253 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::client::{ClientError, TestClient};
+use super::faults::{Faults, FaultySocket};
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, Opts, OwnedPacket, Packet};
+use crate::server::{OversizedDatagrams, TftpServerBuilder, TransferOutcome};
+use crate::test_util::RequestBuilder;
+
+const WAIT: Duration = Duration::from_secs(5);
+
+fn builder() -> TftpServerBuilder<MemHandler> {
+    TftpServerBuilder::with_handler(MemHandler::new(content(100)))
+        .max_filename_len(16)
+        .max_request_options(2)
+        .max_request_size(64)
+}
+
+/// Send `data` as a request and return the decoded reply.
+async fn send_request<T>(
+    addr: SocketAddr,
+    data: &[u8],
+    f: impl FnOnce(Packet) -> T,
+) -> T {
+    let mut client = RawClient::bind();
+    client.send_bytes_to(addr, data).await;
+
+    let reply = client.recv_packet(WAIT).await;
+    f(reply.expect("no packet received").as_packet())
+}
+
+fn rrq(filename: &[u8]) -> Vec<u8> {
+    Packet::Rrq(RequestBuilder::new(filename).build()).to_vec()
+}
+
+fn wrq(transfer_size: u64) -> Vec<u8> {
+    let req = RequestBuilder::new("upload").transfer_size(transfer_size);
+    Packet::Wrq(req.build()).to_vec()
+}
+
+fn is_illegal_operation(packet: Packet) -> bool {
+    matches!(packet, Packet::Error(packet::Error::IllegalOperation))
+}
+
+fn is_data(packet: Packet) -> bool {
+    matches!(packet, Packet::Data(1, _))
+}
+
+fn is_oack(packet: Packet) -> bool {
+    matches!(packet, Packet::OAck(_))
+}
+
+fn is_disk_full(packet: Packet) -> bool {
+    matches!(packet, Packet::Error(packet::Error::DiskFull))
+}
+
+#[test]
+fn filename_len_limit() {
+    run_with_server(builder(), |addr| async move {
+        assert!(send_request(addr, &rrq(&[b'a'; 16]), is_data).await);
+        assert!(
+            send_request(addr, &rrq(&[b'a'; 17]), is_illegal_operation).await
+        );
+    });
+}
+
+#[test]
+fn request_options_limit() {
+    run_with_server(builder(), |addr| async move {
+        // Unknown options are counted too
+        let req = b"\x00\x01test\0octet\0a\0b\0c\0d\0";
+        assert!(send_request(addr, req, is_data).await);
+
+        let req = b"\x00\x01test\0octet\0a\0b\0c\0d\0e\0f\0";
+        assert!(send_request(addr, req, is_illegal_operation).await);
+    });
+}
+
+#[test]
+fn request_size_limit() {
+    run_with_server(builder(), |addr| async move {
+        let mut req = rrq(b"test");
+        req.resize(64, 0);
+        // Padding makes it invalid, so it is ignored. Anything bigger is
+        // rejected before parsing.
+        req.push(0);
+        assert!(send_request(addr, &req, is_illegal_operation).await);
+    });
+}
+
+#[test]
+fn request_exactly_at_size_limit() {
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(100)))
+            .max_request_size(64);
+
+    run_with_server(builder, |addr| async move {
+        // Opcode, filename, NUL and `octet\0` take 9 bytes
+        assert_eq!(rrq(&[b'a'; 55]).len(), 64);
+        assert!(send_request(addr, &rrq(&[b'a'; 55]), is_data).await);
+        assert!(
+            send_request(addr, &rrq(&[b'a'; 56]), is_illegal_operation).await
+        );
+    });
+}
+
+#[test]
+fn request_exactly_at_options_limit() {
+    run_with_server(builder(), |addr| async move {
+        // Known options are counted like unknown ones
+        let req = b"\x00\x01test\0octet\0blksize\x00512\0tsize\x000\0";
+        assert!(send_request(addr, req, is_oack).await);
+
+        let req =
+            b"\x00\x01test\0octet\0blksize\x00512\0tsize\x000\0timeout\x001\0";
+        assert!(send_request(addr, req, is_illegal_operation).await);
+    });
+}
+
+#[test]
+fn write_size_limit() {
+    let builder = builder().max_write_size(1000);
+
+    run_with_server(builder, |addr| async move {
+        assert!(send_request(addr, &wrq(1000), is_oack).await);
+        assert!(send_request(addr, &wrq(1001), is_disk_full).await);
+
+        // Uploads without `tsize` are stopped at the limit
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+
+        let res = client.write("upload", Opts::default(), &content(1500)).await;
+        assert!(matches!(res, Err(ClientError::Tftp(packet::Error::DiskFull))));
+    });
+}
+
+#[test]
+fn upload_exactly_at_write_size_limit() {
+    // Limit that ends in the middle of a block
+    run_with_server(builder().max_write_size(1000), |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+        let res = client.write("upload", Opts::default(), &content(1000)).await;
+        assert!(res.is_ok());
+
+        // Last block is not acknowledged
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+        let res = client.write("upload", Opts::default(), &content(1001)).await;
+        assert!(matches!(res, Err(ClientError::Tftp(packet::Error::DiskFull))));
+    });
+
+    // Limit that is a multiple of the block size
+    run_with_server(builder().max_write_size(1024), |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+        let res = client.write("upload", Opts::default(), &content(1024)).await;
+        assert!(res.is_ok());
+    });
+}
+
+#[test]
+fn oversized_datagram_is_rejected() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let builder =
+        TftpServerBuilder::with_handler(handler).audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::wrq(addr, "upload").await;
+        client.expect(Packet::Ack(0)).await;
+        client.send(Packet::Data(1, &content(600))).await;
+
+        let reply = client.recv_bytes(WAIT).await;
+        assert_eq!(
+            reply.unwrap(),
+            b"\x00\x05\x00\x04Datagram exceeds block size\x00"
+        );
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::OversizedDatagram);
+        assert_eq!(record.oversized_datagrams, 1);
+    });
+
+    assert!(written.lock().unwrap().is_empty());
+}
+
+/// ERROR of a client with small blocks that is longer than a DATA packet.
+fn long_client_error(policy: OversizedDatagrams) {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(Vec::new());
+    let builder = TftpServerBuilder::with_handler(handler)
+        .oversized_datagrams(policy)
+        .timeout(Duration::from_millis(200))
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let req = RequestBuilder::new("upload").block_size(8);
+        let mut client = RawClient::wrq_with(addr, req).await;
+        let oack = client.recv_packet(WAIT).await;
+        assert!(matches!(oack, Some(OwnedPacket::OAck(_))));
+
+        let msg = "Disk full or allocation exceeded".to_string();
+        client.send(Packet::Error(packet::Error::Msg(msg))).await;
+
+        // Message is cut off after the size of a DATA packet and one byte
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::ClientError);
+        assert_eq!(record.error, Some(packet::Error::Msg("Disk full".into())));
+        assert_eq!(record.oversized_datagrams, 0);
+
+        // Errors are not answered
+        assert!(client.is_idle(Duration::from_millis(100)).await);
+    });
+}
+
+#[test]
+fn long_client_error_is_not_oversized() {
+    long_client_error(OversizedDatagrams::Reject);
+    long_client_error(OversizedDatagrams::Ignore);
+}
+
+#[test]
+fn oversized_datagram_is_ignored() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let builder = TftpServerBuilder::with_handler(handler)
+        .oversized_datagrams(OversizedDatagrams::Ignore)
+        .timeout(Duration::from_millis(200))
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::wrq(addr, "upload").await;
+        client.expect(Packet::Ack(0)).await;
+        client.send(Packet::Data(1, &content(600))).await;
+
+        // The oversized block is dropped, so the ACK 0 is retransmitted
+        client.expect(Packet::Ack(0)).await;
+
+        client.send(Packet::Data(1, &content(100))).await;
+        client.expect(Packet::Ack(1)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+        assert_eq!(record.oversized_datagrams, 1);
+    });
+
+    assert_eq!(*written.lock().unwrap(), content(100));
+}
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, Packet};
+use crate::server::{TftpServerBuilder, TransferOutcome};
+
+fn busy() -> Packet<'static> {
+    Packet::Error(packet::Error::Msg("Server busy".to_string()))
+}
+
+#[test]
+fn requests_over_limit_are_shed() {
+    let (tx, rx) = async_channel::unbounded();
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(2000)))
+            .max_active_requests(1)
+            .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(Duration::from_secs(5)).await, Some((1, 512)));
+
+        // Shed requests are answered from the listening socket
+        let mut shed = RawClient::rrq(addr, "busy").await;
+        shed.expect(busy()).await;
+        assert_eq!(shed.server_addr(), Some(addr));
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.filename, "busy");
+        assert_eq!(record.outcome, TransferOutcome::Shed);
+        assert_eq!(record.outcome.as_str(), "shed");
+
+        client.finish().await;
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+
+        // Slot is released when the request is finished
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(Duration::from_secs(5)).await, Some((1, 512)));
+    });
+}
+
+#[test]
+fn requests_are_shed_while_memory_budget_is_exhausted() {
+    let (tx, rx) = async_channel::unbounded();
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(2000)))
+            .memory_budget(100)
+            .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        // Buffer of the transfer exceeds the budget
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(Duration::from_secs(5)).await, Some((1, 512)));
+
+        let mut shed = RawClient::rrq(addr, "busy").await;
+        shed.expect(busy()).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Shed);
+
+        client.finish().await;
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.outcome, TransferOutcome::Completed);
+
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(Duration::from_secs(5)).await, Some((1, 512)));
+    });
+}
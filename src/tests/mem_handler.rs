@@ -0,0 +1,128 @@
+use futures_lite::io::{AsyncWrite, Cursor};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use crate::packet;
+use crate::server::{Direction, Handler, TransferParams, TransferState};
+
+/// Handler that serves a buffer for every read request and stores the
+/// data of write requests in memory.
+pub struct MemHandler {
+    content: Vec<u8>,
+    written: Arc<Mutex<Vec<u8>>>,
+    writes: Arc<Mutex<Vec<usize>>>,
+    reads: Arc<AtomicUsize>,
+    params: TransferParams,
+}
+
+pub struct MemWriter {
+    written: Arc<Mutex<Vec<u8>>>,
+    writes: Arc<Mutex<Vec<usize>>>,
+}
+
+impl MemHandler {
+    pub fn new(content: Vec<u8>) -> Self {
+        MemHandler {
+            content,
+            written: Arc::new(Mutex::new(Vec::new())),
+            writes: Arc::new(Mutex::new(Vec::new())),
+            reads: Arc::new(AtomicUsize::new(0)),
+            params: TransferParams::default(),
+        }
+    }
+
+    /// Use `params` for every transfer.
+    pub fn with_params(self, params: TransferParams) -> Self {
+        MemHandler {
+            params,
+            ..self
+        }
+    }
+
+    /// Shared buffer that holds the data of the last write request.
+    pub fn written(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.written.clone()
+    }
+
+    /// Sizes of the writes of the last write request.
+    pub fn writes(&self) -> Arc<Mutex<Vec<usize>>> {
+        self.writes.clone()
+    }
+
+    /// Counter of read requests.
+    pub fn reads(&self) -> Arc<AtomicUsize> {
+        self.reads.clone()
+    }
+}
+
+#[crate::async_trait]
+impl Handler for MemHandler {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        let len = self.content.len() as u64;
+        Ok((Cursor::new(self.content.clone()), Some(len)))
+    }
+
+    async fn write_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.written.lock().unwrap().clear();
+        self.writes.lock().unwrap().clear();
+
+        Ok(MemWriter {
+            written: self.written.clone(),
+            writes: self.writes.clone(),
+        })
+    }
+
+    async fn transfer_params(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+        _state: &mut TransferState,
+    ) -> TransferParams {
+        self.params.clone()
+    }
+}
+
+impl AsyncWrite for MemWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        self.writes.lock().unwrap().push(buf.len());
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
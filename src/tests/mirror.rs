@@ -0,0 +1,96 @@
+use std::net::SocketAddr;
+use std::path::Path;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet;
+use crate::server::{
+    AuditRecord, Authorizer, Direction, MirrorMode, TftpServerBuilder,
+    TransferOutcome,
+};
+
+/// Read `test` from a server that mirrors to a secondary one, and return
+/// the audit record of the secondary server.
+fn mirrored_read(mode: MirrorMode) -> AuditRecord {
+    let (tx, rx) = async_channel::unbounded();
+    let secondary =
+        TftpServerBuilder::with_handler(MemHandler::new(content(3000)))
+            .audit(ChannelSink(tx));
+
+    run_with_server(secondary, |secondary| async move {
+        let handler = MemHandler::new(content(2000));
+        let builder =
+            TftpServerBuilder::with_handler(handler).mirror(secondary, mode);
+
+        with_server(builder, |addr| async move {
+            let client = TftpClient::new(addr).block_size(1024);
+            let data = client.read_to_vec("test").await.unwrap();
+            assert_eq!(data, content(2000));
+
+            rx.recv().await.unwrap()
+        })
+        .await
+    })
+}
+
+#[test]
+fn mirror_requests() {
+    let record = mirrored_read(MirrorMode::Requests);
+    assert_eq!(record.filename, "test");
+    assert_eq!(record.transferred, 0);
+    assert_eq!(record.outcome, TransferOutcome::ClientError);
+}
+
+#[test]
+fn mirror_transfers() {
+    let record = mirrored_read(MirrorMode::Transfers);
+    assert_eq!(record.filename, "test");
+    assert_eq!(record.transferred, 3000);
+    assert_eq!(record.outcome, TransferOutcome::Completed);
+}
+
+/// Denies reading `secret`.
+struct DenySecret;
+
+#[crate::async_trait]
+impl Authorizer for DenySecret {
+    async fn authorize(
+        &self,
+        _client: &SocketAddr,
+        path: &Path,
+        _direction: Direction,
+    ) -> Result<(), packet::Error> {
+        if path == Path::new("secret") {
+            Err(packet::Error::PermissionDenied)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn denied_requests_are_not_mirrored() {
+    let (tx, rx) = async_channel::unbounded();
+    let secondary =
+        TftpServerBuilder::with_handler(MemHandler::new(content(3000)))
+            .audit(ChannelSink(tx));
+
+    run_with_server(secondary, |secondary| async move {
+        let builder =
+            TftpServerBuilder::with_handler(MemHandler::new(content(2000)))
+                .authorizer(DenySecret)
+                .mirror(secondary, MirrorMode::Requests);
+
+        with_server(builder, |addr| async move {
+            let client = TftpClient::new(addr);
+            assert!(client.read_to_vec("secret").await.is_err());
+            client.read_to_vec("test").await.unwrap();
+
+            // The first request that reaches the secondary server
+            let record = rx.recv().await.unwrap();
+            assert_eq!(record.filename, "test");
+        })
+        .await
+    })
+}
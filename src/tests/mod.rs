@@ -1,7 +1,68 @@
 #![cfg(test)]
 
+mod ack_regression;
+mod audit;
+mod authorizer;
+mod bind;
+mod block_size;
+mod block_source;
+mod buf_reader;
+mod cancellation;
+mod client;
+mod clock;
+#[cfg(feature = "bytes-api")]
+mod codec;
+mod compressed;
+mod conformance;
+mod conn_reset;
+mod dir_handler;
+mod dtls;
+mod duplicate_blocks;
+mod duplicates;
+mod empty_files;
+mod error_messages;
 mod external_client;
+mod faults;
 mod handlers;
+mod handshakes;
+mod huge_files;
+mod keep_state;
+mod limits;
+mod load_shedding;
+mod mem_handler;
+mod mirror;
+mod modes;
+mod neighbor;
+mod netascii;
+mod otel;
 mod packet;
+mod panics;
+mod pcap;
+mod presets;
+mod priority;
 mod random_file;
+mod rejected_options;
+mod rejections;
+mod relay;
+mod reply_addr;
+mod request_counts;
+mod resolver;
+mod restarts;
+mod retransmission;
 mod rrq;
+mod self_check;
+mod service;
+mod shards;
+mod shared_handler;
+mod test_util;
+mod tftp_client;
+mod timeouts;
+mod transfer_size;
+mod transfer_started;
+mod transfer_state;
+mod transport;
+mod unix_transport;
+mod utils;
+mod windows;
+mod windows_sockets;
+mod write_buffer;
@@ -0,0 +1,79 @@
+use futures_lite::io::Cursor;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::packet::{self, Mode, Packet, RwReq};
+use crate::server::{Handler, TftpServerBuilder};
+use crate::test_util::RequestBuilder;
+
+const UNSUPPORTED_MODE: &[u8] =
+    b"\x00\x05\x00\x04Transfer mode is not supported\0";
+
+/// Handler that accepts mail requests.
+struct MailHandler(MemHandler);
+
+#[crate::async_trait]
+impl Handler for MailHandler {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.0.read_req_open(client, path).await
+    }
+
+    async fn write_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        Err(packet::Error::IllegalOperation)
+    }
+
+    async fn mail_req_open(
+        &mut self,
+        client: &SocketAddr,
+        recipient: &str,
+    ) -> Result<Self::Writer, packet::Error> {
+        assert_eq!(recipient, "root");
+        self.0.write_req_open(client, Path::new(recipient), None).await
+    }
+}
+
+fn mail_req(filename: &str) -> RwReq {
+    RequestBuilder::new(filename).mode(Mode::Mail).build()
+}
+
+#[test]
+fn mail_mode_is_rejected() {
+    let builder = TftpServerBuilder::with_handler(MemHandler::new(content(10)));
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, Packet::Rrq(mail_req("root"))).await;
+        assert_eq!(reply, UNSUPPORTED_MODE);
+
+        let reply = request(addr, Packet::Wrq(mail_req("root"))).await;
+        assert_eq!(reply, UNSUPPORTED_MODE);
+    });
+}
+
+#[test]
+fn mail_mode_with_custom_handler() {
+    let handler = MailHandler(MemHandler::new(content(10)));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, Packet::Wrq(mail_req("root"))).await;
+        assert!(matches!(Packet::decode(&reply), Ok(Packet::Ack(0))));
+
+        // Mail mode is only for write requests
+        let reply = request(addr, Packet::Rrq(mail_req("root"))).await;
+        assert_eq!(reply, UNSUPPORTED_MODE);
+    });
+}
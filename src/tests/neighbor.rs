@@ -0,0 +1,36 @@
+use std::net::Ipv4Addr;
+
+use crate::server::MacAddr;
+
+#[test]
+fn mac_addr() {
+    let mac: MacAddr = "52:54:00:AB:cd:0f".parse().unwrap();
+    assert_eq!(mac, MacAddr([0x52, 0x54, 0x00, 0xab, 0xcd, 0x0f]));
+    assert_eq!(mac.to_string(), "52:54:00:ab:cd:0f");
+
+    assert!("52:54:00:ab:cd".parse::<MacAddr>().is_err());
+    assert!("52:54:00:ab:cd:0f:00".parse::<MacAddr>().is_err());
+    assert!("52:54:00:ab:cd:zz".parse::<MacAddr>().is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn arp_table() {
+    use crate::server::neighbor::find_mac;
+
+    let table = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.20     0x1         0x2         52:54:00:12:34:56     *        eth0
+192.168.1.21     0x1         0x0         00:00:00:00:00:00     *        eth0
+";
+
+    let ip = |last| Ipv4Addr::new(192, 168, 1, last);
+
+    assert_eq!(
+        find_mac(table, ip(20)),
+        Some(MacAddr([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]))
+    );
+    // Incomplete entry
+    assert_eq!(find_mac(table, ip(21)), None);
+    assert_eq!(find_mac(table, ip(22)), None);
+}
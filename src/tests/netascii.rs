@@ -0,0 +1,36 @@
+use crate::netascii::{self, Decoder};
+
+fn decode(chunks: &[&[u8]]) -> Vec<u8> {
+    let mut decoder = Decoder::new();
+    let mut out = Vec::new();
+
+    for chunk in chunks {
+        decoder.decode(chunk, &mut out);
+    }
+
+    decoder.finish(&mut out);
+    out
+}
+
+#[test]
+fn encode_line_endings() {
+    let mut out = Vec::new();
+    netascii::encode(b"a\nb\rc\r\n", &mut out);
+    assert_eq!(out, b"a\r\nb\r\0c\r\0\r\n");
+}
+
+#[test]
+fn decode_line_endings() {
+    assert_eq!(decode(&[b"a\r\nb\r\0c\r\0\r\n"]), b"a\nb\rc\r\n");
+}
+
+#[test]
+fn decode_cr_split_across_chunks() {
+    assert_eq!(decode(&[b"a\r", b"\nb\r", b"\0"]), b"a\nb\r");
+}
+
+#[test]
+fn decode_invalid_cr() {
+    // Bare CR is kept, also at the end of the data
+    assert_eq!(decode(&[b"a\rb\r"]), b"a\rb\r");
+}
@@ -0,0 +1,342 @@
+#![cfg(feature = "otel")]
+
+//! Export of [`OtelAudit`] through in-memory tracer and meter providers.
+
+use futures_lite::future::block_on;
+use opentelemetry::metrics::noop::NoopRegistration;
+use opentelemetry::metrics::{
+    CallbackRegistration, Counter, Histogram, InstrumentProvider, Meter,
+    MeterProvider, Observer, Result, SyncCounter, SyncHistogram, Unit,
+};
+use opentelemetry::trace::{
+    Span, SpanBuilder, SpanContext, SpanKind, Status, Tracer, TracerProvider,
+};
+use opentelemetry::{global, Context, InstrumentationLibrary, KeyValue, Value};
+use std::any::Any;
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::packet;
+use crate::server::{
+    AuditRecord, AuditSink, Direction, NegotiatedOptions, OtelAudit,
+    Retransmissions, TransferOutcome,
+};
+
+/// Span that ended.
+#[derive(Debug, Clone)]
+struct ExportedSpan {
+    name: String,
+    kind: Option<SpanKind>,
+    start_time: Option<SystemTime>,
+    end_time: Option<SystemTime>,
+    attributes: Vec<KeyValue>,
+    status: Status,
+}
+
+impl ExportedSpan {
+    fn attr(&self, key: &str) -> Option<&Value> {
+        attr(&self.attributes, key)
+    }
+}
+
+/// Value that was added to a counter or recorded in a histogram.
+#[derive(Debug, Clone)]
+struct Measurement {
+    instrument: String,
+    value: f64,
+    attributes: Vec<KeyValue>,
+}
+
+/// Spans and measurements of the in-memory providers.
+#[derive(Debug, Default)]
+struct Exported {
+    spans: Vec<ExportedSpan>,
+    measurements: Vec<Measurement>,
+}
+
+impl Exported {
+    /// Measurements of `instrument` with the `tftp.direction` attribute of
+    /// `direction`.
+    fn measurements(&self, instrument: &str, direction: &str) -> Vec<f64> {
+        self.measurements
+            .iter()
+            .filter(|m| m.instrument == instrument)
+            .filter(|m| {
+                attr(&m.attributes, "tftp.direction")
+                    == Some(&Value::from(direction.to_string()))
+            })
+            .map(|m| m.value)
+            .collect()
+    }
+}
+
+type Store = Arc<Mutex<Exported>>;
+
+fn attr<'a>(attributes: &'a [KeyValue], key: &str) -> Option<&'a Value> {
+    attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| &kv.value)
+}
+
+struct MemTracerProvider(Store);
+
+impl TracerProvider for MemTracerProvider {
+    type Tracer = MemTracer;
+
+    fn library_tracer(&self, _: Arc<InstrumentationLibrary>) -> MemTracer {
+        MemTracer(self.0.clone())
+    }
+}
+
+struct MemTracer(Store);
+
+impl Tracer for MemTracer {
+    type Span = MemSpan;
+
+    fn build_with_context(&self, builder: SpanBuilder, _: &Context) -> MemSpan {
+        MemSpan {
+            store: self.0.clone(),
+            context: SpanContext::empty_context(),
+            span: ExportedSpan {
+                name: builder.name.into_owned(),
+                kind: builder.span_kind,
+                start_time: builder.start_time,
+                end_time: None,
+                attributes: builder.attributes.unwrap_or_default(),
+                status: Status::Unset,
+            },
+        }
+    }
+}
+
+struct MemSpan {
+    store: Store,
+    context: SpanContext,
+    span: ExportedSpan,
+}
+
+impl Span for MemSpan {
+    fn add_event_with_timestamp<T>(
+        &mut self,
+        _name: T,
+        _timestamp: SystemTime,
+        _attributes: Vec<KeyValue>,
+    ) where
+        T: Into<Cow<'static, str>>,
+    {
+    }
+
+    fn span_context(&self) -> &SpanContext {
+        &self.context
+    }
+
+    fn is_recording(&self) -> bool {
+        true
+    }
+
+    fn set_attribute(&mut self, attribute: KeyValue) {
+        self.span.attributes.push(attribute);
+    }
+
+    fn set_status(&mut self, status: Status) {
+        self.span.status = status;
+    }
+
+    fn update_name<T>(&mut self, new_name: T)
+    where
+        T: Into<Cow<'static, str>>,
+    {
+        self.span.name = new_name.into().into_owned();
+    }
+
+    fn end_with_timestamp(&mut self, timestamp: SystemTime) {
+        self.span.end_time = Some(timestamp);
+        self.store.lock().unwrap().spans.push(self.span.clone());
+    }
+}
+
+struct MemMeterProvider(Store);
+
+impl MeterProvider for MemMeterProvider {
+    fn versioned_meter(
+        &self,
+        _name: impl Into<Cow<'static, str>>,
+        _version: Option<impl Into<Cow<'static, str>>>,
+        _schema_url: Option<impl Into<Cow<'static, str>>>,
+        _attributes: Option<Vec<KeyValue>>,
+    ) -> Meter {
+        Meter::new(Arc::new(MemInstruments(self.0.clone())))
+    }
+}
+
+struct MemInstruments(Store);
+
+impl MemInstruments {
+    fn instrument(&self, name: Cow<'static, str>) -> Arc<MemInstrument> {
+        Arc::new(MemInstrument {
+            name: name.into_owned(),
+            store: self.0.clone(),
+        })
+    }
+}
+
+impl InstrumentProvider for MemInstruments {
+    fn u64_counter(
+        &self,
+        name: Cow<'static, str>,
+        _description: Option<Cow<'static, str>>,
+        _unit: Option<Unit>,
+    ) -> Result<Counter<u64>> {
+        Ok(Counter::new(self.instrument(name)))
+    }
+
+    fn f64_histogram(
+        &self,
+        name: Cow<'static, str>,
+        _description: Option<Cow<'static, str>>,
+        _unit: Option<Unit>,
+    ) -> Result<Histogram<f64>> {
+        Ok(Histogram::new(self.instrument(name)))
+    }
+
+    // `OtelAudit` has no observable instruments
+    fn register_callback(
+        &self,
+        _instruments: &[Arc<dyn Any>],
+        _callback: Box<dyn Fn(&dyn Observer) + Send + Sync>,
+    ) -> Result<Box<dyn CallbackRegistration>> {
+        Ok(Box::new(NoopRegistration::new()))
+    }
+}
+
+struct MemInstrument {
+    name: String,
+    store: Store,
+}
+
+impl MemInstrument {
+    fn push(&self, value: f64, attributes: &[KeyValue]) {
+        self.store.lock().unwrap().measurements.push(Measurement {
+            instrument: self.name.clone(),
+            value,
+            attributes: attributes.to_vec(),
+        });
+    }
+}
+
+impl SyncCounter<u64> for MemInstrument {
+    fn add(&self, value: u64, attributes: &[KeyValue]) {
+        self.push(value as f64, attributes);
+    }
+}
+
+impl SyncHistogram<f64> for MemInstrument {
+    fn record(&self, value: f64, attributes: &[KeyValue]) {
+        self.push(value, attributes);
+    }
+}
+
+fn record(direction: Direction, error: Option<packet::Error>) -> AuditRecord {
+    AuditRecord {
+        time: UNIX_EPOCH + Duration::from_millis(1_600_000_000_123),
+        duration: Duration::from_millis(42),
+        client: "10.0.0.1:1234".parse::<SocketAddr>().unwrap(),
+        local_addr: Some("10.0.0.2:5000".parse::<SocketAddr>().unwrap()),
+        direction,
+        options: None,
+        filename: "file".to_string(),
+        transferred: 512,
+        outcome: match error {
+            Some(_) => TransferOutcome::HandlerIo,
+            None => TransferOutcome::Completed,
+        },
+        error,
+        restarted: false,
+        retransmissions: Retransmissions::default(),
+        oversized_datagrams: 0,
+        duplicate_blocks: 0,
+    }
+}
+
+#[test]
+fn exports_spans_and_metrics() {
+    // Only this test sets the global providers
+    let store = Store::default();
+    global::set_tracer_provider(MemTracerProvider(store.clone()));
+    global::set_meter_provider(MemMeterProvider(store.clone()));
+
+    let audit = OtelAudit::new();
+
+    let mut read = record(Direction::Read, None);
+    read.restarted = true;
+    read.retransmissions = Retransmissions {
+        total: 3,
+        blocks: vec![(1, 2), (4, 1)],
+    };
+    read.options = Some(NegotiatedOptions {
+        block_size: 1024,
+        timeout: Duration::from_secs(3),
+        window_size: 4,
+        transfer_size: Some(512),
+    });
+    let write = record(Direction::Write, Some(packet::Error::DiskFull));
+
+    block_on(async {
+        audit.record(&read).await;
+        audit.record(&write).await;
+    });
+
+    let exported = store.lock().unwrap();
+
+    assert_eq!(exported.spans.len(), 2);
+
+    let span = &exported.spans[0];
+    assert_eq!(span.name, "tftp read");
+    assert_eq!(span.kind, Some(SpanKind::Server));
+    assert_eq!(span.start_time, Some(read.time));
+    assert_eq!(span.end_time, Some(read.time + read.duration));
+    assert_eq!(span.status, Status::Unset);
+    assert_eq!(span.attr("client.address"), Some(&Value::from("10.0.0.1")));
+    assert_eq!(span.attr("client.port"), Some(&Value::I64(1234)));
+    assert_eq!(span.attr("server.address"), Some(&Value::from("10.0.0.2")));
+    assert_eq!(span.attr("server.port"), Some(&Value::I64(5000)));
+    assert_eq!(span.attr("tftp.filename"), Some(&Value::from("file")));
+    assert_eq!(span.attr("tftp.transferred"), Some(&Value::I64(512)));
+    assert_eq!(span.attr("tftp.restarted"), Some(&Value::Bool(true)));
+    assert_eq!(span.attr("tftp.retransmissions"), Some(&Value::I64(3)));
+    assert_eq!(span.attr("tftp.block_size"), Some(&Value::I64(1024)));
+    assert_eq!(span.attr("tftp.window_size"), Some(&Value::I64(4)));
+    assert_eq!(span.attr("tftp.error_code"), None);
+
+    let span = &exported.spans[1];
+    assert_eq!(span.name, "tftp write");
+    assert_eq!(span.status, Status::error("Disk is full"));
+    assert_eq!(span.attr("tftp.error_code"), Some(&Value::I64(3)));
+    assert_eq!(span.attr("tftp.block_size"), None);
+
+    assert_eq!(exported.measurements("tftp.server.requests", "read"), [1.0]);
+    assert_eq!(exported.measurements("tftp.server.requests", "write"), [1.0]);
+    assert_eq!(
+        exported.measurements("tftp.server.transferred", "read"),
+        [512.0]
+    );
+    assert_eq!(exported.measurements("tftp.server.duration", "write"), [0.042]);
+    assert_eq!(exported.measurements("tftp.server.restarts", "read"), [1.0]);
+    assert!(exported.measurements("tftp.server.restarts", "write").is_empty());
+    assert_eq!(
+        exported.measurements("tftp.server.retransmissions", "read"),
+        [3.0]
+    );
+
+    // Metrics are split by outcome
+    let outcomes: Vec<_> = exported
+        .measurements
+        .iter()
+        .filter(|m| m.instrument == "tftp.server.requests")
+        .map(|m| attr(&m.attributes, "tftp.outcome").cloned())
+        .collect();
+    assert_eq!(
+        outcomes,
+        [Some(Value::from("completed")), Some(Value::from("handler_io"))]
+    );
+}
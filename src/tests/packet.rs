@@ -1,8 +1,12 @@
+#![cfg(feature = "bytes-api")]
 #![allow(clippy::octal_escapes)]
 use bytes::{Bytes, BytesMut};
+use proptest::prelude::*;
 
 use crate::error::Error;
-use crate::packet::{self, Mode, Opts, Packet, RwReq};
+use std::convert::TryFrom;
+
+use crate::packet::{self, Mode, Opts, OwnedPacket, Packet, PacketType, RwReq};
 use crate::parse::parse_opts;
 
 fn packet_to_bytes(packet: &Packet) -> Bytes {
@@ -17,7 +21,7 @@ fn check_rrq() {
 
     assert!(matches!(packet, Ok(Packet::Rrq(ref req))
                     if req == &RwReq {
-                        filename: "abc".to_string(),
+                        filename: b"abc".to_vec(),
                         mode: Mode::Netascii,
                         opts: Opts::default()
                     }
@@ -32,7 +36,7 @@ fn check_rrq() {
 
     assert!(matches!(packet, Ok(Packet::Rrq(ref req))
                     if req == &RwReq {
-                        filename: "abc".to_string(),
+                        filename: b"abc".to_vec(),
                         mode: Mode::Netascii,
                         opts: Opts::default()
                     }
@@ -58,7 +62,7 @@ fn check_rrq() {
 
     assert!(matches!(packet, Ok(Packet::Rrq(ref req))
                     if req == &RwReq {
-                        filename: "abc".to_string(),
+                        filename: b"abc".to_vec(),
                         mode: Mode::Netascii,
                         opts: Opts {
                             block_size: Some(123),
@@ -82,7 +86,7 @@ fn check_rrq() {
     let packet = Packet::decode(b"\x00\x01abc\0netascii\0blksizeX\0123\0");
     assert!(matches!(packet, Ok(Packet::Rrq(ref req))
                     if req == &RwReq {
-                        filename: "abc".to_string(),
+                        filename: b"abc".to_vec(),
                         mode: Mode::Netascii,
                         opts: Opts::default()
                     }
@@ -95,7 +99,7 @@ fn check_wrq() {
 
     assert!(matches!(packet, Ok(Packet::Wrq(ref req))
                     if req == &RwReq {
-                        filename: "abc".to_string(),
+                        filename: b"abc".to_vec(),
                         mode: Mode::Octet,
                         opts: Opts::default()
                     }
@@ -107,7 +111,7 @@ fn check_wrq() {
 
     assert!(matches!(packet, Ok(Packet::Wrq(ref req))
                     if req == &RwReq {
-                        filename: "abc".to_string(),
+                        filename: b"abc".to_vec(),
                         mode: Mode::Octet,
                         opts: Opts::default()
                     }
@@ -130,7 +134,7 @@ fn check_wrq() {
 
     assert!(matches!(packet, Ok(Packet::Wrq(ref req))
                     if req == &RwReq {
-                        filename: "abc".to_string(),
+                        filename: b"abc".to_vec(),
                         mode: Mode::Octet,
                         opts: Opts {
                             block_size: Some(123),
@@ -149,13 +153,31 @@ fn check_wrq() {
     let packet = Packet::decode(b"\x00\x02abc\0octet\0blksizeX\0123\0");
     assert!(matches!(packet, Ok(Packet::Wrq(ref req))
                     if req == &RwReq {
-                        filename: "abc".to_string(),
+                        filename: b"abc".to_vec(),
                         mode: Mode::Octet,
                         opts: Opts::default()
                     }
     ));
 }
 
+#[test]
+fn check_non_utf8_filename() {
+    let packet = Packet::decode(b"\x00\x01caf\xe9\0octet\0");
+
+    let req = match packet {
+        Ok(Packet::Rrq(ref req)) => req,
+        _ => panic!("invalid packet: {:?}", packet),
+    };
+
+    assert_eq!(req.filename, b"caf\xe9");
+    assert_eq!(req.filename_lossy(), "caf\u{fffd}");
+
+    assert_eq!(
+        packet_to_bytes(&packet.unwrap()),
+        b"\x00\x01caf\xe9\0octet\0"[..]
+    );
+}
+
 #[test]
 fn check_data() {
     let packet = Packet::decode(b"\x00\x03\x00\x09abcde");
@@ -261,6 +283,88 @@ fn check_oack() {
     ));
 }
 
+#[test]
+fn check_oack_encoding() {
+    let opts = Opts {
+        block_size: Some(65464),
+        timeout: Some(0),
+        transfer_size: Some(u64::MAX),
+        window_size: Some(10),
+    };
+    assert_eq!(
+        packet_to_bytes(&Packet::OAck(opts)),
+        &b"\x00\x06blksize\x0065464\x00timeout\x000\x00windowsize\x0010\x00tsize\x0018446744073709551615\x00"[..]
+    );
+}
+
+#[test]
+fn check_vec_encoding() {
+    let packets = [
+        Packet::Ack(7),
+        Packet::Data(3, b"abc"),
+        Packet::Error(packet::Error::FileNotFound),
+        Packet::OAck(Opts {
+            block_size: Some(1428),
+            ..Opts::default()
+        }),
+    ];
+
+    for packet in &packets {
+        let mut buf = b"prefix".to_vec();
+        packet.encode_vec(&mut buf);
+
+        assert_eq!(&buf[..6], b"prefix");
+        assert_eq!(&buf[6..], &packet_to_bytes(packet)[..]);
+        assert_eq!(packet.to_vec(), buf[6..]);
+    }
+}
+
+#[test]
+fn check_packet_type() {
+    let types = [
+        PacketType::Rrq,
+        PacketType::Wrq,
+        PacketType::Data,
+        PacketType::Ack,
+        PacketType::Error,
+        PacketType::OAck,
+    ];
+
+    for (i, ty) in types.iter().enumerate() {
+        let code = i as u16 + 1;
+        assert_eq!(PacketType::try_from(code).unwrap(), *ty);
+        assert_eq!(u16::from(*ty), code);
+    }
+
+    assert!(matches!(PacketType::try_from(0), Err(Error::InvalidPacket)));
+    assert!(matches!(PacketType::try_from(7), Err(Error::InvalidPacket)));
+}
+
+proptest! {
+    #[test]
+    fn check_owned_round_trip(owned in any::<OwnedPacket>()) {
+        let packet = owned.as_packet();
+        let bytes = packet.to_vec();
+
+        let decoded = OwnedPacket::try_from(&bytes[..])
+            .unwrap_or_else(|_| panic!("failed to decode {:?}", packet));
+        prop_assert_eq!(&decoded, &owned);
+        prop_assert_eq!(decoded.as_packet(), packet.clone());
+        prop_assert_eq!(decoded.packet_type(), packet.packet_type());
+        prop_assert_eq!(decoded.to_vec(), bytes.clone());
+
+        prop_assert_eq!(Packet::try_from(&bytes[..]).unwrap(), packet);
+    }
+}
+
+#[test]
+fn check_owned_decode() {
+    let data = b"\x00\x03\x00\x01abc";
+    let owned = OwnedPacket::decode(data).unwrap();
+    assert_eq!(owned, OwnedPacket::Data(1, b"abc".to_vec()));
+    assert!(matches!(OwnedPacket::decode(b"\x00"), Err(Error::InvalidPacket)));
+}
+
 #[test]
 fn check_packet() {
     let packet = Packet::decode(b"\x00\x07");
@@ -347,3 +451,34 @@ fn check_timeout_boundaries() {
         }
     );
 }
+
+proptest! {
+    #[test]
+    fn check_arbitrary_round_trip(owned in any::<OwnedPacket>()) {
+        let packet = owned.as_packet();
+        let bytes = packet_to_bytes(&packet);
+
+        let decoded = Packet::decode(&bytes[..])
+            .unwrap_or_else(|_| panic!("failed to decode {:?}", packet));
+        prop_assert_eq!(&decoded, &packet);
+        prop_assert_eq!(packet_to_bytes(&decoded), bytes);
+    }
+
+    #[test]
+    fn check_arbitrary_opts_round_trip(opts in any::<Opts>()) {
+        let bytes = packet_to_bytes(&Packet::OAck(opts.clone()));
+
+        let (rest, parsed) = parse_opts(&bytes[2..]).unwrap();
+        prop_assert!(rest.is_empty());
+        prop_assert_eq!(parsed, opts);
+    }
+}
+
+#[test]
+fn check_quickcheck_round_trip() {
+    fn round_trip(owned: OwnedPacket) -> bool {
+        OwnedPacket::decode(&owned.to_vec()).ok() == Some(owned)
+    }
+
+    quickcheck::quickcheck(round_trip as fn(OwnedPacket) -> bool);
+}
@@ -0,0 +1,60 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::packet::{self, Packet};
+use crate::server::{Handler, TftpServerBuilder, TransferOutcome};
+
+/// Handler that panics when `panic` is requested.
+struct PanicHandler(MemHandler);
+
+#[crate::async_trait]
+impl Handler for PanicHandler {
+    type Reader = <MemHandler as Handler>::Reader;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        if path == Path::new("panic") {
+            panic!("handler bug");
+        }
+
+        self.0.read_req_open(client, path).await
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.0.write_req_open(client, path, size).await
+    }
+}
+
+#[test]
+fn handler_panic_is_contained() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = PanicHandler(MemHandler::new(content(100)));
+    let builder =
+        TftpServerBuilder::with_handler(handler).audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "panic").await;
+        let error = packet::Error::Msg("Internal server error".to_string());
+        client.expect(Packet::Error(error)).await;
+
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.filename, "panic");
+        assert_eq!(record.outcome, TransferOutcome::Panicked);
+
+        // Server and handler keep working
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(Duration::from_secs(5)).await, Some((1, 100)));
+    });
+}
@@ -0,0 +1,152 @@
+use async_io::Async;
+use futures_lite::future;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet::Packet;
+use crate::server::TftpServerBuilder;
+use crate::test_util::{
+    replay_client, replay_server, Capture, MockHandler, RequestBuilder,
+};
+
+/// Encode datagrams as pcap file of Ethernet frames, one millisecond apart.
+fn pcap(datagrams: &[(&str, &str, Vec<u8>)]) -> Vec<u8> {
+    // Little endian header with microsecond timestamps
+    let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+    file.extend_from_slice(&[0; 8]);
+    file.extend_from_slice(&65535u32.to_le_bytes());
+    file.extend_from_slice(&1u32.to_le_bytes());
+
+    for (i, (src, dst, data)) in datagrams.iter().enumerate() {
+        let src: SocketAddr = src.parse().unwrap();
+        let dst: SocketAddr = dst.parse().unwrap();
+
+        let ip = |addr: SocketAddr| match addr.ip() {
+            IpAddr::V4(ip) => ip.octets(),
+            IpAddr::V6(_) => unreachable!(),
+        };
+
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+
+        let total_len = (20 + 8 + data.len()) as u16;
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&total_len.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+        frame.extend_from_slice(&ip(src));
+        frame.extend_from_slice(&ip(dst));
+
+        frame.extend_from_slice(&src.port().to_be_bytes());
+        frame.extend_from_slice(&dst.port().to_be_bytes());
+        frame.extend_from_slice(&((8 + data.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(data);
+
+        file.extend_from_slice(&1u32.to_le_bytes());
+        file.extend_from_slice(&(i as u32 * 1000).to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&frame);
+    }
+
+    file
+}
+
+fn data(block_id: u16, len: usize) -> Vec<u8> {
+    Packet::Data(block_id, &content(len)).to_vec()
+}
+
+fn ack(block_id: u16) -> Vec<u8> {
+    Packet::Ack(block_id).to_vec()
+}
+
+fn captured_session() -> Capture {
+    let client = "10.0.0.1:2000";
+    let server = "10.0.0.2:3000";
+
+    let file = pcap(&[
+        (
+            client,
+            "10.0.0.2:69",
+            Packet::Rrq(RequestBuilder::new("kernel").build()).to_vec(),
+        ),
+        (server, client, data(1, 512)),
+        (client, server, ack(1)),
+        (server, client, data(2, 88)),
+        (client, server, ack(2)),
+    ]);
+
+    Capture::parse(&file).unwrap()
+}
+
+#[test]
+fn parse_capture() {
+    let capture = captured_session();
+    let packets = capture.packets();
+
+    assert_eq!(packets.len(), 5);
+    assert_eq!(packets[0].src, "10.0.0.1:2000".parse().unwrap());
+    assert_eq!(packets[0].dst, "10.0.0.2:69".parse().unwrap());
+    assert_eq!(packets[4].time, Duration::from_millis(4));
+    assert_eq!(packets[4].data, ack(2));
+
+    assert_eq!(capture.clients(), ["10.0.0.1:2000".parse().unwrap()]);
+    assert_eq!(capture.server_packets().len(), 2);
+
+    assert!(Capture::parse(b"not a capture").is_err());
+}
+
+#[test]
+fn replay_captured_session() {
+    let handler = MockHandler::new().file("kernel", content(600));
+    let builder = TftpServerBuilder::with_handler(handler.clone());
+    let capture = captured_session();
+
+    let replies = run_with_server(builder, |addr| async move {
+        let wait = Duration::from_secs(1);
+        replay_client(&capture, addr, wait).await.unwrap()
+    });
+
+    // Replies match the captured session
+    let blocks: Vec<_> = replies
+        .iter()
+        .map(|reply| match Packet::decode(reply) {
+            Ok(Packet::Data(id, data)) => (id, data.len()),
+            packet => panic!("unexpected packet: {:?}", packet),
+        })
+        .collect();
+    assert_eq!(blocks, [(1, 512), (2, 88)]);
+    assert_eq!(handler.requests().len(), 1);
+}
+
+#[test]
+fn replay_captured_server() {
+    let capture = captured_session();
+
+    let (data, requests) = future::block_on(async {
+        let listener = Async::<UdpSocket>::bind(([127, 0, 0, 1], 0)).unwrap();
+        let addr = listener.get_ref().local_addr().unwrap();
+        let wait = Duration::from_secs(1);
+
+        let client = TftpClient::new(addr);
+        future::zip(
+            Box::pin(client.read_to_vec("kernel")),
+            Box::pin(replay_server(&capture, &listener, wait)),
+        )
+        .await
+    });
+
+    assert_eq!(data.unwrap().len(), 600);
+
+    // Client replied like the captured one
+    let requests = requests.unwrap();
+    assert_eq!(requests.len(), 3);
+    match Packet::decode(&requests[0]) {
+        Ok(Packet::Rrq(req)) => assert_eq!(req.filename, b"kernel"),
+        packet => panic!("unexpected packet: {:?}", packet),
+    }
+    assert_eq!(requests[1], ack(1));
+    assert_eq!(requests[2], ack(2));
+}
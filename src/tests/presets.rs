@@ -0,0 +1,57 @@
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, Packet};
+use crate::server::TftpServerBuilder;
+use crate::test_util::RequestBuilder;
+
+/// Block size that the server acknowledges to a client that requests 8192.
+fn negotiated_block_size<F>(preset: F) -> Option<u16>
+where
+    F: FnOnce(TftpServerBuilder<MemHandler>) -> TftpServerBuilder<MemHandler>,
+{
+    let handler = MemHandler::new(content(10000));
+    let builder = preset(TftpServerBuilder::with_handler(handler));
+
+    run_with_server(builder, |addr| async move {
+        let rrq = RequestBuilder::new("test").block_size(8192).build();
+        let reply = request(addr, Packet::Rrq(rrq)).await;
+
+        match Packet::decode(&reply) {
+            Ok(Packet::OAck(opts)) => opts.block_size,
+            packet => panic!("unexpected packet: {:?}", packet),
+        }
+    })
+}
+
+#[test]
+fn pxe_defaults() {
+    let block_size = negotiated_block_size(|b| b.pxe_defaults());
+    assert_eq!(block_size, Some(1468));
+
+    // Settings of the preset can be overridden
+    let block_size =
+        negotiated_block_size(|b| b.pxe_defaults().max_block_size(1024));
+    assert_eq!(block_size, Some(1024));
+}
+
+#[test]
+fn firmware_upload_defaults() {
+    let block_size = negotiated_block_size(|b| b.firmware_upload_defaults());
+    assert_eq!(block_size, Some(1428));
+
+    let handler = MemHandler::new(Vec::new());
+    let builder =
+        TftpServerBuilder::with_handler(handler).firmware_upload_defaults();
+
+    run_with_server(builder, |addr| async move {
+        let wrq = RequestBuilder::new("firmware")
+            .transfer_size(1024 * 1024 * 1024)
+            .build();
+        let reply = request(addr, Packet::Wrq(wrq)).await;
+
+        assert_eq!(
+            Packet::decode(&reply).unwrap(),
+            Packet::Error(packet::Error::DiskFull)
+        );
+    });
+}
@@ -0,0 +1,80 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::packet;
+use crate::server::{Direction, Handler, Priority, TftpServerBuilder};
+
+const FILE_SIZE: usize = 512 * 3 + 10;
+
+/// Handler that assigns priority according to the filename.
+struct PriorityHandler(MemHandler);
+
+#[crate::async_trait]
+impl Handler for PriorityHandler {
+    type Reader = <MemHandler as Handler>::Reader;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.0.read_req_open(client, path).await
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.0.write_req_open(client, path, size).await
+    }
+
+    async fn transfer_priority(
+        &mut self,
+        _client: &SocketAddr,
+        path: &Path,
+        _direction: Direction,
+    ) -> Priority {
+        match path.to_str() {
+            Some("high") => Priority::High,
+            Some("low") => Priority::Low,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+#[test]
+fn pending_transfers_start_in_priority_order() {
+    let handler = PriorityHandler(MemHandler::new(content(FILE_SIZE)));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_secs(3))
+        .max_concurrent_transfers(1);
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(200);
+
+        let mut running = RawClient::rrq(addr, "normal").await;
+        assert!(running.recv(wait).await.is_some());
+
+        // Only one transfer runs at a time
+        let mut low = RawClient::rrq(addr, "low").await;
+        assert!(low.recv(wait).await.is_none());
+
+        let mut high = RawClient::rrq(addr, "high").await;
+        assert!(high.recv(wait).await.is_none());
+
+        running.finish().await;
+
+        // `high` is served first, even though `low` came earlier
+        assert!(high.recv(wait).await.is_some());
+        assert!(low.recv(wait).await.is_none());
+
+        high.finish().await;
+        low.finish().await;
+    });
+}
@@ -0,0 +1,81 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, OwnedPacket, Packet};
+use crate::server::TftpServerBuilder;
+use crate::test_util::RequestBuilder;
+
+const WAIT: Duration = Duration::from_millis(500);
+
+fn builder() -> TftpServerBuilder<MemHandler> {
+    let handler = MemHandler::new(content(512 * 2 + 10));
+    TftpServerBuilder::with_handler(handler).timeout(Duration::from_secs(3))
+}
+
+fn req() -> RequestBuilder {
+    RequestBuilder::new("test").block_size(1024)
+}
+
+/// Reject the OACK that `client` receives. Returns the address of the
+/// transfer.
+async fn reject_oack(client: &mut RawClient) -> SocketAddr {
+    let oack = client.recv_packet(WAIT).await;
+    assert!(matches!(oack, Some(OwnedPacket::OAck(_))), "no OACK received");
+
+    let rejection = Packet::Error(packet::Error::OptionsNegotiationFailed);
+    client.send(rejection).await;
+
+    client.server_addr().unwrap()
+}
+
+#[test]
+fn rejected_rrq_is_terminated_by_default() {
+    run_with_server(builder(), |addr| async move {
+        let mut client = RawClient::rrq_with(addr, req()).await;
+        reject_oack(&mut client).await;
+
+        // The transfer ends without an error reply
+        assert!(client.is_idle(WAIT).await);
+    });
+}
+
+#[test]
+fn rejected_rrq_is_restarted() {
+    let builder = builder().downgrade_rejected_options();
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq_with(addr, req()).await;
+        let peer = reject_oack(&mut client).await;
+
+        // Default block size is used from the same address
+        for block_id in 1..=3 {
+            let len = if block_id < 3 {
+                512
+            } else {
+                10
+            };
+            assert_eq!(client.recv(WAIT).await, Some((block_id, len)));
+            assert_eq!(client.server_addr(), Some(peer));
+
+            client.ack().await;
+        }
+    });
+}
+
+#[test]
+fn rejected_wrq_is_restarted() {
+    let builder = builder().downgrade_rejected_options();
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::wrq_with(addr, req()).await;
+        let peer = reject_oack(&mut client).await;
+
+        client.expect(Packet::Ack(0)).await;
+        assert_eq!(client.server_addr(), Some(peer));
+
+        client.send(Packet::Data(1, b"short")).await;
+        client.expect(Packet::Ack(1)).await;
+    });
+}
@@ -0,0 +1,193 @@
+use futures_lite::future::block_on;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, Mode, OwnedPacket, Packet};
+use crate::server::{
+    AuditRecord, AuditSink, Authorizer, BlockSizePolicy, Direction,
+    RejectionReason, RejectionStats, TftpServerBuilder, TransferOutcome,
+};
+use crate::test_util::RequestBuilder;
+
+struct DenySecret;
+
+#[crate::async_trait]
+impl Authorizer for DenySecret {
+    async fn authorize(
+        &self,
+        _client: &SocketAddr,
+        path: &Path,
+        _direction: Direction,
+    ) -> Result<(), packet::Error> {
+        match path == Path::new("secret") {
+            true => Err(packet::Error::PermissionDenied),
+            false => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn rejection_stats() {
+    let stats = RejectionStats::new();
+    let builder = TftpServerBuilder::with_handler(MemHandler::new(content(10)))
+        .authorizer(DenySecret)
+        .min_block_size(64, BlockSizePolicy::Reject)
+        .audit(stats.clone());
+    let handle = stats.clone();
+
+    run_with_server(builder, |addr| async move {
+        let requests = [
+            RequestBuilder::new("secret"),
+            RequestBuilder::new("secret"),
+            RequestBuilder::new("file").block_size(8),
+            RequestBuilder::new("file").mode(Mode::Mail),
+        ];
+
+        for req in requests {
+            let mut client = RawClient::rrq_with(addr, req).await;
+            let reply = client.recv_packet(Duration::from_secs(5)).await;
+            assert!(matches!(reply, Some(OwnedPacket::Error(_))));
+        }
+
+        // Completed transfers are not counted
+        let mut client = RawClient::rrq(addr, "file").await;
+        assert_eq!(client.recv(Duration::from_secs(5)).await, Some((1, 10)));
+        client.finish().await;
+
+        wait_until(Duration::from_secs(5), || {
+            handle.by_client().values().sum::<u64>() == 4
+        })
+        .await;
+    });
+
+    assert_eq!(stats.count(RejectionReason::AccessDenied), 2);
+    assert_eq!(stats.count(RejectionReason::BadOptions), 1);
+    assert_eq!(stats.count(RejectionReason::UnsupportedMode), 1);
+    assert_eq!(stats.count(RejectionReason::NotFound), 0);
+
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let by_client = stats.by_client();
+    assert_eq!(by_client.len(), 3);
+    assert_eq!(by_client[&(localhost, RejectionReason::AccessDenied)], 2);
+
+    stats.reset();
+    assert!(stats.by_client().is_empty());
+}
+
+#[test]
+fn limits_are_counted() {
+    let stats = RejectionStats::new();
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(10 * 512)))
+            .max_filename_len(8)
+            .max_pending_handshakes_per_ip(1)
+            .audit(stats.clone());
+    let handle = stats.clone();
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(200);
+
+        let mut client = RawClient::rrq(addr, "too_long_name").await;
+        let error = packet::Error::IllegalOperation;
+        client.expect(Packet::Error(error)).await;
+
+        // The first handshake is pending, so the second request is dropped
+        let mut pending = RawClient::rrq(addr, "test").await;
+        assert!(pending.recv(wait).await.is_some());
+        let mut dropped = RawClient::rrq(addr, "test").await;
+        assert!(dropped.recv(wait).await.is_none());
+
+        wait_until(Duration::from_secs(5), || {
+            handle.count(RejectionReason::Dropped) == 1
+                && handle.count(RejectionReason::OversizedRequest) == 1
+        })
+        .await;
+    });
+
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let by_client = stats.by_client();
+    assert_eq!(by_client[&(localhost, RejectionReason::Dropped)], 1);
+    assert_eq!(by_client[&(localhost, RejectionReason::OversizedRequest)], 1);
+}
+
+fn not_found(ip: Ipv4Addr) -> AuditRecord {
+    AuditRecord {
+        time: SystemTime::now(),
+        duration: Duration::ZERO,
+        client: SocketAddr::new(ip.into(), 69),
+        local_addr: None,
+        direction: Direction::Read,
+        options: None,
+        filename: "test".to_string(),
+        transferred: 0,
+        error: Some(packet::Error::FileNotFound),
+        outcome: TransferOutcome::Rejected,
+        retransmissions: Default::default(),
+        oversized_datagrams: 0,
+        duplicate_blocks: 0,
+        restarted: false,
+    }
+}
+
+#[test]
+fn rejection_stats_clients_are_capped() {
+    let stats = RejectionStats::new();
+    let frequent = Ipv4Addr::new(10, 0, 0, 1);
+
+    block_on(async {
+        for _ in 0..5 {
+            stats.record(&not_found(frequent)).await;
+        }
+
+        for i in 0..2 * RejectionStats::MAX_CLIENTS as u32 {
+            let ip = Ipv4Addr::from(0x0b00_0000 + i);
+            stats.record(&not_found(ip)).await;
+        }
+    });
+
+    let total = 5 + 2 * RejectionStats::MAX_CLIENTS as u64;
+    assert_eq!(stats.count(RejectionReason::NotFound), total);
+
+    // Clients with the lowest counts are evicted first
+    let by_client = stats.by_client();
+    assert!(by_client.len() <= RejectionStats::MAX_CLIENTS);
+    assert_eq!(by_client[&(frequent.into(), RejectionReason::NotFound)], 5);
+}
+
+#[test]
+fn rejection_stats_from_threads() {
+    let stats = RejectionStats::new();
+
+    let threads: Vec<_> = (0..4u8)
+        .map(|i| {
+            let stats = stats.clone();
+
+            thread::spawn(move || {
+                let record = not_found(Ipv4Addr::new(10, 0, 0, i));
+
+                for _ in 0..1000 {
+                    block_on(stats.record(&record));
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(stats.count(RejectionReason::NotFound), 4000);
+    assert_eq!(stats.count(RejectionReason::AccessDenied), 0);
+
+    let by_client = stats.by_client();
+    assert_eq!(by_client.len(), 4);
+    assert!(by_client.values().all(|&count| count == 1000));
+
+    stats.reset();
+    assert_eq!(stats.count(RejectionReason::NotFound), 0);
+    assert!(stats.by_client().is_empty());
+}
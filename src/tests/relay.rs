@@ -0,0 +1,173 @@
+use futures_lite::future;
+use futures_lite::io::{Cursor, Sink};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet;
+use crate::server::handlers::RelayHandler;
+use crate::server::{Handler, TftpServerBuilder};
+
+#[test]
+fn relay() {
+    let handler = MemHandler::new(content(5000));
+    let reads = handler.reads();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |upstream| async move {
+        let relay = RelayHandler::new(upstream);
+
+        with_server(
+            TftpServerBuilder::with_handler(relay),
+            |addr| async move {
+                let client = TftpClient::new(addr);
+
+                for _ in 0..2 {
+                    // Size is not known before the upstream transfer
+                    let stream = client.read("test").await.unwrap();
+                    assert_eq!(stream.transfer_size(), None);
+                    drop(stream);
+
+                    let data = client.read_to_vec("test").await.unwrap();
+                    assert_eq!(data, content(5000));
+                }
+            },
+        )
+        .await;
+    });
+
+    // Every request reached upstream
+    assert_eq!(reads.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn relay_cache() {
+    let handler = MemHandler::new(content(5000));
+    let reads = handler.reads();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |upstream| async move {
+        let relay = RelayHandler::new(upstream).cache(10_000);
+        let handle = relay.clone();
+
+        with_server(
+            TftpServerBuilder::with_handler(relay),
+            |addr| async move {
+                let client = TftpClient::new(addr);
+
+                for _ in 0..3 {
+                    let data = client.read_to_vec("test").await.unwrap();
+                    assert_eq!(data, content(5000));
+                }
+                assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+                handle.clear_cache();
+                let data = client.read_to_vec("test").await.unwrap();
+                assert_eq!(data, content(5000));
+                assert_eq!(reads.load(Ordering::SeqCst), 2);
+            },
+        )
+        .await;
+    });
+}
+
+#[test]
+fn relay_cache_full() {
+    let handler = MemHandler::new(content(5000));
+    let reads = handler.reads();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |upstream| async move {
+        let relay = RelayHandler::new(upstream).cache(4000);
+
+        with_server(
+            TftpServerBuilder::with_handler(relay),
+            |addr| async move {
+                let client = TftpClient::new(addr);
+
+                for _ in 0..2 {
+                    let data = client.read_to_vec("test").await.unwrap();
+                    assert_eq!(data, content(5000));
+                }
+            },
+        )
+        .await;
+    });
+
+    // File does not fit in the cache
+    assert_eq!(reads.load(Ordering::SeqCst), 2);
+}
+
+/// Handler that serves `content`, and opens `slow` after `release`
+/// receives.
+struct SlowHandler {
+    content: Vec<u8>,
+    release: async_channel::Receiver<()>,
+}
+
+#[crate::async_trait]
+impl Handler for SlowHandler {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = Sink;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        if path == Path::new("slow") {
+            self.release.recv().await.unwrap();
+        }
+
+        let len = self.content.len() as u64;
+        Ok((Cursor::new(self.content.clone()), Some(len)))
+    }
+
+    async fn write_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        Err(packet::Error::IllegalOperation)
+    }
+}
+
+#[test]
+fn slow_upstream_does_not_block_cached_files() {
+    let (release_tx, release) = async_channel::unbounded();
+    let handler = SlowHandler {
+        content: content(1000),
+        release,
+    };
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |upstream| async move {
+        let relay = RelayHandler::new(upstream).cache(10_000);
+
+        with_server(
+            TftpServerBuilder::with_handler(relay),
+            |addr| async move {
+                let client = TftpClient::new(addr);
+                let data = client.read_to_vec("test").await.unwrap();
+                assert_eq!(data, content(1000));
+
+                let slow = client.read_to_vec("slow");
+                let cached = async {
+                    let data = client.read_to_vec("test").await.unwrap();
+                    release_tx.send(()).await.unwrap();
+                    data
+                };
+
+                let (slow, cached) =
+                    future::zip(Box::pin(slow), Box::pin(cached)).await;
+                assert_eq!(slow.unwrap(), content(1000));
+                assert_eq!(cached, content(1000));
+            },
+        )
+        .await;
+    });
+}
@@ -0,0 +1,75 @@
+#![cfg(target_os = "linux")]
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::server::TftpServerBuilder;
+
+#[test]
+fn reply_from_listen_port() {
+    let handler = MemHandler::new(content(2000));
+    let builder =
+        TftpServerBuilder::with_handler(handler).reply_from_listen_port();
+
+    run_with_server(builder, |addr| async move {
+        let mut first = RawClient::rrq(addr, "test").await;
+        let mut second = RawClient::rrq(addr, "test").await;
+
+        // Concurrent transfers share the port
+        assert_eq!(first.recv(Duration::from_secs(5)).await, Some((1, 512)));
+        assert_eq!(second.recv(Duration::from_secs(5)).await, Some((1, 512)));
+        assert_eq!(first.server_addr(), Some(addr));
+        assert_eq!(second.server_addr(), Some(addr));
+
+        first.finish().await;
+        second.finish().await;
+
+        // Listening socket still receives requests
+        let mut third = RawClient::rrq(addr, "test").await;
+        assert!(third.recv(Duration::from_secs(5)).await.is_some());
+        assert_eq!(third.server_addr(), Some(addr));
+        third.finish().await;
+    });
+}
+
+#[test]
+fn transfer_ip() {
+    let ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+    let handler = MemHandler::new(content(100));
+    let builder = TftpServerBuilder::with_handler(handler).transfer_ip(ip);
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert!(client.recv(Duration::from_secs(5)).await.is_some());
+        assert_eq!(client.server_addr().map(|a| a.ip()), Some(ip));
+        client.finish().await;
+    });
+}
+
+#[test]
+fn transfer_port_range() {
+    let handler = MemHandler::new(content(100));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .transfer_port_range(41000..=41999);
+
+    let ports = run_with_server(builder, |addr| async move {
+        let mut ports = Vec::new();
+
+        for _ in 0..8 {
+            let mut client = RawClient::rrq(addr, "test").await;
+            assert!(client.recv(Duration::from_secs(5)).await.is_some());
+            ports.push(client.server_addr().unwrap().port());
+            client.finish().await;
+        }
+
+        ports
+    });
+
+    assert!(ports.iter().all(|port| (41000..=41999).contains(port)));
+
+    // Ports are not sequential
+    let sequential = ports.windows(2).all(|w| w[1] == w[0].wrapping_add(1));
+    assert!(!sequential, "sequential ports: {:?}", ports);
+}
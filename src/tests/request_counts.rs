@@ -0,0 +1,202 @@
+use async_channel::Sender;
+use futures_lite::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::packet::{self, Packet};
+use crate::server::counter::{RequestCounter, MAX_ENTRIES};
+use crate::server::{
+    Authorizer, Direction, Handler, TftpServerBuilder, TransferContext,
+    TransferState,
+};
+use crate::test_util::RequestBuilder;
+
+/// Handler that serves a fallback after the third request of a file.
+struct FallbackHandler {
+    inner: MemHandler,
+    started: Sender<TransferContext>,
+}
+
+#[crate::async_trait]
+impl Handler for FallbackHandler {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        panic!("request was not counted");
+    }
+
+    async fn read_req_open_counted(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        count: u32,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        if count > 2 {
+            return Ok((Cursor::new(b"fallback".to_vec()), None));
+        }
+
+        self.inner.read_req_open(client, path).await
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.inner.write_req_open(client, path, size).await
+    }
+
+    async fn transfer_started(
+        &mut self,
+        ctx: &TransferContext,
+        _state: &mut TransferState,
+    ) {
+        self.started.send(ctx.clone()).await.unwrap();
+    }
+}
+
+#[test]
+fn fallback_after_repeated_requests() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = FallbackHandler {
+        inner: MemHandler::new(content(100)),
+        started: tx,
+    };
+    let builder = TftpServerBuilder::with_handler(handler)
+        .count_requests(Duration::from_secs(10));
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_secs(5);
+
+        for count in 1..=3 {
+            // Every attempt comes from a new port
+            let mut client = RawClient::rrq(addr, "test").await;
+            let (_, len) = client.recv(wait).await.unwrap();
+            client.finish().await;
+
+            let expected = if count > 2 {
+                "fallback".len()
+            } else {
+                100
+            };
+            assert_eq!(len, expected);
+
+            let ctx = rx.recv().await.unwrap();
+            assert_eq!(ctx.request_count, Some(count));
+        }
+
+        // Files are counted separately
+        let mut client = RawClient::rrq(addr, "other").await;
+        assert_eq!(client.recv(wait).await, Some((1, 100)));
+        client.finish().await;
+        assert_eq!(rx.recv().await.unwrap().request_count, Some(1));
+    });
+}
+
+#[test]
+fn count_starts_over_after_window() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = FallbackHandler {
+        inner: MemHandler::new(content(100)),
+        started: tx,
+    };
+    let builder = TftpServerBuilder::with_handler(handler)
+        .count_requests(Duration::from_millis(100));
+
+    run_with_server(builder, |addr| async move {
+        for _ in 0..3 {
+            let mut client = RawClient::rrq(addr, "test").await;
+            client.recv(Duration::from_secs(5)).await.unwrap();
+            client.finish().await;
+            assert_eq!(rx.recv().await.unwrap().request_count, Some(1));
+
+            async_io::Timer::after(Duration::from_millis(200)).await;
+        }
+    });
+}
+
+/// Authorizer that denies the first requests.
+struct DenyFirst(AtomicU32);
+
+#[crate::async_trait]
+impl Authorizer for DenyFirst {
+    async fn authorize(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+    ) -> Result<(), packet::Error> {
+        let denied = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                n.checked_sub(1)
+            })
+            .is_ok();
+
+        match denied {
+            true => Err(packet::Error::PermissionDenied),
+            false => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn denied_requests_are_not_counted() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = FallbackHandler {
+        inner: MemHandler::new(content(100)),
+        started: tx,
+    };
+    let builder = TftpServerBuilder::with_handler(handler)
+        .authorizer(DenyFirst(AtomicU32::new(3)))
+        .count_requests(Duration::from_secs(10));
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_secs(5);
+
+        for _ in 0..3 {
+            let rrq = Packet::Rrq(RequestBuilder::new("test").build());
+            let reply = request(addr, rrq).await;
+            assert!(matches!(
+                Packet::decode(&reply),
+                Ok(Packet::Error(packet::Error::PermissionDenied))
+            ));
+        }
+
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(wait).await, Some((1, 100)));
+        client.finish().await;
+        assert_eq!(rx.recv().await.unwrap().request_count, Some(1));
+    });
+}
+
+#[test]
+fn counted_files_are_capped() {
+    let counter = RequestCounter::new(Duration::from_secs(60));
+    let ip = |n: u32| IpAddr::V4(Ipv4Addr::from(0x0a00_0000 + n));
+
+    assert_eq!(counter.count(ip(0), b"test"), 1);
+
+    // Files of many clients, while the first one keeps requesting
+    let clients = 2 * MAX_ENTRIES as u32;
+    for n in 1..=clients {
+        counter.count(ip(n), b"test");
+        counter.count(ip(0), b"test");
+    }
+    assert!(counter.len() <= MAX_ENTRIES);
+
+    // Least recently requested files are forgotten, not the recent ones
+    assert_eq!(counter.count(ip(0), b"test"), clients + 2);
+    assert_eq!(counter.count(ip(clients), b"test"), 2);
+    assert_eq!(counter.count(ip(1), b"test"), 1);
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet;
+use crate::server::{BootSessionResolver, TftpServerBuilder};
+use crate::Error;
+
+/// Serves `boot` from the config of the client, like a lease database.
+struct Leases;
+
+#[crate::async_trait]
+impl BootSessionResolver for Leases {
+    async fn resolve(
+        &self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<Option<PathBuf>, packet::Error> {
+        if path != Path::new("boot") {
+            return Ok(None);
+        }
+
+        match client.ip().to_string().as_str() {
+            "127.0.0.1" => Ok(Some(PathBuf::from("hosts/known.img"))),
+            _ => Err(packet::Error::FileNotFound),
+        }
+    }
+}
+
+#[test]
+fn boot_session_resolver() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("hosts")).unwrap();
+    fs::write(dir.path().join("hosts/known.img"), content(700)).unwrap();
+    fs::write(dir.path().join("other"), content(10)).unwrap();
+
+    let builder = TftpServerBuilder::with_dir_ro(dir.path())
+        .unwrap()
+        .boot_session_resolver(Leases);
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+        assert_eq!(client.read_to_vec("boot").await.unwrap(), content(700));
+        assert_eq!(client.read_to_vec("other").await.unwrap(), content(10));
+
+        match client.read_to_vec("missing").await {
+            Err(Error::Remote(e)) => {
+                assert_eq!(e.kind(), packet::Error::FileNotFound)
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    });
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet;
+use crate::server::{TftpServerBuilder, TransferOutcome};
+
+const FILE_SIZE: usize = 512 * 3 + 10;
+
+#[test]
+fn restart_from_new_port() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_secs(3))
+        .max_concurrent_transfers(1)
+        .restart_window(Duration::from_secs(5))
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(200);
+
+        let mut abandoned = RawClient::rrq(addr, "test").await;
+        assert!(abandoned.recv(wait).await.is_some());
+
+        // Transfer of `abandoned` is aborted, so the restarted request
+        // starts without waiting for a transfer slot
+        let mut restarted = RawClient::rrq(addr, "test").await;
+        assert!(restarted.recv(wait).await.is_some());
+        restarted.finish().await;
+
+        let record = rx.recv().await.unwrap();
+        assert!(!record.restarted);
+        assert!(matches!(record.error, Some(packet::Error::Msg(_))));
+        assert_eq!(record.outcome, TransferOutcome::Cancelled);
+
+        let record = rx.recv().await.unwrap();
+        assert!(record.restarted);
+        assert_eq!(record.transferred, FILE_SIZE as u64);
+        assert_eq!(record.error, None);
+    });
+}
+
+#[test]
+fn different_file_is_not_restart() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_secs(3))
+        .restart_window(Duration::from_secs(5))
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(200);
+
+        let mut first = RawClient::rrq(addr, "kernel").await;
+        assert!(first.recv(wait).await.is_some());
+
+        let mut second = RawClient::rrq(addr, "initrd").await;
+        assert!(second.recv(wait).await.is_some());
+
+        first.finish().await;
+        second.finish().await;
+
+        for _ in 0..2 {
+            let record = rx.recv().await.unwrap();
+            assert!(!record.restarted);
+            assert_eq!(record.error, None);
+        }
+    });
+}
+
+#[test]
+fn restarts_are_not_detected_by_default() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_secs(3))
+        .audit(ChannelSink(tx));
+
+    run_with_server(builder, |addr| async move {
+        let wait = Duration::from_millis(200);
+
+        let mut first = RawClient::rrq(addr, "test").await;
+        assert!(first.recv(wait).await.is_some());
+
+        let mut second = RawClient::rrq(addr, "test").await;
+        assert!(second.recv(wait).await.is_some());
+
+        first.finish().await;
+        second.finish().await;
+
+        for _ in 0..2 {
+            let record = rx.recv().await.unwrap();
+            assert!(!record.restarted);
+            assert_eq!(record.error, None);
+        }
+    });
+}
@@ -0,0 +1,161 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::client::{TestClient, TransferLog};
+use super::faults::{FaultStats, Faults, FaultySocket};
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::Opts;
+use crate::server::TftpServerBuilder;
+use crate::test_util::MemoryNetwork;
+
+const FILE_SIZE: usize = 512 * 40 + 123;
+
+fn builder(handler: MemHandler) -> TftpServerBuilder<MemHandler> {
+    TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_millis(20))
+        .max_send_retries(50)
+}
+
+async fn read(
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    faults: Faults,
+) -> (TransferLog, FaultStats) {
+    let socket = FaultySocket::new(memory_client(&network), faults);
+    let mut client = TestClient::new(socket, addr);
+
+    let (data, log) =
+        client.read("test", Opts::default()).await.expect("transfer failed");
+
+    assert_eq!(data, content(FILE_SIZE));
+    (log, client.socket().stats().clone())
+}
+
+#[test]
+fn read_without_faults() {
+    let handler = MemHandler::new(content(FILE_SIZE));
+
+    let (log, stats) =
+        run_with_memory_server(builder(handler), |network, addr| async move {
+            read(network, addr, Faults::none()).await
+        });
+
+    assert_eq!(stats, FaultStats::default());
+    assert_eq!(log.retransmissions(), 0);
+}
+
+#[test]
+fn read_with_loss() {
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let faults = Faults::none().loss(0.2).seed(1);
+
+    let (log, stats) =
+        run_with_memory_server(builder(handler), |network, addr| async move {
+            read(network, addr, faults).await
+        });
+
+    // Every lost DATA or ACK must be recovered by a retransmission
+    assert!(stats.lost > 0);
+    assert!(!log.retransmitted_blocks().is_empty());
+}
+
+#[test]
+fn read_with_duplication() {
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let faults = Faults::none().duplicate(0.3).seed(2);
+
+    let (_, stats) =
+        run_with_memory_server(builder(handler), |network, addr| async move {
+            read(network, addr, faults).await
+        });
+
+    assert!(stats.duplicated > 0);
+}
+
+#[test]
+fn read_with_reordering() {
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let faults = Faults::none().reorder(0.3).seed(3);
+
+    let (_, stats) =
+        run_with_memory_server(builder(handler), |network, addr| async move {
+            read(network, addr, faults).await
+        });
+
+    assert!(stats.reordered > 0);
+}
+
+#[test]
+fn read_with_latency() {
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let faults = Faults::none()
+        .latency(Duration::from_millis(1), Duration::from_millis(10))
+        .seed(4);
+
+    run_with_memory_server(builder(handler), |network, addr| async move {
+        read(network, addr, faults).await
+    });
+}
+
+#[test]
+fn read_with_all_faults() {
+    let handler = MemHandler::new(content(FILE_SIZE));
+    let faults = Faults::none()
+        .loss(0.1)
+        .duplicate(0.1)
+        .reorder(0.1)
+        .latency(Duration::ZERO, Duration::from_millis(5))
+        .seed(5);
+
+    run_with_memory_server(builder(handler), |network, addr| async move {
+        read(network, addr, faults).await
+    });
+}
+
+#[test]
+fn write_with_loss() {
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let faults = Faults::none().loss(0.2).seed(6);
+
+    run_with_memory_server(builder(handler), |network, addr| async move {
+        let socket = FaultySocket::new(memory_client(&network), faults);
+        let mut client = TestClient::new(socket, addr);
+
+        let log = client
+            .write("test", Opts::default(), &content(FILE_SIZE))
+            .await
+            .expect("transfer failed");
+
+        assert!(client.socket().stats().lost > 0);
+        assert!(log.retransmissions() > 0);
+
+        wait_until(Duration::from_secs(1), || {
+            *written.lock().unwrap() == content(FILE_SIZE)
+        })
+        .await;
+    });
+}
+
+#[test]
+fn write_with_duplication_and_reordering() {
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let faults = Faults::none().duplicate(0.2).reorder(0.2).seed(7);
+
+    run_with_memory_server(builder(handler), |network, addr| async move {
+        let socket = FaultySocket::new(memory_client(&network), faults);
+        let mut client = TestClient::new(socket, addr);
+
+        client
+            .write("test", Opts::default(), &content(FILE_SIZE))
+            .await
+            .expect("transfer failed");
+
+        wait_until(Duration::from_secs(1), || {
+            *written.lock().unwrap() == content(FILE_SIZE)
+        })
+        .await;
+    });
+}
@@ -0,0 +1,68 @@
+use async_executor::Executor;
+use futures_lite::future::block_on;
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::error::Error;
+use crate::server::{Handler, SelfCheck, SelfCheckReport, TftpServerBuilder};
+
+/// Serve the server of `builder` on the wildcard address and run the
+/// self-check that `f` configures.
+fn run_self_check<H, F>(builder: TftpServerBuilder<H>, f: F) -> SelfCheckReport
+where
+    H: Handler + 'static,
+    F: FnOnce(SelfCheck) -> SelfCheck,
+{
+    let ex = Executor::new();
+
+    block_on(ex.run(async {
+        let tftpd = builder
+            .bind("0.0.0.0:0".parse().unwrap())
+            .build()
+            .await
+            .expect("failed to build server");
+        let check = f(tftpd.self_check().unwrap());
+        assert!(check.addr().ip().is_loopback());
+
+        let _server = ex.spawn(tftpd.serve());
+        check.run().await
+    }))
+}
+
+#[test]
+fn self_check_passes() {
+    let handler = MemHandler::new(content(1000));
+    let written = handler.written();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    let report = run_self_check(builder, |check| check);
+
+    assert!(report.is_ok());
+    assert!(report.responded());
+    assert_eq!(report.read.filename, "self-check");
+    assert_eq!(report.read.transferred, 1000);
+
+    let written = written.lock().unwrap().len() as u64;
+    assert_eq!(report.write.transferred, written);
+}
+
+#[test]
+fn self_check_reports_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let builder = TftpServerBuilder::with_dir_ro(dir.path()).unwrap();
+
+    let report = run_self_check(builder, |check| {
+        check
+            .read_file("missing")
+            .write_file("upload")
+            .timeout(Duration::from_millis(200))
+    });
+
+    assert!(!report.is_ok());
+    assert!(report.responded());
+    assert_eq!(report.read.filename, "missing");
+    assert!(matches!(report.read.result, Err(Error::Remote(_))));
+    assert!(matches!(report.write.result, Err(Error::Remote(_))));
+    assert_eq!(report.write.transferred, 0);
+}
@@ -0,0 +1,142 @@
+#![cfg(feature = "tower")]
+
+use futures_lite::future::{self, Boxed, Ready};
+use futures_lite::io::Cursor;
+use std::task::{Context, Poll};
+
+use super::client::{ClientError, TestClient};
+use super::faults::{Faults, FaultySocket};
+use super::utils::*;
+use crate::packet::{self, Opts};
+use crate::server::handlers::{ServiceHandler, TftpRequest, TftpResponse};
+use crate::server::{Direction, TftpServerBuilder};
+
+/// Service that serves `content` and refuses write requests.
+#[derive(Clone)]
+struct ContentService {
+    content: Vec<u8>,
+}
+
+impl tower_service::Service<TftpRequest> for ContentService {
+    type Response = TftpResponse;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Ready<Result<TftpResponse, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: TftpRequest) -> Self::Future {
+        let res = match (req.direction, req.path.to_str()) {
+            (Direction::Read, Some("test")) => {
+                let size = Some(self.content.len() as u64);
+                let reader = Box::new(Cursor::new(self.content.clone()));
+                Ok(TftpResponse::Read(reader, size))
+            }
+            (Direction::Read, _) => Err(packet::Error::FileNotFound.into()),
+            (Direction::Write, _) => Err("writes are disabled".into()),
+        };
+
+        future::ready(res)
+    }
+}
+
+#[test]
+fn service_handler() {
+    let content = content(1000);
+    let handler = ServiceHandler::new(ContentService {
+        content: content.clone(),
+    });
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+
+        let (data, _) = client.read("test", Opts::default()).await.unwrap();
+        assert_eq!(data, content);
+
+        // Errors of the protocol are sent as they are
+        let res = client.read("missing", Opts::default()).await;
+        assert!(matches!(
+            res,
+            Err(ClientError::Tftp(packet::Error::FileNotFound))
+        ));
+
+        // Other errors are sent as messages
+        let res = client.write("test", Opts::default(), b"data").await;
+        assert!(matches!(
+            res,
+            Err(ClientError::Tftp(packet::Error::Msg(ref msg)))
+                if msg == "writes are disabled"
+        ));
+    });
+}
+
+/// Service that answers requests of `slow` after `release` receives.
+#[derive(Clone)]
+struct SlowService {
+    content: Vec<u8>,
+    release: async_channel::Receiver<()>,
+}
+
+impl tower_service::Service<TftpRequest> for SlowService {
+    type Response = TftpResponse;
+    type Error = packet::Error;
+    type Future = Boxed<Result<TftpResponse, packet::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: TftpRequest) -> Self::Future {
+        let content = self.content.clone();
+        let release = self.release.clone();
+
+        Box::pin(async move {
+            if req.path.to_str() == Some("slow") {
+                release.recv().await.unwrap();
+            }
+
+            let reader = Box::new(Cursor::new(content));
+            Ok(TftpResponse::Read(reader, None))
+        })
+    }
+}
+
+#[test]
+fn slow_service_does_not_block_other_requests() {
+    let content = content(1000);
+    let (release_tx, release) = async_channel::unbounded();
+    let handler = ServiceHandler::new(SlowService {
+        content: content.clone(),
+        release,
+    });
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let slow = async {
+            let socket = FaultySocket::bind(Faults::none()).unwrap();
+            let mut client = TestClient::new(socket, addr);
+            client.read("slow", Opts::default()).await.unwrap().0
+        };
+
+        let fast = async {
+            let socket = FaultySocket::bind(Faults::none()).unwrap();
+            let mut client = TestClient::new(socket, addr);
+            let (data, _) = client.read("test", Opts::default()).await.unwrap();
+            release_tx.send(()).await.unwrap();
+            data
+        };
+
+        let (slow, fast) = future::zip(Box::pin(slow), Box::pin(fast)).await;
+        assert_eq!(slow, content);
+        assert_eq!(fast, content);
+    });
+}
@@ -0,0 +1,135 @@
+use futures_lite::io::Cursor;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet;
+use crate::server::{Handler, Scheduling, TftpServerBuilder};
+
+/// Handler that records the threads that open the files.
+struct ThreadsHandler {
+    inner: MemHandler,
+    threads: Arc<Mutex<Vec<String>>>,
+}
+
+#[crate::async_trait]
+impl Handler for ThreadsHandler {
+    type Reader = <MemHandler as Handler>::Reader;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        let name = thread::current().name().unwrap_or_default().to_owned();
+        self.threads.lock().unwrap().push(name);
+
+        self.inner.read_req_open(client, path).await
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.inner.write_req_open(client, path, size).await
+    }
+}
+
+#[test]
+fn requests_run_on_shard_of_client() {
+    let threads = Arc::new(Mutex::new(Vec::new()));
+    let handler = ThreadsHandler {
+        inner: MemHandler::new(content(2000)),
+        threads: threads.clone(),
+    };
+    let builder = TftpServerBuilder::with_handler(handler).scheduling(
+        Scheduling::Sharded {
+            executors: 4,
+            pin_to_cores: true,
+        },
+    );
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+
+        for _ in 0..3 {
+            let data = client.read_to_vec("test").await.unwrap();
+            assert_eq!(data, content(2000));
+        }
+    });
+
+    let threads = threads.lock().unwrap();
+    assert_eq!(threads.len(), 3);
+    assert!(threads[0].starts_with("tftp-shard-"));
+    assert!(threads.iter().all(|name| name == &threads[0]));
+}
+
+/// Handler that counts its clones.
+struct CountedHandler {
+    content: Vec<u8>,
+    clones: Arc<AtomicUsize>,
+}
+
+impl Clone for CountedHandler {
+    fn clone(&self) -> Self {
+        self.clones.fetch_add(1, Ordering::SeqCst);
+
+        CountedHandler {
+            content: self.content.clone(),
+            clones: self.clones.clone(),
+        }
+    }
+}
+
+#[crate::async_trait]
+impl Handler for CountedHandler {
+    type Reader = <MemHandler as Handler>::Reader;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        Ok((Cursor::new(self.content.clone()), None))
+    }
+
+    async fn write_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        Err(packet::Error::IllegalOperation)
+    }
+}
+
+#[test]
+fn every_shard_gets_a_handler() {
+    let clones = Arc::new(AtomicUsize::new(0));
+    let handler = CountedHandler {
+        content: content(2000),
+        clones: clones.clone(),
+    };
+    let builder = TftpServerBuilder::with_handler(handler)
+        .scheduling(Scheduling::Sharded {
+            executors: 4,
+            pin_to_cores: false,
+        })
+        .shard_handlers();
+
+    run_with_server(builder, |addr| async move {
+        let data = TftpClient::new(addr).read_to_vec("test").await.unwrap();
+        assert_eq!(data, content(2000));
+    });
+
+    assert_eq!(clones.load(Ordering::SeqCst), 3);
+}
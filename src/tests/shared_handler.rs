@@ -0,0 +1,221 @@
+use async_io::Timer;
+use futures_lite::future;
+use futures_lite::io::Cursor;
+use futures_lite::{AsyncBufRead, AsyncRead, AsyncWrite};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet;
+use crate::server::{SharedHandler, TftpServerBuilder};
+
+/// Handler that counts reads and keeps the last upload.
+#[derive(Default)]
+struct CountingHandler {
+    reads: AtomicUsize,
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+/// Writer that stores the upload when it is dropped.
+struct SharedWriter {
+    written: Arc<Mutex<Vec<u8>>>,
+    data: Cursor<Vec<u8>>,
+}
+
+impl AsyncWrite for SharedWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.data).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.data).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.data).poll_close(cx)
+    }
+}
+
+impl Drop for SharedWriter {
+    fn drop(&mut self) {
+        let data = std::mem::take(self.data.get_mut());
+        *self.written.lock().unwrap() = data;
+    }
+}
+
+#[crate::async_trait]
+impl SharedHandler for CountingHandler {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = SharedWriter;
+
+    async fn read_req_open(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        Ok((Cursor::new(content(1000)), Some(1000)))
+    }
+
+    async fn write_req_open(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        Ok(SharedWriter {
+            written: Arc::clone(&self.written),
+            data: Cursor::new(Vec::new()),
+        })
+    }
+}
+
+#[test]
+fn arc_handler() {
+    let handler = Arc::new(CountingHandler::default());
+    let builder = TftpServerBuilder::with_handler(Arc::clone(&handler));
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+
+        for _ in 0..2 {
+            let data = client.read_to_vec("test").await.unwrap();
+            assert_eq!(data, content(1000));
+        }
+    });
+
+    // The server is dropped, but the state is still reachable
+    assert_eq!(handler.reads.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn arc_dyn_handler() {
+    let counting = Arc::new(CountingHandler::default());
+    let handler: Arc<
+        dyn SharedHandler<Reader = Cursor<Vec<u8>>, Writer = SharedWriter>,
+    > = counting.clone();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+        let data = Cursor::new(content(100));
+        client.write("test", data, None).await.unwrap();
+    });
+
+    assert_eq!(*counting.written.lock().unwrap(), content(100));
+}
+
+/// Handler whose reads wait until two of them are open at the same time.
+#[derive(Default)]
+struct ConcurrentHandler {
+    opened: AtomicUsize,
+    buffered: Arc<AtomicBool>,
+}
+
+/// Reader that records whether it is accessed as `AsyncBufRead`.
+struct BufferedReader {
+    data: Cursor<Vec<u8>>,
+    buffered: Arc<AtomicBool>,
+}
+
+impl AsyncRead for BufferedReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.data).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for BufferedReader {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        this.buffered.store(true, Ordering::SeqCst);
+        Pin::new(&mut this.data).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.data).consume(amt)
+    }
+}
+
+#[crate::async_trait]
+impl SharedHandler for ConcurrentHandler {
+    type Reader = BufferedReader;
+    type Writer = SharedWriter;
+
+    async fn read_req_open(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.opened.fetch_add(1, Ordering::SeqCst);
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while self.opened.load(Ordering::SeqCst) < 2 {
+            if Instant::now() > deadline {
+                return Err(packet::Error::Msg("reads are serialized".into()));
+            }
+            Timer::after(Duration::from_millis(10)).await;
+        }
+
+        let reader = BufferedReader {
+            data: Cursor::new(content(1000)),
+            buffered: Arc::clone(&self.buffered),
+        };
+        Ok((reader, Some(1000)))
+    }
+
+    fn buf_reader(
+        reader: &mut Self::Reader,
+    ) -> Option<&mut (dyn AsyncBufRead + Unpin + Send)> {
+        Some(reader)
+    }
+
+    async fn write_req_open(
+        &self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        Err(packet::Error::PermissionDenied)
+    }
+}
+
+#[test]
+fn arc_handler_is_not_locked() {
+    let handler = Arc::new(ConcurrentHandler::default());
+    let builder = TftpServerBuilder::with_handler(Arc::clone(&handler));
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+
+        let (a, b) =
+            future::zip(client.read_to_vec("a"), client.read_to_vec("b")).await;
+        assert_eq!(a.unwrap(), content(1000));
+        assert_eq!(b.unwrap(), content(1000));
+    });
+
+    assert!(handler.buffered.load(Ordering::SeqCst));
+}
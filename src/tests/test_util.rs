@@ -0,0 +1,132 @@
+use futures_lite::future::block_on;
+use futures_lite::io::Cursor;
+use std::path::PathBuf;
+
+use super::client::{ClientError, TestClient};
+use super::faults::{Faults, FaultySocket};
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet::{self, Mode, Opts, Packet};
+use crate::server::{Direction, TftpServerBuilder};
+use crate::test_util::{self, MockHandler, MockRequest, RequestBuilder};
+
+#[test]
+fn mock_handler() {
+    let handler = MockHandler::new()
+        .file("kernel", content(1000))
+        .error("secret", packet::Error::PermissionDenied);
+    let builder = TftpServerBuilder::with_handler(handler.clone());
+
+    run_with_server(builder, |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let client_addr = socket.local_addr().unwrap();
+        let mut client = TestClient::new(socket, addr);
+
+        let (data, _) = client.read("kernel", Opts::default()).await.unwrap();
+        assert_eq!(data, content(1000));
+
+        let res = client.read("missing", Opts::default()).await;
+        assert!(matches!(
+            res,
+            Err(ClientError::Tftp(packet::Error::FileNotFound))
+        ));
+
+        let res = client.write("secret", Opts::default(), b"data").await;
+        assert!(matches!(
+            res,
+            Err(ClientError::Tftp(packet::Error::PermissionDenied))
+        ));
+
+        client.write("log", Opts::default(), b"data").await.unwrap();
+        assert_eq!(handler.written("log"), Some(b"data".to_vec()));
+        assert_eq!(handler.written("secret"), None);
+
+        let request = |path: &str, direction| MockRequest {
+            client: client_addr,
+            path: PathBuf::from(path),
+            direction,
+        };
+
+        assert_eq!(
+            handler.requests(),
+            [
+                request("kernel", Direction::Read),
+                request("missing", Direction::Read),
+                request("secret", Direction::Write),
+                request("log", Direction::Write),
+            ]
+        );
+    });
+}
+
+#[test]
+fn request_builder() {
+    let req = RequestBuilder::new("pxelinux.0")
+        .block_size(1468)
+        .transfer_size(0)
+        .build();
+
+    assert_eq!(req.filename, b"pxelinux.0");
+    assert_eq!(req.mode, Mode::Octet);
+    assert_eq!(
+        req.opts,
+        Opts {
+            block_size: Some(1468),
+            transfer_size: Some(0),
+            ..Opts::default()
+        }
+    );
+
+    // Window is ignored by default, so data is sent without OACK
+    let handler = MockHandler::new().file("kernel", content(10));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let rrq = RequestBuilder::new("kernel").window_size(4).build();
+        let reply = request(addr, Packet::Rrq(rrq)).await;
+        assert!(matches!(Packet::decode(&reply), Ok(Packet::Data(1, _))));
+    });
+}
+
+#[test]
+#[cfg(feature = "bytes-api")]
+fn request_builder_encode() {
+    let wrq = RequestBuilder::new("log").mode(Mode::Netascii).wrq();
+
+    match Packet::decode(&wrq) {
+        Ok(Packet::Wrq(req)) => {
+            assert_eq!(req.filename, b"log");
+            assert_eq!(req.mode, Mode::Netascii);
+            assert_eq!(req.opts, Opts::default());
+        }
+        packet => panic!("unexpected packet: {:?}", packet),
+    }
+
+    let rrq = RequestBuilder::new("kernel").block_size(1024).rrq();
+    assert!(matches!(Packet::decode(&rrq), Ok(Packet::Rrq(_))));
+}
+
+#[test]
+fn loopback_pair() {
+    let handler = MockHandler::new().file("kernel", content(1000));
+    let builder = TftpServerBuilder::with_handler(handler.clone());
+
+    let (client, server) = block_on(test_util::loopback_pair(builder)).unwrap();
+    assert_eq!(client.server(), server.addr());
+
+    let data = block_on(client.read_to_vec("kernel")).unwrap();
+    assert_eq!(data, content(1000));
+
+    let data = Cursor::new(content(100));
+    block_on(client.write("log", data, None)).unwrap();
+
+    // Other clients join the network of the server
+    let socket = server.network().bind("10.0.0.3:0".parse().unwrap()).unwrap();
+    let other = TftpClient::new(server.addr()).transport(socket);
+    let data = block_on(other.read_to_vec("kernel")).unwrap();
+    assert_eq!(data, content(1000));
+
+    // Waits for the transfer to finish on the server side
+    server.shutdown().unwrap();
+    assert_eq!(handler.written("log"), Some(content(100)));
+}
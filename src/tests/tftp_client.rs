@@ -0,0 +1,337 @@
+use futures_lite::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::client::{StrayErrors, TftpClient, TraceDirection};
+use crate::packet::{self, Mode, Opts, OwnedPacket, Packet, RwReq};
+use crate::server::handlers::RelayHandler;
+use crate::server::TftpServerBuilder;
+use crate::Error;
+
+#[test]
+fn read() {
+    let handler = MemHandler::new(content(3000));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    let (data, size) = run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+        let stream = client.read("test").await.unwrap();
+        let size = stream.transfer_size();
+        drop(stream);
+
+        (client.read_to_vec("test").await.unwrap(), size)
+    });
+
+    assert_eq!(data, content(3000));
+    assert_eq!(size, Some(3000));
+}
+
+#[test]
+fn read_block_size() {
+    // Exact multiple of the block size ends with an empty block
+    let handler = MemHandler::new(content(2048));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    let data = run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr).block_size(1024);
+        client.read_to_vec("test").await.unwrap()
+    });
+
+    assert_eq!(data, content(2048));
+}
+
+#[test]
+fn write() {
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    let sent = run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr).block_size(1000);
+        let reader = Cursor::new(content(2500));
+        client.write("test", reader, Some(2500)).await.unwrap()
+    });
+
+    assert_eq!(sent, 2500);
+    assert_eq!(*written.lock().unwrap(), content(2500));
+}
+
+#[test]
+fn server_error() {
+    let handler = RelayHandler::new("127.0.0.1:9".parse().unwrap());
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    let res = run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+        client.write("test", Cursor::new(content(10)), None).await
+    });
+
+    match res {
+        Err(Error::Remote(e)) => {
+            assert_eq!(e.code, 4);
+            assert_eq!(e.kind(), packet::Error::IllegalOperation);
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn no_server() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let client =
+        TftpClient::new(addr).timeout(Duration::from_millis(50)).max_retries(1);
+    let res = futures_lite::future::block_on(client.read("test"));
+
+    match res {
+        Err(Error::MaxSendRetriesReached(peer, 0)) => assert_eq!(peer, addr),
+        Err(e) => panic!("unexpected error: {:?}", e),
+        Ok(_) => panic!("unexpected success"),
+    }
+}
+
+#[test]
+fn request_timeout() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let client = TftpClient::new(addr)
+        .timeout(Duration::from_secs(30))
+        .request_timeout(Duration::from_millis(50))
+        .max_retries(1);
+
+    let started = Instant::now();
+    let res = futures_lite::future::block_on(client.read("test"));
+
+    assert!(matches!(res, Err(Error::MaxSendRetriesReached(_, 0))));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn write_drops_stale_acks() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    // Server that acknowledges the request, and then only block 0
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 1024];
+        let ack = Packet::Ack(0).to_vec();
+        let (_, client) = socket.recv_from(&mut buf).unwrap();
+
+        for _ in 0..500 {
+            let _ = socket.send_to(&ack, client);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    });
+
+    let client =
+        TftpClient::new(addr).timeout(Duration::from_secs(30)).max_retries(5);
+    let started = Instant::now();
+    let res = futures_lite::future::block_on(client.write(
+        "test",
+        Cursor::new(content(2000)),
+        None,
+    ));
+
+    // Stale ACKs do not keep the upload waiting for the timeout
+    assert!(matches!(res, Err(Error::MaxSendRetriesReached(_, 1))));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+/// Answer the first request with `UnknownTransferId` from another port and
+/// the second one with a single DATA block.
+fn stray_responder() -> SocketAddr {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let stray = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 1024];
+        let error = Packet::Error(packet::Error::UnknownTransferId).to_vec();
+        let (_, client) = socket.recv_from(&mut buf).unwrap();
+        stray.send_to(&error, client).unwrap();
+
+        let (_, client) = socket.recv_from(&mut buf).unwrap();
+        let data = Packet::Data(1, b"abc").to_vec();
+        socket.send_to(&data, client).unwrap();
+    });
+
+    addr
+}
+
+#[test]
+fn ignore_stray_error() {
+    let addr = stray_responder();
+    let client = TftpClient::new(addr).timeout(Duration::from_secs(5));
+
+    let data = futures_lite::future::block_on(client.read_to_vec("test"));
+    assert_eq!(data.unwrap(), b"abc");
+}
+
+#[test]
+fn fail_on_stray_error() {
+    let addr = stray_responder();
+    let client = TftpClient::new(addr)
+        .timeout(Duration::from_secs(5))
+        .stray_errors(StrayErrors::Fail);
+
+    match futures_lite::future::block_on(client.read_to_vec("test")) {
+        Err(Error::Remote(e)) => {
+            assert_eq!(e.kind(), packet::Error::UnknownTransferId)
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn fallback_to_next_address() {
+    let handler = MemHandler::new(content(1000));
+    let builder = TftpServerBuilder::with_handler(handler);
+    let dead = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let dead_addr = dead.local_addr().unwrap();
+
+    let data = run_with_server(builder, |addr| async move {
+        let client = TftpClient::from_addrs([dead_addr, addr])
+            .request_timeout(Duration::from_millis(50))
+            .max_retries(1);
+        client.read_to_vec("test").await.unwrap()
+    });
+
+    assert_eq!(data, content(1000));
+}
+
+#[test]
+fn resolve_host() {
+    let handler = MemHandler::new(content(1000));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    let data = run_with_server(builder, |addr| async move {
+        let client = TftpClient::resolve("localhost", addr.port())
+            .await
+            .unwrap()
+            .request_timeout(Duration::from_millis(100));
+        assert!(client.servers().iter().all(|s| s.ip().is_loopback()));
+
+        client.read_to_vec("test").await.unwrap()
+    });
+
+    assert_eq!(data, content(1000));
+}
+
+#[test]
+fn address_order() {
+    let v4: SocketAddr = "127.0.0.1:69".parse().unwrap();
+    let v6: SocketAddr = "[::1]:69".parse().unwrap();
+
+    let client = TftpClient::from_addrs([v4, v6]);
+    assert_eq!(client.servers(), [v6, v4]);
+    assert_eq!(client.prefer_ipv4().servers(), [v4, v6]);
+}
+
+#[test]
+fn connection() {
+    let handler = MemHandler::new(content(3000));
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+        let req = RwReq {
+            filename: b"test".to_vec(),
+            mode: Mode::Octet,
+            opts: Opts {
+                block_size: Some(1024),
+                transfer_size: Some(0),
+                ..Opts::default()
+            },
+        };
+
+        // Accept the negotiated options
+        let mut conn = client.connect().unwrap();
+        let reply = conn.send_request(&Packet::Rrq(req.clone())).await.unwrap();
+        assert_ne!(conn.peer(), addr);
+
+        match reply {
+            OwnedPacket::OAck(opts) => {
+                assert_eq!(opts.block_size, Some(1024));
+                assert_eq!(opts.transfer_size, Some(3000));
+            }
+            packet => panic!("unexpected packet: {:?}", packet),
+        }
+
+        conn.send_packet(&Packet::Ack(0)).await.unwrap();
+        assert_eq!(
+            conn.recv_packet().await.unwrap(),
+            OwnedPacket::Data(1, content(3000)[..1024].to_vec())
+        );
+
+        // Reject them
+        let mut conn = client.connect().unwrap();
+        let reply = conn.send_request(&Packet::Rrq(req)).await.unwrap();
+        assert!(matches!(reply, OwnedPacket::OAck(_)));
+
+        let error = packet::Error::OptionsNegotiationFailed;
+        conn.send_packet(&Packet::Error(error)).await.unwrap();
+    });
+}
+
+#[test]
+fn remote_error_keeps_message() {
+    let dir = tempdir().unwrap();
+    let builder = TftpServerBuilder::with_dir_ro(dir.path())
+        .unwrap()
+        .error_messages(|e| match e {
+            packet::Error::FileNotFound => Some("no such boot file".into()),
+            _ => None,
+        });
+
+    let res = run_with_server(builder, |addr| async move {
+        TftpClient::new(addr).read_to_vec("missing").await
+    });
+
+    match res {
+        Err(Error::Remote(e)) => {
+            assert_eq!(e.code, 1);
+            assert_eq!(e.message, "no such boot file");
+            assert_eq!(e.kind(), packet::Error::FileNotFound);
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn trace_packets() {
+    let handler = MemHandler::new(content(100));
+    let builder = TftpServerBuilder::with_handler(handler);
+    let packets = Arc::new(Mutex::new(Vec::new()));
+    let traced = packets.clone();
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr).trace_packets(move |trace| {
+            let packet = trace.packet.as_ref().unwrap().to_owned_packet();
+            traced.lock().unwrap().push((trace.direction, packet));
+        });
+        client.read_to_vec("test").await.unwrap();
+    });
+
+    let packets = packets.lock().unwrap();
+    let expected = [
+        TraceDirection::Sent,
+        TraceDirection::Received,
+        TraceDirection::Sent,
+        TraceDirection::Received,
+        TraceDirection::Sent,
+    ];
+
+    assert_eq!(packets.len(), 5);
+    assert!(packets.iter().map(|(d, _)| *d).eq(expected));
+    assert!(matches!(packets[0].1, OwnedPacket::Rrq(_)));
+    assert!(matches!(packets[1].1, OwnedPacket::OAck(_)));
+    assert_eq!(packets[2].1, OwnedPacket::Ack(0));
+    assert_eq!(packets[3].1, OwnedPacket::Data(1, content(100)));
+    assert_eq!(packets[4].1, OwnedPacket::Ack(1));
+}
@@ -0,0 +1,325 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::clock::MockClock;
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{self, OwnedPacket, Packet};
+use crate::server::{TftpServerBuilder, TransferParams};
+use crate::test_util::RequestBuilder;
+
+fn builder(clock: &MockClock) -> TftpServerBuilder<MemHandler> {
+    let handler = MemHandler::new(content(512 * 4));
+    TftpServerBuilder::with_handler(handler).clock(Arc::new(clock.clone()))
+}
+
+/// Wait for packets that are expected, so a stuck server fails the test.
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Wait for packets that are not expected.
+const SILENCE: Duration = Duration::from_millis(50);
+
+fn test() -> RequestBuilder {
+    RequestBuilder::new("test")
+}
+
+#[test]
+fn data_is_retransmitted_on_timeout() {
+    let clock = MockClock::new();
+
+    run_with_server(builder(&clock), |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Data(1, _))));
+        let peer = client.server_addr();
+
+        // Nothing happens until time moves forward
+        wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+            .await;
+        assert!(client.is_idle(SILENCE).await);
+
+        clock.advance(Duration::from_secs(3));
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Data(1, _))));
+        assert_eq!(client.server_addr(), peer);
+    });
+}
+
+#[test]
+fn handler_overrides_timeout() {
+    let clock = MockClock::new();
+    let handler =
+        MemHandler::new(content(512 * 4)).with_params(TransferParams {
+            timeout: Some(Duration::from_secs(1)),
+            ..TransferParams::default()
+        });
+    let builder =
+        TftpServerBuilder::with_handler(handler).clock(Arc::new(clock.clone()));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Data(1, _))));
+
+        wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+            .await;
+        clock.advance(Duration::from_secs(1));
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Data(1, _))));
+    });
+}
+
+#[test]
+fn legacy_client_timeout() {
+    let clock = MockClock::new();
+    let builder = builder(&clock).legacy_client_params(TransferParams {
+        timeout: Some(Duration::from_secs(1)),
+        ..TransferParams::default()
+    });
+
+    run_with_server(builder, |addr| async move {
+        let mut legacy = RawClient::rrq(addr, "test").await;
+        let packet = legacy.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Data(1, _))));
+
+        let req = test().transfer_size(0);
+        let mut modern = RawClient::rrq_with(addr, req).await;
+        let packet = modern.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::OAck(_))));
+
+        wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 2)
+            .await;
+        clock.advance(Duration::from_secs(1));
+
+        // Only the client without options has the shorter timeout
+        let packet = legacy.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Data(1, _))));
+        assert!(modern.is_idle(SILENCE).await);
+
+        clock.advance(Duration::from_secs(2));
+        let packet = modern.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::OAck(_))));
+    });
+}
+
+#[test]
+fn handler_limits_data_rate() {
+    let clock = MockClock::new();
+    let handler =
+        MemHandler::new(content(512 * 4)).with_params(TransferParams {
+            max_bytes_per_sec: Some(512),
+            ..TransferParams::default()
+        });
+    let builder =
+        TftpServerBuilder::with_handler(handler).clock(Arc::new(clock.clone()));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Data(1, _))));
+        client.send(Packet::Ack(1)).await;
+
+        // A block of 512 bytes takes a second at 512 bytes per second
+        wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+            .await;
+        assert!(client.is_idle(SILENCE).await);
+
+        clock.advance(Duration::from_secs(1));
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Data(2, _))));
+    });
+}
+
+#[test]
+fn max_send_retries_reached() {
+    let clock = MockClock::new();
+    let builder = builder(&clock).max_send_retries(2);
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+
+        // First transmission and 2 retries
+        for _ in 0..3 {
+            let packet = client.recv_packet(WAIT).await;
+            assert!(matches!(packet, Some(OwnedPacket::Data(1, _))));
+
+            wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+                .await;
+            clock.advance(Duration::from_secs(3));
+        }
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(
+            packet,
+            Some(OwnedPacket::Error(packet::Error::Msg(_)))
+        ));
+    });
+}
+
+#[test]
+fn ack_is_retransmitted_on_timeout() {
+    let clock = MockClock::new();
+
+    run_with_server(builder(&clock), |addr| async move {
+        let mut client = RawClient::wrq_with(addr, test()).await;
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Ack(0))));
+
+        wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+            .await;
+        assert!(client.is_idle(SILENCE).await);
+
+        clock.advance(Duration::from_secs(3));
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Ack(0))));
+    });
+}
+
+#[test]
+fn client_timeout_drives_retransmission() {
+    let clock = MockClock::new();
+
+    run_with_server(builder(&clock), |addr| async move {
+        let mut client = RawClient::rrq_with(addr, test().timeout(1)).await;
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::OAck(_))));
+
+        wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+            .await;
+
+        // Server's default timeout is 3 seconds, but client asked for 1
+        clock.advance(Duration::from_secs(1));
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::OAck(_))));
+    });
+}
+
+#[test]
+fn client_timeout_is_clamped() {
+    let clock = MockClock::new();
+    let builder = builder(&clock).min_client_timeout(Duration::from_secs(2));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq_with(addr, test().timeout(1)).await;
+
+        match client.recv_packet(WAIT).await {
+            Some(OwnedPacket::OAck(opts)) => assert_eq!(opts.timeout, Some(2)),
+            packet => panic!("unexpected packet: {:?}", packet),
+        }
+
+        wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+            .await;
+
+        // Client asked for 1 second, but the minimum is 2
+        clock.advance(Duration::from_secs(1));
+        assert!(client.is_idle(SILENCE).await);
+        clock.advance(Duration::from_secs(1));
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::OAck(_))));
+    });
+}
+
+#[test]
+fn handler_advertises_client_timeout() {
+    let clock = MockClock::new();
+    let handler =
+        MemHandler::new(content(512 * 4)).with_params(TransferParams {
+            client_timeout: Some(Duration::from_secs(5)),
+            ..TransferParams::default()
+        });
+    let builder =
+        TftpServerBuilder::with_handler(handler).clock(Arc::new(clock.clone()));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq_with(addr, test().timeout(1)).await;
+
+        match client.recv_packet(WAIT).await {
+            Some(OwnedPacket::OAck(opts)) => assert_eq!(opts.timeout, Some(5)),
+            packet => panic!("unexpected packet: {:?}", packet),
+        }
+
+        // Clients that did not request the option do not get it
+        let mut client = RawClient::rrq(addr, "test").await;
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Data(1, _))));
+    });
+}
+
+#[test]
+fn oack_retransmission_is_configured_separately() {
+    let clock = MockClock::new();
+    let builder = builder(&clock)
+        .max_send_retries(1)
+        .max_oack_retries(3)
+        .oack_timeout(Duration::from_secs(1));
+
+    run_with_server(builder, |addr| async move {
+        let req = test().transfer_size(0);
+        let mut client = RawClient::rrq_with(addr, req).await;
+
+        // First transmission and 3 retries, every second
+        for _ in 0..4 {
+            let packet = client.recv_packet(WAIT).await;
+            assert!(matches!(packet, Some(OwnedPacket::OAck(_))));
+
+            wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+                .await;
+            clock.advance(Duration::from_secs(1));
+        }
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(
+            packet,
+            Some(OwnedPacket::Error(packet::Error::Msg(_)))
+        ));
+    });
+}
+
+#[test]
+fn oack_of_wrq_is_retransmitted_on_oack_timeout() {
+    let clock = MockClock::new();
+    let builder = builder(&clock).oack_timeout(Duration::from_secs(1));
+
+    run_with_server(builder, |addr| async move {
+        let req = test().transfer_size(100);
+        let mut client = RawClient::wrq_with(addr, req).await;
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::OAck(_))));
+
+        wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+            .await;
+        clock.advance(Duration::from_secs(1));
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::OAck(_))));
+
+        // Data blocks use the regular timeout
+        client.send(Packet::Data(1, &[0u8; 512])).await;
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Ack(1))));
+
+        wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+            .await;
+        clock.advance(Duration::from_secs(1));
+        assert!(client.is_idle(SILENCE).await);
+
+        clock.advance(Duration::from_secs(2));
+
+        let packet = client.recv_packet(WAIT).await;
+        assert!(matches!(packet, Some(OwnedPacket::Ack(1))));
+    });
+}
@@ -0,0 +1,112 @@
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{Opts, Packet};
+use crate::server::{TftpServerBuilder, TransferParams};
+use crate::test_util::RequestBuilder;
+
+fn rrq(block_size: Option<u16>) -> Packet<'static> {
+    let mut req = RequestBuilder::new("test").transfer_size(0);
+    if let Some(block_size) = block_size {
+        req = req.block_size(block_size);
+    }
+
+    Packet::Rrq(req.build())
+}
+
+fn wrq(transfer_size: u64) -> Packet<'static> {
+    let req = RequestBuilder::new("test").transfer_size(transfer_size);
+    Packet::Wrq(req.build())
+}
+
+#[test]
+fn transfer_size_is_acknowledged() {
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(2000)));
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, rrq(None)).await;
+        assert_eq!(
+            Packet::decode(&reply).unwrap(),
+            Packet::OAck(Opts {
+                transfer_size: Some(2000),
+                ..Opts::default()
+            })
+        );
+
+        let reply = request(addr, wrq(100)).await;
+        assert_eq!(
+            Packet::decode(&reply).unwrap(),
+            Packet::OAck(Opts {
+                transfer_size: Some(100),
+                ..Opts::default()
+            })
+        );
+    });
+}
+
+#[test]
+fn omit_transfer_size() {
+    let builder =
+        TftpServerBuilder::with_handler(MemHandler::new(content(2000)))
+            .omit_transfer_size();
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, rrq(Some(1024))).await;
+        assert_eq!(
+            Packet::decode(&reply).unwrap(),
+            Packet::OAck(Opts {
+                block_size: Some(1024),
+                ..Opts::default()
+            })
+        );
+
+        // Nothing is left to acknowledge
+        let reply = request(addr, rrq(None)).await;
+        assert!(matches!(
+            Packet::decode(&reply).unwrap(),
+            Packet::Data(1, data) if data.len() == 512
+        ));
+
+        let reply = request(addr, wrq(100)).await;
+        assert_eq!(Packet::decode(&reply).unwrap(), Packet::Ack(0));
+    });
+}
+
+#[test]
+fn handler_omits_transfer_size() {
+    let params = TransferParams {
+        omit_transfer_size: Some(true),
+        ..TransferParams::default()
+    };
+    let handler = MemHandler::new(content(2000)).with_params(params);
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, rrq(Some(1024))).await;
+        assert_eq!(
+            Packet::decode(&reply).unwrap(),
+            Packet::OAck(Opts {
+                block_size: Some(1024),
+                ..Opts::default()
+            })
+        );
+    });
+
+    let params = TransferParams {
+        omit_transfer_size: Some(false),
+        ..TransferParams::default()
+    };
+    let handler = MemHandler::new(content(2000)).with_params(params);
+    let builder = TftpServerBuilder::with_handler(handler).omit_transfer_size();
+
+    run_with_server(builder, |addr| async move {
+        let reply = request(addr, rrq(None)).await;
+        assert_eq!(
+            Packet::decode(&reply).unwrap(),
+            Packet::OAck(Opts {
+                transfer_size: Some(2000),
+                ..Opts::default()
+            })
+        );
+    });
+}
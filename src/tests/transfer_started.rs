@@ -0,0 +1,144 @@
+use async_channel::Sender;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::client::TestClient;
+use super::faults::{Faults, FaultySocket};
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::packet::{self, Opts};
+use crate::server::{
+    Direction, Handler, TftpServerBuilder, TransferContext, TransferState,
+};
+
+/// Handler that reports started transfers to a channel.
+struct StartedHandler {
+    inner: MemHandler,
+    started: Sender<TransferContext>,
+}
+
+#[crate::async_trait]
+impl Handler for StartedHandler {
+    type Reader = <MemHandler as Handler>::Reader;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.inner.read_req_open(client, path).await
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.inner.write_req_open(client, path, size).await
+    }
+
+    async fn transfer_started(
+        &mut self,
+        ctx: &TransferContext,
+        _state: &mut TransferState,
+    ) {
+        self.started.send(ctx.clone()).await.unwrap();
+    }
+}
+
+#[test]
+fn negotiated_options_are_reported() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = StartedHandler {
+        inner: MemHandler::new(content(3000)),
+        started: tx,
+    };
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_secs(2))
+        .block_size_limit(1024);
+
+    run_with_server(builder, |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let client_addr = socket.local_addr().unwrap();
+        let mut client = TestClient::new(socket, addr);
+
+        let opts = Opts {
+            block_size: Some(1400),
+            transfer_size: Some(0),
+            ..Opts::default()
+        };
+        client.read("test", opts).await.unwrap();
+
+        let ctx = rx.recv().await.unwrap();
+        assert_eq!(ctx.client, client_addr);
+        assert_eq!(ctx.path, PathBuf::from("test"));
+        assert_eq!(ctx.direction, Direction::Read);
+        assert_eq!(ctx.options.block_size, 1024);
+        assert_eq!(ctx.options.timeout, Duration::from_secs(2));
+        assert_eq!(ctx.options.transfer_size, Some(3000));
+        assert_eq!(ctx.options.window_size, 1);
+        assert_eq!(ctx.client_mac, None);
+
+        let opts = Opts {
+            timeout: Some(5),
+            transfer_size: Some(100),
+            ..Opts::default()
+        };
+        client.write("upload", opts, &content(100)).await.unwrap();
+
+        let ctx = rx.recv().await.unwrap();
+        assert_eq!(ctx.path, PathBuf::from("upload"));
+        assert_eq!(ctx.direction, Direction::Write);
+        assert_eq!(ctx.options.block_size, 512);
+        assert_eq!(ctx.options.timeout, Duration::from_secs(5));
+        assert_eq!(ctx.options.transfer_size, Some(100));
+    });
+}
+
+#[test]
+fn clamped_timeout_is_reported() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = StartedHandler {
+        inner: MemHandler::new(content(100)),
+        started: tx,
+    };
+    let builder = TftpServerBuilder::with_handler(handler)
+        .max_client_timeout(Duration::from_secs(3));
+
+    run_with_server(builder, |addr| async move {
+        let socket = FaultySocket::bind(Faults::none()).unwrap();
+        let mut client = TestClient::new(socket, addr);
+
+        let opts = Opts {
+            timeout: Some(10),
+            ..Opts::default()
+        };
+        client.read("test", opts).await.unwrap();
+
+        let ctx = rx.recv().await.unwrap();
+        assert_eq!(ctx.options.timeout, Duration::from_secs(3));
+    });
+}
+
+#[test]
+fn local_addr_is_reported() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = StartedHandler {
+        inner: MemHandler::new(content(100)),
+        started: tx,
+    };
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert!(client.recv(Duration::from_secs(5)).await.is_some());
+        client.finish().await;
+
+        let ctx = rx.recv().await.unwrap();
+        assert_eq!(Some(ctx.local_addr), client.server_addr());
+        assert_ne!(ctx.local_addr, addr);
+    });
+}
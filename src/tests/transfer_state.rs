@@ -0,0 +1,386 @@
+use async_channel::Sender;
+use futures_lite::io::Cursor;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use super::mem_handler::{MemHandler, MemWriter};
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::packet::{self, OwnedPacket, Packet};
+use crate::server::{
+    Direction, Handler, TftpServerBuilder, TransferContext, TransferOutcome,
+    TransferParams, TransferState,
+};
+use crate::test_util::RequestBuilder;
+
+const WAIT: Duration = Duration::from_secs(2);
+
+/// Handler that reports the calls of each transfer with its id.
+struct StateHandler {
+    inner: MemHandler,
+    next_id: u32,
+    events: Sender<(u32, &'static str)>,
+}
+
+/// State of a transfer, which reports when it is dropped.
+struct Transfer {
+    id: u32,
+    events: Sender<(u32, &'static str)>,
+}
+
+impl Transfer {
+    fn report(&self, event: &'static str) {
+        self.events.try_send((self.id, event)).unwrap();
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        self.report("finished");
+    }
+}
+
+fn report(state: &TransferState, event: &'static str) {
+    state.get::<Transfer>().unwrap().report(event);
+}
+
+#[crate::async_trait]
+impl Handler for StateHandler {
+    type Reader = <MemHandler as Handler>::Reader;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        self.inner.read_req_open(client, path).await
+    }
+
+    async fn write_req_open(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        self.inner.write_req_open(client, path, size).await
+    }
+
+    async fn write_req_failed(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _writer: Self::Writer,
+        state: &mut TransferState,
+    ) {
+        report(state, "failed");
+    }
+
+    async fn transfer_params(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+        state: &mut TransferState,
+    ) -> TransferParams {
+        report(state, "params");
+        TransferParams::default()
+    }
+
+    async fn transfer_state(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+    ) -> TransferState {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        TransferState::new(Transfer {
+            id,
+            events: self.events.clone(),
+        })
+    }
+
+    async fn transfer_started(
+        &mut self,
+        ctx: &TransferContext,
+        state: &mut TransferState,
+    ) {
+        if ctx.path == Path::new("panic") {
+            panic!("transfer_started panicked");
+        }
+
+        report(state, "started");
+    }
+
+    async fn transfer_finished(
+        &mut self,
+        _ctx: &TransferContext,
+        outcome: TransferOutcome,
+        state: &mut TransferState,
+    ) {
+        match outcome {
+            TransferOutcome::Completed => report(state, "completed"),
+            TransferOutcome::Cancelled => report(state, "cancelled"),
+            TransferOutcome::Panicked => report(state, "panicked"),
+            _ => report(state, "aborted"),
+        }
+    }
+}
+
+/// Handler that creates the state of a transfer when it opens the file.
+struct OpenStateHandler {
+    inner: StateHandler,
+}
+
+#[crate::async_trait]
+impl Handler for OpenStateHandler {
+    type Reader = <MemHandler as Handler>::Reader;
+    type Writer = MemWriter;
+
+    async fn read_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+    ) -> Result<(Self::Reader, Option<u64>), packet::Error> {
+        unreachable!("reads are opened with their state")
+    }
+
+    async fn read_req_open_with_state(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        _count: Option<u32>,
+    ) -> Result<(Self::Reader, Option<u64>, TransferState), packet::Error> {
+        let (reader, size) = self.inner.read_req_open(client, path).await?;
+        let state = TransferState::new(Transfer {
+            id: 7,
+            events: self.inner.events.clone(),
+        });
+
+        Ok((reader, size, state))
+    }
+
+    async fn write_req_open(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _size: Option<u64>,
+    ) -> Result<Self::Writer, packet::Error> {
+        unreachable!("writes are opened with their state")
+    }
+
+    async fn write_req_open_with_state(
+        &mut self,
+        client: &SocketAddr,
+        path: &Path,
+        size: Option<u64>,
+    ) -> Result<(Self::Writer, TransferState), packet::Error> {
+        let writer = self.inner.write_req_open(client, path, size).await?;
+        let state = TransferState::new(Transfer {
+            id: 8,
+            events: self.inner.events.clone(),
+        });
+
+        Ok((writer, state))
+    }
+
+    async fn transfer_state(
+        &mut self,
+        _client: &SocketAddr,
+        _path: &Path,
+        _direction: Direction,
+    ) -> TransferState {
+        unreachable!("state is created with the open")
+    }
+
+    async fn transfer_finished(
+        &mut self,
+        ctx: &TransferContext,
+        outcome: TransferOutcome,
+        state: &mut TransferState,
+    ) {
+        self.inner.transfer_finished(ctx, outcome, state).await
+    }
+}
+
+fn state_handler(events: Sender<(u32, &'static str)>) -> StateHandler {
+    StateHandler {
+        inner: MemHandler::new(content(1000)),
+        next_id: 0,
+        events,
+    }
+}
+
+#[test]
+fn state_is_handed_to_hooks() {
+    let (tx, rx) = async_channel::unbounded();
+    let builder = TftpServerBuilder::with_handler(state_handler(tx));
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+
+        for id in 0..2 {
+            let data = client.read_to_vec("test").await.unwrap();
+            assert_eq!(data, content(1000));
+
+            for event in ["params", "started", "completed", "finished"] {
+                assert_eq!(rx.recv().await.unwrap(), (id, event));
+            }
+        }
+    });
+}
+
+#[test]
+fn failed_upload_gets_state() {
+    let (tx, rx) = async_channel::unbounded();
+    let builder = TftpServerBuilder::with_handler(state_handler(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut conn = TftpClient::new(addr).connect().unwrap();
+        let wrq = Packet::Wrq(RequestBuilder::new("test").build());
+
+        let reply = conn.send_request(&wrq).await.unwrap();
+        assert!(matches!(reply, OwnedPacket::Ack(0)));
+
+        let block = content(512);
+        conn.send_packet(&Packet::Data(1, &block)).await.unwrap();
+        let reply = conn.recv_packet().await.unwrap();
+        assert!(matches!(reply, OwnedPacket::Ack(1)));
+
+        let error = Packet::Error(packet::Error::DiskFull);
+        conn.send_packet(&error).await.unwrap();
+
+        for event in ["params", "started", "failed", "aborted", "finished"] {
+            assert_eq!(rx.recv().await.unwrap(), (0, event));
+        }
+    });
+}
+
+#[test]
+fn rejected_options_fail_upload_before_restart() {
+    let (tx, rx) = async_channel::unbounded();
+    let builder = TftpServerBuilder::with_handler(state_handler(tx))
+        .downgrade_rejected_options();
+
+    run_with_server(builder, |addr| async move {
+        let mut conn = TftpClient::new(addr).connect().unwrap();
+        let req = RequestBuilder::new("test").block_size(1024);
+        let wrq = Packet::Wrq(req.build());
+
+        let reply = conn.send_request(&wrq).await.unwrap();
+        assert!(matches!(reply, OwnedPacket::OAck(_)));
+
+        let error = Packet::Error(packet::Error::OptionsNegotiationFailed);
+        conn.send_packet(&error).await.unwrap();
+
+        let reply = conn.recv_packet().await.unwrap();
+        assert!(matches!(reply, OwnedPacket::Ack(0)));
+
+        conn.send_packet(&Packet::Data(1, b"short")).await.unwrap();
+        let reply = conn.recv_packet().await.unwrap();
+        assert!(matches!(reply, OwnedPacket::Ack(1)));
+
+        // Writer of the rejected attempt is cleaned up before the restart
+        for event in ["params", "failed", "finished"] {
+            assert_eq!(rx.recv().await.unwrap(), (0, event));
+        }
+
+        for event in ["params", "started", "completed", "finished"] {
+            assert_eq!(rx.recv().await.unwrap(), (1, event));
+        }
+    });
+}
+
+#[test]
+fn panicked_upload_fails_writer() {
+    let (tx, rx) = async_channel::unbounded();
+    let builder = TftpServerBuilder::with_handler(state_handler(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut conn = TftpClient::new(addr).connect().unwrap();
+        let wrq = Packet::Wrq(RequestBuilder::new("panic").build());
+
+        let reply = conn.send_request(&wrq).await.unwrap();
+        assert!(matches!(reply, OwnedPacket::Ack(0)));
+
+        // First block runs the hook that panics
+        conn.send_packet(&Packet::Data(1, b"short")).await.unwrap();
+
+        // Writer is cleaned up before the transfer is finished
+        for event in ["params", "failed", "panicked", "finished"] {
+            assert_eq!(rx.recv().await.unwrap(), (0, event));
+        }
+    });
+}
+
+#[test]
+fn state_is_created_with_open() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = OpenStateHandler {
+        inner: state_handler(tx),
+    };
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_server(builder, |addr| async move {
+        let client = TftpClient::new(addr);
+
+        let data = client.read_to_vec("test").await.unwrap();
+        assert_eq!(data, content(1000));
+        for event in ["completed", "finished"] {
+            assert_eq!(rx.recv().await.unwrap(), (7, event));
+        }
+
+        let data = Cursor::new(content(100));
+        client.write("test", data, None).await.unwrap();
+        for event in ["completed", "finished"] {
+            assert_eq!(rx.recv().await.unwrap(), (8, event));
+        }
+    });
+}
+
+#[test]
+fn panicked_read_is_finished() {
+    let (tx, rx) = async_channel::unbounded();
+    let builder = TftpServerBuilder::with_handler(state_handler(tx));
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "panic").await;
+        assert!(client.recv(WAIT).await.is_some());
+        client.send(Packet::Ack(1)).await;
+
+        for event in ["params", "panicked", "finished"] {
+            assert_eq!(rx.recv().await.unwrap(), (0, event));
+        }
+    });
+}
+
+#[test]
+fn restarted_read_is_finished() {
+    let (tx, rx) = async_channel::unbounded();
+    let builder = TftpServerBuilder::with_handler(state_handler(tx))
+        .restart_window(Duration::from_secs(5));
+
+    run_with_server(builder, |addr| async move {
+        let mut abandoned = RawClient::rrq(addr, "test").await;
+        assert!(abandoned.recv(WAIT).await.is_some());
+        abandoned.send(Packet::Ack(1)).await;
+        assert!(abandoned.recv(WAIT).await.is_some());
+
+        let mut restarted = RawClient::rrq(addr, "test").await;
+        restarted.finish().await;
+
+        // Events of both transfers are interleaved
+        let mut events = [Vec::new(), Vec::new()];
+        for _ in 0..8 {
+            let (id, event) = rx.recv().await.unwrap();
+            events[id as usize].push(event);
+        }
+
+        let [abandoned, restarted] = events;
+        assert_eq!(abandoned, ["params", "started", "cancelled", "finished"]);
+        assert_eq!(restarted, ["params", "started", "completed", "finished"]);
+    });
+}
@@ -0,0 +1,47 @@
+use futures_lite::io::Cursor;
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::server::TftpServerBuilder;
+use crate::test_util::MemoryNetwork;
+
+#[test]
+fn transfers_over_memory_network() {
+    let handler = MemHandler::new(content(3000));
+    let written = handler.written();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    run_with_memory_server(builder, |network, addr| async move {
+        let client = TftpClient::new(addr)
+            .block_size(1024)
+            .transport(memory_client(&network));
+
+        let data = client.read_to_vec("test").await.unwrap();
+        assert_eq!(data, content(3000));
+
+        let sent = client
+            .write("upload", Cursor::new(content(1500)), Some(1500))
+            .await
+            .unwrap();
+        assert_eq!(sent, 1500);
+
+        wait_until(Duration::from_secs(1), || {
+            *written.lock().unwrap() == content(1500)
+        })
+        .await;
+    });
+}
+
+#[test]
+fn memory_sockets_are_released_on_drop() {
+    let network = MemoryNetwork::new();
+    let addr = "10.0.0.1:69".parse().unwrap();
+
+    let socket = network.bind(addr).unwrap();
+    assert!(network.bind(addr).is_err());
+
+    drop(socket);
+    assert!(network.bind(addr).is_ok());
+}
@@ -0,0 +1,90 @@
+#![cfg(unix)]
+
+use futures_lite::io::Cursor;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+use tempfile::tempdir;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::client::TftpClient;
+use crate::clock::SystemClock;
+use crate::packet::Packet;
+use crate::server::TftpServerBuilder;
+use crate::test_util::RequestBuilder;
+use crate::transport::{Transport, UnixTransport};
+use crate::utils::io_timeout;
+
+#[test]
+fn transfers_over_unix_sockets() {
+    let tmp = tempdir().unwrap();
+    let server_path = tmp.path().join("tftpd.sock");
+    let client_path = tmp.path().join("client.sock");
+
+    let handler = MemHandler::new(content(3000));
+    let written = handler.written();
+    let builder = TftpServerBuilder::with_handler(handler);
+    let socket = UnixTransport::bind(&server_path).unwrap();
+
+    run_with_transport(builder, socket, |_| async move {
+        let transport = UnixTransport::bind(&client_path).unwrap();
+        let server = transport.peer_addr(&server_path);
+        let client =
+            TftpClient::new(server).block_size(1024).transport(transport);
+
+        let data = client.read_to_vec("test").await.unwrap();
+        assert_eq!(data, content(3000));
+
+        let sent = client
+            .write("upload", Cursor::new(content(1500)), Some(1500))
+            .await
+            .unwrap();
+        assert_eq!(sent, 1500);
+
+        wait_until(Duration::from_secs(1), || {
+            *written.lock().unwrap() == content(1500)
+        })
+        .await;
+    });
+
+    // Only the sockets that are still bound are left
+    let mut paths: Vec<_> = std::fs::read_dir(tmp.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    paths.sort();
+    assert!(paths.is_empty(), "sockets left: {:?}", paths);
+}
+
+#[test]
+fn unbound_sockets_are_ignored() {
+    let tmp = tempdir().unwrap();
+    let server_path = tmp.path().join("tftpd.sock");
+
+    let handler = MemHandler::new(content(1000));
+    let builder = TftpServerBuilder::with_handler(handler);
+    let socket = UnixTransport::bind(&server_path).unwrap();
+
+    run_with_transport(builder, socket, |_| async move {
+        let rrq = Packet::Rrq(RequestBuilder::new("test").build());
+
+        // Replies can not reach a socket without a path
+        let unbound = UnixDatagram::unbound().unwrap();
+        unbound.send_to(&rrq.to_vec(), &server_path).unwrap();
+
+        // Requests of the next socket are still served
+        let socket =
+            UnixTransport::bind(tmp.path().join("client.sock")).unwrap();
+        let server = socket.peer_addr(&server_path);
+        let socket: &dyn Transport = &socket;
+        socket.send_to(&rrq.to_vec(), server).await.unwrap();
+
+        let mut buf = [0; 1024];
+        let wait = Duration::from_secs(1);
+        let (len, _) =
+            io_timeout(&SystemClock, wait, socket.recv_from(&mut buf))
+                .await
+                .unwrap();
+        assert!(matches!(Packet::decode(&buf[..len]), Ok(Packet::Data(1, _))));
+    });
+}
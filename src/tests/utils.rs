@@ -0,0 +1,389 @@
+use async_channel::Sender;
+use async_executor::Executor;
+use async_io::Async;
+use futures_lite::future::{self, block_on};
+use futures_lite::AsyncReadExt;
+use std::future::Future;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::clock::SystemClock;
+use crate::packet::{self, OwnedPacket, Packet};
+use crate::server::{AuditRecord, AuditSink, Handler, TftpServerBuilder};
+use crate::test_util::{MemoryNetwork, MemoryTransport, RequestBuilder};
+use crate::transport::Transport;
+use crate::utils::io_timeout;
+
+/// Start the server that `builder` produces on loopback, run `f` and return
+/// its output.
+///
+/// The server is dropped when `f` finishes.
+pub fn run_with_server<H, F, Fut, T>(builder: TftpServerBuilder<H>, f: F) -> T
+where
+    H: Handler + 'static,
+    F: FnOnce(SocketAddr) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let ex = Executor::new();
+
+    block_on(ex.run(async {
+        let tftpd = builder
+            .bind("127.0.0.1:0".parse().unwrap())
+            .build()
+            .await
+            .expect("failed to build server");
+        let addr = tftpd.listen_addr().unwrap();
+
+        let server = ex.spawn(async move {
+            tftpd.serve().await.expect("server failed");
+        });
+
+        let res = f(addr).await;
+        drop(server);
+        res
+    }))
+}
+
+/// Start the server that `builder` produces on a [`MemoryNetwork`], run `f`
+/// with the network and the server address, and return its output.
+///
+/// Datagrams are never lost or delayed by the OS, so tests of injected
+/// faults are not disturbed by real ones.
+pub fn run_with_memory_server<H, F, Fut, T>(
+    builder: TftpServerBuilder<H>,
+    f: F,
+) -> T
+where
+    H: Handler + 'static,
+    F: FnOnce(MemoryNetwork, SocketAddr) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let ex = Executor::new();
+    let network = MemoryNetwork::new();
+
+    block_on(ex.run(async {
+        let socket = network
+            .bind("10.0.0.1:69".parse().unwrap())
+            .expect("failed to bind server");
+        let tftpd = builder
+            .transport(socket)
+            .build()
+            .await
+            .expect("failed to build server");
+        let addr = tftpd.listen_addr().unwrap();
+
+        let server = ex.spawn(async move {
+            tftpd.serve().await.expect("server failed");
+        });
+
+        let res = f(network, addr).await;
+        drop(server);
+        res
+    }))
+}
+
+/// Start the server that `builder` produces on `transport`, run `f` with
+/// the server address and return its output.
+pub fn run_with_transport<H, T, F, Fut, R>(
+    builder: TftpServerBuilder<H>,
+    transport: T,
+    f: F,
+) -> R
+where
+    H: Handler + 'static,
+    T: Transport + 'static,
+    F: FnOnce(SocketAddr) -> Fut,
+    Fut: Future<Output = R>,
+{
+    let ex = Executor::new();
+
+    block_on(ex.run(async {
+        let tftpd = builder
+            .transport(transport)
+            .build()
+            .await
+            .expect("failed to build server");
+        let addr = tftpd.listen_addr().unwrap();
+
+        let server = ex.spawn(async move {
+            tftpd.serve().await.expect("server failed");
+        });
+
+        let res = f(addr).await;
+        drop(server);
+        res
+    }))
+}
+
+/// Bind a client socket on `network`.
+pub fn memory_client(network: &MemoryNetwork) -> MemoryTransport {
+    network.bind("10.0.0.2:0".parse().unwrap()).expect("failed to bind client")
+}
+
+/// Serve the server that `builder` produces on loopback while `f` runs.
+///
+/// Unlike [`run_with_server`] this runs on the current executor, so it can
+/// be nested in it.
+pub async fn with_server<H, F, Fut, T>(builder: TftpServerBuilder<H>, f: F) -> T
+where
+    H: Handler + 'static,
+    F: FnOnce(SocketAddr) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let tftpd = builder
+        .bind("127.0.0.1:0".parse().unwrap())
+        .build()
+        .await
+        .expect("failed to build server");
+    let addr = tftpd.listen_addr().unwrap();
+
+    let serve = async move {
+        tftpd.serve().await.expect("server failed");
+        unreachable!("server stopped");
+    };
+
+    future::or(serve, f(addr)).await
+}
+
+/// Wait until `cond` is satisfied or panic after `timeout`.
+pub async fn wait_until(timeout: Duration, mut cond: impl FnMut() -> bool) {
+    let wait = async {
+        while !cond() {
+            async_io::Timer::after(Duration::from_millis(5)).await;
+        }
+    };
+
+    future::or(wait, async {
+        async_io::Timer::after(timeout).await;
+        panic!("condition was not satisfied in {:?}", timeout);
+    })
+    .await
+}
+
+/// Audit sink that sends every record to a channel.
+pub struct ChannelSink(pub Sender<AuditRecord>);
+
+#[crate::async_trait]
+impl AuditSink for ChannelSink {
+    async fn record(&self, record: &AuditRecord) {
+        self.0.send(record.clone()).await.unwrap();
+    }
+}
+
+/// Address of the client in tests that call handlers directly.
+pub fn handler_client() -> SocketAddr {
+    "127.0.0.1:1234".parse().unwrap()
+}
+
+/// Open `path` with `handler` and read it to the end. Returns the content
+/// and the size that the handler reported.
+pub fn handler_read<H: Handler>(
+    handler: &mut H,
+    path: &str,
+) -> Result<(Vec<u8>, Option<u64>), packet::Error> {
+    block_on(async {
+        let (mut reader, size) =
+            handler.read_req_open(&handler_client(), Path::new(path)).await?;
+
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).await.unwrap();
+        Ok((content, size))
+    })
+}
+
+/// Deterministic content of `len` bytes.
+pub fn content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// Send `packet` from a new socket and return the raw reply.
+pub async fn request(addr: SocketAddr, packet: Packet<'_>) -> Vec<u8> {
+    let mut client = RawClient::request(addr, packet).await;
+
+    let reply = client.recv_bytes(Duration::from_secs(5)).await;
+    reply.expect("no packet received")
+}
+
+/// Client that drives a transfer packet by packet.
+///
+/// Replies are sent to the address of the last received packet, i.e. the
+/// socket of the transfer.
+pub struct RawClient {
+    socket: Async<UdpSocket>,
+    peer: Option<SocketAddr>,
+    last_block: Option<(u16, usize)>,
+}
+
+impl RawClient {
+    /// Bind a new socket without sending anything.
+    pub fn bind() -> RawClient {
+        RawClient::bind_to(([127, 0, 0, 1], 0).into())
+    }
+
+    /// Bind a socket to `addr`, e.g. the address of a dropped client.
+    pub fn bind_to(addr: SocketAddr) -> RawClient {
+        RawClient {
+            socket: Async::<UdpSocket>::bind(addr).unwrap(),
+            peer: None,
+            last_block: None,
+        }
+    }
+
+    /// Send `packet` from a new socket, e.g. a request of
+    /// [`RequestBuilder`](crate::test_util::RequestBuilder).
+    pub async fn request(addr: SocketAddr, packet: Packet<'_>) -> RawClient {
+        let mut client = RawClient::bind();
+        client.send_to(addr, packet).await;
+        client
+    }
+
+    /// Send RRQ of `req` from a new socket.
+    pub async fn rrq_with(addr: SocketAddr, req: RequestBuilder) -> RawClient {
+        RawClient::request(addr, Packet::Rrq(req.build())).await
+    }
+
+    /// Send WRQ of `req` from a new socket.
+    pub async fn wrq_with(addr: SocketAddr, req: RequestBuilder) -> RawClient {
+        RawClient::request(addr, Packet::Wrq(req.build())).await
+    }
+
+    /// Send RRQ of `filename` from a new socket.
+    pub async fn rrq(addr: SocketAddr, filename: &str) -> RawClient {
+        RawClient::rrq_with(addr, RequestBuilder::new(filename)).await
+    }
+
+    /// Send WRQ of `filename` from a new socket.
+    pub async fn wrq(addr: SocketAddr, filename: &str) -> RawClient {
+        RawClient::wrq_with(addr, RequestBuilder::new(filename)).await
+    }
+
+    /// Send RRQ of `filename` from the same socket.
+    pub async fn rrq_again(&mut self, addr: SocketAddr, filename: &str) {
+        let req = RequestBuilder::new(filename).build();
+        self.send_to(addr, Packet::Rrq(req)).await;
+
+        self.peer = None;
+        self.last_block = None;
+    }
+
+    /// Address that the socket is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.socket.get_ref().local_addr().unwrap()
+    }
+
+    /// Send `packet` to `addr`.
+    pub async fn send_to(&mut self, addr: SocketAddr, packet: Packet<'_>) {
+        self.send_bytes_to(addr, &packet.to_vec()).await;
+    }
+
+    /// Send `data` to `addr` as it is, e.g. a malformed request.
+    pub async fn send_bytes_to(&mut self, addr: SocketAddr, data: &[u8]) {
+        self.socket.send_to(data, addr).await.unwrap();
+    }
+
+    /// Send `packet` to the server socket that serves the transfer.
+    pub async fn send(&mut self, packet: Packet<'_>) {
+        let peer = self.peer.expect("no packet received");
+        self.send_to(peer, packet).await;
+    }
+
+    /// Receive the next datagram as it is, or `None` if nothing arrives in
+    /// `wait`.
+    pub async fn recv_bytes(&mut self, wait: Duration) -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; 65536];
+
+        let recv =
+            io_timeout(&SystemClock, wait, self.socket.recv_from(&mut buf));
+        let (len, peer) = recv.await.ok()?;
+
+        self.peer = Some(peer);
+        buf.truncate(len);
+        Some(buf)
+    }
+
+    /// Receive the next packet of any type, or `None` if nothing arrives
+    /// in `wait`.
+    pub async fn recv_packet(&mut self, wait: Duration) -> Option<OwnedPacket> {
+        let buf = self.recv_bytes(wait).await?;
+        Some(OwnedPacket::decode(&buf).expect("invalid packet"))
+    }
+
+    /// Receive the next packet and check that it is `packet`.
+    pub async fn expect(&mut self, packet: Packet<'_>) {
+        let received = self
+            .recv_packet(Duration::from_secs(5))
+            .await
+            .expect("no packet received");
+
+        assert_eq!(received.as_packet(), packet);
+    }
+
+    /// Whether nothing arrives in `wait`.
+    pub async fn is_idle(&mut self, wait: Duration) -> bool {
+        let mut buf = [0u8; 1024];
+        let recv = self.socket.recv_from(&mut buf);
+        io_timeout(&SystemClock, wait, recv).await.is_err()
+    }
+
+    /// Receive the next DATA packet without acknowledging it. Returns
+    /// its block id and payload length, or `None` if nothing arrives
+    /// in `wait`.
+    ///
+    /// Panics if any other packet arrives.
+    pub async fn recv(&mut self, wait: Duration) -> Option<(u16, usize)> {
+        let block = match self.recv_packet(wait).await? {
+            OwnedPacket::Data(id, payload) => (id, payload.len()),
+            packet => panic!("unexpected packet: {:?}", packet),
+        };
+
+        self.last_block = Some(block);
+        Some(block)
+    }
+
+    /// Address of the server socket that serves the transfer.
+    pub fn server_addr(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// Acknowledge the last received block.
+    pub async fn ack(&mut self) {
+        let (id, _) = self.last_block.expect("no block received");
+        self.send(Packet::Ack(id)).await;
+    }
+
+    /// Terminate the transfer with `error`.
+    pub async fn error(&mut self, error: packet::Error) {
+        self.send(Packet::Error(error)).await;
+    }
+
+    /// Receive and acknowledge the rest of the transfer.
+    pub async fn finish(&mut self) {
+        if let Some((_, len)) = self.last_block {
+            self.ack().await;
+
+            if len < 512 {
+                return;
+            }
+        }
+
+        loop {
+            let last_id = self.last_block.map(|(id, _)| id);
+            let (id, len) = self
+                .recv(Duration::from_secs(5))
+                .await
+                .expect("no packet received");
+
+            self.ack().await;
+
+            // Retransmission of a block that is already acknowledged
+            if Some(id) == last_id {
+                continue;
+            }
+
+            if len < 512 {
+                break;
+            }
+        }
+    }
+}
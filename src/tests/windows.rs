@@ -0,0 +1,204 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::clock::MockClock;
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::{Opts, OwnedPacket, Packet};
+use crate::server::TftpServerBuilder;
+use crate::test_util::RequestBuilder;
+
+const FILE_SIZE: usize = 512 * 6 + 10;
+
+fn builder() -> TftpServerBuilder<MemHandler> {
+    let handler = MemHandler::new(content(FILE_SIZE));
+    TftpServerBuilder::with_handler(handler).timeout(Duration::from_secs(3))
+}
+
+/// Wait for blocks that are expected, and for silence.
+const WAIT: Duration = Duration::from_millis(500);
+
+/// Send RRQ that requests `window_size` and acknowledge the OACK.
+async fn rrq(addr: SocketAddr, window_size: u64) -> (RawClient, Opts) {
+    let req =
+        RequestBuilder::new("test").window_size(window_size).transfer_size(0);
+    let mut client = RawClient::rrq_with(addr, req).await;
+
+    let opts = match client.recv_packet(WAIT).await {
+        Some(OwnedPacket::OAck(opts)) => opts,
+        packet => panic!("unexpected packet: {:?}", packet),
+    };
+
+    client.send(Packet::Ack(0)).await;
+    (client, opts)
+}
+
+async fn expect_blocks(client: &mut RawClient, ids: &[u16]) {
+    for &id in ids {
+        let len = if id == 7 {
+            10
+        } else {
+            512
+        };
+        assert_eq!(client.recv(WAIT).await, Some((id, len)));
+    }
+}
+
+#[test]
+fn window_is_ignored_by_default() {
+    run_with_server(builder(), |addr| async move {
+        let (mut client, opts) = rrq(addr, 4).await;
+        assert_eq!(opts.window_size, None);
+
+        expect_blocks(&mut client, &[1]).await;
+        assert_eq!(client.recv(WAIT).await, None);
+    });
+}
+
+#[test]
+fn window_is_negotiated() {
+    let builder = builder().max_window_size(4);
+
+    run_with_server(builder, |addr| async move {
+        let (mut client, opts) = rrq(addr, 8).await;
+        assert_eq!(opts.window_size, Some(4));
+
+        expect_blocks(&mut client, &[1, 2, 3, 4]).await;
+        assert_eq!(client.recv(WAIT).await, None);
+
+        client.send(Packet::Ack(4)).await;
+        expect_blocks(&mut client, &[5, 6, 7]).await;
+
+        client.send(Packet::Ack(7)).await;
+        assert_eq!(client.recv(WAIT).await, None);
+    });
+}
+
+#[test]
+fn window_resumes_after_acked_block() {
+    let builder = builder().max_window_size(4);
+
+    run_with_server(builder, |addr| async move {
+        let (mut client, _) = rrq(addr, 4).await;
+        expect_blocks(&mut client, &[1, 2, 3, 4]).await;
+
+        // Block 3 was lost
+        client.send(Packet::Ack(2)).await;
+        expect_blocks(&mut client, &[3, 4, 5, 6]).await;
+
+        client.send(Packet::Ack(6)).await;
+        expect_blocks(&mut client, &[7]).await;
+        client.send(Packet::Ack(7)).await;
+    });
+}
+
+#[test]
+fn adaptive_window() {
+    let builder = builder().max_window_size(4).adaptive_window();
+
+    run_with_server(builder, |addr| async move {
+        let (mut client, _) = rrq(addr, 4).await;
+        expect_blocks(&mut client, &[1, 2, 3, 4]).await;
+
+        // Window shrinks to 2 blocks on loss
+        client.send(Packet::Ack(2)).await;
+        expect_blocks(&mut client, &[3, 4]).await;
+        assert_eq!(client.recv(WAIT).await, None);
+
+        // And grows again when all blocks are acknowledged
+        client.send(Packet::Ack(4)).await;
+        expect_blocks(&mut client, &[5, 6, 7]).await;
+        client.send(Packet::Ack(7)).await;
+    });
+}
+
+#[test]
+fn duplicate_ack_resends_window() {
+    let builder = builder().max_window_size(4);
+
+    run_with_server(builder, |addr| async move {
+        let (mut client, _) = rrq(addr, 4).await;
+        expect_blocks(&mut client, &[1, 2, 3, 4]).await;
+
+        // Long before the timeout
+        client.send(Packet::Ack(0)).await;
+        expect_blocks(&mut client, &[1, 2, 3, 4]).await;
+
+        client.send(Packet::Ack(4)).await;
+        expect_blocks(&mut client, &[5, 6, 7]).await;
+        client.send(Packet::Ack(7)).await;
+    });
+}
+
+#[test]
+fn adaptive_window_shrinks_on_duplicate_ack() {
+    let builder = builder().max_window_size(4).adaptive_window();
+
+    run_with_server(builder, |addr| async move {
+        let (mut client, _) = rrq(addr, 4).await;
+        expect_blocks(&mut client, &[1, 2, 3, 4]).await;
+
+        client.send(Packet::Ack(0)).await;
+        expect_blocks(&mut client, &[1, 2]).await;
+        assert_eq!(client.recv(WAIT).await, None);
+
+        client.send(Packet::Ack(2)).await;
+        expect_blocks(&mut client, &[3, 4, 5]).await;
+    });
+}
+
+#[test]
+fn packets_of_window_are_paced() {
+    let clock = MockClock::new();
+    let builder = builder()
+        .clock(Arc::new(clock.clone()))
+        .max_window_size(3)
+        .window_packet_gap(Duration::from_millis(10));
+
+    run_with_server(builder, |addr| async move {
+        let (mut client, _) = rrq(addr, 3).await;
+
+        for id in 1..=3 {
+            expect_blocks(&mut client, &[id]).await;
+
+            // Next block waits for the gap, and the last one for the ACK
+            wait_until(Duration::from_secs(1), || clock.pending_sleeps() == 1)
+                .await;
+            assert_eq!(client.recv(WAIT).await, None);
+
+            if id < 3 {
+                clock.advance(Duration::from_millis(10));
+            }
+        }
+
+        client.send(Packet::Ack(3)).await;
+        expect_blocks(&mut client, &[4]).await;
+    });
+}
+
+#[test]
+fn window_is_limited_by_memory_budget() {
+    // Budget of two DATA packets
+    let builder = builder().max_window_size(4).memory_budget(2 * 516);
+
+    run_with_server(builder, |addr| async move {
+        let (mut client, opts) = rrq(addr, 4).await;
+        assert_eq!(opts.window_size, Some(4));
+
+        expect_blocks(&mut client, &[1, 2]).await;
+        assert_eq!(client.recv(WAIT).await, None);
+
+        client.send(Packet::Ack(2)).await;
+        expect_blocks(&mut client, &[3, 4]).await;
+
+        client.send(Packet::Ack(4)).await;
+        expect_blocks(&mut client, &[5, 6]).await;
+
+        client.send(Packet::Ack(6)).await;
+        expect_blocks(&mut client, &[7]).await;
+
+        client.send(Packet::Ack(7)).await;
+        assert_eq!(client.recv(WAIT).await, None);
+    });
+}
@@ -0,0 +1,113 @@
+#![cfg(windows)]
+
+use async_io::{Async, Timer};
+use futures_lite::future::{self, block_on};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::server::{TftpServerBuilder, TransferOutcome};
+use crate::transport::Transport;
+use crate::utils::{bind_with, set_udp_conn_reset};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Address that no socket is bound to.
+fn closed_port() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.local_addr().unwrap()
+}
+
+/// Send a datagram from `socket` to a closed port, so Windows gets an ICMP
+/// "port unreachable", and receive the next datagram.
+fn recv_after_unreachable(socket: &UdpSocket) -> io::Result<usize> {
+    socket.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    socket.send_to(b"ping", closed_port()).unwrap();
+
+    let mut buf = [0; 16];
+    socket.recv_from(&mut buf).map(|(len, _)| len)
+}
+
+fn local_addr() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+#[test]
+fn port_unreachable_is_not_reported() {
+    let socket = bind_with(local_addr(), false, |_| Ok(())).unwrap();
+
+    let err = recv_after_unreachable(&socket).unwrap_err();
+    assert!(
+        matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ),
+        "unexpected error: {:?}",
+        err
+    );
+}
+
+#[test]
+fn port_unreachable_is_reported_if_enabled() {
+    let socket = bind_with(local_addr(), false, |socket| {
+        set_udp_conn_reset(socket, true)
+    })
+    .unwrap();
+
+    let err = recv_after_unreachable(&socket).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+}
+
+/// Windows fails the receive of a datagram that does not fit in the buffer
+/// with `WSAEMSGSIZE`, which is received as truncated datagram.
+#[test]
+fn oversized_datagrams_are_truncated() {
+    block_on(async {
+        let socket = Async::<UdpSocket>::bind(local_addr()).unwrap();
+        let addr = socket.get_ref().local_addr().unwrap();
+        let sender = UdpSocket::bind(local_addr()).unwrap();
+
+        sender.send_to(&[1; 100], addr).unwrap();
+        sender.send_to(&[2; 5], addr).unwrap();
+
+        let socket: &dyn Transport = &socket;
+        let mut buf = [0; 10];
+
+        let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(len, 10);
+        assert_eq!(buf, [1; 10]);
+        assert_eq!(peer, sender.local_addr().unwrap());
+
+        let (len, _) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], [2; 5]);
+    });
+}
+
+#[test]
+fn port_unreachable_fails_transfer() {
+    let (tx, rx) = async_channel::unbounded();
+    let handler = MemHandler::new(content(512 * 3));
+    let builder = TftpServerBuilder::with_handler(handler)
+        .timeout(Duration::from_millis(100))
+        .max_send_retries(1000)
+        .audit(ChannelSink(tx))
+        .fail_on_port_unreachable();
+
+    run_with_server(builder, |addr| async move {
+        let mut client = RawClient::rrq(addr, "test").await;
+        assert_eq!(client.recv(WAIT).await, Some((1, 512)));
+        drop(client);
+
+        // Long before the retransmissions of the server run out
+        let record = future::or(async { rx.recv().await.ok() }, async {
+            Timer::after(WAIT).await;
+            None
+        })
+        .await
+        .expect("transfer did not fail");
+
+        assert_eq!(record.outcome, TransferOutcome::Other);
+    });
+}
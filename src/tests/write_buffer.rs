@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::client::TestClient;
+use super::faults::{Faults, FaultySocket};
+use super::mem_handler::MemHandler;
+use super::utils::*;
+use crate::packet::Opts;
+use crate::server::{TftpServerBuilder, TransferParams};
+
+/// Upload `len` bytes and wait until the server wrote them.
+fn upload(
+    builder: TftpServerBuilder<MemHandler>,
+    written: Arc<Mutex<Vec<u8>>>,
+    faults: Faults,
+    len: usize,
+) {
+    run_with_memory_server(builder, |network, addr| async move {
+        let socket = FaultySocket::new(memory_client(&network), faults);
+        let mut client = TestClient::new(socket, addr);
+
+        client
+            .write("upload", Opts::default(), &content(len))
+            .await
+            .expect("transfer failed");
+
+        wait_until(Duration::from_secs(1), || {
+            *written.lock().unwrap() == content(len)
+        })
+        .await;
+    });
+}
+
+#[test]
+fn blocks_are_written_right_away_by_default() {
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let writes = handler.writes();
+    let builder = TftpServerBuilder::with_handler(handler);
+
+    upload(builder, written, Faults::none(), 1100);
+
+    assert_eq!(*writes.lock().unwrap(), vec![512, 512, 76]);
+}
+
+#[test]
+fn blocks_are_written_in_chunks() {
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let writes = handler.writes();
+    let builder =
+        TftpServerBuilder::with_handler(handler).write_buffer_size(2000);
+
+    upload(builder, written, Faults::none(), 5000);
+
+    assert_eq!(*writes.lock().unwrap(), vec![2048, 2048, 904]);
+}
+
+#[test]
+fn lossy_upload_is_written_in_chunks() {
+    let handler = MemHandler::new(Vec::new());
+    let written = handler.written();
+    let writes = handler.writes();
+    let builder = TftpServerBuilder::with_handler(handler)
+        .write_buffer_size(4096)
+        .timeout(Duration::from_millis(20))
+        .max_send_retries(50);
+    let faults = Faults::none().loss(0.1).duplicate(0.1).reorder(0.1).seed(8);
+
+    upload(builder, written, faults, 20000);
+
+    assert_eq!(*writes.lock().unwrap(), vec![4096, 4096, 4096, 4096, 3616]);
+}
+
+#[test]
+fn handler_overrides_write_buffer_size() {
+    let handler = MemHandler::new(Vec::new()).with_params(TransferParams {
+        write_buffer_size: Some(0),
+        ..TransferParams::default()
+    });
+    let written = handler.written();
+    let writes = handler.writes();
+    let builder =
+        TftpServerBuilder::with_handler(handler).write_buffer_size(2000);
+
+    upload(builder, written, Faults::none(), 1100);
+
+    assert_eq!(*writes.lock().unwrap(), vec![512, 512, 76]);
+}
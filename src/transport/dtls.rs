@@ -0,0 +1,790 @@
+//! Experimental transport over DTLS.
+
+use async_io::Timer;
+use futures_lite::Stream;
+use log::{debug, trace};
+use openssl::error::ErrorStack;
+use openssl::ex_data::Index;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::{PKey, Private};
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use openssl::ssl::{
+    ErrorCode, Ssl, SslAcceptor, SslAcceptorBuilder, SslConnector, SslOptions,
+    SslStream,
+};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use super::Transport;
+
+/// MTU of the handshake datagrams.
+const MTU: u32 = 1400;
+
+/// Largest datagram of the underlying transport.
+const MAX_DATAGRAM: usize = 65535;
+
+/// Largest plaintext of a DTLS record, and so of a TFTP packet.
+const MAX_PLAINTEXT: usize = 16384;
+
+/// Decrypted datagrams that wait to be received, per socket, before new
+/// ones are dropped.
+const QUEUE_LIMIT: usize = 64;
+
+/// Sessions of the server, including the ones that are being negotiated.
+const MAX_SESSIONS: usize = 1024;
+
+/// Sessions without a transfer are dropped after they are idle for this
+/// long.
+const SESSION_IDLE: Duration = Duration::from_secs(60);
+
+/// Interval of the checks for idle sessions.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Length of the header of a DTLS record.
+const RECORD_HEADER: usize = 13;
+
+/// Length of the header of a DTLS handshake message.
+const HANDSHAKE_HEADER: usize = 12;
+
+/// Transport that runs TFTP over DTLS, on top of another [`Transport`].
+///
+/// This is experimental. It is meant for clients that speak DTLS to a
+/// gateway or to the server directly, e.g. to deliver firmware over an
+/// encrypted channel.
+///
+/// A DTLS session is established per peer address. The server accepts
+/// sessions on its listening socket, and the transfers of a client run
+/// over the session that carried their request: replies come from the
+/// address the request was sent to, not from a new port. The client
+/// connects a new session for every transfer.
+///
+/// The server answers a new peer with a stateless cookie (RFC 6347,
+/// section 4.2.1), and keeps a session only once the peer returns it, so
+/// spoofed addresses can not fill the sessions.
+///
+/// A TFTP packet must fit in a DTLS record, so block sizes are limited to
+/// 16380 bytes. Handshake datagrams that are lost are sent again when the
+/// client sends its request again.
+///
+/// # Example
+///
+/// ```ignore
+/// let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::dtls())?;
+/// acceptor.set_private_key_file("key.pem", SslFiletype::PEM)?;
+/// acceptor.set_certificate_chain_file("cert.pem")?;
+///
+/// let udp = Async::<UdpSocket>::bind("0.0.0.0:6969".parse()?)?;
+/// let tftpd = TftpServerBuilder::with_dir("/srv/tftp")?
+///     .transport(DtlsTransport::server(udp, acceptor)?)
+///     .build()
+///     .await?;
+/// ```
+pub struct DtlsTransport {
+    shared: Arc<Shared>,
+    // Peer of the transfer, or `None` for the socket that receives
+    // datagrams of any peer
+    peer: Option<SocketAddr>,
+    // Id of the session of the transfer, so a later session of the same
+    // peer is not taken for it
+    session: u64,
+}
+
+#[derive(Clone)]
+enum Role {
+    Server(SslAcceptor, Cookies),
+    Client(SslConnector, String),
+}
+
+/// Stateless cookies of the handshakes of the server, which are bound to
+/// the address of the peer.
+#[derive(Clone)]
+struct Cookies {
+    key: PKey<Private>,
+    // Peer of a session, for the callbacks of OpenSSL
+    peer: Index<Ssl, SocketAddr>,
+}
+
+struct Shared {
+    inner: Box<dyn Transport>,
+    role: Role,
+    state: Mutex<State>,
+    wakers: Arc<Wakers>,
+}
+
+struct State {
+    sessions: HashMap<SocketAddr, Session>,
+    next_id: u64,
+    // Decrypted datagrams of peers without a transfer
+    unbound: VecDeque<(Vec<u8>, SocketAddr)>,
+    recv_buf: Vec<u8>,
+    // Timer of the checks for idle sessions of the server
+    sweep: Option<Timer>,
+}
+
+struct Session {
+    id: u64,
+    stream: SslStream<Datagrams>,
+    established: bool,
+    // Transfers that run over the session
+    transfers: usize,
+    // Decrypted datagrams of the transfers
+    received: VecDeque<Vec<u8>>,
+    // Datagram that waits for the handshake to finish
+    pending: Option<Vec<u8>>,
+    last_active: Instant,
+}
+
+/// Datagrams between the DTLS session and the underlying transport.
+#[derive(Default)]
+struct Datagrams {
+    incoming: VecDeque<Vec<u8>>,
+    outgoing: VecDeque<Vec<u8>>,
+}
+
+/// Tasks that wait for datagrams, per socket.
+///
+/// The underlying transport is polled with a waker that wakes all of them,
+/// since any socket may receive the datagram of another one.
+#[derive(Default)]
+struct Wakers(Mutex<HashMap<Option<SocketAddr>, Waker>>);
+
+impl DtlsTransport {
+    /// Accept DTLS sessions on `inner` with `acceptor`.
+    ///
+    /// The cookie exchange of `acceptor` is configured, which replaces
+    /// cookie callbacks that it already has.
+    pub fn server<T>(
+        inner: T,
+        mut acceptor: SslAcceptorBuilder,
+    ) -> io::Result<Self>
+    where
+        T: Transport + 'static,
+    {
+        let cookies = Cookies::new()?;
+        cookies.configure(&mut acceptor);
+
+        let role = Role::Server(acceptor.build(), cookies);
+        Ok(DtlsTransport::new(Box::new(inner), role))
+    }
+
+    /// Connect DTLS sessions from `inner` with `connector`, verifying that
+    /// the certificate of the server is valid for `domain`.
+    pub fn client<T>(inner: T, connector: SslConnector, domain: &str) -> Self
+    where
+        T: Transport + 'static,
+    {
+        let role = Role::Client(connector, domain.to_string());
+        DtlsTransport::new(Box::new(inner), role)
+    }
+
+    fn new(inner: Box<dyn Transport>, role: Role) -> Self {
+        let sweep = match role {
+            Role::Server(..) => Some(Timer::interval(SWEEP_INTERVAL)),
+            Role::Client(..) => None,
+        };
+
+        DtlsTransport {
+            shared: Arc::new(Shared {
+                inner,
+                role,
+                state: Mutex::new(State {
+                    sessions: HashMap::new(),
+                    next_id: 1,
+                    unbound: VecDeque::new(),
+                    recv_buf: vec![0; MAX_DATAGRAM],
+                    sweep,
+                }),
+                wakers: Arc::default(),
+            }),
+            peer: None,
+            session: 0,
+        }
+    }
+}
+
+impl Cookies {
+    fn new() -> Result<Self, ErrorStack> {
+        let mut secret = [0; 32];
+        rand_bytes(&mut secret)?;
+
+        Ok(Cookies {
+            key: PKey::hmac(&secret)?,
+            peer: Ssl::new_ex_index()?,
+        })
+    }
+
+    /// Let `acceptor` send and verify the cookies.
+    fn configure(&self, acceptor: &mut SslAcceptorBuilder) {
+        acceptor.set_options(SslOptions::COOKIE_EXCHANGE);
+
+        let cookies = self.clone();
+        acceptor.set_cookie_generate_cb(move |ssl, buf| {
+            let peer =
+                *ssl.ex_data(cookies.peer).ok_or_else(ErrorStack::get)?;
+            let cookie = cookies.cookie(peer)?;
+            let len = cookie.len().min(buf.len());
+            buf[..len].copy_from_slice(&cookie[..len]);
+            Ok(len)
+        });
+
+        let cookies = self.clone();
+        acceptor.set_cookie_verify_cb(move |ssl, cookie| {
+            match ssl.ex_data(cookies.peer) {
+                Some(&peer) => cookies.verify(peer, cookie),
+                None => false,
+            }
+        });
+    }
+
+    /// Cookie of `peer`.
+    fn cookie(&self, peer: SocketAddr) -> Result<Vec<u8>, ErrorStack> {
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+        signer.sign_oneshot_to_vec(peer.to_string().as_bytes())
+    }
+
+    /// Whether `cookie` is the cookie of `peer`.
+    fn verify(&self, peer: SocketAddr, cookie: &[u8]) -> bool {
+        match self.cookie(peer) {
+            Ok(expected) => {
+                expected.len() == cookie.len() && memcmp::eq(&expected, cookie)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl State {
+    /// Keep `session` of `peer` under a new id.
+    fn insert(
+        &mut self,
+        peer: SocketAddr,
+        mut session: Session,
+    ) -> &mut Session {
+        session.id = self.next_id;
+        self.next_id += 1;
+
+        match self.sessions.entry(peer) {
+            Entry::Occupied(mut entry) => {
+                entry.insert(session);
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => entry.insert(session),
+        }
+    }
+
+    /// Session of `peer`, if its id is `id`.
+    fn session(&mut self, peer: SocketAddr, id: u64) -> Option<&mut Session> {
+        self.sessions.get_mut(&peer).filter(|s| s.id == id)
+    }
+
+    /// Drop idle sessions without a transfer, whenever the sweep timer
+    /// fires.
+    fn sweep(&mut self, cx: &mut Context<'_>) {
+        let timer = match self.sweep.as_mut() {
+            Some(timer) => timer,
+            None => return,
+        };
+
+        let mut fired = false;
+        while Pin::new(&mut *timer).poll_next(cx).is_ready() {
+            fired = true;
+        }
+
+        if fired {
+            let now = Instant::now();
+            self.sessions.retain(|_, s| {
+                s.transfers > 0 || now - s.last_active < SESSION_IDLE
+            });
+        }
+    }
+}
+
+impl Shared {
+    fn new_session(&self, peer: SocketAddr) -> io::Result<Session> {
+        let mut ssl = match self.role {
+            Role::Server(ref acceptor, ref cookies) => {
+                let mut ssl = Ssl::new(acceptor.context())?;
+                ssl.set_ex_data(cookies.peer, peer);
+                ssl.set_accept_state();
+                ssl
+            }
+            Role::Client(ref connector, ref domain) => {
+                let mut ssl = connector.configure()?.into_ssl(domain)?;
+                ssl.set_connect_state();
+                ssl
+            }
+        };
+        ssl.set_mtu(MTU)?;
+
+        trace!("New DTLS session with {}", peer);
+
+        Ok(Session {
+            id: 0,
+            stream: SslStream::new(ssl, Datagrams::default())?,
+            established: false,
+            transfers: 0,
+            received: VecDeque::new(),
+            pending: None,
+            last_active: Instant::now(),
+        })
+    }
+
+    /// Handle a datagram that `inner` received from `peer`, and return the
+    /// socket that got a decrypted datagram.
+    fn process(
+        &self,
+        state: &mut State,
+        cx: &mut Context<'_>,
+        data: Vec<u8>,
+        peer: SocketAddr,
+    ) -> Option<Option<SocketAddr>> {
+        if !state.sessions.contains_key(&peer) {
+            let session = self.accept(cx, &data, peer)?;
+
+            if state.sessions.len() >= MAX_SESSIONS {
+                debug!("Too many DTLS sessions, dropping datagram of {}", peer);
+                return None;
+            }
+
+            state.insert(peer, session);
+        }
+
+        let session = state.sessions.get_mut(&peer)?;
+        session.stream.get_mut().incoming.push_back(data);
+        session.last_active = Instant::now();
+
+        let mut target = None;
+        let mut closed = false;
+
+        if !session.established {
+            if let Err(e) = session.handshake() {
+                debug!("DTLS handshake with {} failed: {}", peer, e);
+                closed = true;
+            }
+        }
+
+        let mut buf = [0; MAX_PLAINTEXT];
+
+        while session.established && !closed {
+            match session.stream.ssl_read(&mut buf) {
+                Ok(len) => {
+                    let queue_len = match session.transfers {
+                        0 => state.unbound.len(),
+                        _ => session.received.len(),
+                    };
+
+                    if queue_len >= QUEUE_LIMIT {
+                        continue;
+                    }
+
+                    if session.transfers > 0 {
+                        session.received.push_back(buf[..len].to_vec());
+                        target = Some(Some(peer));
+                    } else {
+                        state.unbound.push_back((buf[..len].to_vec(), peer));
+                        target = Some(None);
+                    }
+                }
+                Err(e) if e.code() == ErrorCode::WANT_READ => break,
+                Err(e) => {
+                    if e.code() != ErrorCode::ZERO_RETURN {
+                        debug!("DTLS session with {} failed: {}", peer, e);
+                    }
+                    closed = true;
+                }
+            }
+        }
+
+        // Datagrams that can not be sent now go with the next ones
+        let _ = self.flush(session, cx, peer);
+
+        if closed {
+            state.sessions.remove(&peer);
+        }
+
+        target
+    }
+
+    /// Session of a new peer whose ClientHello returns its cookie.
+    ///
+    /// Other ClientHellos are answered with a HelloVerifyRequest, without
+    /// keeping state. The session is brought to the state in which it sent
+    /// the cookie by replaying the first ClientHello, which only lacks it.
+    fn accept(
+        &self,
+        cx: &mut Context<'_>,
+        data: &[u8],
+        peer: SocketAddr,
+    ) -> Option<Session> {
+        // Only servers accept new sessions
+        let cookies = match self.role {
+            Role::Server(_, ref cookies) => cookies,
+            Role::Client(..) => return None,
+        };
+
+        let (cookie, first_hello) = split_client_hello(data)?;
+
+        let mut session = match self.new_session(peer) {
+            Ok(session) => session,
+            Err(e) => {
+                debug!("Failed to create DTLS session: {}", e);
+                return None;
+            }
+        };
+
+        session.stream.get_mut().incoming.push_back(first_hello);
+
+        if let Err(e) = session.handshake() {
+            debug!("DTLS handshake with {} failed: {}", peer, e);
+            return None;
+        }
+
+        if !cookies.verify(peer, cookie) {
+            // HelloVerifyRequest with the cookie
+            let _ = self.flush(&mut session, cx, peer);
+            return None;
+        }
+
+        // It was sent when the first ClientHello was received
+        session.stream.get_mut().outgoing.clear();
+
+        Some(session)
+    }
+
+    /// Let the peers know that the sessions are closed, without waiting
+    /// for datagrams that can not be sent right away.
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        let waker = Waker::from(Arc::clone(&self.wakers));
+        let mut cx = Context::from_waker(&waker);
+
+        for (&peer, session) in state.sessions.iter_mut() {
+            if session.established {
+                let _ = session.stream.shutdown();
+                let _ = self.flush(session, &mut cx, peer);
+            }
+        }
+    }
+
+    /// Send the datagrams of `session` that are ready.
+    ///
+    /// Datagrams that can not be sent without waiting are kept, and
+    /// `Poll::Pending` is returned.
+    fn flush(
+        &self,
+        session: &mut Session,
+        cx: &mut Context<'_>,
+        peer: SocketAddr,
+    ) -> Poll<io::Result<()>> {
+        let outgoing = &mut session.stream.get_mut().outgoing;
+
+        while let Some(data) = outgoing.front() {
+            match self.inner.poll_send_to(cx, data, peer) {
+                Poll::Ready(Ok(_)) => {
+                    outgoing.pop_front();
+                }
+                Poll::Ready(Err(e)) => {
+                    outgoing.pop_front();
+                    return Poll::Ready(Err(e));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Session {
+    /// Continue the handshake. A datagram that waits for it is encrypted
+    /// once it is finished.
+    fn handshake(&mut self) -> Result<(), openssl::ssl::Error> {
+        match self.stream.do_handshake() {
+            Ok(()) => {
+                self.established = true;
+
+                if let Some(data) = self.pending.take() {
+                    self.stream.ssl_write(&data)?;
+                }
+
+                Ok(())
+            }
+            Err(e) if e.code() == ErrorCode::WANT_READ => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Read for Datagrams {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.incoming.pop_front() {
+            Some(data) => {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                Ok(len)
+            }
+            None => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl Write for Datagrams {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.push_back(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Wake for Wakers {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = std::mem::take(&mut *self.0.lock().unwrap());
+
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+}
+
+impl Wakers {
+    fn register(&self, socket: Option<SocketAddr>, waker: &Waker) {
+        self.0.lock().unwrap().insert(socket, waker.clone());
+    }
+
+    fn wake_socket(&self, socket: Option<SocketAddr>) {
+        let waker = self.0.lock().unwrap().remove(&socket);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Transport for DtlsTransport {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        let session = match self.peer {
+            Some(peer) => state.session(peer, self.session),
+            None => state.sessions.get_mut(&target),
+        };
+
+        let session = match (session, &self.shared.role) {
+            (Some(session), _) => session,
+            (None, Role::Client(..)) => {
+                let session = self.shared.new_session(target)?;
+                state.insert(target, session)
+            }
+            (None, Role::Server(..)) => {
+                return Poll::Ready(Err(io::ErrorKind::NotConnected.into()))
+            }
+        };
+
+        // Datagrams of the last call go first
+        if self.shared.flush(session, cx, target)?.is_pending() {
+            return Poll::Pending;
+        }
+
+        if session.established {
+            session.stream.ssl_write(buf).map_err(io::Error::other)?;
+        } else {
+            // The last datagram is sent once the handshake is finished.
+            // Continuing the handshake for every datagram sends its last
+            // flight again after its timer expired, in case it was lost.
+            session.pending = Some(buf.to_vec());
+
+            if let Err(e) = session.handshake() {
+                state.sessions.remove(&target);
+                return Poll::Ready(Err(io::Error::other(e)));
+            }
+        }
+
+        let _ = self.shared.flush(session, cx, target)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let mut state = self.shared.state.lock().unwrap();
+        let waker = Waker::from(Arc::clone(&self.shared.wakers));
+        let mut inner_cx = Context::from_waker(&waker);
+
+        // Idle sessions are dropped while the server waits for requests
+        if self.peer.is_none() {
+            state.sweep(cx);
+        }
+
+        loop {
+            let received = match self.peer {
+                Some(peer) => state
+                    .session(peer, self.session)
+                    .and_then(|s| s.received.pop_front())
+                    .map(|data| (data, peer)),
+                None => state.unbound.pop_front(),
+            };
+
+            if let Some((data, peer)) = received {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                return Poll::Ready(Ok((len, peer)));
+            }
+
+            self.shared.wakers.register(self.peer, cx.waker());
+
+            let mut recv_buf = std::mem::take(&mut state.recv_buf);
+            let res =
+                self.shared.inner.poll_recv_from(&mut inner_cx, &mut recv_buf);
+
+            let (len, peer) = match res {
+                Poll::Ready(Ok(res)) => res,
+                Poll::Ready(Err(e)) => {
+                    state.recv_buf = recv_buf;
+                    return Poll::Ready(Err(e));
+                }
+                Poll::Pending => {
+                    state.recv_buf = recv_buf;
+                    return Poll::Pending;
+                }
+            };
+
+            let data = recv_buf[..len].to_vec();
+            state.recv_buf = recv_buf;
+
+            let target = self.shared.process(&mut state, cx, data, peer);
+
+            match target {
+                Some(socket) if socket != self.peer => {
+                    self.shared.wakers.wake_socket(socket)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.inner.local_addr()
+    }
+
+    fn bind_transfer(
+        &self,
+        peer: SocketAddr,
+    ) -> io::Result<Box<dyn Transport>> {
+        if let Role::Client(..) = self.shared.role {
+            let inner = self.shared.inner.bind_transfer(peer)?;
+            return Ok(Box::new(DtlsTransport::new(
+                inner,
+                self.shared.role.clone(),
+            )));
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        let session =
+            state.sessions.get_mut(&peer).ok_or(io::ErrorKind::NotConnected)?;
+        session.transfers += 1;
+
+        Ok(Box::new(DtlsTransport {
+            shared: Arc::clone(&self.shared),
+            peer: Some(peer),
+            session: session.id,
+        }))
+    }
+}
+
+impl Drop for DtlsTransport {
+    fn drop(&mut self) {
+        let Some(peer) = self.peer else {
+            // Sessions of a client are not shared with other sockets
+            if let Role::Client(..) = self.shared.role {
+                self.shared.close();
+            }
+            return;
+        };
+
+        self.shared.wakers.0.lock().unwrap().remove(&Some(peer));
+
+        let mut state = self.shared.state.lock().unwrap();
+
+        // A session that was closed and replaced is not counted down
+        if let Some(session) = state.session(peer, self.session) {
+            session.transfers -= 1;
+
+            if session.transfers == 0 {
+                session.received.clear();
+            }
+        }
+    }
+}
+
+/// Cookie of a ClientHello datagram, and the ClientHello without it that
+/// the client sent first.
+///
+/// `None` is returned for other datagrams, and for a ClientHello that is
+/// fragmented or that does not fill the datagram.
+fn split_client_hello(data: &[u8]) -> Option<(&[u8], Vec<u8>)> {
+    // Record of a handshake message
+    if data.len() < RECORD_HEADER + HANDSHAKE_HEADER || data[0] != 22 {
+        return None;
+    }
+    let record_len = usize::from(u16::from_be_bytes([data[11], data[12]]));
+    if RECORD_HEADER + record_len != data.len() {
+        return None;
+    }
+
+    // Unfragmented ClientHello
+    let message = &data[RECORD_HEADER..];
+    let len = |at: usize| {
+        u32::from_be_bytes([0, message[at], message[at + 1], message[at + 2]])
+    };
+    let body_len = len(1);
+    if message[0] != 1 || len(6) != 0 || len(9) != body_len {
+        return None;
+    }
+    if HANDSHAKE_HEADER + body_len as usize != message.len() {
+        return None;
+    }
+
+    // Version and random, followed by the session id and the cookie
+    let mut at = RECORD_HEADER + HANDSHAKE_HEADER + 34;
+    at += 1 + usize::from(*data.get(at)?);
+    let cookie_len = usize::from(*data.get(at)?);
+    let cookie = data.get(at + 1..at + 1 + cookie_len)?;
+
+    let mut first = data[..at].to_vec();
+    first.push(0);
+    first.extend_from_slice(&data[at + 1 + cookie_len..]);
+
+    // First record and message of the handshake, whose lengths lack the
+    // cookie
+    let body_len = (body_len as usize - cookie_len) as u32;
+    let record_len = (record_len - cookie_len) as u16;
+    first[3..11].fill(0);
+    first[11..13].copy_from_slice(&record_len.to_be_bytes());
+    first[14..17].copy_from_slice(&body_len.to_be_bytes()[1..]);
+    first[17..19].fill(0);
+    first[22..25].copy_from_slice(&body_len.to_be_bytes()[1..]);
+
+    Some((cookie, first))
+}
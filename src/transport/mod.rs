@@ -0,0 +1,197 @@
+//! Datagram transports that TFTP runs over.
+//!
+//! The server and the client use UDP sockets, unless another [`Transport`]
+//! is set with [`TftpServerBuilder::transport`] or
+//! [`TftpClient::transport`].
+//!
+//! On Unix, `UnixTransport` runs TFTP over Unix datagram sockets between
+//! processes on the same host. With the `dtls` feature, `DtlsTransport`
+//! runs it over DTLS on top of another transport.
+//!
+//! [`TftpServerBuilder::transport`]: crate::server::TftpServerBuilder::transport
+//! [`TftpClient::transport`]: crate::client::TftpClient::transport
+
+use async_io::Async;
+use futures_lite::{future, ready};
+use socket2::{SockAddr, SockRef};
+use std::io::{self, IoSlice};
+use std::net::{SocketAddr, UdpSocket};
+use std::task::{Context, Poll};
+
+use crate::utils::{bind_async, recv_from};
+
+#[cfg(feature = "dtls")]
+mod dtls;
+#[cfg(unix)]
+mod unix;
+
+#[cfg(feature = "dtls")]
+pub use self::dtls::DtlsTransport;
+#[cfg(unix)]
+pub use self::unix::UnixTransport;
+
+/// Socket that sends and receives datagrams.
+///
+/// Peers are identified by [`SocketAddr`], like with UDP. Transports over
+/// other kinds of addresses map them to socket addresses.
+///
+/// Every transfer runs over its own socket. The server binds it with
+/// [`bind_transfer`](Self::bind_transfer) of its listening socket, and the
+/// client with the one of the socket that is set with
+/// [`TftpClient::transport`].
+///
+/// [`TftpClient::transport`]: crate::client::TftpClient::transport
+pub trait Transport: Send + Sync {
+    /// Attempt to send `buf` as a single datagram to `target`.
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>>;
+
+    /// Attempt to send `bufs` as a single datagram to `target`.
+    ///
+    /// The default implementation copies `bufs` into a common buffer.
+    fn poll_send_to_vectored(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        let buf: Vec<u8> =
+            bufs.iter().flat_map(|b| b.iter().copied()).collect();
+        self.poll_send_to(cx, &buf, target)
+    }
+
+    /// Attempt to receive a datagram and the address it came from.
+    ///
+    /// A datagram that does not fit in `buf` must be truncated, and not fail
+    /// the receive. E.g. UDP sockets on Windows fail with `WSAEMSGSIZE`,
+    /// which the implementation for UDP sockets of this crate maps to the
+    /// truncated datagram.
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>>;
+
+    /// Local address of the socket.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// UDP socket that the transport runs over, e.g. to read its options.
+    ///
+    /// The default implementation returns `None`.
+    fn udp_socket(&self) -> Option<&UdpSocket> {
+        None
+    }
+
+    /// Bind a new socket on the same host for a transfer with `peer`.
+    fn bind_transfer(&self, peer: SocketAddr)
+        -> io::Result<Box<dyn Transport>>;
+}
+
+impl dyn Transport + '_ {
+    /// Send `buf` as a single datagram to `target`.
+    pub async fn send_to(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> io::Result<usize> {
+        future::poll_fn(|cx| self.poll_send_to(cx, buf, target)).await
+    }
+
+    /// Send `bufs` as a single datagram to `target`.
+    pub async fn send_to_vectored(
+        &self,
+        bufs: &[IoSlice<'_>],
+        target: SocketAddr,
+    ) -> io::Result<usize> {
+        future::poll_fn(|cx| self.poll_send_to_vectored(cx, bufs, target)).await
+    }
+
+    /// Receive a datagram and the address it came from.
+    pub async fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr)> {
+        future::poll_fn(|cx| self.poll_recv_from(cx, buf)).await
+    }
+
+    /// Send `buf` to `target` if it can be sent without waiting, e.g. from
+    /// `Drop`.
+    pub fn try_send_to(&self, buf: &[u8], target: SocketAddr) -> bool {
+        let send = future::poll_once(self.send_to(buf, target));
+        matches!(future::block_on(send), Some(Ok(_)))
+    }
+}
+
+impl Transport for Async<UdpSocket> {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            match self.get_ref().send_to(buf, target) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+
+            ready!(self.poll_writable(cx))?;
+        }
+    }
+
+    fn poll_send_to_vectored(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        let target = SockAddr::from(target);
+
+        // Sent without copying `bufs` into a common buffer
+        loop {
+            let socket = SockRef::from(self.get_ref());
+
+            match socket.send_to_vectored(bufs, &target) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+
+            ready!(self.poll_writable(cx))?;
+        }
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        loop {
+            match recv_from(self.get_ref(), buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+
+            ready!(self.poll_readable(cx))?;
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.get_ref().local_addr()
+    }
+
+    fn udp_socket(&self) -> Option<&UdpSocket> {
+        Some(self.get_ref())
+    }
+
+    fn bind_transfer(
+        &self,
+        _peer: SocketAddr,
+    ) -> io::Result<Box<dyn Transport>> {
+        let addr = SocketAddr::new(self.local_addr()?.ip(), 0);
+        Ok(Box::new(bind_async(addr)?))
+    }
+}
@@ -0,0 +1,268 @@
+//! Transport over Unix datagram sockets.
+
+use async_io::Async;
+use futures_lite::ready;
+use log::trace;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use super::Transport;
+
+/// Paths that are mapped to addresses before the oldest ones are
+/// forgotten.
+const MAX_PEERS: usize = 4096;
+
+/// Port of the addresses that stand for sockets that are not bound for a
+/// transfer.
+const PEER_PORT: u16 = 69;
+
+/// First port of the sockets that are bound for transfers.
+const FIRST_TRANSFER_PORT: u16 = 49152;
+
+/// Attempts to bind the socket of a transfer before giving up.
+const BIND_ATTEMPTS: u32 = 16;
+
+/// Transport over a Unix datagram socket, for processes on the same host.
+///
+/// Sockets are identified by their paths. Since TFTP identifies peers by
+/// [`SocketAddr`], every path is mapped to an address of the unique local
+/// IPv6 prefix `fd00::/96`. The address of the socket of a server, to
+/// pass to [`TftpClient::new`], is looked up with
+/// [`peer_addr`](Self::peer_addr) of the socket of the client.
+///
+/// The socket of a transfer is bound next to the socket that it is bound
+/// from, with the port of the transfer as a suffix, e.g.
+/// `tftpd.sock.49152`. It is mapped to the address of that socket with
+/// the port of the suffix, so clients accept its replies like the ones
+/// of a new UDP port of the server. Sockets remove their path when they
+/// are dropped. Datagrams from unbound sockets are dropped, since they
+/// can not be replied to.
+///
+/// # Example
+///
+/// ```ignore
+/// let tftpd = TftpServerBuilder::with_dir("/srv/tftp")?
+///     .transport(UnixTransport::bind("/run/tftpd.sock")?)
+///     .build()
+///     .await?;
+///
+/// let transport = UnixTransport::bind("/run/jig.sock")?;
+/// let server = transport.peer_addr("/run/tftpd.sock");
+/// let client = TftpClient::new(server).transport(transport);
+/// ```
+///
+/// [`TftpClient::new`]: crate::client::TftpClient::new
+#[derive(Debug)]
+pub struct UnixTransport {
+    socket: Async<UnixDatagram>,
+    path: PathBuf,
+    // Path of the socket that transfer sockets are bound next to
+    base: PathBuf,
+    addr: SocketAddr,
+    peers: Arc<Mutex<Peers>>,
+}
+
+/// Addresses of the paths that a socket and its transfers talk to.
+#[derive(Debug, Default)]
+struct Peers {
+    addrs: HashMap<PathBuf, SocketAddr>,
+    paths: HashMap<SocketAddr, PathBuf>,
+    // Addresses in the order they were assigned
+    order: VecDeque<SocketAddr>,
+    next_addr: u32,
+    next_port: u16,
+}
+
+impl UnixTransport {
+    /// Bind a socket to `path`.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        UnixTransport::bind_with(path, path, Arc::default())
+    }
+
+    fn bind_with(
+        path: &Path,
+        base: &Path,
+        peers: Arc<Mutex<Peers>>,
+    ) -> io::Result<Self> {
+        let socket = Async::<UnixDatagram>::bind(path)?;
+        let addr = peers.lock().unwrap().addr(path);
+
+        Ok(UnixTransport {
+            socket,
+            path: path.to_path_buf(),
+            base: base.to_path_buf(),
+            addr,
+            peers,
+        })
+    }
+
+    /// Address that stands for the socket at `path`.
+    pub fn peer_addr<P: AsRef<Path>>(&self, path: P) -> SocketAddr {
+        self.peers.lock().unwrap().addr(path.as_ref())
+    }
+
+    /// Path of the socket that `addr` stands for.
+    pub fn peer_path(&self, addr: SocketAddr) -> Option<PathBuf> {
+        self.peers.lock().unwrap().paths.get(&addr).cloned()
+    }
+
+    /// Path that the socket is bound to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Peers {
+    fn addr(&mut self, path: &Path) -> SocketAddr {
+        if let Some(addr) = self.addrs.get(path) {
+            return *addr;
+        }
+
+        let addr = match self.transfer_addr(path) {
+            Some(addr) => addr,
+            None => self.new_addr(),
+        };
+
+        if self.order.len() >= MAX_PEERS {
+            if let Some(oldest) = self.order.pop_front() {
+                if let Some(path) = self.paths.remove(&oldest) {
+                    self.addrs.remove(&path);
+                }
+            }
+        }
+
+        self.addrs.insert(path.to_path_buf(), addr);
+        self.paths.insert(addr, path.to_path_buf());
+        self.order.push_back(addr);
+
+        addr
+    }
+
+    /// Address of `path` if it is the socket of a transfer, i.e. a known
+    /// path with a port as suffix.
+    fn transfer_addr(&self, path: &Path) -> Option<SocketAddr> {
+        let name = path.file_name()?.to_str()?;
+        let (base, port) = name.rsplit_once('.')?;
+        let port = port.parse::<u16>().ok();
+        let port = port.filter(|port| *port != 0 && *port != PEER_PORT)?;
+        let base = self.addrs.get(&path.with_file_name(base))?;
+
+        Some(SocketAddr::new(base.ip(), port))
+    }
+
+    fn new_addr(&mut self) -> SocketAddr {
+        self.next_addr = self.next_addr.wrapping_add(1).max(1);
+        let [a, b, c, d] = self.next_addr.to_be_bytes();
+        let ip = Ipv6Addr::new(
+            0xfd00,
+            0,
+            0,
+            0,
+            0,
+            0,
+            u16::from_be_bytes([a, b]),
+            u16::from_be_bytes([c, d]),
+        );
+
+        SocketAddr::new(ip.into(), PEER_PORT)
+    }
+
+    fn next_port(&mut self) -> u16 {
+        let ports = u16::MAX - FIRST_TRANSFER_PORT + 1;
+        let port = FIRST_TRANSFER_PORT + self.next_port;
+        self.next_port = (self.next_port + 1) % ports;
+        port
+    }
+}
+
+impl Transport for UnixTransport {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        let path = match self.peer_path(target) {
+            Some(path) => path,
+            None => return Poll::Ready(Err(io::ErrorKind::NotFound.into())),
+        };
+
+        loop {
+            match self.socket.get_ref().send_to(buf, &path) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+
+            ready!(self.socket.poll_writable(cx))?;
+        }
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        loop {
+            match self.socket.get_ref().recv_from(buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Poll::Ready(Err(e)),
+                Ok((len, peer)) => match peer.as_pathname() {
+                    Some(path) => {
+                        let addr = self.peer_addr(path);
+                        return Poll::Ready(Ok((len, addr)));
+                    }
+                    None => {
+                        trace!("Dropping datagram of unbound Unix socket");
+                        continue;
+                    }
+                },
+            }
+
+            ready!(self.socket.poll_readable(cx))?;
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn bind_transfer(
+        &self,
+        _peer: SocketAddr,
+    ) -> io::Result<Box<dyn Transport>> {
+        let mut last_error = None;
+
+        for _ in 0..BIND_ATTEMPTS {
+            let port = self.peers.lock().unwrap().next_port();
+            let mut path = OsString::from(&self.base);
+            path.push(format!(".{}", port));
+
+            let path = Path::new(&path);
+            match UnixTransport::bind_with(path, &self.base, self.peers.clone())
+            {
+                Ok(transport) => return Ok(Box::new(transport)),
+                // Path of a socket that is still bound, or of a stale one
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| io::ErrorKind::AddrInUse.into()))
+    }
+}
+
+impl Drop for UnixTransport {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
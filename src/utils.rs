@@ -1,16 +1,238 @@
-use async_io::Timer;
+use async_io::Async;
 use futures_lite::future;
+use socket2::{Domain, Socket, Type};
 use std::future::Future;
 use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::clock::Clock;
+
 pub async fn io_timeout<T>(
+    clock: &dyn Clock,
     dur: Duration,
     f: impl Future<Output = io::Result<T>>,
 ) -> io::Result<T> {
     future::race(f, async move {
-        Timer::after(dur).await;
+        clock.sleep(dur).await;
         Err(io::ErrorKind::TimedOut.into())
     })
     .await
 }
+
+/// Returns a future that sleeps long enough for `len` bytes to stay under
+/// the data rate of `max_bytes_per_sec`, or resolves at once without a
+/// limit.
+pub fn throttle(
+    clock: &dyn Clock,
+    max_bytes_per_sec: Option<u64>,
+    len: usize,
+) -> impl Future<Output = ()> {
+    let sleep = max_bytes_per_sec.map(|rate| {
+        let secs = len as f64 / rate.max(1) as f64;
+        clock.sleep(Duration::from_secs_f64(secs))
+    });
+
+    async move {
+        if let Some(sleep) = sleep {
+            sleep.await;
+        }
+    }
+}
+
+/// Returns `true` if `err` is caused by an ICMP "port unreachable" that
+/// was triggered by a previous `send_to`.
+///
+/// Windows reports these on the next `recv_from` of a UDP socket as
+/// `WSAECONNRESET`, unless it is disabled with `set_udp_conn_reset`.
+/// This is not an error of the socket itself, so the caller should ignore
+/// it and continue receiving.
+pub fn is_conn_reset(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::ConnectionReset
+}
+
+/// Returns `true` if `err` is caused by a datagram that does not fit in
+/// the buffer of the receive.
+///
+/// Windows reports this as `WSAEMSGSIZE`, instead of truncating the
+/// datagram. UDP sockets of the crate receive the truncated datagram, see
+/// [`recv_from`], but other transports may fail with it.
+pub fn is_msg_size(err: &io::Error) -> bool {
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Networking::WinSock::WSAEMSGSIZE;
+        err.raw_os_error() == Some(WSAEMSGSIZE)
+    }
+
+    #[cfg(not(windows))]
+    {
+        let _ = err;
+        false
+    }
+}
+
+/// Create an unbound UDP socket for `addr`. With `reuse_port` other
+/// sockets of the process can be bound to the same address.
+///
+/// On Windows, ICMP "port unreachable" is not reported on the socket, see
+/// `set_udp_conn_reset`.
+pub fn udp_socket(addr: SocketAddr, reuse_port: bool) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, None)?;
+
+    if reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(unix))]
+        socket.set_reuse_address(true)?;
+    }
+
+    #[cfg(windows)]
+    set_udp_conn_reset(&socket, false)?;
+
+    Ok(socket)
+}
+
+/// Bind a UDP socket to `addr` for the reactor of `async-io`.
+pub fn bind_async(addr: SocketAddr) -> io::Result<Async<UdpSocket>> {
+    Async::new(bind_with(addr, false, |_| Ok(()))?)
+}
+
+/// Enable or disable `SIO_UDP_CONNRESET` of a UDP socket.
+///
+/// While it is enabled, an ICMP "port unreachable" of a sent datagram
+/// fails the next `recv_from` of the socket with `WSAECONNRESET`, even if
+/// datagrams of other peers are waiting. Windows enables it by default.
+#[cfg(windows)]
+pub fn set_udp_conn_reset(socket: &Socket, enabled: bool) -> io::Result<()> {
+    use std::os::windows::io::AsRawSocket;
+    use std::{mem, ptr};
+    use windows_sys::Win32::Networking::WinSock::{
+        WSAIoctl, SIO_UDP_CONNRESET, SOCKET_ERROR,
+    };
+
+    let enabled = i32::from(enabled);
+    let mut returned = 0u32;
+
+    // SAFETY: The input buffer is a `BOOL` that outlives the call. There is
+    // no output buffer and the call is not overlapped.
+    let res = unsafe {
+        WSAIoctl(
+            socket.as_raw_socket() as _,
+            SIO_UDP_CONNRESET,
+            &enabled as *const i32 as *const _,
+            mem::size_of::<i32>() as u32,
+            ptr::null_mut(),
+            0,
+            &mut returned,
+            ptr::null_mut(),
+            None,
+        )
+    };
+
+    if res == SOCKET_ERROR {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Receive a datagram of `socket` and the address it came from.
+///
+/// A datagram that does not fit in `buf` is truncated. Windows fails the
+/// receive with `WSAEMSGSIZE` instead, after it copied the start of the
+/// datagram into `buf`, so it is returned as truncated datagram as well.
+pub fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr)> {
+    #[cfg(windows)]
+    {
+        use socket2::{MaybeUninitSlice, SockRef};
+        use std::mem::MaybeUninit;
+
+        let len = buf.len();
+        // SAFETY: The socket writes only initialized bytes to `buf`.
+        let buf = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+
+        let socket = SockRef::from(socket);
+        let mut bufs = [MaybeUninitSlice::new(buf)];
+        let (received, flags, addr) = socket.recv_from_vectored(&mut bufs)?;
+
+        let addr = addr.as_socket().ok_or(io::ErrorKind::InvalidData)?;
+        match flags.is_truncated() {
+            true => Ok((len, addr)),
+            false => Ok((received, addr)),
+        }
+    }
+
+    #[cfg(not(windows))]
+    socket.recv_from(buf)
+}
+
+/// Bind a UDP socket to `addr`, after `setup` set options of it.
+pub fn bind_with(
+    addr: SocketAddr,
+    reuse_port: bool,
+    setup: impl FnOnce(&Socket) -> io::Result<()>,
+) -> io::Result<UdpSocket> {
+    let socket = udp_socket(addr, reuse_port)?;
+    setup(&socket)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// Options that are set on every socket of the server before it is bound.
+///
+/// `mark` and `device` are supported only on Linux, `conn_reset` only on
+/// Windows. They are ignored elsewhere.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub struct SocketOpts {
+    // `SO_MARK`
+    pub mark: Option<u32>,
+    // `SO_BINDTODEVICE`, e.g. a VRF device
+    pub device: Option<Arc<str>>,
+    // `SIO_UDP_CONNRESET`, disabled by `udp_socket`
+    pub conn_reset: bool,
+}
+
+impl SocketOpts {
+    pub fn apply(&self, socket: &Socket) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(mark) = self.mark {
+                socket.set_mark(mark)?;
+            }
+
+            if let Some(ref device) = self.device {
+                socket.bind_device(Some(device.as_bytes()))?;
+            }
+        }
+
+        #[cfg(windows)]
+        if self.conn_reset {
+            set_udp_conn_reset(socket, true)?;
+        }
+
+        #[cfg(not(any(target_os = "linux", windows)))]
+        let _ = socket;
+
+        Ok(())
+    }
+}
+
+/// Filename bytes of `path`, as they are sent in a request.
+pub fn path_to_bytes(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    }
+
+    #[cfg(not(unix))]
+    {
+        path.to_string_lossy().into_owned().into_bytes()
+    }
+}